@@ -1,126 +1,43 @@
-use crate::{
-  files::{create_index_path, get_path_str},
-  traits::TypeWrite,
+use crate::{files::get_path_str, traits::TypeWrite};
+use sstables::{
+  cbor::{CborRead, CborWrite},
+  read::{create_index_offsets_path, create_summary_path},
+  SSTable,
 };
-use sstables::{cbor::CborRead, FromPath, SSTableIndex, SSTableReader};
-use std::{
-  fmt::Display,
-  fs::File,
-  io::{self, Seek},
-  path::PathBuf,
-};
-
-fn get_index_entry_by_key<K>(sstable_index: &SSTableIndex<K>, key: &K) -> Option<(K, u64)>
-where
-  K: Ord + Clone,
-{
-  match sstable_index.indices.binary_search_by(|(k, _)| (k.cmp(key))) {
-    Ok(x) => sstable_index.indices.get(x).cloned(),
-    Err(_) => None,
-  }
-}
-
-fn write_next_n_with_key<I, K, V>(
-  iterator: &mut I,
-  index_key: K,
-  n: Option<usize>,
-  writer: &mut impl TypeWrite<String>,
-) -> io::Result<()>
-where
-  I: Iterator<Item = io::Result<(K, V)>>,
-  K: Ord + Display,
-  V: Display,
-{
-  let mut kv_maybe = iterator.next();
-  let mut count = 0;
-  while let Some(kv_result) = kv_maybe {
-    let (key, value) = match kv_result {
-      Ok(x) => x,
-      Err(e) => return Err(e),
-    };
-
-    if key != index_key {
-      break;
-    }
-
-    writer.write(format!("{}: {}", key, value))?;
-    count += 1;
-
-    if let Some(n) = n {
-      if count >= n {
-        break;
-      }
-    }
-
-    kv_maybe = iterator.next();
-  }
-  Ok(())
-}
-
-fn get_kv_by_linear_search<I, K, V>(
-  iterator: &mut I,
-  key: &K,
-  n: Option<usize>,
-  writer: &mut impl TypeWrite<String>,
-) -> io::Result<()>
-where
-  I: Iterator<Item = io::Result<(K, V)>>,
-  K: Ord + Display,
-  V: Display,
-{
-  let mut count = 0;
-  for result in iterator {
-    let (k, v) = result?;
-    if &k == key {
-      writer.write(v.to_string())?;
-      count += 1;
-    }
-    if let Some(n) = n {
-      if count >= n {
-        break;
-      }
-    }
-  }
-
-  Ok(())
-}
+use std::{fmt::Display, fs::File, io, path::PathBuf};
 
-pub fn get<K: Ord + Clone + Display, V: Display>(
+/// Writes up to n entries with the key from each SSTable. The bloom filter and index of each
+/// SSTable are used if available, otherwise the data file is scanned. An index with a summary is
+/// read a block at a time, and an index with an offset table is binary searched on disk.
+pub fn get<K, V>(
   input_paths: &[PathBuf],
   key: K,
   n: Option<usize>,
   writer: &mut impl TypeWrite<String>,
 ) -> io::Result<()>
 where
-  K: Ord + Clone + Display,
+  K: Ord + Clone + Display + CborWrite,
   V: Display,
-  io::BufReader<File>: CborRead<K>,
-  io::BufReader<File>: CborRead<V>,
-  SSTableIndex<K>: FromPath<K>,
+  io::BufReader<File>: CborRead<K> + CborRead<V>,
+  for<'a> &'a [u8]: CborRead<K>,
 {
   for input_path in input_paths {
     if !input_path.is_file() {
       writer.write(format!("File does not exist: {}", get_path_str(input_path)))?
     } else {
-      // First check for the presence of the data file.
-      let mut sstable_reader = SSTableReader::<(K, V)>::from_path(input_path)?;
-
-      // Second, check for the presence of the index file.
-      match SSTableIndex::<K>::from_path(create_index_path(input_path)) {
-        Ok(sstable_index) => {
-          let (index_key, offset) = match get_index_entry_by_key(&sstable_index, &key) {
-            Some(x) => x,
-            None => {
-              // Don't print any error message if the key is not found in the index.
-              return Ok(());
-            }
-          };
-          sstable_reader.seek(io::SeekFrom::Start(offset))?;
-          write_next_n_with_key(&mut sstable_reader, index_key, n, writer)?;
-        }
-        Err(_) => {
-          get_kv_by_linear_search(&mut sstable_reader, &key, n, writer)?;
-        }
+      // A summary or an offset table lets the index be searched on disk instead of being loaded.
+      let mut sstable = if create_summary_path(input_path).is_file() {
+        SSTable::<K, V>::open_with_summary(input_path)?
+      } else if create_index_offsets_path(input_path).is_file() {
+        SSTable::<K, V>::open_with_disk_index(input_path)?
+      } else {
+        SSTable::<K, V>::open(input_path)?
+      };
+
+      // Don't print any error message if the key is not found.
+      for kv_result in sstable.get_all(&key)?.take(n.unwrap_or(usize::MAX)) {
+        let (k, v) = kv_result?;
+        writer.write(format!("{}: {}", k, v))?;
       }
     }
   }
@@ -131,7 +48,14 @@ where
 #[cfg(test)]
 mod tests {
   use super::*;
-  use common_testing::assert;
+  use common_testing::{assert, setup};
+  use sstables::{
+    cbor::Timestamp,
+    read::{create_bloom_path, create_index_path},
+    sstable_writer::IndexDensity,
+    SSTableWriterBuilder,
+  };
+  use std::path::Path;
 
   struct MockTypeWriter<T> {
     pub items: Vec<T>,
@@ -154,71 +78,129 @@ mod tests {
     }
   }
 
-  #[test]
-  fn get_index_entry_by_key_works() {
-    let sstable_index = SSTableIndex {
-      indices: vec![("a".to_string(), 0), ("b".to_string(), 1)],
-    };
-
-    assert::equal(
-      get_index_entry_by_key(&sstable_index, &"a".to_string()),
-      ("a".to_string(), 0u64),
-    );
-    assert::equal(
-      get_index_entry_by_key(&sstable_index, &"b".to_string()),
-      ("b".to_string(), 1u64),
-    );
-    assert::equal(get_index_entry_by_key(&sstable_index, &"d".to_string()), None);
+  fn setup_test_sstable(path: &str, entries: &[(&str, &str)], index_density: IndexDensity) -> io::Result<()> {
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(path)?;
+    setup::remove_file(create_index_path(Path::new(path)))?;
+    setup::remove_file(create_bloom_path(Path::new(path)))?;
+    setup::remove_file(create_index_offsets_path(Path::new(path)))?;
+    setup::remove_file(create_summary_path(Path::new(path)))?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(path)
+      .index_density(index_density)
+      .bloom_filter(10, 0.01)
+      .build()?;
+    for entry in entries {
+      sstable_writer.write(*entry)?;
+    }
+    sstable_writer.close()
   }
 
-  fn create_iterator() -> impl Iterator<Item = io::Result<(String, String)>> {
-    vec![
-      Ok(("a".to_string(), "1".to_string())),
-      Ok(("a".to_string(), "2".to_string())),
-      Ok(("b".to_string(), "3".to_string())),
-      Ok(("b".to_string(), "4".to_string())),
-      Ok(("c".to_string(), "5".to_string())),
-      Ok(("c".to_string(), "6".to_string())),
-    ]
-    .into_iter()
+  /// Makes the key of the first entry unreadable as text, so that only a lookup that seeks past it
+  /// can succeed.
+  fn corrupt_first_key(path: &str) -> io::Result<()> {
+    let mut data = std::fs::read(path)?;
+    data[1] = 0xff;
+    std::fs::write(path, data)
   }
 
   #[test]
-  fn get_kv_by_linear_search_works() {
-    // Start
-    let mut iterator = create_iterator();
+  fn get_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = [("a", "1"), ("a", "2"), ("b", "3"), ("b", "4"), ("c", "5"), ("c", "6")];
+    setup_test_sstable(".tmp/get_test_1", &entries, IndexDensity::Dense)?;
+    setup_test_sstable(".tmp/get_test_2", &entries, IndexDensity::EveryRecords(4))?;
+    let input_paths = vec![PathBuf::from(".tmp/get_test_1"), PathBuf::from(".tmp/get_test_2")];
+
+    let mut writer = MockTypeWriter::new();
+    get::<String, String>(&input_paths, "b".to_string(), None, &mut writer)?;
+    assert::equal(writer.items, vec!["b: 3", "b: 4", "b: 3", "b: 4"]);
+
     let mut writer = MockTypeWriter::new();
-    get_kv_by_linear_search(&mut iterator, &"a".to_string(), None, &mut writer).unwrap();
-    assert::equal(writer.items, vec!["1".to_string(), "2".to_string()]);
+    get::<String, String>(&input_paths, "c".to_string(), Some(1), &mut writer)?;
+    assert::equal(writer.items, vec!["c: 5", "c: 5"]);
 
-    // End
-    let mut iterator = create_iterator();
     let mut writer = MockTypeWriter::new();
-    get_kv_by_linear_search(&mut iterator, &"c".to_string(), Some(2), &mut writer).unwrap();
-    assert::equal(writer.items, vec!["5".to_string(), "6".to_string()]);
+    get::<String, String>(&input_paths, "d".to_string(), None, &mut writer)?;
+    assert::equal(writer.items, Vec::<String>::new());
+
+    Ok(())
   }
 
   #[test]
-  fn get_kv_by_linear_search_with_limit() {
-    let mut iterator = create_iterator();
+  fn get_with_offset_table_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = [("a", "1"), ("a", "2"), ("a", "3"), ("b", "4"), ("b", "5"), ("c", "6")];
+    setup_test_sstable(".tmp/get_test_1", &entries, IndexDensity::EveryRecords(2))?;
+    SSTableWriterBuilder::new(".tmp/get_test_1")
+      .index_offsets()
+      .build()?
+      .close()?;
+    corrupt_first_key(".tmp/get_test_1")?;
+    let input_paths = vec![PathBuf::from(".tmp/get_test_1")];
+
     let mut writer = MockTypeWriter::new();
-    get_kv_by_linear_search(&mut iterator, &"b".to_string(), Some(1), &mut writer).unwrap();
-    assert::equal(writer.items, vec!["3".to_string()]);
+    get::<String, String>(&input_paths, "b".to_string(), None, &mut writer)?;
+    assert::equal(writer.items, vec!["b: 4", "b: 5"]);
+
+    setup::remove_file(create_index_offsets_path(Path::new(".tmp/get_test_1")))
   }
 
   #[test]
-  fn write_next_n_with_key_works() {
-    let mut iterator = create_iterator();
+  fn get_with_summary_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = [("a", "1"), ("a", "2"), ("a", "3"), ("b", "4"), ("b", "5"), ("c", "6")];
+    setup_test_sstable(".tmp/get_test_1", &[], IndexDensity::Dense)?;
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/get_test_1").summary(2).build()?;
+    for entry in entries {
+      sstable_writer.write(entry)?;
+    }
+    sstable_writer.close()?;
+    corrupt_first_key(".tmp/get_test_1")?;
+    let input_paths = vec![PathBuf::from(".tmp/get_test_1")];
+
     let mut writer = MockTypeWriter::new();
-    write_next_n_with_key(&mut iterator, "a".to_string(), Some(2), &mut writer).unwrap();
-    assert::equal(writer.items, vec!["a: 1".to_string(), "a: 2".to_string()]);
+    get::<String, String>(&input_paths, "b".to_string(), None, &mut writer)?;
+    assert::equal(writer.items, vec!["b: 4", "b: 5"]);
+
+    setup::remove_file(create_summary_path(Path::new(".tmp/get_test_1")))
   }
 
   #[test]
-  fn write_next_n_with_key_with_limit() {
-    let mut iterator = create_iterator();
+  fn get_without_index_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = [("b", "1"), ("a", "2"), ("b", "3")];
+    setup_test_sstable(".tmp/get_test_1", &entries, IndexDensity::Dense)?;
+    setup::remove_file(".tmp/get_test_1.index")?;
+    let input_paths = vec![PathBuf::from(".tmp/get_test_1"), PathBuf::from(".tmp/get_test_missing")];
+
     let mut writer = MockTypeWriter::new();
-    write_next_n_with_key(&mut iterator, "a".to_string(), Some(1), &mut writer).unwrap();
-    assert::equal(writer.items, vec!["a: 1".to_string()]);
+    get::<String, String>(&input_paths, "b".to_string(), None, &mut writer)?;
+    assert::equal(
+      writer.items,
+      vec!["b: 1", "b: 3", "File does not exist: .tmp/get_test_missing"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn get_works_on_timestamps() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(".tmp/get_test_1")?;
+    setup::remove_file(".tmp/get_test_1.index")?;
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/get_test_1").build()?;
+    sstable_writer.write((Timestamp::from_secs(-1), "before"))?;
+    sstable_writer.write((Timestamp::new(1363896240, 500_000), "after"))?;
+    sstable_writer.close()?;
+    let input_paths = vec![PathBuf::from(".tmp/get_test_1")];
+
+    let mut writer = MockTypeWriter::new();
+    let key = "2013-03-21T20:04:00.5Z".parse::<Timestamp>()?;
+    get::<Timestamp, String>(&input_paths, key, None, &mut writer)?;
+    assert::equal(writer.items, vec!["2013-03-21T20:04:00.5Z: after"]);
+
+    Ok(())
   }
 }
@@ -0,0 +1,280 @@
+use crate::traits::TypeWrite;
+use sstables::{
+  bloom::{load_bloom_filter, DEFAULT_EXPECTED_ITEMS},
+  cbor::CborRead,
+  merge::{DuplicatePolicy, MergingIterator},
+  FromPath, SSTableReader, Tombstone,
+};
+use std::{fs::File, io, path::PathBuf};
+
+/// Merges SSTables that are each sorted by key into the writer. The data files are streamed from
+/// start to end without reading their indices. Entries with equal keys are seen in the order of the
+/// input paths, which should go from oldest to newest, and are resolved by the policy. Tombstones
+/// are dropped after resolving duplicates if asked to. Fails if an input is not sorted, which the
+/// `sort` command can fix.
+pub fn merge<K, V>(
+  input_paths: &[PathBuf],
+  policy: DuplicatePolicy<V>,
+  drop_tombstones: bool,
+  writer: &mut impl TypeWrite<(K, V)>,
+) -> io::Result<()>
+where
+  K: Ord,
+  V: Tombstone<Live = V>,
+  io::BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  let sources = input_paths
+    .iter()
+    .map(SSTableReader::<(K, V)>::from_path)
+    .collect::<io::Result<Vec<_>>>()?;
+
+  let entries = MergingIterator::new(sources).resolve_duplicates(policy);
+  if drop_tombstones {
+    for kv_result in entries.drop_tombstones() {
+      writer.write(kv_result?)?;
+    }
+  } else {
+    for kv_result in entries {
+      writer.write(kv_result?)?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Estimates the number of keys of the SSTables from their bloom filters, to size the bloom filter
+/// of the merged SSTable. SSTables without a bloom filter count as `DEFAULT_EXPECTED_ITEMS`.
+pub fn count_expected_items(input_paths: &[PathBuf]) -> io::Result<usize> {
+  let mut expected_items = 0;
+  for input_path in input_paths {
+    expected_items += match load_bloom_filter(input_path)? {
+      Some(bloom_filter) => bloom_filter.len() as usize,
+      None => DEFAULT_EXPECTED_ITEMS,
+    };
+  }
+  Ok(expected_items)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use sstables::{cbor::CborValue, sstable_writer::IndexDensity, SSTableWriterBuilder};
+
+  /// Setup the test by removing any existing files.
+  fn setup_remove_test_sstables() -> io::Result<()> {
+    setup::create_dir_all(".tmp")?;
+    for path in [
+      ".tmp/merge_test",
+      ".tmp/merge_test_1",
+      ".tmp/merge_test_2",
+      ".tmp/merge_test_3",
+    ] {
+      setup::remove_file(path)?;
+      setup::remove_file(format!("{}.index", path))?;
+      setup::remove_file(format!("{}.bloom", path))?;
+    }
+
+    Ok(())
+  }
+
+  /// Write three SSTables with the following key-value pairs:
+  fn setup_test_sstables() -> io::Result<Vec<PathBuf>> {
+    setup_remove_test_sstables()?;
+
+    let mut sstable_writer_1 = SSTableWriterBuilder::new(".tmp/merge_test_1").build()?;
+    sstable_writer_1.write(("a", "1"))?;
+    sstable_writer_1.write(("d", "4"))?;
+    sstable_writer_1.close()?;
+
+    let mut sstable_writer_2 = SSTableWriterBuilder::new(".tmp/merge_test_2")
+      .bloom_filter(10, 0.01)
+      .build()?;
+    sstable_writer_2.write(("b", "2"))?;
+    sstable_writer_2.write(("e", "5"))?;
+    sstable_writer_2.close()?;
+
+    let mut sstable_writer_3 = SSTableWriterBuilder::new(".tmp/merge_test_3").build()?;
+    sstable_writer_3.write(("c", "3"))?;
+    sstable_writer_3.write(("f", "6"))?;
+    sstable_writer_3.close()?;
+
+    Ok(vec![
+      PathBuf::from(".tmp/merge_test_1"),
+      PathBuf::from(".tmp/merge_test_2"),
+      PathBuf::from(".tmp/merge_test_3"),
+    ])
+  }
+
+  /// Read every key-value pair of an SSTable into a string.
+  fn read_test_sstable(path: &str) -> io::Result<String> {
+    let mut result = String::new();
+    for kv_result in SSTableReader::<(String, String)>::from_path(path)? {
+      let (key, value) = kv_result?;
+      result.push_str(&format!("{}: {}\n", &key, &value));
+    }
+    Ok(result)
+  }
+
+  #[test]
+  fn test_merge_1() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let input_paths = setup_test_sstables()?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+    merge::<String, CborValue>(&input_paths[0..1], DuplicatePolicy::KeepAll, false, &mut sstable_writer)?;
+    sstable_writer.close()?;
+
+    assert::equal(read_test_sstable(".tmp/merge_test")?, "a: 1\nd: 4\n");
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let input_paths = setup_test_sstables()?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+    merge::<String, CborValue>(&input_paths, DuplicatePolicy::KeepAll, false, &mut sstable_writer)?;
+    sstable_writer.close()?;
+
+    assert::equal(
+      read_test_sstable(".tmp/merge_test")?,
+      "a: 1\nb: 2\nc: 3\nd: 4\ne: 5\nf: 6\n",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge_sparse() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup_remove_test_sstables()?;
+
+    // Interleave the keys of two SSTables that only index every third record.
+    for (path, start) in [(".tmp/merge_test_1", 0), (".tmp/merge_test_2", 1)] {
+      let mut sstable_writer = SSTableWriterBuilder::new(path)
+        .index_density(IndexDensity::EveryRecords(3))
+        .build()?;
+      for i in (start..10).step_by(2) {
+        sstable_writer.write((i as u64, i.to_string()))?;
+      }
+      sstable_writer.close()?;
+    }
+    let input_paths = vec![PathBuf::from(".tmp/merge_test_1"), PathBuf::from(".tmp/merge_test_2")];
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+    merge::<u64, CborValue>(&input_paths, DuplicatePolicy::KeepAll, false, &mut sstable_writer)?;
+    sstable_writer.close()?;
+
+    let sstable_reader = SSTableReader::<(u64, String)>::from_path(".tmp/merge_test")?;
+    let result = sstable_reader.collect::<io::Result<Vec<_>>>()?;
+    assert::equal(result, (0..10).map(|i| (i, i.to_string())).collect::<Vec<_>>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge_unsorted() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup_remove_test_sstables()?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test_1").build()?;
+    sstable_writer.write(("b", "1"))?;
+    sstable_writer.write(("a", "2"))?;
+    sstable_writer.close()?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+    let input_paths = [PathBuf::from(".tmp/merge_test_1")];
+    let result = merge::<String, CborValue>(&input_paths, DuplicatePolicy::KeepAll, false, &mut sstable_writer);
+    assert!(result.is_err());
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge_duplicates() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup_remove_test_sstables()?;
+
+    for (path, entries) in [
+      (".tmp/merge_test_1", [("a", "old"), ("b", "old")]),
+      (".tmp/merge_test_2", [("a", "new"), ("c", "new")]),
+    ] {
+      let mut sstable_writer = SSTableWriterBuilder::new(path).build()?;
+      for entry in entries {
+        sstable_writer.write(entry)?;
+      }
+      sstable_writer.close()?;
+    }
+    let input_paths = vec![PathBuf::from(".tmp/merge_test_1"), PathBuf::from(".tmp/merge_test_2")];
+
+    for (policy, expected) in [
+      (DuplicatePolicy::KeepAll, "a: old\na: new\nb: old\nc: new\n"),
+      (DuplicatePolicy::KeepFirst, "a: old\nb: old\nc: new\n"),
+      (DuplicatePolicy::KeepLast, "a: new\nb: old\nc: new\n"),
+      (
+        DuplicatePolicy::reduce(|a, b| CborValue::from(format!("{}+{}", a, b))),
+        "a: \"old\"+\"new\"\nb: old\nc: new\n",
+      ),
+    ] {
+      setup::remove_file(".tmp/merge_test")?;
+      let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+      merge::<String, CborValue>(&input_paths, policy, false, &mut sstable_writer)?;
+      sstable_writer.close()?;
+      assert::equal(read_test_sstable(".tmp/merge_test")?, expected);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_merge_tombstones() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup_remove_test_sstables()?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test_1").build()?;
+    sstable_writer.write(("a", "1"))?;
+    sstable_writer.write(("b", "2"))?;
+    sstable_writer.close()?;
+    let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test_2").build()?;
+    sstable_writer.write_tombstone("a")?;
+    sstable_writer.close()?;
+    let input_paths = vec![PathBuf::from(".tmp/merge_test_1"), PathBuf::from(".tmp/merge_test_2")];
+
+    for (drop_tombstones, expected) in [
+      (
+        false,
+        vec![("a".to_string(), None), ("b".to_string(), Some("2".to_string()))],
+      ),
+      (true, vec![("b".to_string(), Some("2".to_string()))]),
+    ] {
+      setup::remove_file(".tmp/merge_test")?;
+      let mut sstable_writer = SSTableWriterBuilder::new(".tmp/merge_test").build()?;
+      merge::<String, CborValue>(
+        &input_paths,
+        DuplicatePolicy::KeepLast,
+        drop_tombstones,
+        &mut sstable_writer,
+      )?;
+      sstable_writer.close()?;
+
+      let sstable_reader = SSTableReader::<(String, Option<String>)>::from_path(".tmp/merge_test")?;
+      assert::equal(sstable_reader.collect::<io::Result<Vec<_>>>()?, expected);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_count_expected_items() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let input_paths = setup_test_sstables()?;
+
+    assert::equal(count_expected_items(&input_paths[1..2])?, 2);
+    assert::equal(count_expected_items(&input_paths)?, 2 + 2 * DEFAULT_EXPECTED_ITEMS);
+
+    Ok(())
+  }
+}
@@ -1,2 +1,12 @@
 pub mod get;
+pub mod merge;
+pub mod range;
+pub mod reindex;
+pub mod sort;
+pub mod validate;
 pub use get::*;
+pub use merge::*;
+pub use range::*;
+pub use reindex::*;
+pub use sort::*;
+pub use validate::*;
@@ -0,0 +1,133 @@
+use crate::{files::get_path_str, traits::TypeWrite};
+use sstables::{
+  cbor::{CborRead, CborWrite},
+  SSTable,
+};
+use std::{fmt::Display, fs::File, io, ops::Bound, path::PathBuf};
+
+/// Writes up to n entries with a key from start up to but not including end from each SSTable. A
+/// missing bound means the range is open on that side. The index of each SSTable is used to skip
+/// ahead to the start if available, otherwise the data file is scanned.
+pub fn range<K, V>(
+  input_paths: &[PathBuf],
+  start: Option<K>,
+  end: Option<K>,
+  n: Option<usize>,
+  writer: &mut impl TypeWrite<String>,
+) -> io::Result<()>
+where
+  K: Ord + Clone + Display + CborWrite,
+  V: Display,
+  io::BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  let bounds = (
+    start.map_or(Bound::Unbounded, Bound::Included),
+    end.map_or(Bound::Unbounded, Bound::Excluded),
+  );
+
+  for input_path in input_paths {
+    if !input_path.is_file() {
+      writer.write(format!("File does not exist: {}", get_path_str(input_path)))?
+    } else {
+      let mut sstable = SSTable::<K, V>::open(input_path)?;
+
+      for kv_result in sstable.range(bounds.clone())?.take(n.unwrap_or(usize::MAX)) {
+        let (k, v) = kv_result?;
+        writer.write(format!("{}: {}", k, v))?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use sstables::{
+    cbor::Timestamp,
+    read::{create_bloom_path, create_index_path},
+    sstable_writer::IndexDensity,
+    SSTableWriterBuilder,
+  };
+  use std::path::Path;
+
+  struct MockTypeWriter<T> {
+    pub items: Vec<T>,
+  }
+
+  impl<T> TypeWrite<T> for MockTypeWriter<T> {
+    fn write(&mut self, target: T) -> io::Result<()> {
+      self.items.push(target);
+      Ok(())
+    }
+  }
+
+  fn setup_test_sstable<K: CborWrite>(path: &str, entries: Vec<(K, &str)>) -> io::Result<()> {
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(path)?;
+    setup::remove_file(create_index_path(Path::new(path)))?;
+    setup::remove_file(create_bloom_path(Path::new(path)))?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(path)
+      .index_density(IndexDensity::EveryRecords(2))
+      .build()?;
+    for entry in entries {
+      sstable_writer.write(entry)?;
+    }
+    sstable_writer.close()
+  }
+
+  #[test]
+  fn range_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = vec![("a", "1"), ("b", "2"), ("b", "3"), ("c", "4"), ("d", "5")];
+    setup_test_sstable(".tmp/range_test_1", entries)?;
+    let input_paths = vec![PathBuf::from(".tmp/range_test_1")];
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    range::<String, String>(
+      &input_paths,
+      Some("b".to_string()),
+      Some("d".to_string()),
+      None,
+      &mut writer,
+    )?;
+    assert::equal(writer.items, vec!["b: 2", "b: 3", "c: 4"]);
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    range::<String, String>(&input_paths, None, None, Some(2), &mut writer)?;
+    assert::equal(writer.items, vec!["a: 1", "b: 2"]);
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    range::<String, String>(&input_paths, Some("c".to_string()), None, None, &mut writer)?;
+    assert::equal(writer.items, vec!["c: 4", "d: 5"]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn range_works_on_timestamps() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let entries = vec![
+      (Timestamp::from_secs(-1), "before"),
+      (Timestamp::from_secs(0), "epoch"),
+      (Timestamp::from_millis(1500), "later"),
+      (Timestamp::from_secs(1363896240), "much later"),
+    ];
+    setup_test_sstable(".tmp/range_test_1", entries)?;
+    let input_paths = vec![PathBuf::from(".tmp/range_test_1")];
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    let start = "1970-01-01T00:00:00Z".parse().ok();
+    let end = "2000-01-01T00:00:00Z".parse().ok();
+    range::<Timestamp, String>(&input_paths, start, end, None, &mut writer)?;
+    assert::equal(
+      writer.items,
+      vec!["1970-01-01T00:00:00Z: epoch", "1970-01-01T00:00:01.5Z: later"],
+    );
+
+    Ok(())
+  }
+}
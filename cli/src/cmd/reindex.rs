@@ -0,0 +1,92 @@
+use crate::{files::get_path_str, traits::TypeWrite};
+use sstables::{
+  cbor::CborRead,
+  reindex::{IndexOrder, IndexRebuilder},
+  sstable_writer::IndexDensity,
+};
+use std::{io, path::PathBuf};
+
+/// Rebuilds the index of each SSTable from its data file, writing how many entries were indexed.
+/// The keys are only parsed as `K` for `IndexOrder::Native`. With `summary`, the index summary is
+/// rebuilt too; otherwise a stale summary is removed.
+pub fn reindex<K>(
+  input_paths: &[PathBuf],
+  index_density: IndexDensity,
+  order: IndexOrder,
+  summary: Option<u64>,
+  writer: &mut impl TypeWrite<String>,
+) -> io::Result<()>
+where
+  K: Ord,
+  for<'a> &'a [u8]: CborRead<K>,
+{
+  for input_path in input_paths {
+    if !input_path.is_file() {
+      writer.write(format!("File does not exist: {}", get_path_str(input_path)))?
+    } else {
+      let mut rebuilder = IndexRebuilder::new(input_path)
+        .index_density(index_density)
+        .order(order);
+      if let Some(entries_per_block) = summary {
+        rebuilder = rebuilder.summary(entries_per_block);
+      }
+      let entries = rebuilder.rebuild::<K>()?;
+      writer.write(format!("Indexed {} entries of {}", entries, get_path_str(input_path)))?;
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use sstables::{read::create_index_path, FromPath, SSTableIndex, SSTableWriterBuilder};
+  use std::path::Path;
+
+  struct MockTypeWriter<T> {
+    pub items: Vec<T>,
+  }
+
+  impl<T> TypeWrite<T> for MockTypeWriter<T> {
+    fn write(&mut self, target: T) -> io::Result<()> {
+      self.items.push(target);
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn reindex_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let path = ".tmp/reindex_test_1";
+    let index_path = create_index_path(Path::new(path));
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(path)?;
+    let mut sstable_writer = SSTableWriterBuilder::new(path).build()?;
+    for (key, value) in [(3, "c"), (1, "a"), (2, "b")] {
+      sstable_writer.write((key, value))?;
+    }
+    sstable_writer.close()?;
+    setup::remove_file(&index_path)?;
+    let input_paths = vec![PathBuf::from(path), PathBuf::from(".tmp/reindex_test_missing")];
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    reindex::<i64>(&input_paths, IndexDensity::Dense, IndexOrder::Native, None, &mut writer)?;
+    assert::equal(
+      writer.items,
+      vec![
+        "Indexed 3 entries of .tmp/reindex_test_1",
+        "File does not exist: .tmp/reindex_test_missing",
+      ],
+    );
+    let keys: Vec<i64> = SSTableIndex::<i64>::from_path(&index_path)?
+      .indices
+      .into_iter()
+      .map(|(key, _)| key)
+      .collect();
+    assert::equal(keys, vec![1, 2, 3]);
+
+    Ok(())
+  }
+}
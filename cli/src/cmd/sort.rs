@@ -0,0 +1,86 @@
+use crate::traits::{TypeWrite, TypeWriter};
+use sstables::{
+  cbor::{CborRead, CborWrite},
+  external_sort::ExternalSorterBuilder,
+  FromPath, SSTableReader,
+};
+use std::{fmt::Display, fs::File, io, path::PathBuf};
+
+/// Sorts every entry of the SSTables by key into a single SSTable with a new index and bloom
+/// filter, or prints them if there is no output path. The data files are read sequentially, and
+/// entries beyond the sorter's memory limit are sorted in temporary runs, so the inputs can be much
+/// larger than memory. Entries with equal keys keep their order from the inputs.
+pub fn sort<K, V>(
+  input_paths: &[PathBuf],
+  output_path: &Option<PathBuf>,
+  sorter_builder: ExternalSorterBuilder,
+) -> io::Result<()>
+where
+  K: Ord + Clone + Display + CborWrite,
+  V: Display + CborWrite,
+  io::BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  let mut sorter = sorter_builder.build::<K, V>();
+  for input_path in input_paths {
+    for kv_result in SSTableReader::<(K, V)>::from_path(input_path)? {
+      sorter.push(kv_result?)?;
+    }
+  }
+
+  let mut output_writer = TypeWriter::new(output_path, sorter.len() as usize)?;
+  for kv_result in sorter.finish()? {
+    output_writer.write(kv_result?)?;
+  }
+  output_writer.close()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use sstables::{
+    read::{create_bloom_path, create_index_path},
+    SSTable, SSTableWriterBuilder,
+  };
+  use std::path::Path;
+
+  fn setup_test_sstable(path: &str, entries: &[(&str, &str)]) -> io::Result<()> {
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(path)?;
+    setup::remove_file(create_index_path(Path::new(path)))?;
+    setup::remove_file(create_bloom_path(Path::new(path)))?;
+
+    let mut sstable_writer = SSTableWriterBuilder::new(path).build()?;
+    for entry in entries {
+      sstable_writer.write(*entry)?;
+    }
+    sstable_writer.close()
+  }
+
+  #[test]
+  fn sort_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup_test_sstable(".tmp/sort_test_1", &[("c", "1"), ("a", "2"), ("b", "3")])?;
+    setup_test_sstable(".tmp/sort_test_2", &[("b", "4"), ("d", "5"), ("a", "6")])?;
+    setup_test_sstable(".tmp/sort_test", &[])?;
+    let input_paths = vec![PathBuf::from(".tmp/sort_test_1"), PathBuf::from(".tmp/sort_test_2")];
+    let output_path = Some(PathBuf::from(".tmp/sort_test"));
+
+    setup::create_dir_all(".tmp/sort_test_runs")?;
+    let sorter_builder = ExternalSorterBuilder::new()
+      .memory_limit(64)
+      .temp_dir(".tmp/sort_test_runs");
+    sort::<String, String>(&input_paths, &output_path, sorter_builder)?;
+
+    let entries = SSTableReader::<(String, String)>::from_path(".tmp/sort_test")?.collect::<io::Result<Vec<_>>>()?;
+    let entries = entries.iter().map(|(k, v)| format!("{}{}", k, v)).collect::<Vec<_>>();
+    assert::equal(entries, vec!["a2", "a6", "b3", "b4", "c1", "d5"]);
+
+    let mut sstable = SSTable::<String, String>::open(".tmp/sort_test")?;
+    assert::equal(sstable.get(&"d".to_string())?, "5".to_string());
+    assert!(sstable.bloom_filter.is_some());
+    assert::equal(std::fs::read_dir(".tmp/sort_test_runs")?.count(), 0);
+
+    Ok(())
+  }
+}
@@ -0,0 +1,130 @@
+use crate::{files::get_path_str, traits::TypeWrite};
+use sstables::checksum::verify;
+use std::{io, path::PathBuf};
+
+/// Verifies each SSTable, writing a summary and the location of any corruption. An SSTable that
+/// can't be read is reported and the rest are still verified. Returns whether every SSTable exists
+/// and is free of corruption.
+pub fn validate(input_paths: &[PathBuf], writer: &mut impl TypeWrite<String>) -> io::Result<bool> {
+  let mut valid = true;
+  for input_path in input_paths {
+    if !input_path.is_file() {
+      writer.write(format!("File does not exist: {}", get_path_str(input_path)))?;
+      valid = false;
+    } else {
+      let verification = match verify(input_path) {
+        Ok(verification) => verification,
+        Err(e) => {
+          writer.write(format!("Failed to verify {}: {}", get_path_str(input_path), e))?;
+          valid = false;
+          continue;
+        }
+      };
+      writer.write(format!("{}: {}", get_path_str(input_path), verification))?;
+      for offset in &verification.corrupt_records {
+        writer.write(format!("  Corrupt record at offset {}", offset))?;
+      }
+      for position in &verification.corrupt_index_entries {
+        writer.write(format!("  Corrupt index entry at position {}", position))?;
+      }
+      if let Some(position) = verification.corrupt_checksum_entry {
+        writer.write(format!("  Unreadable checksum entry at position {}", position))?;
+      }
+      valid &= verification.is_ok();
+    }
+  }
+
+  Ok(valid)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use sstables::{
+    read::{create_checksum_path, create_index_path},
+    SSTableWriterBuilder,
+  };
+  use std::{fs, path::Path};
+
+  struct MockTypeWriter<T> {
+    pub items: Vec<T>,
+  }
+
+  impl<T> TypeWrite<T> for MockTypeWriter<T> {
+    fn write(&mut self, target: T) -> io::Result<()> {
+      self.items.push(target);
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn validate_works() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let path = ".tmp/validate_test_1";
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(path)?;
+    setup::remove_file(create_index_path(Path::new(path)))?;
+    setup::remove_file(create_checksum_path(Path::new(path)))?;
+    let mut sstable_writer = SSTableWriterBuilder::new(path).checksums().build()?;
+    sstable_writer.write(("a", "1"))?;
+    sstable_writer.write(("b", "2"))?;
+    sstable_writer.close()?;
+    let input_paths = vec![PathBuf::from(path)];
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    assert!(validate(&input_paths, &mut writer)?);
+    assert::equal(
+      writer.items,
+      vec![".tmp/validate_test_1: 2 records, 2 with checksums, 0 corrupt records and 0 corrupt index entries"],
+    );
+
+    // Change the value of the second record, which starts at offset 4.
+    let mut bytes = fs::read(path)?;
+    bytes[7] = b'3';
+    fs::write(path, bytes)?;
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    assert!(!validate(&input_paths, &mut writer)?);
+    assert::equal(
+      writer.items,
+      vec![
+        ".tmp/validate_test_1: 2 records, 2 with checksums, 1 corrupt records and 0 corrupt index entries",
+        "  Corrupt record at offset 4",
+      ],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn validate_continues_after_unreadable_files() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let paths = [".tmp/validate_test_2", ".tmp/validate_test_3"];
+    setup::create_dir_all(".tmp")?;
+    for path in paths {
+      setup::remove_file(path)?;
+      setup::remove_file(create_index_path(Path::new(path)))?;
+      fs::remove_dir(create_checksum_path(Path::new(path))).unwrap_or_default();
+      setup::remove_file(create_checksum_path(Path::new(path)))?;
+      let mut sstable_writer = SSTableWriterBuilder::new(path).build()?;
+      sstable_writer.write(("a", "1"))?;
+      sstable_writer.close()?;
+    }
+    // A directory where the checksum file should be can be opened but not read.
+    setup::create_dir_all(create_checksum_path(Path::new(paths[0])))?;
+    let input_paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+    let mut writer = MockTypeWriter { items: Vec::new() };
+    assert!(!validate(&input_paths, &mut writer)?);
+    assert::equal(writer.items.len(), 2);
+    assert!(writer.items[0].starts_with("Failed to verify .tmp/validate_test_2: "));
+    assert::equal(
+      writer.items[1].as_str(),
+      ".tmp/validate_test_3: 1 records, 0 with checksums, 0 corrupt records and 0 corrupt index entries",
+    );
+
+    fs::remove_dir(create_checksum_path(Path::new(paths[0])))?;
+    Ok(())
+  }
+}
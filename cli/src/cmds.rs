@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use sstables::{merge::DuplicatePolicy, reindex::IndexOrder, sstable_writer::IndexDensity};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -27,6 +28,17 @@ pub enum Commands {
     #[arg(short, long, value_name = "DATA")]
     data: String,
   },
+  /// Deletes a key by adding a tombstone to the end of a set of SSTables. The tombstone hides the
+  /// older values of the key when merging with `--duplicates keep-newest`.
+  Delete {
+    /// The file to append the tombstone to
+    #[arg(value_name = "INPUT_PATHS")]
+    input_paths: Vec<PathBuf>,
+
+    /// The key to delete
+    #[arg(short, long, value_name = "KEY")]
+    key: String,
+  },
   Dump {
     /// The file to export
     #[arg(value_name = "INPUT_PATHS")]
@@ -61,6 +73,10 @@ pub enum Commands {
     /// The number of values to get
     #[arg(short, long, value_name = "N")]
     n: Option<usize>,
+
+    /// How to parse the key, which must match the type the keys were written with
+    #[arg(short = 't', long, value_enum, default_value_t = KeyType::Text)]
+    key_type: KeyType,
   },
   /// Get the data from the index of the SSTable.
   Index {
@@ -79,20 +95,78 @@ pub enum Commands {
     #[arg(value_name = "INPUT_PATHS")]
     input_paths: Vec<PathBuf>,
   },
-  /// Merge one or more SSTables into a single SSTable.
-  /// Currently also sorts, but later will assume data is already sorted.
+  /// Merge one or more sorted SSTables into a single SSTable. The inputs must each be sorted by
+  /// key; use `sort` for SSTables that aren't.
   Merge {
-    /// One or more files to sort and merge.
+    /// One or more sorted files to merge, from oldest to newest.
     #[arg(value_name = "INPUT_PATHS")]
     input_paths: Vec<PathBuf>,
 
     /// Optional output file. If unset, writes to stdout.
     #[arg(short, long, value_name = "OUTPUT_PATH")]
     output_path: Option<PathBuf>,
+
+    /// What to do with entries that have the same key
+    #[arg(short, long, value_enum, default_value_t = Duplicates::KeepAll)]
+    duplicates: Duplicates,
+
+    /// Leave out deleted keys, which is only safe when the inputs include every older SSTable
+    #[arg(long)]
+    drop_tombstones: bool,
   },
-  /// Sort one or more SSTables into a single SSTable.
-  /// Later, there will be optimizations to handle larger indices.
-  /// Currently same behavior as Merge.
+  /// Get every entry with a key from START up to but not including END. Without START, starts
+  /// from the first key, and without END, continues to the last key.
+  Range {
+    /// The file to get the entries from
+    #[arg(value_name = "INPUT_PATHS")]
+    input_paths: Vec<PathBuf>,
+
+    /// The first key to get
+    #[arg(short, long, value_name = "START")]
+    start: Option<String>,
+
+    /// The key to stop before
+    #[arg(short, long, value_name = "END")]
+    end: Option<String>,
+
+    /// The number of values to get
+    #[arg(short, long, value_name = "N")]
+    n: Option<usize>,
+
+    /// How to parse the keys, which must match the type the keys were written with
+    #[arg(short = 't', long, value_enum, default_value_t = KeyType::Text)]
+    key_type: KeyType,
+  },
+  /// Rebuild the index of one or more SSTables from their data files, replacing any existing index.
+  /// The index is dense unless `--every-records` or `--every-bytes` is given.
+  Reindex {
+    /// The files to reindex
+    #[arg(value_name = "INPUT_PATHS")]
+    input_paths: Vec<PathBuf>,
+
+    /// Index one entry for every N entries
+    #[arg(long, value_name = "N", conflicts_with = "every_bytes")]
+    every_records: Option<u64>,
+
+    /// Index one entry once at least N bytes of data were written since the last indexed entry
+    #[arg(long, value_name = "N")]
+    every_bytes: Option<u64>,
+
+    /// The order of the index entries. Sorted orders need a dense index
+    #[arg(short, long, value_enum, default_value_t = Order::Data)]
+    order: Order,
+
+    /// How to parse the keys when sorting them in native order
+    #[arg(short = 't', long, value_enum, default_value_t = KeyType::Text)]
+    key_type: KeyType,
+
+    /// Rebuild the index summary too, with one summary entry for every N index entries
+    #[arg(long, value_name = "N")]
+    summary: Option<u64>,
+  },
+  /// Sort one or more SSTables into a single SSTable with a new index.
+  /// Every entry is read sequentially, so the indices don't need to be complete or sorted, and
+  /// inputs larger than memory are sorted in runs written to a temporary directory.
   Sort {
     /// One or more files to sort and merge.
     #[arg(value_name = "INPUT_PATHS")]
@@ -101,6 +175,23 @@ pub enum Commands {
     /// Optional output file. If unset, writes to stdout.
     #[arg(short, long, value_name = "OUTPUT_PATH")]
     output_path: Option<PathBuf>,
+
+    /// The approximate number of megabytes of entries to sort in memory at a time.
+    #[arg(short, long, value_name = "MEGABYTES", default_value_t = 64)]
+    memory_limit: usize,
+
+    /// The directory for temporary runs, which needs room for a copy of the input. Defaults to the
+    /// system's temporary directory.
+    #[arg(short, long, value_name = "TEMP_DIR")]
+    temp_dir: Option<PathBuf>,
+  },
+  /// Check one or more SSTables for corruption. Every record is read, records are checked against
+  /// their checksums if the SSTable has a checksum file, and index entries are checked against the
+  /// records they point at. Exits with an error if anything is corrupt.
+  Validate {
+    /// The files to validate
+    #[arg(value_name = "INPUT_PATHS")]
+    input_paths: Vec<PathBuf>,
   },
   Values {
     /// The file to get values from.
@@ -109,6 +200,68 @@ pub enum Commands {
   },
 }
 
+/// The type of the keys of an SSTable, used to parse keys given on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum KeyType {
+  /// UTF-8 text
+  Text,
+  /// A signed integer, such as -42
+  Integer,
+  /// An RFC 3339 date/time, such as 2013-03-21T20:04:00Z, written as an epoch-based timestamp
+  Timestamp,
+}
+
+/// How merge resolves entries with the same key. Input paths are given from oldest to newest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Duplicates {
+  /// Keep every entry, in the order of the input paths
+  KeepAll,
+  /// Keep the entry from the oldest input path
+  KeepOldest,
+  /// Keep the entry from the newest input path, which is last-write-wins
+  KeepNewest,
+}
+
+impl<V> From<Duplicates> for DuplicatePolicy<V> {
+  fn from(duplicates: Duplicates) -> Self {
+    match duplicates {
+      Duplicates::KeepAll => DuplicatePolicy::KeepAll,
+      Duplicates::KeepOldest => DuplicatePolicy::KeepFirst,
+      Duplicates::KeepNewest => DuplicatePolicy::KeepLast,
+    }
+  }
+}
+
+/// The order of the entries of a rebuilt index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+  /// The order of the data file
+  Data,
+  /// Sorted by the encoded keys
+  Cbor,
+  /// Sorted by the keys in their native order, as parsed with `--key-type`
+  Native,
+}
+
+impl From<Order> for IndexOrder {
+  fn from(order: Order) -> Self {
+    match order {
+      Order::Data => IndexOrder::Data,
+      Order::Cbor => IndexOrder::Cbor,
+      Order::Native => IndexOrder::Native,
+    }
+  }
+}
+
+/// Returns the index density given by the `--every-records` and `--every-bytes` options.
+pub fn index_density(every_records: Option<u64>, every_bytes: Option<u64>) -> IndexDensity {
+  match (every_records, every_bytes) {
+    (Some(n), _) => IndexDensity::EveryRecords(n),
+    (None, Some(n)) => IndexDensity::EveryBytes(n),
+    (None, None) => IndexDensity::Dense,
+  }
+}
+
 pub fn get_cli() -> Cli {
   Cli::parse()
 }
@@ -4,8 +4,16 @@ use crate::{
   util::{compare_tuples, get_min_max, is_sorted_by, is_unique},
 };
 use colored::Colorize;
-use sstables::{cbor::is_cbor_sorted, FromPath, SSTableIndex};
-use std::path::PathBuf;
+use sstables::{
+  bloom::BloomFilter,
+  cbor::is_cbor_sorted,
+  disk_index::INDEX_OFFSET_SIZE,
+  header::load_header,
+  metadata::Metadata,
+  read::{create_bloom_path, create_index_offsets_path, create_metadata_path, create_summary_path},
+  FromPath, IndexDensity, SSTableIndex,
+};
+use std::{io::ErrorKind, path::PathBuf};
 
 const CONSOLE_CHECKMARK: &str = "\u{2714}";
 const CONSOLE_CROSS: &str = "\u{2718}";
@@ -21,6 +29,7 @@ pub fn get_info(
     // For each input_path, print the following:
     // - The file path to the data file relative to the current working directory
     // - The data file size
+    // - The format version, index density, checksums and key type of the data file header, if any
     // - The number of data file blocks
     // - The file path to the index file relative to the current working directory
     // - The index file size
@@ -28,10 +37,18 @@ pub fn get_info(
     // - The total number of entries
     // - The min of index keys
     // - The max of index keys
+    // - The file path to the offset table of the index relative to the current working directory,
+    //   its size and its number of entries
+    // - The file path to the index summary relative to the current working directory, its size and
+    //   its number of blocks
     // - The file path to the bloom filter file relative to the current working directory
     // - The bloom filter file size
     // - The number of bloom filter file blocks
     // - The bloom filter file false positive rate
+    // - The file path to the metadata file relative to the current working directory, and the
+    //   summary of the table it holds
+    //
+    // Fresh metadata already has the count, sortedness and key range, so the index isn't loaded.
 
     // YAML file spilt marker.
     writer.write("---".to_string())?;
@@ -43,8 +60,43 @@ pub fn get_info(
     } else {
       writer.write(format!("data path: {} {}", input_path_str, exists_str))?;
       writer.write(format!(" size: {}", get_file_size(&input_path)?))?;
+
+      match load_header(&input_path)? {
+        None => writer.write(" header: false".to_string())?,
+        Some(header) => {
+          writer.write(" header: true".to_string())?;
+          writer.write(format!(" format version: {}", header.version))?;
+          writer.write(format!(" min format version: {}", header.min_version))?;
+          let index_density = match header.index_density {
+            IndexDensity::Dense => "dense".to_string(),
+            IndexDensity::EveryRecords(n) => format!("every {} records", n),
+            IndexDensity::EveryBytes(n) => format!("every {} bytes", n),
+          };
+          writer.write(format!(" index density: {}", index_density))?;
+          writer.write(format!(" checksums: {}", header.checksums))?;
+          if let Some(key_type) = &header.key_type {
+            writer.write(format!(" key type: {}", key_type))?;
+          }
+        }
+      }
     }
 
+    // Metadata that can't be decoded is ignored by readers, so it's reported but not shown.
+    let input_metadata_path = create_metadata_path(&input_path);
+    let (metadata, metadata_unreadable) = if input_metadata_path.is_file() {
+      match Metadata::from_path(&input_metadata_path) {
+        Ok(metadata) => (Some(metadata), false),
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => (None, true),
+        Err(e) => return Err(e.into()),
+      }
+    } else {
+      (None, false)
+    };
+    let metadata_fresh = data_file_exists
+      && metadata
+        .as_ref()
+        .is_some_and(|metadata| metadata.data_size == get_file_size(&input_path).unwrap_or_default());
+
     let input_index_path = create_index_path(&input_path);
     let index_file_exists = input_index_path.is_file();
     let input_index_path_str = get_path_str(&input_index_path);
@@ -53,7 +105,8 @@ pub fn get_info(
     } else {
       writer.write(format!("index path: {} {}", input_index_path_str, exists_str))?;
       writer.write(format!(" size: {}", get_file_size(&input_index_path)?))?;
-
+    }
+    if index_file_exists && !metadata_fresh {
       let sstable_index = SSTableIndex::<String>::from_path(&input_index_path)?;
       writer.write(format!(" count: {}", sstable_index.indices.len()))?;
 
@@ -82,6 +135,95 @@ pub fn get_info(
         is_unique(&sstable_index.indices, |a, b| a.0 < b.0)
       ))?;
     }
+
+    let input_offsets_path = create_index_offsets_path(&input_path);
+    let input_offsets_path_str = get_path_str(&input_offsets_path);
+    if !input_offsets_path.is_file() {
+      writer.write(format!("offsets path: {} {}", input_offsets_path_str, missing_str))?;
+    } else {
+      let offsets_size = get_file_size(&input_offsets_path)?;
+      writer.write(format!("offsets path: {} {}", input_offsets_path_str, exists_str))?;
+      writer.write(format!(" size: {}", offsets_size))?;
+      writer.write(format!(" count: {}", offsets_size / INDEX_OFFSET_SIZE))?;
+    }
+
+    let input_summary_path = create_summary_path(&input_path);
+    let input_summary_path_str = get_path_str(&input_summary_path);
+    if !input_summary_path.is_file() {
+      writer.write(format!("summary path: {} {}", input_summary_path_str, missing_str))?;
+    } else {
+      writer.write(format!("summary path: {} {}", input_summary_path_str, exists_str))?;
+      writer.write(format!(" size: {}", get_file_size(&input_summary_path)?))?;
+      let summary = SSTableIndex::<String>::from_path(&input_summary_path)?;
+      writer.write(format!(" blocks: {}", summary.indices.len()))?;
+    }
+
+    let input_bloom_path = create_bloom_path(&input_path);
+    let bloom_file_exists = input_bloom_path.is_file();
+    let input_bloom_path_str = get_path_str(&input_bloom_path);
+    if !bloom_file_exists {
+      writer.write(format!("bloom path: {} {}", input_bloom_path_str, missing_str))?;
+    } else {
+      writer.write(format!("bloom path: {} {}", input_bloom_path_str, exists_str))?;
+      writer.write(format!(" size: {}", get_file_size(&input_bloom_path)?))?;
+
+      let bloom_filter = BloomFilter::from_path(&input_bloom_path)?;
+      writer.write(format!(" count: {}", bloom_filter.len()))?;
+      writer.write(format!(" bits: {}", bloom_filter.num_bits()))?;
+      writer.write(format!(" hashes: {}", bloom_filter.num_hashes()))?;
+      writer.write(format!(
+        " false positive rate: {:.6}",
+        bloom_filter.false_positive_rate()
+      ))?;
+
+      // A stale filter is ignored by readers, so call it out.
+      let stale = data_file_exists && bloom_filter.data_size != get_file_size(&input_path)?;
+      writer.write(format!(
+        " stale: {}",
+        if stale { "true".red() } else { "false".green() }
+      ))?;
+    }
+
+    let input_metadata_path_str = get_path_str(&input_metadata_path);
+    match metadata {
+      None if metadata_unreadable => writer.write(format!(
+        "metadata path: {} {}",
+        input_metadata_path_str,
+        format!("(unreadable {})", CONSOLE_CROSS).red().bold()
+      ))?,
+      None => writer.write(format!("metadata path: {} {}", input_metadata_path_str, missing_str))?,
+      Some(metadata) => {
+        writer.write(format!("metadata path: {} {}", input_metadata_path_str, exists_str))?;
+        writer.write(format!(" size: {}", get_file_size(&input_metadata_path)?))?;
+        writer.write(format!(" count: {}", metadata.entry_count))?;
+        writer.write(format!(
+          " sorted: {}",
+          if metadata.sorted { "true".green() } else { "false".red() }
+        ))?;
+        if let (Some(min_key), Some(max_key)) = (&metadata.min_key, &metadata.max_key) {
+          writer.write(format!(" min: {}", min_key))?;
+          writer.write(format!(" max: {}", max_key))?;
+        }
+        if let Some(key_type) = &metadata.key_type {
+          writer.write(format!(" key type: {}", key_type))?;
+        }
+        if let Some(value_type) = &metadata.value_type {
+          writer.write(format!(" value type: {}", value_type))?;
+        }
+        writer.write(format!(" created at: {}", metadata.created_at))?;
+        writer.write(format!(" writer version: {}", metadata.writer_version))?;
+        writer.write(format!(" format version: {}", metadata.format_version))?;
+        for (name, value) in &metadata.properties {
+          writer.write(format!(" property {}: {}", name, value))?;
+        }
+
+        // Stale metadata no longer describes the data file, so call it out.
+        writer.write(format!(
+          " stale: {}",
+          if metadata_fresh { "false".green() } else { "true".red() }
+        ))?;
+      }
+    }
   }
   Ok(())
 }
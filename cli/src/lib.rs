@@ -2,7 +2,6 @@ pub mod cmd;
 pub mod cmds;
 pub mod files;
 pub mod info;
-pub mod merge;
 pub mod outputs;
 pub mod traits;
 pub mod util;
@@ -8,43 +8,20 @@
 
 use sstable_cli::{
   cmd,
-  cmds::{get_cli, Commands},
+  cmds::{get_cli, index_density, Commands, KeyType},
   files::{self, create_index_path, get_path_str},
   info::get_info,
-  merge::Mergeable,
   traits::{Terminal, TypeWrite, TypeWriter},
-  util::compare_tuples,
+  util::parse_key,
 };
-use sstables::{FromPath, SSTableIndex, SSTableReader, SSTableWriterBuilder};
-use std::{
-  io::{self, Seek},
-  path::{Path, PathBuf},
+use sstables::{
+  bloom::{DEFAULT_EXPECTED_ITEMS, DEFAULT_FALSE_POSITIVE_RATE},
+  cbor::{CborValue, Timestamp},
+  external_sort::ExternalSorterBuilder,
+  read::create_checksum_path,
+  FromPath, SSTableIndex, SSTableReader, SSTableWriter, SSTableWriterBuilder,
 };
-
-fn get_sorted_sstable_index<K>(index_path: &Path) -> io::Result<SSTableIndex<K>>
-where
-  K: Ord,
-  SSTableIndex<K>: FromPath<K>,
-{
-  let mut sstable_index = SSTableIndex::<K>::from_path(index_path)?;
-  // Sort the index file in-place.
-  sstable_index.indices.sort_by(compare_tuples);
-  Ok(sstable_index)
-}
-
-fn get_sorted_sstable_index_pairs(
-  input_paths: &[PathBuf],
-) -> io::Result<Vec<(SSTableReader<(String, String)>, SSTableIndex<String>)>> {
-  input_paths
-    .iter()
-    .map(|input_path| {
-      Ok((
-        SSTableReader::<(String, String)>::from_path(input_path)?,
-        get_sorted_sstable_index(&create_index_path(input_path))?,
-      ))
-    })
-    .collect()
-}
+use std::{io::Seek, path::Path};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
   let cli = get_cli();
@@ -54,12 +31,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
   match &cli.command {
     Some(Commands::Append { input_paths, key, data }) => {
       for input_path in input_paths {
-        let mut sstable_writer = SSTableWriterBuilder::new(input_path).build()?;
+        let mut sstable_writer = open_for_append(input_path)?;
+        report_recovery(input_path, &sstable_writer);
         sstable_writer.write((key.as_str(), data.as_str()))?;
         sstable_writer.close()?;
       }
     }
 
+    Some(Commands::Delete { input_paths, key }) => {
+      for input_path in input_paths {
+        let mut sstable_writer = open_for_append(input_path)?;
+        report_recovery(input_path, &sstable_writer);
+        sstable_writer.write_tombstone(key.as_str())?;
+        sstable_writer.close()?;
+      }
+    }
+
     Some(Commands::Dump { input_paths, format: _ }) => {
       let mut writer = Terminal {};
       for input_path in input_paths {
@@ -155,29 +142,90 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
       }
     }
 
-    Some(Commands::Get { input_paths, key, n }) => {
+    Some(Commands::Get {
+      input_paths,
+      key,
+      n,
+      key_type,
+    }) => {
       let mut writer = Terminal {};
-      cmd::get::<String, String>(input_paths, key.clone(), *n, &mut writer)?;
+      match key_type {
+        KeyType::Text => cmd::get::<String, String>(input_paths, key.clone(), *n, &mut writer)?,
+        KeyType::Integer => cmd::get::<i64, String>(input_paths, parse_key(key)?, *n, &mut writer)?,
+        KeyType::Timestamp => cmd::get::<Timestamp, String>(input_paths, parse_key(key)?, *n, &mut writer)?,
+      }
+    }
+
+    Some(Commands::Range {
+      input_paths,
+      start,
+      end,
+      n,
+      key_type,
+    }) => {
+      let mut writer = Terminal {};
+      match key_type {
+        KeyType::Text => cmd::range::<String, String>(input_paths, start.clone(), end.clone(), *n, &mut writer)?,
+        KeyType::Integer => {
+          let start = start.as_deref().map(parse_key).transpose()?;
+          let end = end.as_deref().map(parse_key).transpose()?;
+          cmd::range::<i64, String>(input_paths, start, end, *n, &mut writer)?
+        }
+        KeyType::Timestamp => {
+          let start = start.as_deref().map(parse_key).transpose()?;
+          let end = end.as_deref().map(parse_key).transpose()?;
+          cmd::range::<Timestamp, String>(input_paths, start, end, *n, &mut writer)?
+        }
+      }
     }
 
     Some(Commands::Merge {
       input_paths,
       output_path,
+      duplicates,
+      drop_tombstones,
     }) => {
-      // Pull the index files of each SSTable into memory along with their File objects.
-      let sstable_index_pairs = get_sorted_sstable_index_pairs(input_paths)?;
-      let mut output_writer = TypeWriter::new(output_path)?;
-      sstable_index_pairs.merge(&mut output_writer)?;
+      let mut output_writer = TypeWriter::new(output_path, cmd::count_expected_items(input_paths)?)?;
+      cmd::merge::<String, CborValue>(input_paths, (*duplicates).into(), *drop_tombstones, &mut output_writer)?;
+      output_writer.close()?;
+    }
+
+    Some(Commands::Reindex {
+      input_paths,
+      every_records,
+      every_bytes,
+      order,
+      key_type,
+      summary,
+    }) => {
+      let mut writer = Terminal {};
+      let index_density = index_density(*every_records, *every_bytes);
+      let order = (*order).into();
+      match key_type {
+        KeyType::Text => cmd::reindex::<String>(input_paths, index_density, order, *summary, &mut writer)?,
+        KeyType::Integer => cmd::reindex::<i64>(input_paths, index_density, order, *summary, &mut writer)?,
+        KeyType::Timestamp => cmd::reindex::<Timestamp>(input_paths, index_density, order, *summary, &mut writer)?,
+      }
     }
 
     Some(Commands::Sort {
       input_paths,
       output_path,
+      memory_limit,
+      temp_dir,
     }) => {
-      // Pull the index files of each SSTable into memory along with their File objects.
-      let sstable_index_pairs = get_sorted_sstable_index_pairs(input_paths)?;
-      let mut output_writer = TypeWriter::new(output_path)?;
-      sstable_index_pairs.merge(&mut output_writer)?;
+      let mut sorter_builder = ExternalSorterBuilder::new().memory_limit(memory_limit * 1024 * 1024);
+      if let Some(temp_dir) = temp_dir {
+        sorter_builder = sorter_builder.temp_dir(temp_dir);
+      }
+      cmd::sort::<String, String>(input_paths, output_path, sorter_builder)?;
+    }
+
+    Some(Commands::Validate { input_paths }) => {
+      let valid = cmd::validate(input_paths, &mut Terminal {})?;
+      if !valid {
+        return Err("Validation failed".into());
+      }
     }
 
     Some(Commands::Values { input_paths }) => {
@@ -201,3 +249,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
   Ok(())
 }
+
+/// Opens an SSTable to append to, keeping its bloom filter, its metadata and, if it has one, its
+/// checksum file up to date.
+fn open_for_append(input_path: &Path) -> std::io::Result<SSTableWriter> {
+  let mut builder = SSTableWriterBuilder::new(input_path)
+    .bloom_filter(DEFAULT_EXPECTED_ITEMS, DEFAULT_FALSE_POSITIVE_RATE)
+    .metadata();
+  if create_checksum_path(input_path).is_file() {
+    builder = builder.checksums();
+  }
+  builder.build()
+}
+
+/// Warns if the SSTable had to be repaired before appending to it, such as after a crash.
+fn report_recovery(input_path: &Path, sstable_writer: &SSTableWriter) {
+  let recovery = sstable_writer.recovery();
+  if !recovery.is_clean() {
+    eprintln!("Repaired {}: {}", get_path_str(input_path), recovery);
+  }
+}
@@ -26,7 +26,7 @@ impl OutputWriterBuilder {
     match self.destination {
       OutputDestination::File(path) => {
         let sstable_writer = SSTableWriterBuilder::new(path).build()?;
-        Ok(OutputWriter::SSTable(sstable_writer))
+        Ok(OutputWriter::SSTable(Box::new(sstable_writer)))
       }
       OutputDestination::Stdout => Ok(OutputWriter::Stdout(BufWriter::new(io::stdout()))),
       OutputDestination::Cursor(cursor) => Ok(OutputWriter::Cursor(cursor)),
@@ -36,7 +36,7 @@ impl OutputWriterBuilder {
 
 pub enum OutputWriter {
   /// Writes to an SSTable.
-  SSTable(SSTableWriter),
+  SSTable(Box<SSTableWriter>),
   /// Writes to stdout.
   Stdout(BufWriter<Stdout>),
   /// Writes to a generic writer.
@@ -1,4 +1,4 @@
-use sstables::{cbor::CborWrite, SSTableWriter, SSTableWriterBuilder};
+use sstables::{bloom::DEFAULT_FALSE_POSITIVE_RATE, cbor::CborWrite, SSTableWriter, SSTableWriterBuilder};
 use std::{cmp::Ordering, fmt::Display, io, path::PathBuf};
 
 /// A generic write method for a specific type.
@@ -28,6 +28,7 @@ impl<K: Ord + Clone, V> PartialEq for KeyValue<K, V> {
 }
 
 /// Ordering for KeyValue is based on the key.
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl<K: Ord + Clone, V> PartialOrd for KeyValue<K, V> {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
     Some(self.0.cmp(&other.0))
@@ -63,18 +64,33 @@ impl<T: std::fmt::Display> TypeWrite<T> for Terminal {
 
 /// Either a SSTable or a Terminal.
 pub enum TypeWriter {
-  SSTable(SSTableWriter),
+  SSTable(Box<SSTableWriter>),
   Terminal(Terminal),
 }
 
-/// If the output path exists, then write to a SSTable, otherwise write to the Terminal.
+/// If the output path exists, then write to a SSTable, otherwise write to the Terminal. The SSTable
+/// gets a bloom filter sized for the expected number of items, and a metadata file.
 impl TypeWriter {
-  pub fn new(output_path: &Option<PathBuf>) -> io::Result<TypeWriter> {
+  pub fn new(output_path: &Option<PathBuf>, expected_items: usize) -> io::Result<TypeWriter> {
     Ok(match output_path {
-      Some(output_path) => TypeWriter::SSTable(SSTableWriterBuilder::new(output_path).build()?),
+      Some(output_path) => TypeWriter::SSTable(Box::new(
+        SSTableWriterBuilder::new(output_path)
+          .bloom_filter(expected_items, DEFAULT_FALSE_POSITIVE_RATE)
+          .metadata()
+          .build()?,
+      )),
       None => TypeWriter::Terminal(Terminal {}),
     })
   }
+
+  /// Flushes and closes the SSTable, which also writes its bloom filter and metadata. Does nothing for the
+  /// Terminal.
+  pub fn close(&mut self) -> io::Result<()> {
+    match self {
+      TypeWriter::SSTable(sstable_writer) => sstable_writer.close(),
+      TypeWriter::Terminal(_) => Ok(()),
+    }
+  }
 }
 
 /// Write a (K, V) tuple to either a SSTable or a Terminal.
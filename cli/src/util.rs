@@ -1,4 +1,4 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, fmt::Display, io, str::FromStr};
 
 /// Returns true if the slice is sorted by the given comparison function.
 /// # Examples
@@ -93,3 +93,23 @@ pub fn is_unique<T>(slice: &[T], compare: fn(&T, &T) -> bool) -> bool {
 pub fn compare_tuples<T: Ord, U>(a: &(T, U), b: &(T, U)) -> Ordering {
   a.0.cmp(&b.0)
 }
+
+/// Parses a key given on the command line.
+///
+/// # Examples
+///
+/// ```
+/// use sstable_cli::util::parse_key;
+///
+/// assert_eq!(parse_key::<i64>("-42").unwrap(), -42);
+/// assert!(parse_key::<i64>("a").is_err());
+/// ```
+pub fn parse_key<K>(key: &str) -> io::Result<K>
+where
+  K: FromStr,
+  K::Err: Display,
+{
+  key
+    .parse()
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid key {:?}: {}", key, e)))
+}
@@ -0,0 +1,463 @@
+//! Async reader and writer
+//!
+//! With the `tokio` feature, `AsyncSSTableWriter` and `AsyncSSTableReader` write and read SSTables
+//! through tokio's `AsyncWrite` and `AsyncRead`, so async services don't need `spawn_blocking`
+//! around every write. The files are the same as those of `SSTableWriter` and `SSTableReader`, so
+//! either side can be sync or async.
+//!
+//! Keys and values are encoded and decoded with the same `CborWrite` and `CborRead` as the sync
+//! reader and writer: the writer encodes each entry into a buffer before writing it, and the
+//! reader decodes entries from the bytes it has read so far, reading more when an entry is cut off.
+//!
+//! The async writer keeps the data file and the index, with any `IndexDensity`. For bloom filters,
+//! checksums, metadata and the other sidecar files, use `SSTableWriter`, or rebuild them afterwards,
+//! for example with [`crate::reindex`].
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::async_io::{AsyncSSTableReader, AsyncSSTableWriter};
+//! use sstables::IndexDensity;
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let path = std::env::temp_dir().join("async_example.sst");
+//! let mut writer = AsyncSSTableWriter::create(&path, IndexDensity::Dense).await.unwrap();
+//! writer.write(("apple", "1")).await.unwrap();
+//! writer.write(("banana", "2")).await.unwrap();
+//! writer.close().await.unwrap();
+//!
+//! let mut reader = AsyncSSTableReader::<(String, String)>::open(&path).await.unwrap();
+//! let mut keys = Vec::new();
+//! while let Some(entry) = reader.next_entry().await {
+//!   keys.push(entry.unwrap().0);
+//! }
+//! assert_eq!(keys, vec!["apple", "banana"]);
+//! # });
+//! ```
+
+use crate::cbor::{CborRead, CborValue, CborWrite};
+use crate::header::{read_header, Header, SELF_DESCRIBE_PREFIX};
+use crate::read::create_index_path;
+use crate::sstable_writer::IndexDensity;
+use futures_core::Stream;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+
+/// The smallest number of bytes the reader asks for at a time. It asks for more when an entry
+/// doesn't fit in what it has buffered, so large entries aren't decoded many times over.
+const READ_SIZE: usize = 8192;
+
+/// Writes entries to an SSTable through tokio's `AsyncWrite`. See the module documentation. Like
+/// `SSTableWriter`, a write that fails or is cancelled partway makes every later write, flush and
+/// close fail.
+#[derive(Debug)]
+pub struct AsyncSSTableWriter<D = BufWriter<File>, I = BufWriter<File>> {
+  data_writer: D,
+  index_writer: I,
+  index_density: IndexDensity,
+  data_offset: u64,
+  last_indexed_offset: Option<u64>,
+  records_since_indexed: u64,
+  key_buffer: Vec<u8>,
+  value_buffer: Vec<u8>,
+  /// Whether a write failed partway, after which the sinks no longer match the offsets.
+  poisoned: bool,
+}
+
+impl AsyncSSTableWriter {
+  /// Creates a data file at the given path and an index file beside it, replacing any that exist.
+  /// Unlike `SSTableWriterBuilder::build`, existing files are not appended to.
+  pub async fn create<P: AsRef<Path>>(path: P, index_density: IndexDensity) -> io::Result<Self> {
+    let path = path.as_ref();
+    let data_writer = BufWriter::new(File::create(path).await?);
+    let index_writer = BufWriter::new(File::create(create_index_path(path)).await?);
+    Ok(AsyncSSTableWriter::new(data_writer, index_writer, index_density))
+  }
+}
+
+impl<D, I> AsyncSSTableWriter<D, I>
+where
+  D: AsyncWrite + Unpin,
+  I: AsyncWrite + Unpin,
+{
+  /// Creates a writer over any async sinks, such as sockets or in-memory buffers. Offsets start at
+  /// zero, so the sinks should be empty. The sinks aren't buffered, so wrap them in a `BufWriter`
+  /// if they are unbuffered.
+  pub fn new(data_writer: D, index_writer: I, index_density: IndexDensity) -> Self {
+    AsyncSSTableWriter {
+      data_writer,
+      index_writer,
+      index_density,
+      data_offset: 0,
+      last_indexed_offset: None,
+      records_since_indexed: 0,
+      key_buffer: Vec::new(),
+      value_buffer: Vec::new(),
+      poisoned: false,
+    }
+  }
+
+  /// Writes a key-value pair, and an index entry for it if the index density calls for one.
+  pub async fn write<K: CborWrite, V: CborWrite>(&mut self, (key, value): (K, V)) -> io::Result<()> {
+    self.check_poisoned()?;
+    self.key_buffer.clear();
+    self.value_buffer.clear();
+    key.cbor_write(&mut self.key_buffer)?;
+    value.cbor_write(&mut self.value_buffer)?;
+
+    // Poisoned until the write finishes, which also covers a write future dropped partway.
+    self.poisoned = true;
+    self.write_buffers().await?;
+    self.poisoned = false;
+    Ok(())
+  }
+
+  /// Writes the encoded key and value in the buffers to the data and index.
+  async fn write_buffers(&mut self) -> io::Result<()> {
+    let initial_offset = self.data_offset;
+    let should_index =
+      self
+        .index_density
+        .should_index(self.last_indexed_offset, self.records_since_indexed, initial_offset);
+    self.data_writer.write_all(&self.key_buffer).await?;
+    self.data_writer.write_all(&self.value_buffer).await?;
+    self.data_offset += (self.key_buffer.len() + self.value_buffer.len()) as u64;
+
+    if should_index {
+      // The key is already encoded, so the offset goes after it in the same buffer.
+      initial_offset.cbor_write(&mut self.key_buffer)?;
+      self.index_writer.write_all(&self.key_buffer).await?;
+      self.last_indexed_offset = Some(initial_offset);
+      self.records_since_indexed = 1;
+    } else {
+      self.records_since_indexed += 1;
+    }
+
+    Ok(())
+  }
+
+  /// Writes a tombstone for the key, as `SSTableWriter::write_tombstone` does.
+  pub async fn write_tombstone<K: CborWrite>(&mut self, key: K) -> io::Result<()> {
+    self.write((key, CborValue::Null)).await
+  }
+
+  /// Returns an error if an earlier write failed partway.
+  fn check_poisoned(&self) -> io::Result<()> {
+    if self.poisoned {
+      return Err(io::Error::other("The writer can't be used after a failed write"));
+    }
+    Ok(())
+  }
+
+  /// The offset in the data file of the next entry.
+  pub fn data_offset(&self) -> u64 {
+    self.data_offset
+  }
+
+  /// Flushes the data and index writers.
+  pub async fn flush(&mut self) -> io::Result<()> {
+    self.check_poisoned()?;
+    self.data_writer.flush().await?;
+    self.index_writer.flush().await
+  }
+
+  /// Flushes and shuts down the data and index writers. Dropping the writer without closing it
+  /// can lose buffered entries, since async writers can't flush on drop.
+  pub async fn close(mut self) -> io::Result<()> {
+    self.check_poisoned()?;
+    self.data_writer.shutdown().await?;
+    self.index_writer.shutdown().await
+  }
+
+  /// Returns the underlying data and index writers, without flushing them.
+  pub fn into_inner(self) -> (D, I) {
+    (self.data_writer, self.index_writer)
+  }
+}
+
+/// Reads the entries of an SSTable through tokio's `AsyncRead`, as a `Stream` of key-value pairs.
+/// Like `SSTableReader`, a header is skipped, and a truncated last entry ends the stream. Any other
+/// error is returned once, and then the stream ends, since the start of the next entry isn't known.
+#[derive(Debug)]
+pub struct AsyncSSTableReader<T, R = File> {
+  data_reader: R,
+  buffer: Vec<u8>,
+  position: usize,
+  eof: bool,
+  header: Option<Header>,
+  data_start: Option<u64>,
+  // A function pointer keeps the reader `Unpin` whatever the entry type is.
+  phantom: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> AsyncSSTableReader<T> {
+  /// Opens the data file at the given path.
+  pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    Ok(AsyncSSTableReader::new(File::open(path).await?))
+  }
+}
+
+impl<T, R> AsyncSSTableReader<T, R> {
+  /// Creates a reader over any async source of SSTable data, which may start with a header. The
+  /// reader buffers what it reads, so the source doesn't need to be buffered.
+  pub fn new(data_reader: R) -> Self {
+    AsyncSSTableReader {
+      data_reader,
+      buffer: Vec::new(),
+      position: 0,
+      eof: false,
+      header: None,
+      data_start: None,
+      phantom: std::marker::PhantomData,
+    }
+  }
+
+  /// The header of the data, if it had one. It is read with the first entry.
+  pub fn header(&self) -> Option<&Header> {
+    self.header.as_ref()
+  }
+
+  /// The offset of the first entry in the data, which is after the header, once the first entry
+  /// has been read.
+  pub fn data_start(&self) -> Option<u64> {
+    self.data_start
+  }
+
+  /// Returns the underlying reader. Data that was read but not yet decoded is lost.
+  pub fn into_inner(self) -> R {
+    self.data_reader
+  }
+
+  /// Ends the stream after an error, since the start of the next entry isn't known.
+  fn fail(&mut self, e: io::Error) -> Poll<Option<io::Result<T>>> {
+    self.buffer.clear();
+    self.position = 0;
+    self.eof = true;
+    Poll::Ready(Some(Err(e)))
+  }
+}
+
+impl<T, R: AsyncRead + Unpin> AsyncSSTableReader<T, R> {
+  /// Reads more data onto the end of the buffer, dropping what was already decoded first.
+  fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    self.buffer.drain(..self.position);
+    self.position = 0;
+
+    let len = self.buffer.len();
+    self.buffer.resize(len + READ_SIZE.max(len), 0);
+    let mut read_buf = ReadBuf::new(&mut self.buffer[len..]);
+    let result = Pin::new(&mut self.data_reader).poll_read(cx, &mut read_buf);
+    let filled = read_buf.filled().len();
+    self.buffer.truncate(len + filled);
+    ready!(result)?;
+    self.eof = filled == 0;
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<K, V, R> AsyncSSTableReader<(K, V), R>
+where
+  R: AsyncRead + Unpin,
+  for<'a> &'a [u8]: CborRead<K> + CborRead<V>,
+{
+  /// Reads the next entry, or `None` at the end of the data. This is `StreamExt::next` without
+  /// needing a stream extension crate.
+  pub async fn next_entry(&mut self) -> Option<io::Result<(K, V)>> {
+    std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+  }
+}
+
+impl<K, V, R> Stream for AsyncSSTableReader<(K, V), R>
+where
+  R: AsyncRead + Unpin,
+  for<'a> &'a [u8]: CborRead<K> + CborRead<V>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    loop {
+      let mut bytes = &this.buffer[this.position..];
+      let available = bytes.len();
+
+      if this.data_start.is_none() {
+        // The header is only detected once there are enough bytes to tell.
+        if available >= SELF_DESCRIBE_PREFIX.len() || this.eof {
+          match read_header(&mut bytes) {
+            Ok(header) => {
+              let size = header.as_ref().map_or(0, |(_, size)| *size);
+              this.header = header.map(|(header, _)| header);
+              this.data_start = Some(size);
+              this.position += size as usize;
+              continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !this.eof => {}
+            Err(e) => return this.fail(e),
+          }
+        }
+      } else if available > 0 {
+        let result =
+          CborRead::<K>::cbor_read(&mut bytes).and_then(|k| CborRead::<V>::cbor_read(&mut bytes).map(|v| (k, v)));
+        match result {
+          Ok(entry) => {
+            this.position += available - bytes.len();
+            return Poll::Ready(Some(Ok(entry)));
+          }
+          Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            if this.eof {
+              return Poll::Ready(None);
+            }
+          }
+          Err(e) => return this.fail(e),
+        }
+      } else if this.eof {
+        return Poll::Ready(None);
+      }
+
+      if let Err(e) = ready!(this.poll_fill(cx)) {
+        return this.fail(e);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{FromPath, SSTableIndex, SSTableReader, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+
+  const TEST_FILE_NAME: &str = ".tmp/async_io_test.sst";
+
+  /// Returns at most one byte per read, to split every entry across reads.
+  struct OneByteReader<'a>(&'a [u8]);
+
+  impl AsyncRead for OneByteReader<'_> {
+    fn poll_read(mut self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+      if let Some((first, rest)) = self.0.split_first() {
+        buf.put_slice(&[*first]);
+        self.0 = rest;
+      }
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().build().unwrap()
+  }
+
+  async fn collect<T, R>(mut reader: AsyncSSTableReader<(T, T), R>) -> Vec<io::Result<(T, T)>>
+  where
+    R: AsyncRead + Unpin,
+    for<'a> &'a [u8]: CborRead<T>,
+  {
+    let mut entries = Vec::new();
+    while let Some(entry) = reader.next_entry().await {
+      entries.push(entry);
+    }
+    entries
+  }
+
+  #[test]
+  fn writes_files_the_sync_reader_reads() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp")?;
+    runtime().block_on(async {
+      let mut writer = AsyncSSTableWriter::create(TEST_FILE_NAME, IndexDensity::EveryRecords(2)).await?;
+      for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+        writer.write((key, value)).await?;
+      }
+      writer.write_tombstone("d").await?;
+      assert::equal(writer.data_offset(), 15);
+      writer.close().await
+    })?;
+
+    let entries =
+      SSTableReader::<(String, Option<String>)>::from_path(TEST_FILE_NAME)?.collect::<io::Result<Vec<_>>>()?;
+    assert::equal(entries.len(), 4);
+    assert::equal(entries[3].clone(), ("d".to_string(), None));
+    let index = SSTableIndex::<String>::from_path(create_index_path(Path::new(TEST_FILE_NAME)))?;
+    assert::equal(index.indices, vec![("a".to_string(), 0), ("c".to_string(), 8)]);
+    Ok(())
+  }
+
+  #[test]
+  fn failed_writes_poison_the_writer() {
+    runtime().block_on(async {
+      // Room for the first entry and part of the second.
+      let mut data = [0u8; 6];
+      let mut writer = AsyncSSTableWriter::new(io::Cursor::new(data.as_mut_slice()), Vec::new(), IndexDensity::Dense);
+      writer.write(("a", "1")).await.unwrap();
+      assert::equal(
+        writer.write(("b", "22")).await.unwrap_err().kind(),
+        io::ErrorKind::WriteZero,
+      );
+
+      assert::equal(writer.data_offset(), 4);
+      assert!(writer.write(("c", "3")).await.is_err());
+      assert!(writer.flush().await.is_err());
+      assert!(writer.close().await.is_err());
+    });
+  }
+
+  #[test]
+  fn reads_files_the_sync_writer_writes() -> io::Result<()> {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(TEST_FILE_NAME)?;
+    setup::remove_file(create_index_path(Path::new(TEST_FILE_NAME)))?;
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).key_type("text").build()?;
+    writer.write(("a", "1"))?;
+    writer.write(("b", "x".repeat(3 * READ_SIZE).as_str()))?;
+    writer.close()?;
+
+    runtime().block_on(async {
+      let mut reader = AsyncSSTableReader::<(String, String)>::open(TEST_FILE_NAME).await?;
+      assert::equal(reader.next_entry().await.unwrap()?, ("a".to_string(), "1".to_string()));
+      assert::equal(
+        reader.header().and_then(|h| h.key_type.clone()),
+        Some("text".to_string()),
+      );
+      assert!(reader.data_start().unwrap() > 0);
+      assert::equal(reader.next_entry().await.unwrap()?.1.len(), 3 * READ_SIZE);
+      assert!(reader.next_entry().await.is_none());
+      Ok(())
+    })
+  }
+
+  #[test]
+  fn reads_entries_split_across_reads() {
+    runtime().block_on(async {
+      let data = [0x61, b'a', 0x61, b'1', 0x61, b'b', 0x62, b'2', b'2', 0x61];
+      let entries = collect::<String, _>(AsyncSSTableReader::new(OneByteReader(&data))).await;
+      let entries = entries.into_iter().collect::<io::Result<Vec<_>>>().unwrap();
+      assert::equal(entries.len(), 2);
+      assert::equal(entries[1].clone(), ("b".to_string(), "22".to_string()));
+
+      let mut data = Header::new(IndexDensity::Dense, false, None).to_bytes().unwrap();
+      data.extend_from_slice(&[0x61, b'a', 0x61, b'1']);
+      let mut reader = AsyncSSTableReader::<(String, String), _>::new(OneByteReader(&data));
+      assert::equal(reader.next_entry().await.unwrap().unwrap().0, "a".to_string());
+      assert!(reader.header().is_some());
+      assert!(reader.next_entry().await.is_none());
+
+      let empty = collect::<String, _>(AsyncSSTableReader::new(OneByteReader(&[]))).await;
+      assert::equal(empty.len(), 0);
+    });
+  }
+
+  #[test]
+  fn ends_after_an_error() {
+    runtime().block_on(async {
+      // A key that is not valid UTF-8, followed by a valid entry.
+      let data = [0x61, 0xFF, 0x61, b'1', 0x61, b'a', 0x61, b'1'];
+      let entries = collect::<String, _>(AsyncSSTableReader::new(data.as_slice())).await;
+      assert::equal(entries.len(), 1);
+      assert!(entries[0].is_err());
+    });
+  }
+}
@@ -0,0 +1,447 @@
+//! Bloom filter
+//!
+//! This module contains the `BloomFilter` struct, which is a probabilistic set of keys that is
+//! written alongside the data and index files of an SSTable. A Bloom filter can answer "this key
+//! is definitely not in the table" without reading the index, which makes negative lookups across
+//! many tables cheap.
+//!
+//! Keys are hashed by their CBOR encoding, so a `String` key and a `&str` key with the same text
+//! hash to the same bits, and the filter can be rebuilt from the data file without knowing the
+//! key type.
+//!
+//! # Format
+//!
+//! The bloom filter file is a sequence of CBOR-encoded values, and therefore can be read by any
+//! CBOR implementation. In order, they are the number of bits, the number of hash functions, the
+//! number of inserted keys, the size of the data file when the filter was written, and the bits
+//! themselves as a byte string.
+//!
+//! The size of the data file is used to detect a stale filter, such as when more entries were
+//! appended to the data file without the filter being written. A stale filter is never trusted,
+//! because it would report keys as missing that are actually present. Neither is a filter file
+//! that can't be read, such as one that was cut off or corrupted, so a bad filter makes lookups
+//! slower but never wrong.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::bloom::BloomFilter;
+//!
+//! let mut bloom_filter = BloomFilter::new(100, 0.01);
+//! bloom_filter.insert(&"hello");
+//!
+//! assert!(bloom_filter.may_contain(&"hello"));
+//! assert!(bloom_filter.may_contain(&"hello".to_string()));
+//! ```
+
+use crate::cbor::{read_cbor_bytes, read_cbor_raw, read_cbor_u64, CborWrite};
+use crate::header::skip_header;
+use crate::read::create_bloom_path;
+use crate::traits::FromPath;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The default number of keys a bloom filter is sized for, when the number of keys is unknown.
+pub const DEFAULT_EXPECTED_ITEMS: usize = 10_000;
+
+/// The default false positive rate of a bloom filter.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// The most hash functions a filter uses per key. Even a false positive rate of one in a trillion
+/// only needs 40.
+const MAX_NUM_HASHES: u64 = 64;
+
+/// The offset basis of the 64-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64-bit FNV-1a hash.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hashes a byte slice with 64-bit FNV-1a. The hash is stable across platforms and Rust versions,
+/// which matters because the bits are persisted to disk.
+#[inline]
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+    (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+  })
+}
+
+/// Derives a second, independent-enough hash from the first with the SplitMix64 finalizer. It is
+/// forced to be odd so that it never cycles early when used as a step.
+#[inline]
+fn splitmix_64(hash: u64) -> u64 {
+  let mut z = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  (z ^ (z >> 31)) | 1
+}
+
+/// A Bloom filter over the CBOR encoding of keys. See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+  num_bits: u64,
+  num_hashes: u64,
+  count: u64,
+  /// The size of the data file when the filter was last written or read.
+  pub data_size: u64,
+  bits: Vec<u8>,
+}
+
+impl BloomFilter {
+  /// Creates an empty filter sized for `expected_items` keys at the given false positive rate.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use sstables::bloom::BloomFilter;
+  ///
+  /// let bloom_filter = BloomFilter::new(1000, 0.01);
+  /// assert_eq!(bloom_filter.num_bits(), 9592);
+  /// assert_eq!(bloom_filter.num_hashes(), 7);
+  /// ```
+  pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+    let n = expected_items.max(1) as f64;
+    let p = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+    let ln2 = std::f64::consts::LN_2;
+
+    // Round up to a whole number of bytes so that every bit is addressable.
+    let num_bits = ((-n * p.ln() / (ln2 * ln2)).ceil() as u64).max(8).div_ceil(8) * 8;
+    let num_hashes = ((num_bits as f64 / n * ln2).round() as u64).clamp(1, MAX_NUM_HASHES);
+
+    BloomFilter {
+      num_bits,
+      num_hashes,
+      count: 0,
+      data_size: 0,
+      bits: vec![0; (num_bits / 8) as usize],
+    }
+  }
+
+  /// The number of bits in the filter.
+  pub fn num_bits(&self) -> u64 {
+    self.num_bits
+  }
+
+  /// The number of hash functions used per key.
+  pub fn num_hashes(&self) -> u64 {
+    self.num_hashes
+  }
+
+  /// The number of keys inserted into the filter, including duplicates.
+  pub fn len(&self) -> u64 {
+    self.count
+  }
+
+  /// Returns true if no keys have been inserted.
+  pub fn is_empty(&self) -> bool {
+    self.count == 0
+  }
+
+  /// Hashes the CBOR encoding of a key.
+  pub fn hash_key_bytes(key_bytes: &[u8]) -> u64 {
+    fnv1a_64(key_bytes)
+  }
+
+  /// Inserts a key by its CBOR encoding.
+  pub fn insert<K: CborWrite>(&mut self, key: &K) {
+    let mut key_bytes = Vec::new();
+    key.cbor_write(&mut key_bytes).unwrap();
+    self.insert_key_bytes(&key_bytes);
+  }
+
+  /// Inserts a key that has already been encoded as CBOR.
+  pub fn insert_key_bytes(&mut self, key_bytes: &[u8]) {
+    self.insert_hash(Self::hash_key_bytes(key_bytes));
+  }
+
+  /// Inserts a key by a hash from [`BloomFilter::hash_key_bytes`].
+  pub fn insert_hash(&mut self, hash: u64) {
+    let step = splitmix_64(hash);
+    for i in 0..self.num_hashes {
+      let bit = hash.wrapping_add(i.wrapping_mul(step)) % self.num_bits;
+      self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+    }
+    self.count += 1;
+  }
+
+  /// Returns false if the key is definitely not in the filter, or true if it may be.
+  pub fn may_contain<K: CborWrite>(&self, key: &K) -> bool {
+    let mut key_bytes = Vec::new();
+    key.cbor_write(&mut key_bytes).unwrap();
+    self.may_contain_key_bytes(&key_bytes)
+  }
+
+  /// Returns false if the CBOR-encoded key is definitely not in the filter, or true if it may be.
+  pub fn may_contain_key_bytes(&self, key_bytes: &[u8]) -> bool {
+    let hash = Self::hash_key_bytes(key_bytes);
+    let step = splitmix_64(hash);
+    (0..self.num_hashes).all(|i| {
+      let bit = hash.wrapping_add(i.wrapping_mul(step)) % self.num_bits;
+      self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+    })
+  }
+
+  /// Estimates the current false positive rate from the fraction of bits that are set.
+  pub fn false_positive_rate(&self) -> f64 {
+    let set_bits: u64 = self.bits.iter().map(|byte| byte.count_ones() as u64).sum();
+    (set_bits as f64 / self.num_bits as f64).powi(self.num_hashes as i32)
+  }
+
+  /// Writes the filter as a sequence of CBOR values.
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    self.num_bits.cbor_write(writer)?;
+    self.num_hashes.cbor_write(writer)?;
+    self.count.cbor_write(writer)?;
+    self.data_size.cbor_write(writer)?;
+    self.bits.as_slice().cbor_write(writer)
+  }
+
+  /// Reads a filter that was written with [`BloomFilter::write_to`].
+  pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    let num_bits = read_cbor_u64(reader)?;
+    let num_hashes = read_cbor_u64(reader)?;
+    let count = read_cbor_u64(reader)?;
+    let data_size = read_cbor_u64(reader)?;
+    let bits = read_cbor_bytes(reader)?;
+
+    if num_bits == 0 || bits.len() as u64 * 8 != num_bits {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid bloom filter size"));
+    }
+    if num_hashes == 0 || num_hashes > MAX_NUM_HASHES {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Invalid number of bloom filter hash functions",
+      ));
+    }
+
+    Ok(BloomFilter {
+      num_bits,
+      num_hashes,
+      count,
+      data_size,
+      bits,
+    })
+  }
+
+  /// Writes the filter to the given path, replacing any previous filter.
+  pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    self.write_to(&mut writer)?;
+    writer.flush()?;
+    writer.get_mut().sync_all()
+  }
+
+  /// Builds a filter from every key in a data file, skipping its header. The filter is sized for at
+  /// least `expected_items` keys, or twice the number of keys found, whichever is larger, so that
+  /// the table has room to grow.
+  pub fn from_data_reader<R: BufRead>(
+    reader: &mut R,
+    expected_items: usize,
+    false_positive_rate: f64,
+  ) -> io::Result<Self> {
+    let mut hashes = Vec::new();
+    let mut key_bytes = Vec::new();
+    let mut value_bytes = Vec::new();
+    let mut data_size = skip_header(reader)?;
+
+    loop {
+      key_bytes.clear();
+      value_bytes.clear();
+      match read_cbor_raw(reader, &mut key_bytes).and_then(|_| read_cbor_raw(reader, &mut value_bytes)) {
+        Ok(()) => {}
+        Err(e) => match e.kind() {
+          io::ErrorKind::UnexpectedEof => break,
+          _ => return Err(e),
+        },
+      }
+
+      data_size += (key_bytes.len() + value_bytes.len()) as u64;
+      hashes.push(Self::hash_key_bytes(&key_bytes));
+    }
+
+    let mut bloom_filter = BloomFilter::new(expected_items.max(hashes.len() * 2), false_positive_rate);
+    for hash in hashes {
+      bloom_filter.insert_hash(hash);
+    }
+    bloom_filter.data_size = data_size;
+
+    Ok(bloom_filter)
+  }
+}
+
+/// Implementation of FromPath for BloomFilter. The path is the bloom filter file itself, not the
+/// data file.
+impl FromPath<BloomFilter> for BloomFilter {
+  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    BloomFilter::read_from(&mut BufReader::new(File::open(path)?))
+  }
+}
+
+/// Loads the bloom filter of the data file at the given path, if one exists, can be read, and is
+/// not stale. A filter is stale if the data file has changed size since the filter was written.
+pub fn load_bloom_filter<P: AsRef<Path>>(data_path: P) -> io::Result<Option<BloomFilter>> {
+  let data_path = data_path.as_ref();
+  let bloom_path = create_bloom_path(data_path);
+  if !bloom_path.is_file() {
+    return Ok(None);
+  }
+
+  let bloom_filter = match BloomFilter::from_path(&bloom_path) {
+    Ok(bloom_filter) => bloom_filter,
+    Err(_) => return Ok(None),
+  };
+  if bloom_filter.data_size != fs::metadata(data_path)?.len() {
+    return Ok(None);
+  }
+
+  Ok(Some(bloom_filter))
+}
+
+/// Checks the bloom filter of the data file at the given path for a key, without reading the
+/// index or the data file. Returns false only if the key is definitely not in the table. If the
+/// table has no usable bloom filter, because it is missing, unreadable or stale, returns true.
+///
+/// # Example
+///
+/// ```
+/// use sstables::bloom::may_contain_key;
+/// use sstables::SSTableWriterBuilder;
+///
+/// let path = std::env::temp_dir().join("bloom_example.sst");
+/// # std::fs::remove_file(&path).ok();
+/// let mut writer = SSTableWriterBuilder::new(&path)
+///   .bloom_filter(100, 0.01)
+///   .build()
+///   .unwrap();
+/// writer.write(("hello", "world")).unwrap();
+/// writer.close().unwrap();
+///
+/// assert!(may_contain_key(&path, &"hello").unwrap());
+/// ```
+pub fn may_contain_key<K, P>(data_path: P, key: &K) -> io::Result<bool>
+where
+  K: CborWrite,
+  P: AsRef<Path>,
+{
+  Ok(match load_bloom_filter(data_path)? {
+    Some(bloom_filter) => bloom_filter.may_contain(key),
+    None => true,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+
+  #[test]
+  fn bloom_filter_has_no_false_negatives() {
+    let mut bloom_filter = BloomFilter::new(1000, 0.01);
+    for i in 0..1000u64 {
+      bloom_filter.insert(&i);
+    }
+
+    assert::equal(bloom_filter.len(), 1000);
+    for i in 0..1000u64 {
+      assert::equal(bloom_filter.may_contain(&i), true);
+    }
+  }
+
+  #[test]
+  fn bloom_filter_false_positive_rate_is_bounded() {
+    let mut bloom_filter = BloomFilter::new(1000, 0.01);
+    for i in 0..1000u64 {
+      bloom_filter.insert(&format!("key-{}", i));
+    }
+
+    let false_positives = (0..10000u64)
+      .filter(|i| bloom_filter.may_contain(&format!("other-{}", i)))
+      .count();
+    assert::equal(false_positives < 300, true);
+    assert::equal(bloom_filter.false_positive_rate() < 0.03, true);
+
+    // A rate that would take more hash functions than a filter can be read back with is capped.
+    assert::equal(BloomFilter::new(1, 0.0).num_hashes(), MAX_NUM_HASHES);
+  }
+
+  #[test]
+  fn bloom_filter_hashes_by_cbor_encoding() {
+    let mut bloom_filter = BloomFilter::new(10, 0.01);
+    bloom_filter.insert(&"hello");
+    assert::equal(bloom_filter.may_contain(&"hello".to_string()), true);
+
+    // The same bytes as text and as a byte string have different encodings.
+    let mut bloom_filter = BloomFilter::new(10, 0.01);
+    bloom_filter.insert(&b"hello".as_slice());
+    assert::equal(
+      bloom_filter.may_contain_key_bytes(&[0x45, b'h', b'e', b'l', b'l', b'o']),
+      true,
+    );
+  }
+
+  #[test]
+  fn bloom_filter_round_trips() {
+    let mut bloom_filter = BloomFilter::new(10, 0.01);
+    bloom_filter.insert(&"hello");
+    bloom_filter.data_size = 12;
+
+    let mut v = Vec::new();
+    bloom_filter.write_to(&mut v).unwrap();
+    let read_bloom_filter = BloomFilter::read_from(&mut io::Cursor::new(v)).unwrap();
+
+    assert::equal(read_bloom_filter, bloom_filter);
+  }
+
+  #[test]
+  fn bloom_filter_from_data_reader() {
+    let mut v = Vec::new();
+    "hello".cbor_write(&mut v).unwrap();
+    "world".cbor_write(&mut v).unwrap();
+    "foo".cbor_write(&mut v).unwrap();
+    "bar".cbor_write(&mut v).unwrap();
+    let data_size = v.len() as u64;
+
+    let bloom_filter = BloomFilter::from_data_reader(&mut io::Cursor::new(v), 1, 0.01).unwrap();
+    assert::equal(bloom_filter.len(), 2);
+    assert::equal(bloom_filter.data_size, data_size);
+    assert::equal(bloom_filter.may_contain(&"hello"), true);
+    assert::equal(bloom_filter.may_contain(&"foo"), true);
+  }
+
+  #[test]
+  fn may_contain_key_with_an_unreadable_bloom_filter() -> io::Result<()> {
+    let _lock = setup::sequential();
+    let path = Path::new(".tmp/bloom_unreadable_test.sst");
+    setup::create_dir_all(".tmp")?;
+    let mut data = Vec::new();
+    "hello".cbor_write(&mut data)?;
+    "world".cbor_write(&mut data)?;
+    fs::write(path, &data)?;
+
+    let bloom_filter = BloomFilter::from_data_reader(&mut io::Cursor::new(data), 10, 0.01)?;
+    bloom_filter.write_to_path(create_bloom_path(path))?;
+    assert::equal(may_contain_key(path, &"other")?, false);
+
+    // A filter that was cut off, or whose size doesn't match its bits, is ignored.
+    let mut bytes = fs::read(create_bloom_path(path))?;
+    fs::write(create_bloom_path(path), &bytes[..bytes.len() - 1])?;
+    assert::none(&load_bloom_filter(path)?);
+    assert::equal(may_contain_key(path, &"other")?, true);
+
+    bytes[0] = 0x07;
+    fs::write(create_bloom_path(path), &bytes)?;
+    assert::none(&load_bloom_filter(path)?);
+    assert::equal(may_contain_key(path, &"other")?, true);
+
+    // So is one with no hash functions, or too many to check.
+    for num_hashes in [0, MAX_NUM_HASHES + 1, u64::MAX] {
+      let mut bloom_filter = bloom_filter.clone();
+      bloom_filter.num_hashes = num_hashes;
+      bloom_filter.write_to_path(create_bloom_path(path))?;
+      assert::none(&load_bloom_filter(path)?);
+      assert::equal(may_contain_key(path, &"other")?, true);
+    }
+    Ok(())
+  }
+}
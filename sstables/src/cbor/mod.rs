@@ -1,8 +1,10 @@
 //! CBOR types and constants.
 //! See https://tools.ietf.org/html/rfc7049 for the spec.
 //!
-//! This is a subset of the spec, only including the types we need for the internal
-//! representation of SSTables that will be compatible with any CBOR implementation.
+//! The functions in this module are a subset of the spec, only including the types we need for
+//! the internal representation of SSTables that will be compatible with any CBOR implementation.
+//! For everything else, such as negative integers, arrays, maps, tags and floats, see
+//! [`CborValue`].
 //!
 //! The spec defines a CBOR data item as a major type and an optional additional info.
 //! The major type is stored in the first three bits of the initial byte.
@@ -12,12 +14,17 @@
 //! The value is stored in the bytes following the initial byte.
 //!
 
-use std::{
-  fs::File,
-  io::{self, Cursor, Read, Write},
-};
+use std::io::{self, BufRead, Cursor, Read, Write};
 
-use crate::read::{take_byte, take_byte_array, take_byte_slice};
+use crate::read::{take_byte, take_byte_array, take_byte_slice, take_byte_slice_into};
+
+#[cfg(feature = "serde")]
+pub mod serde;
+mod timestamp;
+mod value;
+
+pub use timestamp::*;
+pub use value::*;
 
 /// A mask used to get the first three bits of a byte, aka 224 or 1110_0000.
 ///
@@ -46,6 +53,9 @@ const U16_MAX: u64 = u16::MAX as u64;
 /// This is used to determine if we can store a value in a U32.
 const U32_MAX: u64 = u32::MAX as u64;
 
+/// The initial byte of the simple value null, which SSTables use as a tombstone for deleted keys.
+pub const CBOR_NULL: u8 = 0xF6;
+
 /// Major types for CBOR data items. Each type corresponds to the high-order
 /// 3 bits in the initial byte of a CBOR data item. See Section 2.1.
 ///
@@ -185,7 +195,7 @@ impl ExtendedSize {
   pub fn from_u8(value: u8) -> Self {
     let value = value & LAST_FIVE_BITS;
     if (24..=27).contains(&value) {
-      unsafe { ::std::mem::transmute(value) }
+      unsafe { ::std::mem::transmute::<u8, ExtendedSize>(value) }
     } else {
       ExtendedSize::Embedded
     }
@@ -234,6 +244,23 @@ fn get_num_bytes_for_u64(value: u64) -> usize {
   }
 }
 
+/// Returns the size of the CBOR head that `write_cbor_head` writes for the value, which is 1-9
+/// bytes.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::cbor_head_size;
+///
+/// assert_eq!(cbor_head_size(23), 1);
+/// assert_eq!(cbor_head_size(100), 2);
+/// assert_eq!(cbor_head_size(u64::MAX), 9);
+/// ```
+#[inline]
+pub fn cbor_head_size(value: u64) -> u64 {
+  1 + get_num_bytes_for_u64(value) as u64
+}
+
 /// Read the value in the head using the contnt of the initial byte. May consume up
 /// to four additional bytes.
 ///
@@ -260,30 +287,238 @@ pub fn read_cbor_head_u64<R: Read + ?Sized>(b: &mut R, byte: u8) -> io::Result<u
   })
 }
 
-/// A trait for reading CBOR from a cursor.
+/// A trait for reading CBOR from any reader, such as a `BufReader<File>`, a `Cursor` over bytes, or
+/// a decompressing stream.
 pub trait CborRead<R> {
   /// Reads a CBOR value from the given reader.
   fn cbor_read(&mut self) -> io::Result<R>;
 }
 
-impl CborRead<Vec<u8>> for io::BufReader<File> {
+impl<R: Read + ?Sized> CborRead<Vec<u8>> for R {
   fn cbor_read(&mut self) -> io::Result<Vec<u8>> {
     read_cbor_bytes(self)
   }
 }
 
-impl CborRead<String> for io::BufReader<File> {
+impl<R: Read + ?Sized> CborRead<String> for R {
   fn cbor_read(&mut self) -> io::Result<String> {
     read_cbor_text(self)
   }
 }
 
-impl CborRead<u64> for io::BufReader<File> {
+impl<R: Read + ?Sized> CborRead<u64> for R {
   fn cbor_read(&mut self) -> io::Result<u64> {
     read_cbor_u64(self)
   }
 }
 
+/// Null is read as `None` and anything else as `Some` value. The next byte is peeked to tell them
+/// apart, so the reader must be buffered.
+impl<R: BufRead + CborRead<T> + ?Sized, T> CborRead<Option<T>> for R {
+  fn cbor_read(&mut self) -> io::Result<Option<T>> {
+    match self.fill_buf()?.first() {
+      Some(&CBOR_NULL) => {
+        self.consume(1);
+        Ok(None)
+      }
+      Some(_) => CborRead::<T>::cbor_read(self).map(Some),
+      None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+  }
+}
+
+impl<R: Read + ?Sized> CborRead<i128> for R {
+  fn cbor_read(&mut self) -> io::Result<i128> {
+    read_cbor_integer(self)
+  }
+}
+
+/// Implements `CborRead` and `CborWrite` for integer types that fit in an `i128`, so they can be
+/// keys or values without converting them first. Reading a value that doesn't fit is an error.
+macro_rules! impl_cbor_integer {
+  ($($t:ty),*) => {
+    $(
+      impl<R: Read + ?Sized> CborRead<$t> for R {
+        fn cbor_read(&mut self) -> io::Result<$t> {
+          <$t>::try_from(read_cbor_integer(self)?).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, concat!("Integer is out of range for ", stringify!($t)))
+          })
+        }
+      }
+
+      impl CborWrite for $t {
+        fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+          write_cbor_integer(writer, (*self).into())
+        }
+      }
+    )*
+  };
+}
+
+impl_cbor_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+/// A trait for reading CBOR into an existing value, so that scans can reuse the allocations of a
+/// key and a value instead of creating new ones for every entry. Byte and text strings reuse their
+/// buffers, and other types are read as they are by `CborRead`.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::CborReadInto;
+/// use std::io::Cursor;
+///
+/// let mut cursor = Cursor::new([0x61, b'a', 0x62, b'b', b'c']);
+/// let mut text = String::with_capacity(16);
+/// cursor.cbor_read_into(&mut text).unwrap();
+/// assert_eq!(text, "a");
+/// cursor.cbor_read_into(&mut text).unwrap();
+/// assert_eq!(text, "bc");
+/// assert_eq!(text.capacity(), 16);
+/// ```
+pub trait CborReadInto<T> {
+  /// Reads a CBOR value from the given reader into `value`, replacing it.
+  fn cbor_read_into(&mut self, value: &mut T) -> io::Result<()>;
+}
+
+impl<R: Read + ?Sized> CborReadInto<Vec<u8>> for R {
+  fn cbor_read_into(&mut self, value: &mut Vec<u8>) -> io::Result<()> {
+    read_cbor_bytes_into(self, value)
+  }
+}
+
+impl<R: Read + ?Sized> CborReadInto<String> for R {
+  fn cbor_read_into(&mut self, value: &mut String) -> io::Result<()> {
+    read_cbor_text_into(self, value)
+  }
+}
+
+/// Null is read as `None`. Anything else is read into the existing `Some` value, if there is one,
+/// so a tombstone only drops the buffer of the value it replaces.
+impl<R: BufRead + CborReadInto<T> + ?Sized, T: Default> CborReadInto<Option<T>> for R {
+  fn cbor_read_into(&mut self, value: &mut Option<T>) -> io::Result<()> {
+    match self.fill_buf()?.first() {
+      Some(&CBOR_NULL) => {
+        self.consume(1);
+        *value = None;
+        Ok(())
+      }
+      Some(_) => self.cbor_read_into(value.get_or_insert_with(T::default)),
+      None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+  }
+}
+
+/// Implements `CborReadInto` for types without a buffer to reuse by reading them with `CborRead`.
+macro_rules! impl_cbor_read_into_owned {
+  ($($t:ty),*) => {
+    $(
+      impl<R: Read + ?Sized> CborReadInto<$t> for R {
+        fn cbor_read_into(&mut self, value: &mut $t) -> io::Result<()> {
+          *value = CborRead::<$t>::cbor_read(self)?;
+          Ok(())
+        }
+      }
+    )*
+  };
+}
+
+impl_cbor_read_into_owned!(i8, i16, i32, i64, i128, u8, u16, u32, u64, Timestamp, CborValue);
+
+/// A trait for decoding CBOR borrowed from a byte slice, such as a memory-mapped file. Byte and
+/// text strings are returned as slices of the input instead of being copied, and other types are
+/// decoded as they are by `CborRead`.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::CborBorrow;
+///
+/// let data = [0x65, b'h', b'e', b'l', b'l', b'o', 0x42, 0x01, 0x02, 0x18, 0x64];
+/// let mut bytes = &data[..];
+/// assert_eq!(<&str>::cbor_borrow(&mut bytes).unwrap(), "hello");
+/// assert_eq!(<&[u8]>::cbor_borrow(&mut bytes).unwrap(), [1, 2]);
+/// assert_eq!(u64::cbor_borrow(&mut bytes).unwrap(), 100);
+/// assert!(bytes.is_empty());
+/// ```
+pub trait CborBorrow<'a>: Sized {
+  /// Decodes a value from the start of `bytes`, advancing it past the value.
+  fn cbor_borrow(bytes: &mut &'a [u8]) -> io::Result<Self>;
+}
+
+impl<'a> CborBorrow<'a> for &'a [u8] {
+  fn cbor_borrow(bytes: &mut &'a [u8]) -> io::Result<Self> {
+    borrow_cbor_string(bytes, MajorType::Bytes)
+  }
+}
+
+impl<'a> CborBorrow<'a> for &'a str {
+  fn cbor_borrow(bytes: &mut &'a [u8]) -> io::Result<Self> {
+    std::str::from_utf8(borrow_cbor_string(bytes, MajorType::Text)?).map_err(io::Error::other)
+  }
+}
+
+/// Null is read as `None` and anything else as `Some` value, as with `CborRead`.
+impl<'a, T: CborBorrow<'a>> CborBorrow<'a> for Option<T> {
+  fn cbor_borrow(bytes: &mut &'a [u8]) -> io::Result<Self> {
+    match bytes.first() {
+      Some(&CBOR_NULL) => {
+        *bytes = &bytes[1..];
+        Ok(None)
+      }
+      Some(_) => T::cbor_borrow(bytes).map(Some),
+      None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+  }
+}
+
+/// Implements `CborBorrow` for owned types by reading them with `CborRead`.
+macro_rules! impl_cbor_borrow_owned {
+  ($($t:ty),*) => {
+    $(
+      impl CborBorrow<'_> for $t {
+        fn cbor_borrow(bytes: &mut &[u8]) -> io::Result<Self> {
+          CborRead::<$t>::cbor_read(bytes)
+        }
+      }
+    )*
+  };
+}
+
+impl_cbor_borrow_owned!(
+  i8,
+  i16,
+  i32,
+  i64,
+  i128,
+  u8,
+  u16,
+  u32,
+  u64,
+  Vec<u8>,
+  String,
+  Timestamp,
+  CborValue
+);
+
+/// Borrows the contents of a definite-length byte or text string from the start of `bytes`.
+fn borrow_cbor_string<'a>(bytes: &mut &'a [u8], major_type: MajorType) -> io::Result<&'a [u8]> {
+  let byte = take_byte(bytes)?;
+  if MajorType::from_u8(byte) != major_type || byte & LAST_FIVE_BITS > ExtendedSize::U64.as_u8() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Expected a definite-length string",
+    ));
+  }
+
+  let len = read_cbor_head_u64(bytes, byte)?;
+  if len > bytes.len() as u64 {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+  let (string, rest) = bytes.split_at(len as usize);
+  *bytes = rest;
+  Ok(string)
+}
+
 /// Assuming that the next value is known to be an unsigned integer, read it. May
 /// consume 1 to 9 bytes.
 ///
@@ -302,6 +537,34 @@ pub fn read_cbor_u64<R: Read + ?Sized>(b: &mut R) -> io::Result<u64> {
   read_cbor_head_u64(b, byte)
 }
 
+/// Read the next value as a signed integer, which is either an unsigned integer or a negative
+/// integer. A negative integer stores -1 - n, so the full range is -2^64 to 2^64 - 1. May consume
+/// 1 to 9 bytes.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::read_cbor_integer;
+///
+/// let mut cursor = Cursor::new([0x39, 0x03, 0xE7, 0x0A]);
+/// assert_eq!(read_cbor_integer(&mut cursor).unwrap(), -1000);
+/// assert_eq!(read_cbor_integer(&mut cursor).unwrap(), 10);
+/// ```
+pub fn read_cbor_integer<R: Read + ?Sized>(b: &mut R) -> io::Result<i128> {
+  let byte = take_byte(b)?;
+  let major_type = MajorType::from_u8(byte);
+  if major_type != MajorType::UnsignedInteger && major_type != MajorType::NegativeInteger {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "Expected an integer"));
+  }
+
+  let value = i128::from(read_cbor_head_u64(b, byte)?);
+  Ok(match major_type {
+    MajorType::NegativeInteger => -1 - value,
+    _ => value,
+  })
+}
+
 /// Assuming that the next value is known to be a byte array, read it.
 ///
 /// # Example
@@ -336,7 +599,153 @@ pub fn read_cbor_text<R: Read + ?Sized>(b: &mut R) -> io::Result<String> {
   let byte = take_byte(b)?;
   let len = read_cbor_head_u64(b, byte)?;
   let bytes = take_byte_slice(b, len as usize)?;
-  String::from_utf8(bytes.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+  String::from_utf8(bytes).map_err(io::Error::other)
+}
+
+/// Like `read_cbor_bytes`, but reads into `buf`, replacing its contents and reusing its allocation.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::read_cbor_bytes_into;
+///
+/// let mut cursor = Cursor::new([0x42, 0x01, 0x02, 0x41, 0x03]);
+/// let mut buf = Vec::new();
+/// read_cbor_bytes_into(&mut cursor, &mut buf).unwrap();
+/// assert_eq!(buf, [1, 2]);
+/// read_cbor_bytes_into(&mut cursor, &mut buf).unwrap();
+/// assert_eq!(buf, [3]);
+/// ```
+pub fn read_cbor_bytes_into<R: Read + ?Sized>(b: &mut R, buf: &mut Vec<u8>) -> io::Result<()> {
+  let byte = take_byte(b)?;
+  let len = read_cbor_head_u64(b, byte)?;
+  take_byte_slice_into(b, len as usize, buf)
+}
+
+/// Like `read_cbor_text`, but reads into `buf`, replacing its contents and reusing its allocation.
+/// If the head can't be read, `buf` is left as it was. If the text can't be read or isn't valid
+/// UTF-8, an error is returned and `buf` is left empty.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::read_cbor_text_into;
+///
+/// let mut cursor = Cursor::new([0x62, b'h', b'i', 0x61, b'a']);
+/// let mut buf = String::new();
+/// read_cbor_text_into(&mut cursor, &mut buf).unwrap();
+/// assert_eq!(buf, "hi");
+/// read_cbor_text_into(&mut cursor, &mut buf).unwrap();
+/// assert_eq!(buf, "a");
+/// ```
+pub fn read_cbor_text_into<R: Read + ?Sized>(b: &mut R, buf: &mut String) -> io::Result<()> {
+  let byte = take_byte(b)?;
+  let len = read_cbor_head_u64(b, byte)?;
+  let mut bytes = std::mem::take(buf).into_bytes();
+  let result = take_byte_slice_into(b, len as usize, &mut bytes)
+    .and_then(|_| std::str::from_utf8(&bytes).map(|_| ()).map_err(io::Error::other));
+  if result.is_err() {
+    bytes.clear();
+  }
+
+  // Safety: the bytes were checked to be valid UTF-8, or are empty.
+  *buf = unsafe { String::from_utf8_unchecked(bytes) };
+  result
+}
+
+/// Reads the next CBOR data item without decoding it, appending its encoded bytes to `buf`. This
+/// is useful for hashing or copying keys and values without knowing their type. Nested and
+/// indefinite-length items are copied whole.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::read_cbor_raw;
+///
+/// let mut cursor = Cursor::new([0x65, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x18, 0x64]);
+/// let mut buf = Vec::new();
+/// read_cbor_raw(&mut cursor, &mut buf).unwrap();
+/// assert_eq!(buf, [0x65, 0x68, 0x65, 0x6C, 0x6C, 0x6F]);
+/// ```
+pub fn read_cbor_raw<R: Read + ?Sized>(b: &mut R, buf: &mut Vec<u8>) -> io::Result<()> {
+  let byte = take_byte(b)?;
+  read_cbor_raw_with_byte(b, byte, buf, 0)
+}
+
+/// Copies the rest of a data item whose initial byte has already been read.
+fn read_cbor_raw_with_byte<R: Read + ?Sized>(b: &mut R, byte: u8, buf: &mut Vec<u8>, depth: usize) -> io::Result<()> {
+  if depth > MAX_NESTING_DEPTH {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "CBOR nesting is too deep"));
+  }
+  buf.push(byte);
+
+  // Copy the extended size bytes of the head, if any, so we can decode the value from them.
+  let num_bytes = match byte & LAST_FIVE_BITS {
+    0..=23 | INDEFINITE_LENGTH => 0,
+    24 => 1,
+    25 => 2,
+    26 => 4,
+    27 => 8,
+    _ => {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Reserved CBOR additional info",
+      ))
+    }
+  };
+  let head_start = buf.len();
+  buf.resize(head_start + num_bytes, 0);
+  b.read_exact(&mut buf[head_start..])?;
+  let value = read_cbor_head_u64(&mut &buf[head_start..], byte)?;
+  let indefinite = byte & LAST_FIVE_BITS == INDEFINITE_LENGTH;
+
+  let num_items = match MajorType::from_u8(byte) {
+    MajorType::NoContentType if indefinite => {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected CBOR break"));
+    }
+    MajorType::UnsignedInteger | MajorType::NegativeInteger if indefinite => {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Indefinite length is not allowed for this CBOR major type",
+      ));
+    }
+    MajorType::UnsignedInteger | MajorType::NegativeInteger | MajorType::NoContentType => 0,
+    MajorType::Bytes | MajorType::Text if !indefinite => {
+      // Read what is there rather than reserving the length up front, since a corrupt length can
+      // be far larger than the data. Running out of data is a partial item, like a short head.
+      let payload_end = buf.len() as u64 + value;
+      b.take(value).read_to_end(buf)?;
+      if (buf.len() as u64) < payload_end {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+      }
+      return Ok(());
+    }
+    MajorType::Bytes | MajorType::Text | MajorType::Array | MajorType::Object if indefinite => {
+      // Copy items up to and including the break.
+      loop {
+        match take_byte(b)? {
+          BREAK => {
+            buf.push(BREAK);
+            return Ok(());
+          }
+          byte => read_cbor_raw_with_byte(b, byte, buf, depth + 1)?,
+        }
+      }
+    }
+    MajorType::Object => value * 2,
+    // A tag is followed by the one item it tags, whatever the number of the tag.
+    MajorType::SemanticTag => 1,
+    _ => value,
+  };
+
+  for _ in 0..num_items {
+    let byte = take_byte(b)?;
+    read_cbor_raw_with_byte(b, byte, buf, depth + 1)?;
+  }
+  Ok(())
 }
 
 /// Writes a CBOR head that identifies the bytes that follow.
@@ -404,6 +813,22 @@ impl CborWrite for u64 {
   }
 }
 
+/// `None` is written as null, which marks a deleted key, and `Some` is written as its value.
+impl<T: CborWrite> CborWrite for Option<T> {
+  fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    match self {
+      Some(value) => value.cbor_write(writer),
+      None => writer.write_all(&[CBOR_NULL]),
+    }
+  }
+}
+
+impl CborWrite for i128 {
+  fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_cbor_integer(writer, *self)
+  }
+}
+
 /// A convenience function for writing text, encoded as UTF8.
 ///
 /// # Example
@@ -458,16 +883,98 @@ pub fn write_cbor_unsigned_integer<W: Write>(writer: &mut W, value: u64) -> io::
   write_cbor_head(writer, MajorType::UnsignedInteger, value)
 }
 
+/// A convenience function for writing a signed integer, as an unsigned integer when it is
+/// positive and as a negative integer when it is not. Values outside of -2^64 to 2^64 - 1 can't
+/// be represented and return an error.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::write_cbor_integer;
+///
+/// let mut cursor = Cursor::new(Vec::new());
+/// write_cbor_integer(&mut cursor, -1000).unwrap();
+/// assert_eq!(cursor.into_inner(), vec![0x39, 0x03, 0xE7]);
+/// ```
+#[inline]
+pub fn write_cbor_integer<W: Write>(writer: &mut W, value: i128) -> io::Result<()> {
+  let out_of_range = |_| io::Error::new(io::ErrorKind::InvalidInput, "Integer is out of range for CBOR");
+  match value {
+    0.. => write_cbor_head(
+      writer,
+      MajorType::UnsignedInteger,
+      u64::try_from(value).map_err(out_of_range)?,
+    ),
+    _ => write_cbor_head(
+      writer,
+      MajorType::NegativeInteger,
+      u64::try_from(-1 - value).map_err(out_of_range)?,
+    ),
+  }
+}
+
+/// Get the value of an integer that has already been serialized to bytes, or None if the bytes
+/// are some other type of data item.
+pub(crate) fn cbor_integer_from_bytes(bytes: &[u8]) -> Option<i128> {
+  let (&byte, mut rest) = bytes.split_first()?;
+  match MajorType::from_u8(byte) {
+    MajorType::UnsignedInteger => Some(read_cbor_head_u64(&mut rest, byte).ok()?.into()),
+    MajorType::NegativeInteger => Some(-1 - i128::from(read_cbor_head_u64(&mut rest, byte).ok()?)),
+    _ => None,
+  }
+}
+
+/// Get the value of an integer or an epoch-based timestamp (tag 1) that has already been
+/// serialized to bytes, as whole and fractional parts, or None if the bytes are some other type
+/// of data item.
+fn cbor_number_from_bytes(bytes: &[u8]) -> Option<(i128, f64)> {
+  let epoch_time_head = MajorType::SemanticTag.as_u8() | EPOCH_TIME_TAG as u8;
+  match bytes.split_first()? {
+    (&byte, mut rest) if byte == epoch_time_head => match read_cbor_value(&mut rest).ok()? {
+      CborValue::Integer(value) => Some((value, 0.0)),
+      CborValue::Float(value) if value.is_finite() => Some((value.floor() as i128, value - value.floor())),
+      _ => None,
+    },
+    _ => cbor_integer_from_bytes(bytes).map(|value| (value, 0.0)),
+  }
+}
+
 /// A comparison function for CBOR data items that have already been serialized
-/// to bytes.
+/// to bytes. See `cbor_raw_cmp`.
 fn cbor_byte_cmp(a: &Cursor<Vec<u8>>, b: &Cursor<Vec<u8>>) -> std::cmp::Ordering {
-  let len_a = a.position() as usize;
-  let len_b = b.position() as usize;
-  if len_a != len_b {
-    return len_a.cmp(&len_b);
-  }
+  cbor_raw_cmp(
+    &a.get_ref()[0..a.position() as usize],
+    &b.get_ref()[0..b.position() as usize],
+  )
+}
 
-  a.get_ref()[0..len_a].cmp(&b.get_ref()[0..len_a])
+/// Compares two encoded CBOR data items in the order used by `cbor_sort`. Items are ordered by
+/// major type first, with integers of either sign together, so negative integers sort before
+/// positive ones. Integers and epoch-based timestamps (tag 1) are then compared by their value, with
+/// timestamps before other tags. Anything else, and equal values, are compared by length and then
+/// by bytes. This is a total order, so items of mixed types can be sorted and searched together.
+pub fn cbor_raw_cmp(a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+  let (a_number, b_number) = (cbor_number_from_bytes(a), cbor_number_from_bytes(b));
+  cbor_raw_class(a, a_number.is_some())
+    .cmp(&cbor_raw_class(b, b_number.is_some()))
+    .then_with(|| match (a_number, b_number) {
+      (Some(a), Some(b)) => a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)),
+      _ => std::cmp::Ordering::Equal,
+    })
+    .then_with(|| a.len().cmp(&b.len()))
+    .then_with(|| a.cmp(b))
+}
+
+/// The class of an encoded CBOR data item in the order of `cbor_raw_cmp`: its major type, with
+/// negative integers counted as unsigned ones, and whether it isn't a number.
+fn cbor_raw_class(bytes: &[u8], is_number: bool) -> (u8, bool) {
+  let major_type = match bytes.first().map(|byte| byte >> 5) {
+    Some(1) => 0,
+    Some(major_type) => major_type,
+    None => 0,
+  };
+  (major_type, !is_number)
 }
 
 /// A binary search function within an index. The keys are assumed to be sorted as per
@@ -819,6 +1326,57 @@ mod tests {
     assert::equal_bytes(&text, "hello");
   }
 
+  #[test]
+  fn read_cbor_raw_works() {
+    let mut cursor = io::Cursor::new([0x18, 0x64, 0x44, 0x01, 0x02, 0x03, 0x04, 0x60]);
+    let mut buf = Vec::new();
+    read_cbor_raw(&mut cursor, &mut buf).unwrap();
+    assert::equal_bytes(&buf, &[0x18, 0x64]);
+    buf.clear();
+    read_cbor_raw(&mut cursor, &mut buf).unwrap();
+    assert::equal_bytes(&buf, &[0x44, 0x01, 0x02, 0x03, 0x04]);
+    buf.clear();
+    read_cbor_raw(&mut cursor, &mut buf).unwrap();
+    assert::equal_bytes(&buf, &[0x60]);
+    assert::equal(
+      read_cbor_raw(&mut cursor, &mut buf).unwrap_err().kind(),
+      io::ErrorKind::UnexpectedEof,
+    );
+  }
+
+  #[test]
+  fn read_cbor_raw_copies_nested_items() {
+    // [1, {"a": -1}], tag 1 of 100, tag 55799 of 1, an indefinite byte string, and [_ "b", [_ ]].
+    let items: [&[u8]; 5] = [
+      &[0x82, 0x01, 0xA1, 0x61, 0x61, 0x20],
+      &[0xC1, 0x18, 0x64],
+      &[0xD9, 0xD9, 0xF7, 0x01],
+      &[0x5F, 0x41, 0x01, 0x42, 0x02, 0x03, 0xFF],
+      &[0x9F, 0x61, 0x62, 0x9F, 0xFF, 0xFF],
+    ];
+    let mut cursor = io::Cursor::new(items.concat());
+    for item in items {
+      let mut buf = Vec::new();
+      read_cbor_raw(&mut cursor, &mut buf).unwrap();
+      assert::equal_bytes(&buf, &item.to_vec());
+    }
+    assert::cursor_completely_consumed(&cursor);
+
+    for invalid in [&[0xFF][..], &[0x3F], &[0x1C], &[0x82, 0x01]] {
+      assert!(read_cbor_raw(&mut &invalid[..], &mut Vec::new()).is_err());
+    }
+  }
+
+  #[test]
+  fn read_cbor_raw_rejects_lengths_past_the_data() {
+    // A text string with a length of about 7.3e18 bytes, followed by two bytes.
+    let bytes = [0x7B, 0x65, 0x76, 0x61, 0x6C, 0x75, 0x65, 0x00, 0x01, 0x61, 0x62];
+    let mut buf = Vec::new();
+    let error = read_cbor_raw(&mut &bytes[..], &mut buf).unwrap_err();
+    assert::equal(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert::equal(buf.len(), bytes.len());
+  }
+
   #[test]
   fn read_cbor_head_u64_works_for_embedded() {
     let mut cursor = io::Cursor::new([0x00]);
@@ -1021,4 +1579,164 @@ mod tests {
     let indices: Vec<(Vec<u8>, u64)> = Vec::new();
     assert::equal(cbor_binary_search_first(&indices, &vec![1]), Err(0));
   }
+
+  #[test]
+  fn signed_integers_round_trip() {
+    let mut v = Vec::new();
+    for value in [0i64, 1, -1, 23, -24, -25, 1000, -1000, i64::MAX, i64::MIN] {
+      value.cbor_write(&mut v).unwrap();
+    }
+    (-(1i128 << 64)).cbor_write(&mut v).unwrap();
+    assert::equal_bytes(&v[0..7], &vec![0x00, 0x01, 0x20, 0x17, 0x37, 0x38, 0x18]);
+
+    let mut cursor = io::Cursor::new(v);
+    for value in [0i64, 1, -1, 23, -24, -25, 1000, -1000, i64::MAX, i64::MIN] {
+      assert::equal(CborRead::<i64>::cbor_read(&mut cursor).unwrap(), value);
+    }
+    assert!(CborRead::<i64>::cbor_read(&mut cursor.clone()).is_err());
+    assert::equal(CborRead::<i128>::cbor_read(&mut cursor).unwrap(), -(1i128 << 64));
+  }
+
+  #[test]
+  fn signed_integers_reject_invalid_data() {
+    assert!(CborRead::<i64>::cbor_read(&mut io::Cursor::new([0x61, b'a'])).is_err());
+    assert!(CborRead::<u8>::cbor_read(&mut io::Cursor::new([0x19, 0x01, 0x00])).is_err());
+    assert!(CborRead::<u32>::cbor_read(&mut io::Cursor::new([0x20])).is_err());
+    assert!(i128::MAX.cbor_write(&mut Vec::new()).is_err());
+  }
+
+  #[test]
+  fn cbor_sort_works_on_signed_integers() {
+    let mut indices = [
+      (-1i64, 1),
+      (1000, 1),
+      (-1000, 1),
+      (0, 1),
+      (23, 1),
+      (-24, 1),
+      (-25, 1),
+      (-1, 0),
+    ];
+    cbor_sort(&mut indices);
+    assert::equal(
+      indices,
+      [
+        (-1000, 1),
+        (-25, 1),
+        (-24, 1),
+        (-1, 0),
+        (-1, 1),
+        (0, 1),
+        (23, 1),
+        (1000, 1),
+      ],
+    );
+    assert::equal(is_cbor_sorted(&indices), true);
+  }
+
+  #[test]
+  fn cbor_binary_search_first_works_on_signed_integers() {
+    let indices = [
+      (i64::MIN, 0),
+      (-1000, 1),
+      (-1, 2),
+      (-1, 3),
+      (0, 4),
+      (1000, 5),
+      (i64::MAX, 6),
+    ];
+    assert::equal(cbor_binary_search_first(&indices, &i64::MIN), Ok(0));
+    assert::equal(cbor_binary_search_first(&indices, &-1i64), Ok(2));
+    assert::equal(cbor_binary_search_first(&indices, &1000i64), Ok(5));
+    assert::equal(cbor_binary_search_first(&indices, &-2i64), Err(2));
+    assert::equal(cbor_binary_search_first(&indices, &1i64), Err(5));
+  }
+
+  #[test]
+  fn cbor_sort_works_on_timestamps() {
+    let mut indices = [
+      (Timestamp::from_secs(1), 0),
+      (Timestamp::new(0, 500_000), 1),
+      (Timestamp::from_secs(1000), 2),
+      (Timestamp::from_millis(-1), 3),
+      (Timestamp::from_secs(-1000), 4),
+      (Timestamp::from_secs(0), 5),
+    ];
+    cbor_sort(&mut indices);
+    assert::equal(indices.map(|(_, offset)| offset), [4, 3, 5, 1, 0, 2]);
+    assert::equal(cbor_binary_search_first(&indices, &Timestamp::new(0, 500_000)), Ok(3));
+    assert::equal(cbor_binary_search_first(&indices, &Timestamp::from_secs(2)), Err(5));
+  }
+
+  #[test]
+  fn cbor_raw_cmp_is_a_total_order_across_types() {
+    let mut items = Vec::new();
+    for value in [
+      CborValue::from(1000),
+      CborValue::from(-1),
+      CborValue::from(1),
+      CborValue::from(""),
+      CborValue::from("a"),
+      CborValue::from(b"ab".as_slice()),
+      CborValue::Tag(1, Box::new(CborValue::from(1000))),
+      CborValue::Tag(1, Box::new(CborValue::Float(0.5))),
+      CborValue::Tag(2, Box::new(CborValue::from(b"a".as_slice()))),
+      CborValue::from(vec![CborValue::from(1)]),
+      CborValue::from(1.5),
+      CborValue::Bool(true),
+    ] {
+      let mut bytes = Vec::new();
+      value.cbor_write(&mut bytes).unwrap();
+      items.push(bytes);
+    }
+
+    for a in &items {
+      for b in &items {
+        assert::equal(cbor_raw_cmp(a, b), cbor_raw_cmp(b, a).reverse());
+        for c in &items {
+          if cbor_raw_cmp(a, b).is_le() && cbor_raw_cmp(b, c).is_le() {
+            assert!(cbor_raw_cmp(a, c).is_le());
+          }
+        }
+      }
+    }
+
+    items.sort_by(|a, b| cbor_raw_cmp(a, b));
+    let first_bytes = items.iter().map(|item| item[0]).collect::<Vec<_>>();
+    assert::equal(
+      first_bytes,
+      vec![0x20, 0x01, 0x19, 0x42, 0x60, 0x61, 0x81, 0xC1, 0xC1, 0xC2, 0xF5, 0xFA],
+    );
+    // The timestamp of 0.5 seconds is before the one of 1000.
+    assert::equal(items[8][1], 0x19);
+  }
+
+  #[test]
+  fn cbor_borrow_points_into_the_input() {
+    let mut data = Vec::new();
+    write_cbor_text(&mut data, "key").unwrap();
+    write_cbor_bytes(&mut data, &[0; 300]).unwrap();
+    data.push(CBOR_NULL);
+    write_cbor_integer(&mut data, -5).unwrap();
+
+    let mut bytes = data.as_slice();
+    let text = <&str>::cbor_borrow(&mut bytes).unwrap();
+    assert::equal(text, "key");
+    assert::equal(text.as_ptr(), data[1..].as_ptr());
+    assert::equal(<&[u8]>::cbor_borrow(&mut bytes).unwrap().len(), 300);
+    assert::equal(Option::<&str>::cbor_borrow(&mut bytes).unwrap(), None);
+    assert::equal(Option::<i64>::cbor_borrow(&mut bytes).unwrap(), Some(-5));
+    assert::equal(bytes.len(), 0);
+  }
+
+  #[test]
+  fn cbor_borrow_rejects_bad_strings() {
+    // Text where bytes are expected, an indefinite-length string, and a truncated string.
+    for data in [&[0x61, b'a'][..], &[0x5F, 0x41, b'a', 0xFF], &[0x43, 1, 2]] {
+      assert!(<&[u8]>::cbor_borrow(&mut &data[..]).is_err());
+    }
+    let error = <&[u8]>::cbor_borrow(&mut &[0x43, 1, 2][..]).unwrap_err();
+    assert::equal(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(<&str>::cbor_borrow(&mut &[0x61, 0xFF][..]).is_err());
+  }
 }
@@ -0,0 +1,792 @@
+//! Serde support for the CBOR encoding, enabled with the `serde` feature.
+//!
+//! Types that implement `Serialize` and `Deserialize` are written with the same encoding as the
+//! rest of this crate, so they can be read by any CBOR implementation. Structs and maps are
+//! written as CBOR maps, sequences and tuples as arrays, `None` and `()` as `null`, and enums are
+//! externally tagged: a unit variant is its name as text, and any other variant is a map of its
+//! name to its content.
+//!
+//! To use a type as a key or value of an SSTable, implement the `CborSerde` marker trait for it.
+//!
+//! # Example
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use sstables::cbor::serde::CborSerde;
+//! use sstables::{SSTableReader, SSTableWriterBuilder};
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Event {
+//!   name: String,
+//!   delta: i64,
+//! }
+//!
+//! impl CborSerde for Event {}
+//!
+//! let event = Event { name: "login".to_string(), delta: -1 };
+//! let mut writer = SSTableWriterBuilder::new("memory")
+//!   .build_with_writers(Vec::new(), Vec::new())
+//!   .unwrap();
+//! writer.write(("a", &event)).unwrap();
+//!
+//! let (data, _) = writer.into_writers().unwrap();
+//! let mut reader = SSTableReader::<(String, Event), _>::new(data.as_slice());
+//! assert_eq!(reader.next().unwrap().unwrap(), ("a".to_string(), event));
+//! ```
+
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::vec;
+
+use ::serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use ::serde::ser::{self, Serialize};
+use ::serde::{forward_to_deserialize_any, Deserializer as _};
+
+use super::value::{write_cbor_float, BREAK};
+use super::{
+  read_cbor_value, write_cbor_bytes, write_cbor_head, write_cbor_integer, write_cbor_text, CborRead, CborValue,
+  CborWrite, MajorType,
+};
+
+/// The initial byte of an indefinite-length array.
+const INDEFINITE_ARRAY: u8 = 0x9F;
+
+/// The initial byte of an indefinite-length map.
+const INDEFINITE_MAP: u8 = 0xBF;
+
+/// A marker for types that are read and written with serde. Implement it for any type that
+/// implements `Serialize` and `DeserializeOwned` to use it as a key or value of an SSTable.
+pub trait CborSerde {}
+
+impl<T: CborSerde + ?Sized> CborSerde for &T {}
+
+impl<T: Serialize + CborSerde + ?Sized> CborWrite for T {
+  fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    to_writer(writer, self)
+  }
+}
+
+impl<R: Read + ?Sized, T: DeserializeOwned + CborSerde> CborRead<T> for R {
+  fn cbor_read(&mut self) -> io::Result<T> {
+    from_reader(self)
+  }
+}
+
+/// The error of the serializer and deserializer. It converts to and from `io::Error`, which is
+/// what the public functions of this module return.
+#[derive(Debug)]
+pub struct Error(io::Error);
+
+impl Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Self {
+    Error(e)
+  }
+}
+
+impl From<Error> for io::Error {
+  fn from(e: Error) -> Self {
+    e.0
+  }
+}
+
+impl ser::Error for Error {
+  fn custom<T: Display>(msg: T) -> Self {
+    Error(io::Error::new(io::ErrorKind::InvalidInput, msg.to_string()))
+  }
+}
+
+impl de::Error for Error {
+  fn custom<T: Display>(msg: T) -> Self {
+    Error(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))
+  }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Writes a value as CBOR.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::serde::to_writer;
+///
+/// let mut bytes = Vec::new();
+/// to_writer(&mut bytes, &(1, "a", Some(-1))).unwrap();
+/// assert_eq!(bytes, [0x83, 0x01, 0x61, b'a', 0x20]);
+/// ```
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(writer: W, value: &T) -> io::Result<()> {
+  Ok(value.serialize(&mut Serializer { writer })?)
+}
+
+/// Writes a value as CBOR to a new `Vec<u8>`.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> io::Result<Vec<u8>> {
+  let mut bytes = Vec::new();
+  to_writer(&mut bytes, value)?;
+  Ok(bytes)
+}
+
+/// Reads a value from CBOR. Exactly one data item is read, so the reader can be a sequence.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::serde::from_reader;
+///
+/// let bytes = [0x83, 0x01, 0x61, b'a', 0xF6];
+/// let value: (u8, String, Option<i32>) = from_reader(&bytes[..]).unwrap();
+/// assert_eq!(value, (1, "a".to_string(), None));
+/// ```
+pub fn from_reader<R: Read, T: DeserializeOwned>(mut reader: R) -> io::Result<T> {
+  from_value(read_cbor_value(&mut reader)?)
+}
+
+/// Reads a value from the first CBOR data item of a slice.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+  from_reader(bytes)
+}
+
+/// Converts a `CborValue` to any type that implements `Deserialize`.
+pub fn from_value<T: DeserializeOwned>(value: CborValue) -> io::Result<T> {
+  Ok(T::deserialize(value)?)
+}
+
+/// A serializer that writes CBOR to any writer.
+pub struct Serializer<W> {
+  writer: W,
+}
+
+impl<W: Write> Serializer<W> {
+  pub fn new(writer: W) -> Self {
+    Serializer { writer }
+  }
+
+  /// Returns the underlying writer.
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+
+  fn write_head(&mut self, major_type: MajorType, value: u64) -> Result<()> {
+    Ok(write_cbor_head(&mut self.writer, major_type, value)?)
+  }
+
+  fn write_byte(&mut self, byte: u8) -> Result<()> {
+    Ok(self.writer.write_all(&[byte])?)
+  }
+
+  /// Starts an array or map, using an indefinite length if the length is unknown.
+  fn begin(&mut self, major_type: MajorType, len: Option<usize>) -> Result<Compound<'_, W>> {
+    match len {
+      Some(len) => self.write_head(major_type, len as u64)?,
+      None if major_type == MajorType::Array => self.write_byte(INDEFINITE_ARRAY)?,
+      None => self.write_byte(INDEFINITE_MAP)?,
+    }
+
+    Ok(Compound {
+      ser: self,
+      indefinite: len.is_none(),
+    })
+  }
+
+  /// Starts an enum variant with content, which is a map of the variant name to its content.
+  fn begin_variant(&mut self, variant: &str) -> Result<()> {
+    self.write_head(MajorType::Object, 1)?;
+    Ok(write_cbor_text(&mut self.writer, variant)?)
+  }
+}
+
+/// An out of range integer error.
+fn out_of_range() -> Error {
+  Error(io::Error::new(
+    io::ErrorKind::InvalidInput,
+    "Integer is out of range for CBOR",
+  ))
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+  type Ok = ();
+  type Error = Error;
+  type SerializeSeq = Compound<'a, W>;
+  type SerializeTuple = Compound<'a, W>;
+  type SerializeTupleStruct = Compound<'a, W>;
+  type SerializeTupleVariant = Compound<'a, W>;
+  type SerializeMap = Compound<'a, W>;
+  type SerializeStruct = Compound<'a, W>;
+  type SerializeStructVariant = Compound<'a, W>;
+
+  fn serialize_bool(self, v: bool) -> Result<()> {
+    self.write_byte(if v { 0xF5 } else { 0xF4 })
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<()> {
+    self.serialize_i64(v.into())
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<()> {
+    self.serialize_i64(v.into())
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<()> {
+    self.serialize_i64(v.into())
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<()> {
+    self.serialize_i128(v.into())
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<()> {
+    Ok(write_cbor_integer(&mut self.writer, v)?)
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<()> {
+    self.serialize_u64(v.into())
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<()> {
+    self.serialize_u64(v.into())
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<()> {
+    self.serialize_u64(v.into())
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<()> {
+    self.write_head(MajorType::UnsignedInteger, v)
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<()> {
+    self.serialize_u64(u64::try_from(v).map_err(|_| out_of_range())?)
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<()> {
+    self.serialize_f64(v.into())
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<()> {
+    Ok(write_cbor_float(&mut self.writer, v)?)
+  }
+
+  fn serialize_char(self, v: char) -> Result<()> {
+    self.serialize_str(v.encode_utf8(&mut [0; 4]))
+  }
+
+  fn serialize_str(self, v: &str) -> Result<()> {
+    Ok(write_cbor_text(&mut self.writer, v)?)
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+    Ok(write_cbor_bytes(&mut self.writer, v)?)
+  }
+
+  fn serialize_none(self) -> Result<()> {
+    self.serialize_unit()
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> Result<()> {
+    self.write_byte(0xF6)
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+    self.serialize_unit()
+  }
+
+  fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<()> {
+    self.serialize_str(variant)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    _index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<()> {
+    self.begin_variant(variant)?;
+    value.serialize(self)
+  }
+
+  fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+    self.begin(MajorType::Array, len)
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W>> {
+    self.begin(MajorType::Array, Some(len))
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+    self.begin(MajorType::Array, Some(len))
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Compound<'a, W>> {
+    self.begin_variant(variant)?;
+    self.begin(MajorType::Array, Some(len))
+  }
+
+  fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+    self.begin(MajorType::Object, len)
+  }
+
+  fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+    self.begin(MajorType::Object, Some(len))
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<Compound<'a, W>> {
+    self.begin_variant(variant)?;
+    self.begin(MajorType::Object, Some(len))
+  }
+}
+
+/// Serializes the items of an array or map, ending with a break if the length was unknown.
+pub struct Compound<'a, W> {
+  ser: &'a mut Serializer<W>,
+  indefinite: bool,
+}
+
+impl<W: Write> Compound<'_, W> {
+  fn end(self) -> Result<()> {
+    if self.indefinite {
+      self.ser.write_byte(BREAK)?;
+    }
+    Ok(())
+  }
+}
+
+impl<W: Write> ser::SerializeSeq for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeTuple for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeTupleStruct for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeTupleVariant for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeMap for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+    key.serialize(&mut *self.ser)
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeStruct for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+    write_cbor_text(&mut self.ser.writer, key)?;
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+impl<W: Write> ser::SerializeStructVariant for Compound<'_, W> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+    write_cbor_text(&mut self.ser.writer, key)?;
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<()> {
+    Compound::end(self)
+  }
+}
+
+/// Deserializes any type from a decoded value. Tags are ignored, so a tagged value deserializes
+/// as its content.
+impl<'de> de::Deserializer<'de> for CborValue {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    match self {
+      CborValue::Integer(value) => match (u64::try_from(value), i64::try_from(value)) {
+        (Ok(value), _) => visitor.visit_u64(value),
+        (_, Ok(value)) => visitor.visit_i64(value),
+        _ => visitor.visit_i128(value),
+      },
+      CborValue::Bytes(bytes) => visitor.visit_byte_buf(bytes),
+      CborValue::Text(text) => visitor.visit_string(text),
+      CborValue::Array(items) => visitor.visit_seq(SeqDeserializer {
+        items: items.into_iter(),
+      }),
+      CborValue::Map(pairs) => visitor.visit_map(MapDeserializer {
+        pairs: pairs.into_iter(),
+        value: None,
+      }),
+      CborValue::Tag(_, value) => value.deserialize_any(visitor),
+      CborValue::Float(value) => visitor.visit_f64(value),
+      CborValue::Bool(value) => visitor.visit_bool(value),
+      CborValue::Null | CborValue::Undefined => visitor.visit_unit(),
+      CborValue::Simple(value) => visitor.visit_u8(value),
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    match self {
+      CborValue::Null | CborValue::Undefined => visitor.visit_none(),
+      value => visitor.visit_some(value),
+    }
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value> {
+    match self {
+      CborValue::Text(variant) => visitor.visit_enum(variant.into_deserializer()),
+      CborValue::Map(pairs) if pairs.len() == 1 => {
+        let (variant, value) = pairs.into_iter().next().unwrap_or((CborValue::Null, CborValue::Null));
+        visitor.visit_enum(EnumDeserializer { variant, value })
+      }
+      CborValue::Tag(_, value) => value.deserialize_enum(_name, _variants, visitor),
+      _ => Err(de::Error::custom("Expected an enum as text or a map with one entry")),
+    }
+  }
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+  }
+}
+
+impl IntoDeserializer<'_, Error> for CborValue {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self {
+    self
+  }
+}
+
+/// Deserializes the items of an array.
+struct SeqDeserializer {
+  items: vec::IntoIter<CborValue>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+    self.items.next().map(|item| seed.deserialize(item)).transpose()
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.items.len())
+  }
+}
+
+/// Deserializes the pairs of a map.
+struct MapDeserializer {
+  pairs: vec::IntoIter<(CborValue, CborValue)>,
+  value: Option<CborValue>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+  type Error = Error;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+    match self.pairs.next() {
+      Some((key, value)) => {
+        self.value = Some(value);
+        seed.deserialize(key).map(Some)
+      }
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+    match self.value.take() {
+      Some(value) => seed.deserialize(value),
+      None => Err(de::Error::custom("Map value requested before its key")),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.pairs.len())
+  }
+}
+
+/// Deserializes an enum variant with content, written as a map of its name to its content.
+struct EnumDeserializer {
+  variant: CborValue,
+  value: CborValue,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+  type Error = Error;
+  type Variant = CborValue;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, CborValue)> {
+    Ok((seed.deserialize(self.variant)?, self.value))
+  }
+}
+
+/// The content of an enum variant.
+impl<'de> de::VariantAccess<'de> for CborValue {
+  type Error = Error;
+
+  fn unit_variant(self) -> Result<()> {
+    match self {
+      CborValue::Null => Ok(()),
+      _ => Err(de::Error::custom("Expected null for a unit variant")),
+    }
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+    seed.deserialize(self)
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+    self.deserialize_any(visitor)
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+    self.deserialize_any(visitor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use common_testing::assert;
+  use serde::{Deserialize, Serialize};
+  use std::collections::BTreeMap;
+
+  use super::*;
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  enum Shape {
+    Empty,
+    Circle(f64),
+    Point(i32, i32),
+    Rectangle { width: u32, height: u32 },
+  }
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct Document {
+    id: u64,
+    delta: i64,
+    name: String,
+    tags: Vec<String>,
+    shapes: Vec<Shape>,
+    parent: Option<Box<Document>>,
+    attributes: BTreeMap<String, f32>,
+    flag: bool,
+  }
+
+  impl CborSerde for Document {}
+
+  /// Serializes as a byte string instead of an array of integers.
+  struct Bytes<'a>(&'a [u8]);
+
+  impl Serialize for Bytes<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+      serializer.serialize_bytes(self.0)
+    }
+  }
+
+  fn create_document() -> Document {
+    Document {
+      id: 7,
+      delta: -300,
+      name: "root".to_string(),
+      tags: vec!["a".to_string(), "b".to_string()],
+      shapes: vec![
+        Shape::Empty,
+        Shape::Circle(1.5),
+        Shape::Point(-1, 2),
+        Shape::Rectangle { width: 3, height: 4 },
+      ],
+      parent: Some(Box::new(Document {
+        id: 1,
+        delta: 0,
+        name: "parent".to_string(),
+        tags: vec![],
+        shapes: vec![],
+        parent: None,
+        attributes: BTreeMap::new(),
+        flag: false,
+      })),
+      attributes: BTreeMap::from([("x".to_string(), 0.25)]),
+      flag: true,
+    }
+  }
+
+  #[test]
+  fn round_trips_documents() {
+    let document = create_document();
+    let bytes = to_vec(&document).unwrap();
+    assert::equal(from_slice::<Document>(&bytes).unwrap(), document);
+  }
+
+  #[test]
+  fn writes_standard_cbor() {
+    let bytes = to_vec(&create_document()).unwrap();
+    let value = read_cbor_value(&mut bytes.as_slice()).unwrap();
+    let CborValue::Map(pairs) = value else {
+      panic!("expected a map");
+    };
+
+    assert::equal(&pairs[0], &(CborValue::from("id"), CborValue::from(7)));
+    assert::equal(&pairs[1], &(CborValue::from("delta"), CborValue::from(-300)));
+    assert::equal(
+      &pairs[4].1,
+      &CborValue::Array(vec![
+        "Empty".into(),
+        CborValue::Map(vec![("Circle".into(), 1.5.into())]),
+        CborValue::Map(vec![("Point".into(), CborValue::Array(vec![(-1).into(), 2.into()]))]),
+        CborValue::Map(vec![(
+          "Rectangle".into(),
+          CborValue::Map(vec![("width".into(), 3.into()), ("height".into(), 4.into())]),
+        )]),
+      ]),
+    );
+  }
+
+  #[test]
+  fn writes_indefinite_length_for_unknown_lengths() {
+    struct Unsized;
+
+    impl Serialize for Unsized {
+      fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use ser::SerializeSeq;
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&1)?;
+        seq.end()
+      }
+    }
+
+    assert::equal_bytes(&to_vec(&Unsized).unwrap(), &vec![0x9F, 0x01, 0xFF]);
+    assert::equal(from_slice::<Vec<u8>>(&[0x9F, 0x01, 0xFF]).unwrap(), vec![1]);
+  }
+
+  #[test]
+  fn writes_byte_strings() {
+    assert::equal_bytes(&to_vec(&Bytes(&[0, 255])).unwrap(), &vec![0x42, 0x00, 0xFF]);
+    assert::equal(
+      from_value::<Vec<u8>>(CborValue::Array(vec![0.into(), 255.into()])).unwrap(),
+      vec![0, 255],
+    );
+  }
+
+  #[test]
+  fn integers_use_shortest_form() {
+    assert::equal_bytes(&to_vec(&-1i8).unwrap(), &vec![0x20]);
+    assert::equal_bytes(&to_vec(&1000u16).unwrap(), &vec![0x19, 0x03, 0xE8]);
+    assert::equal_bytes(
+      &to_vec(&i64::MIN).unwrap(),
+      &vec![0x3B, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    );
+    assert::equal(from_slice::<i64>(&to_vec(&i64::MIN).unwrap()).unwrap(), i64::MIN);
+    assert::equal(
+      from_slice::<i128>(&[0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+      -(1i128 << 64),
+    );
+    assert!(to_vec(&u128::MAX).is_err());
+    assert!(from_slice::<u8>(&[0x19, 0x03, 0xE8]).is_err());
+  }
+
+  #[test]
+  fn sstable_round_trips_documents() {
+    let document = create_document();
+    let mut writer = crate::SSTableWriterBuilder::new("memory")
+      .build_with_writers(Vec::new(), Vec::new())
+      .unwrap();
+    writer.write(("a", &document)).unwrap();
+    writer.write(("b", document)).unwrap();
+    let (data, _) = writer.into_writers().unwrap();
+
+    let reader = crate::SSTableReader::<(String, Document), _>::new(data.as_slice());
+    let entries = reader.collect::<io::Result<Vec<_>>>().unwrap();
+    assert::equal(entries.len(), 2);
+    assert::equal(&entries[0].1, &create_document());
+    assert::equal(&entries[1].1, &create_document());
+  }
+}
@@ -0,0 +1,479 @@
+//! Timestamps.
+//!
+//! A `Timestamp` is a point in time with microsecond precision, which makes it a natural key for
+//! event logs. It is written as an epoch-based date/time (tag 1, see RFC 8949 Section 3.4.2): an
+//! integer number of seconds when there is no fractional part, and a float otherwise. Doubles keep
+//! microsecond precision for every date between the years 1697 and 2242.
+//!
+//! Standard date/time strings (tag 0, see RFC 8949 Section 3.4.1) can also be read, and can be
+//! written with `write_cbor_rfc3339`. The index only orders epoch-based timestamps by time, so
+//! prefer tag 1 for keys.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::cbor::{CborRead, CborWrite, Timestamp};
+//!
+//! let timestamp: Timestamp = "2013-03-21T20:04:00.5Z".parse().unwrap();
+//! assert_eq!(timestamp, Timestamp::new(1363896240, 500_000));
+//!
+//! let mut bytes = Vec::new();
+//! timestamp.cbor_write(&mut bytes).unwrap();
+//! assert_eq!(bytes, [0xC1, 0xFB, 0x41, 0xD4, 0x52, 0xD9, 0xEC, 0x20, 0x00, 0x00]);
+//!
+//! let read: Timestamp = bytes.as_slice().cbor_read().unwrap();
+//! assert_eq!(read.to_string(), "2013-03-21T20:04:00.5Z");
+//! ```
+
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::value::write_cbor_float;
+use super::{
+  read_cbor_value, write_cbor_head, write_cbor_integer, write_cbor_text, CborRead, CborValue, CborWrite, MajorType,
+};
+
+/// The tag of a standard date/time string.
+pub const DATE_TIME_TAG: u64 = 0;
+
+/// The tag of an epoch-based date/time.
+pub const EPOCH_TIME_TAG: u64 = 1;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A point in time, as seconds since the Unix epoch plus a fraction of a second in microseconds.
+/// Ordered by time, so timestamps before 1970 are negative and sort first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+  seconds: i64,
+  micros: u32,
+}
+
+impl Timestamp {
+  /// Creates a timestamp from seconds since the epoch and microseconds past that second. Any
+  /// microseconds beyond a full second are carried into the seconds.
+  pub fn new(seconds: i64, micros: u32) -> Self {
+    let carry = i64::from(micros) / MICROS_PER_SECOND;
+    Timestamp {
+      seconds: seconds + carry,
+      micros: (i64::from(micros) - carry * MICROS_PER_SECOND) as u32,
+    }
+  }
+
+  /// Creates a timestamp from whole seconds since the epoch.
+  pub fn from_secs(seconds: i64) -> Self {
+    Timestamp { seconds, micros: 0 }
+  }
+
+  /// Creates a timestamp from milliseconds since the epoch.
+  pub fn from_millis(millis: i64) -> Self {
+    Timestamp {
+      seconds: millis.div_euclid(1000),
+      micros: (millis.rem_euclid(1000) * 1000) as u32,
+    }
+  }
+
+  /// Creates a timestamp from microseconds since the epoch, or None if the seconds don't fit in an
+  /// `i64`.
+  pub fn from_micros(micros: i128) -> Option<Self> {
+    let micros_per_second = i128::from(MICROS_PER_SECOND);
+    Some(Timestamp {
+      seconds: i64::try_from(micros.div_euclid(micros_per_second)).ok()?,
+      micros: micros.rem_euclid(micros_per_second) as u32,
+    })
+  }
+
+  /// The current time.
+  pub fn now() -> Self {
+    SystemTime::now().into()
+  }
+
+  /// Seconds since the epoch, rounded down.
+  pub fn seconds(&self) -> i64 {
+    self.seconds
+  }
+
+  /// Microseconds past the second, from 0 to 999,999.
+  pub fn subsec_micros(&self) -> u32 {
+    self.micros
+  }
+
+  /// Microseconds since the epoch.
+  pub fn as_micros(&self) -> i128 {
+    i128::from(self.seconds) * i128::from(MICROS_PER_SECOND) + i128::from(self.micros)
+  }
+
+  /// Creates a timestamp from fractional seconds since the epoch, rounded to the nearest
+  /// microsecond, or None if it is not finite or out of range.
+  fn from_f64(value: f64) -> Option<Self> {
+    if !value.is_finite() || value.abs() >= i64::MAX as f64 {
+      return None;
+    }
+
+    // Round the fraction on its own, since the whole value scaled to microseconds can lose
+    // precision.
+    let seconds = value.floor();
+    let fraction = ((value - seconds) * MICROS_PER_SECOND as f64).round() as i128;
+    Self::from_micros(seconds as i128 * i128::from(MICROS_PER_SECOND) + fraction)
+  }
+
+  /// Fractional seconds since the epoch.
+  fn as_f64(&self) -> f64 {
+    self.seconds as f64 + f64::from(self.micros) / MICROS_PER_SECOND as f64
+  }
+}
+
+impl From<SystemTime> for Timestamp {
+  fn from(time: SystemTime) -> Self {
+    match time.duration_since(UNIX_EPOCH) {
+      Ok(duration) => Self::from_micros(duration.as_micros() as i128),
+      Err(e) => Self::from_micros(-(e.duration().as_micros() as i128)),
+    }
+    .unwrap_or_default()
+  }
+}
+
+impl From<Timestamp> for SystemTime {
+  fn from(timestamp: Timestamp) -> Self {
+    let micros = Duration::from_micros(u64::from(timestamp.micros));
+    match u64::try_from(timestamp.seconds) {
+      Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds) + micros,
+      Err(_) => UNIX_EPOCH - Duration::from_secs(timestamp.seconds.unsigned_abs()) + micros,
+    }
+  }
+}
+
+/// Converts a day count since the epoch to a year, month and day in the proleptic Gregorian
+/// calendar. See http://howardhinnant.github.io/date_algorithms.html.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let shifted_month = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+  let month = if shifted_month < 10 {
+    shifted_month + 3
+  } else {
+    shifted_month - 9
+  };
+  let year = year_of_era + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
+
+/// Converts a year, month and day in the proleptic Gregorian calendar to a day count since the
+/// epoch. The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = year - i64::from(month <= 2);
+  let era = year.div_euclid(400);
+  let year_of_era = year.rem_euclid(400);
+  let shifted_month = (month + 9) % 12;
+  let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146_097 + day_of_era - 719_468
+}
+
+/// Formats as an RFC 3339 date/time in UTC, with as many fractional digits as needed.
+impl Display for Timestamp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (year, month, day) = civil_from_days(self.seconds.div_euclid(SECONDS_PER_DAY));
+    let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+    write!(
+      f,
+      "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+      year,
+      month,
+      day,
+      time / 3600,
+      time / 60 % 60,
+      time % 60
+    )?;
+
+    if self.micros > 0 {
+      let fraction = format!("{:06}", self.micros);
+      write!(f, ".{}", fraction.trim_end_matches('0'))?;
+    }
+
+    write!(f, "Z")
+  }
+}
+
+fn invalid_date_time(text: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("Invalid RFC 3339 date/time: {}", text),
+  )
+}
+
+/// Parses the digits of a fixed-width field.
+fn parse_digits(text: &str, bytes: &[u8]) -> io::Result<i64> {
+  if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+    return Err(invalid_date_time(text));
+  }
+  Ok(bytes.iter().fold(0, |acc, b| acc * 10 + i64::from(b - b'0')))
+}
+
+/// Parses an RFC 3339 date/time, such as `2013-03-21T20:04:00Z` or
+/// `2013-03-21T13:04:00.25-07:00`. Digits past microseconds are truncated.
+impl FromStr for Timestamp {
+  type Err = io::Error;
+
+  fn from_str(text: &str) -> io::Result<Self> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+      return Err(invalid_date_time(text));
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') {
+      return Err(invalid_date_time(text));
+    }
+
+    let year = parse_digits(text, &bytes[0..4])?;
+    let month = parse_digits(text, &bytes[5..7])?;
+    let day = parse_digits(text, &bytes[8..10])?;
+    let hour = parse_digits(text, &bytes[11..13])?;
+    let minute = parse_digits(text, &bytes[14..16])?;
+    let second = parse_digits(text, &bytes[17..19])?;
+    if !(1..=12).contains(&month) || hour > 23 || minute > 59 || second > 59 {
+      return Err(invalid_date_time(text));
+    }
+
+    let days = days_from_civil(year, month, day);
+    if day < 1 || civil_from_days(days) != (year, month, day) {
+      return Err(invalid_date_time(text));
+    }
+
+    let mut rest = &bytes[19..];
+    let mut micros = 0;
+    if let Some(fraction) = rest.strip_prefix(b".") {
+      let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+      let value = parse_digits(text, &fraction[..digits.min(6)])?;
+      micros = value * 10i64.pow(6 - digits.min(6) as u32);
+      rest = &fraction[digits..];
+    }
+
+    let offset = match rest {
+      [b'Z' | b'z'] => 0,
+      [sign @ (b'+' | b'-'), hours @ .., b':', _, _] if hours.len() == 2 => {
+        let hours = parse_digits(text, hours)?;
+        let minutes = parse_digits(text, &rest[4..6])?;
+        if hours > 23 || minutes > 59 {
+          return Err(invalid_date_time(text));
+        }
+        let offset = hours * 3600 + minutes * 60;
+        if *sign == b'-' {
+          -offset
+        } else {
+          offset
+        }
+      }
+      _ => return Err(invalid_date_time(text)),
+    };
+
+    let seconds = days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset;
+    Ok(Timestamp::new(seconds, micros as u32))
+  }
+}
+
+impl TryFrom<CborValue> for Timestamp {
+  type Error = io::Error;
+
+  /// Converts a tag 1 epoch-based date/time or a tag 0 date/time string.
+  fn try_from(value: CborValue) -> io::Result<Self> {
+    let out_of_range = || io::Error::new(io::ErrorKind::InvalidData, "Timestamp is out of range");
+    match value {
+      CborValue::Tag(EPOCH_TIME_TAG, value) => match *value {
+        CborValue::Integer(seconds) => Ok(Timestamp::from_secs(
+          i64::try_from(seconds).map_err(|_| out_of_range())?,
+        )),
+        CborValue::Float(seconds) => Timestamp::from_f64(seconds).ok_or_else(out_of_range),
+        _ => Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "Expected a number for an epoch-based date/time",
+        )),
+      },
+      CborValue::Tag(DATE_TIME_TAG, value) => match *value {
+        CborValue::Text(text) => text.parse(),
+        _ => Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "Expected text for a date/time string",
+        )),
+      },
+      _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a timestamp")),
+    }
+  }
+}
+
+impl<R: Read + ?Sized> CborRead<Timestamp> for R {
+  fn cbor_read(&mut self) -> io::Result<Timestamp> {
+    read_cbor_value(self)?.try_into()
+  }
+}
+
+/// Writes an epoch-based date/time, as an integer if there is no fractional part.
+impl CborWrite for Timestamp {
+  fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_cbor_head(writer, MajorType::SemanticTag, EPOCH_TIME_TAG)?;
+    if self.micros == 0 {
+      write_cbor_integer(writer, self.seconds.into())
+    } else {
+      write_cbor_float(writer, self.as_f64())
+    }
+  }
+}
+
+/// Writes a timestamp as a standard date/time string (tag 0) in UTC.
+///
+/// # Example
+///
+/// ```
+/// use sstables::cbor::{write_cbor_rfc3339, Timestamp};
+///
+/// let mut bytes = Vec::new();
+/// write_cbor_rfc3339(&mut bytes, &Timestamp::from_secs(0)).unwrap();
+/// assert_eq!(bytes[0..2], [0xC0, 0x74]);
+/// assert_eq!(&bytes[2..], b"1970-01-01T00:00:00Z");
+/// ```
+pub fn write_cbor_rfc3339<W: Write>(writer: &mut W, timestamp: &Timestamp) -> io::Result<()> {
+  write_cbor_head(writer, MajorType::SemanticTag, DATE_TIME_TAG)?;
+  write_cbor_text(writer, &timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use common_testing::assert;
+
+  use super::*;
+
+  fn encode(timestamp: Timestamp) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    timestamp.cbor_write(&mut bytes).unwrap();
+    bytes
+  }
+
+  fn decode(bytes: &[u8]) -> io::Result<Timestamp> {
+    let mut bytes = bytes;
+    bytes.cbor_read()
+  }
+
+  #[test]
+  fn reads_rfc_8949_examples() {
+    // 0("2013-03-21T20:04:00Z"), 1(1363896240) and 1(1363896240.5)
+    let mut text = vec![0xC0, 0x74];
+    text.extend_from_slice(b"2013-03-21T20:04:00Z");
+    assert::equal(decode(&text).unwrap(), Timestamp::from_secs(1363896240));
+    assert::equal(
+      decode(&[0xC1, 0x1A, 0x51, 0x4B, 0x67, 0xB0]).unwrap(),
+      Timestamp::from_secs(1363896240),
+    );
+    assert::equal(
+      decode(&[0xC1, 0xFB, 0x41, 0xD4, 0x52, 0xD9, 0xEC, 0x20, 0x00, 0x00]).unwrap(),
+      Timestamp::new(1363896240, 500_000),
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_timestamps() {
+    assert!(decode(&[0x1A, 0x51, 0x4B, 0x67, 0xB0]).is_err());
+    assert!(decode(&[0xC2, 0x01]).is_err());
+    assert!(decode(&[0xC1, 0x61, b'a']).is_err());
+    assert!(decode(&[0xC1, 0xF9, 0x7C, 0x00]).is_err());
+    assert!(decode(&[0xC1, 0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    assert!(decode(&[0xC0, 0x01]).is_err());
+  }
+
+  #[test]
+  fn writes_shortest_form() {
+    assert::equal_bytes(&encode(Timestamp::from_secs(0)), &vec![0xC1, 0x00]);
+    assert::equal_bytes(&encode(Timestamp::from_secs(-1)), &vec![0xC1, 0x20]);
+    assert::equal_bytes(
+      &encode(Timestamp::from_secs(1363896240)),
+      &vec![0xC1, 0x1A, 0x51, 0x4B, 0x67, 0xB0],
+    );
+    assert::equal_bytes(
+      &encode(Timestamp::new(0, 500_000)),
+      &vec![0xC1, 0xFA, 0x3F, 0x00, 0x00, 0x00],
+    );
+  }
+
+  #[test]
+  fn round_trips_microseconds() {
+    for micros in [
+      0,
+      1,
+      -1,
+      999_999,
+      1_700_000_000_123_456,
+      -1_700_000_000_654_321,
+      8_589_934_591_999_999,
+    ] {
+      let timestamp = Timestamp::from_micros(micros).unwrap();
+      assert::equal(decode(&encode(timestamp)).unwrap(), timestamp);
+    }
+  }
+
+  #[test]
+  fn displays_rfc_3339() {
+    assert::equal(Timestamp::from_secs(0).to_string(), "1970-01-01T00:00:00Z");
+    assert::equal(
+      Timestamp::new(1363896240, 500_000).to_string(),
+      "2013-03-21T20:04:00.5Z",
+    );
+    assert::equal(Timestamp::from_millis(-1).to_string(), "1969-12-31T23:59:59.999Z");
+    assert::equal(Timestamp::from_secs(951_782_400).to_string(), "2000-02-29T00:00:00Z");
+  }
+
+  #[test]
+  fn parses_rfc_3339() {
+    let parse = |text: &str| text.parse::<Timestamp>();
+    assert::equal(parse("1970-01-01T00:00:00Z").unwrap(), Timestamp::from_secs(0));
+    assert::equal(
+      parse("2013-03-21T13:04:00-07:00").unwrap(),
+      Timestamp::from_secs(1363896240),
+    );
+    assert::equal(
+      parse("2013-03-21 20:04:00.25+00:00").unwrap(),
+      Timestamp::new(1363896240, 250_000),
+    );
+    assert::equal(
+      parse("1969-12-31t23:59:59.9999999z").unwrap(),
+      Timestamp::new(-1, 999_999),
+    );
+    assert!(parse("2013-02-29T00:00:00Z").is_err());
+    assert!(parse("2013-03-21T24:00:00Z").is_err());
+    assert!(parse("2013-03-21T20:04:00").is_err());
+    assert!(parse("2013-03-21T20:04:00.Z").is_err());
+    assert!(parse("1363896240").is_err());
+  }
+
+  #[test]
+  fn orders_by_time() {
+    let mut timestamps = [
+      Timestamp::from_secs(1),
+      Timestamp::new(0, 500_000),
+      Timestamp::from_millis(-1),
+      Timestamp::from_secs(-1),
+    ];
+    timestamps.sort();
+    assert::equal(
+      timestamps,
+      [
+        Timestamp::from_secs(-1),
+        Timestamp::from_millis(-1),
+        Timestamp::new(0, 500_000),
+        Timestamp::from_secs(1),
+      ],
+    );
+  }
+
+  #[test]
+  fn converts_system_time() {
+    let time = UNIX_EPOCH - Duration::from_micros(1_500_000);
+    assert::equal(Timestamp::from(time), Timestamp::new(-2, 500_000));
+    assert::equal(SystemTime::from(Timestamp::new(-2, 500_000)), time);
+  }
+}
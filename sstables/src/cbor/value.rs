@@ -0,0 +1,668 @@
+//! CBOR values.
+//!
+//! The `CborValue` enum models every CBOR data item: integers of either sign, byte strings, text,
+//! arrays, maps, semantic tags, floats, and the simple values `false`, `true`, `null` and
+//! `undefined`. It can be read from and written to any SSTable, so keys and values can be
+//! structured documents that other CBOR tools can read.
+//!
+//! Indefinite-length items are decoded into their definite-length equivalents, so an indefinite
+//! byte string is read as a single `Bytes` value. Values are always written with definite lengths,
+//! using the shortest head that fits (see RFC 8949 Section 4.2.1). Floats are written as single
+//! precision when that is lossless, and as double precision otherwise.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::cbor::{read_cbor_value, CborValue, CborWrite};
+//!
+//! let value = CborValue::Map(vec![
+//!   (CborValue::from("id"), CborValue::from(-7)),
+//!   (CborValue::from("tags"), CborValue::Array(vec![CborValue::from("a"), CborValue::Null])),
+//! ]);
+//!
+//! let mut bytes = Vec::new();
+//! value.cbor_write(&mut bytes).unwrap();
+//! assert_eq!(read_cbor_value(&mut bytes.as_slice()).unwrap(), value);
+//! assert_eq!(value.to_string(), r#"{"id": -7, "tags": ["a", null]}"#);
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+
+use super::{read_cbor_head_u64, write_cbor_head, write_cbor_integer, CborRead, CborWrite, MajorType, LAST_FIVE_BITS};
+use crate::read::{take_byte, take_byte_array, take_byte_slice};
+
+/// The additional info that marks an indefinite-length item.
+pub(super) const INDEFINITE_LENGTH: u8 = 31;
+
+/// The stop code that ends an indefinite-length item.
+pub(super) const BREAK: u8 = 0xFF;
+
+/// How deeply arrays, maps and tags may be nested before the data is rejected, so that corrupt
+/// data cannot overflow the stack.
+pub(super) const MAX_NESTING_DEPTH: usize = 256;
+
+/// Any CBOR data item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CborValue {
+  /// An unsigned or negative integer, from -2^64 to 2^64 - 1.
+  Integer(i128),
+  /// A string of bytes.
+  Bytes(Vec<u8>),
+  /// A string of UTF-8 text.
+  Text(String),
+  /// A list of items.
+  Array(Vec<CborValue>),
+  /// A list of key/value pairs, in the order they were read or written.
+  Map(Vec<(CborValue, CborValue)>),
+  /// A semantic tag and the item it tags.
+  Tag(u64, Box<CborValue>),
+  /// A half, single or double precision float.
+  Float(f64),
+  /// `false` or `true`.
+  Bool(bool),
+  /// `null`.
+  Null,
+  /// `undefined`.
+  Undefined,
+  /// Any other simple value.
+  Simple(u8),
+}
+
+impl From<u64> for CborValue {
+  fn from(value: u64) -> Self {
+    CborValue::Integer(value.into())
+  }
+}
+
+impl From<i64> for CborValue {
+  fn from(value: i64) -> Self {
+    CborValue::Integer(value.into())
+  }
+}
+
+impl From<i32> for CborValue {
+  fn from(value: i32) -> Self {
+    CborValue::Integer(value.into())
+  }
+}
+
+impl From<f64> for CborValue {
+  fn from(value: f64) -> Self {
+    CborValue::Float(value)
+  }
+}
+
+impl From<bool> for CborValue {
+  fn from(value: bool) -> Self {
+    CborValue::Bool(value)
+  }
+}
+
+impl From<&str> for CborValue {
+  fn from(value: &str) -> Self {
+    CborValue::Text(value.to_string())
+  }
+}
+
+impl From<String> for CborValue {
+  fn from(value: String) -> Self {
+    CborValue::Text(value)
+  }
+}
+
+impl From<Vec<u8>> for CborValue {
+  fn from(value: Vec<u8>) -> Self {
+    CborValue::Bytes(value)
+  }
+}
+
+impl From<&[u8]> for CborValue {
+  fn from(value: &[u8]) -> Self {
+    CborValue::Bytes(value.to_vec())
+  }
+}
+
+impl From<Vec<CborValue>> for CborValue {
+  fn from(value: Vec<CborValue>) -> Self {
+    CborValue::Array(value)
+  }
+}
+
+impl CborValue {
+  /// Compares values in the order of the Rust types they are usually read as, so integers and floats
+  /// compare by number, text and bytes compare by their contents, and arrays compare item by item.
+  /// Tags compare by number and then by their item, so epoch timestamps compare by time. Values of
+  /// different kinds are ordered by kind.
+  pub fn natural_cmp(&self, other: &CborValue) -> Ordering {
+    match (self, other) {
+      (CborValue::Integer(a), CborValue::Integer(b)) => a.cmp(b),
+      (CborValue::Float(a), CborValue::Float(b)) => a.total_cmp(b),
+      (CborValue::Integer(a), CborValue::Float(b)) => (*a as f64).total_cmp(b),
+      (CborValue::Float(a), CborValue::Integer(b)) => a.total_cmp(&(*b as f64)),
+      (CborValue::Bytes(a), CborValue::Bytes(b)) => a.cmp(b),
+      (CborValue::Text(a), CborValue::Text(b)) => a.cmp(b),
+      (CborValue::Array(a), CborValue::Array(b)) => a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| a.natural_cmp(b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len())),
+      (CborValue::Map(a), CborValue::Map(b)) => a
+        .iter()
+        .zip(b)
+        .map(|((a_key, a_value), (b_key, b_value))| a_key.natural_cmp(b_key).then_with(|| a_value.natural_cmp(b_value)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len())),
+      (CborValue::Tag(a_tag, a), CborValue::Tag(b_tag, b)) => a_tag.cmp(b_tag).then_with(|| a.natural_cmp(b)),
+      (CborValue::Bool(a), CborValue::Bool(b)) => a.cmp(b),
+      (CborValue::Simple(a), CborValue::Simple(b)) => a.cmp(b),
+      _ => self.kind_rank().cmp(&other.kind_rank()),
+    }
+  }
+
+  /// The rank of the kind of value, for comparing values of different kinds.
+  fn kind_rank(&self) -> u8 {
+    match self {
+      CborValue::Integer(_) | CborValue::Float(_) => 0,
+      CborValue::Bytes(_) => 1,
+      CborValue::Text(_) => 2,
+      CborValue::Array(_) => 3,
+      CborValue::Map(_) => 4,
+      CborValue::Tag(_, _) => 5,
+      CborValue::Bool(_) => 6,
+      CborValue::Null => 7,
+      CborValue::Undefined => 8,
+      CborValue::Simple(_) => 9,
+    }
+  }
+}
+
+/// Returns an error for data that is not well-formed CBOR.
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the argument of a head, returning `None` for an indefinite length.
+fn read_argument<R: Read + ?Sized>(b: &mut R, byte: u8) -> io::Result<Option<u64>> {
+  match byte & LAST_FIVE_BITS {
+    0..=27 => read_cbor_head_u64(b, byte).map(Some),
+    INDEFINITE_LENGTH => Ok(None),
+    _ => Err(invalid_data("Reserved CBOR additional info")),
+  }
+}
+
+/// Converts the bits of a half precision float to a double. See RFC 8949 Appendix D.
+fn f16_to_f64(bits: u16) -> f64 {
+  let exponent = (bits >> 10) & 0x1F;
+  let mantissa = (bits & 0x3FF) as f64;
+  let value = match exponent {
+    0 => mantissa * 2f64.powi(-24),
+    31 if mantissa == 0.0 => f64::INFINITY,
+    31 => f64::NAN,
+    _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+  };
+
+  if bits & 0x8000 != 0 {
+    -value
+  } else {
+    value
+  }
+}
+
+/// Reads any CBOR data item.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::cbor::{read_cbor_value, CborValue};
+///
+/// // An indefinite-length array of 1 and -1.
+/// let mut cursor = Cursor::new([0x9F, 0x01, 0x20, 0xFF]);
+/// let value = read_cbor_value(&mut cursor).unwrap();
+/// assert_eq!(value, CborValue::Array(vec![CborValue::Integer(1), CborValue::Integer(-1)]));
+/// ```
+pub fn read_cbor_value<R: Read + ?Sized>(b: &mut R) -> io::Result<CborValue> {
+  let byte = take_byte(b)?;
+  read_cbor_value_with_byte(b, byte, 0)
+}
+
+/// Reads the rest of a data item whose initial byte has already been read.
+fn read_cbor_value_with_byte<R: Read + ?Sized>(b: &mut R, byte: u8, depth: usize) -> io::Result<CborValue> {
+  if depth > MAX_NESTING_DEPTH {
+    return Err(invalid_data("CBOR nesting is too deep"));
+  }
+
+  let major_type = MajorType::from_u8(byte);
+  if major_type == MajorType::NoContentType {
+    return read_cbor_simple_or_float(b, byte);
+  }
+
+  let argument = read_argument(b, byte)?;
+  Ok(match (major_type, argument) {
+    (MajorType::UnsignedInteger, Some(value)) => CborValue::Integer(value.into()),
+    (MajorType::NegativeInteger, Some(value)) => CborValue::Integer(-1 - i128::from(value)),
+    (MajorType::Bytes, _) => CborValue::Bytes(read_string_chunks(b, byte, argument)?),
+    (MajorType::Text, _) => {
+      let bytes = read_string_chunks(b, byte, argument)?;
+      CborValue::Text(String::from_utf8(bytes).map_err(io::Error::other)?)
+    }
+    (MajorType::Array, Some(len)) => {
+      let items = (0..len).map(|_| read_nested(b, depth));
+      CborValue::Array(items.collect::<io::Result<_>>()?)
+    }
+    (MajorType::Array, None) => {
+      let mut items = Vec::new();
+      while let Some(byte) = take_item_byte(b)? {
+        items.push(read_cbor_value_with_byte(b, byte, depth + 1)?);
+      }
+      CborValue::Array(items)
+    }
+    (MajorType::Object, Some(len)) => {
+      let pairs = (0..len).map(|_| Ok((read_nested(b, depth)?, read_nested(b, depth)?)));
+      CborValue::Map(pairs.collect::<io::Result<_>>()?)
+    }
+    (MajorType::Object, None) => {
+      let mut pairs = Vec::new();
+      while let Some(byte) = take_item_byte(b)? {
+        let key = read_cbor_value_with_byte(b, byte, depth + 1)?;
+        pairs.push((key, read_nested(b, depth)?));
+      }
+      CborValue::Map(pairs)
+    }
+    (MajorType::SemanticTag, Some(tag)) => CborValue::Tag(tag, Box::new(read_nested(b, depth)?)),
+    _ => {
+      return Err(invalid_data(
+        "Indefinite length is not allowed for this CBOR major type",
+      ))
+    }
+  })
+}
+
+/// Reads a data item nested inside an array, map or tag.
+fn read_nested<R: Read + ?Sized>(b: &mut R, depth: usize) -> io::Result<CborValue> {
+  let byte = take_byte(b)?;
+  read_cbor_value_with_byte(b, byte, depth + 1)
+}
+
+/// Reads the initial byte of the next item of an indefinite-length item, or `None` at the break.
+fn take_item_byte<R: Read + ?Sized>(b: &mut R) -> io::Result<Option<u8>> {
+  match take_byte(b)? {
+    BREAK => Ok(None),
+    byte => Ok(Some(byte)),
+  }
+}
+
+/// Reads the payload of a byte string or text, concatenating the chunks of an indefinite-length
+/// string. Each chunk must be a definite-length string of the same major type.
+fn read_string_chunks<R: Read + ?Sized>(b: &mut R, byte: u8, argument: Option<u64>) -> io::Result<Vec<u8>> {
+  let major_type = match argument {
+    Some(len) => return take_byte_slice(b, len as usize),
+    None => MajorType::from_u8(byte),
+  };
+
+  let mut bytes = Vec::new();
+  while let Some(chunk_byte) = take_item_byte(b)? {
+    match (
+      MajorType::from_u8(chunk_byte) == major_type,
+      read_argument(b, chunk_byte)?,
+    ) {
+      (true, Some(chunk_len)) => bytes.extend(take_byte_slice(b, chunk_len as usize)?),
+      _ => return Err(invalid_data("Invalid chunk in indefinite-length CBOR string")),
+    }
+  }
+  Ok(bytes)
+}
+
+/// Reads the rest of a major type 7 item: a simple value or a float.
+fn read_cbor_simple_or_float<R: Read + ?Sized>(b: &mut R, byte: u8) -> io::Result<CborValue> {
+  Ok(match byte & LAST_FIVE_BITS {
+    20 => CborValue::Bool(false),
+    21 => CborValue::Bool(true),
+    22 => CborValue::Null,
+    23 => CborValue::Undefined,
+    value @ 0..=19 => CborValue::Simple(value),
+    24 => match take_byte(b)? {
+      value @ 32.. => CborValue::Simple(value),
+      _ => return Err(invalid_data("Invalid two-byte CBOR simple value")),
+    },
+    25 => CborValue::Float(f16_to_f64(u16::from_be_bytes(take_byte_array(b)?))),
+    26 => CborValue::Float(f32::from_be_bytes(take_byte_array(b)?).into()),
+    27 => CborValue::Float(f64::from_be_bytes(take_byte_array(b)?)),
+    INDEFINITE_LENGTH => return Err(invalid_data("Unexpected CBOR break")),
+    _ => return Err(invalid_data("Reserved CBOR additional info")),
+  })
+}
+
+/// Writes a float as single precision when that is lossless, and as double precision otherwise.
+pub(super) fn write_cbor_float<W: Write + ?Sized>(writer: &mut W, value: f64) -> io::Result<()> {
+  let single = value as f32;
+  if f64::from(single) == value || value.is_nan() {
+    writer.write_all(&[MajorType::NoContentType.as_u8() | 26])?;
+    writer.write_all(&single.to_be_bytes())
+  } else {
+    writer.write_all(&[MajorType::NoContentType.as_u8() | 27])?;
+    writer.write_all(&value.to_be_bytes())
+  }
+}
+
+impl<R: Read + ?Sized> CborRead<CborValue> for R {
+  fn cbor_read(&mut self) -> io::Result<CborValue> {
+    read_cbor_value(self)
+  }
+}
+
+impl CborWrite for CborValue {
+  fn cbor_write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    match self {
+      CborValue::Integer(value) => write_cbor_integer(writer, *value),
+      CborValue::Bytes(bytes) => bytes.cbor_write(writer),
+      CborValue::Text(text) => text.cbor_write(writer),
+      CborValue::Array(items) => {
+        write_cbor_head(writer, MajorType::Array, items.len() as u64)?;
+        items.iter().try_for_each(|item| item.cbor_write(writer))
+      }
+      CborValue::Map(pairs) => {
+        write_cbor_head(writer, MajorType::Object, pairs.len() as u64)?;
+        pairs.iter().try_for_each(|(key, value)| {
+          key.cbor_write(writer)?;
+          value.cbor_write(writer)
+        })
+      }
+      CborValue::Tag(tag, value) => {
+        write_cbor_head(writer, MajorType::SemanticTag, *tag)?;
+        value.cbor_write(writer)
+      }
+      CborValue::Float(value) => write_cbor_float(writer, *value),
+      CborValue::Bool(false) => writer.write_all(&[0xF4]),
+      CborValue::Bool(true) => writer.write_all(&[0xF5]),
+      CborValue::Null => writer.write_all(&[0xF6]),
+      CborValue::Undefined => writer.write_all(&[0xF7]),
+      CborValue::Simple(value @ 0..=23) => writer.write_all(&[MajorType::NoContentType.as_u8() | value]),
+      CborValue::Simple(value) => writer.write_all(&[0xF8, *value]),
+    }
+  }
+}
+
+/// Displays the value in CBOR diagnostic notation. See RFC 8949 Section 8.
+impl Display for CborValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CborValue::Integer(value) => write!(f, "{}", value),
+      CborValue::Bytes(bytes) => {
+        write!(f, "h'")?;
+        bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))?;
+        write!(f, "'")
+      }
+      CborValue::Text(text) => write!(f, "{:?}", text),
+      CborValue::Array(items) => {
+        write!(f, "[")?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+      }
+      CborValue::Map(pairs) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in pairs.iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+          write!(f, "{}: {}", key, value)?;
+        }
+        write!(f, "}}")
+      }
+      CborValue::Tag(tag, value) => write!(f, "{}({})", tag, value),
+      CborValue::Float(value) if value.is_nan() => write!(f, "NaN"),
+      CborValue::Float(value) if value.is_infinite() && *value > 0.0 => write!(f, "Infinity"),
+      CborValue::Float(value) if value.is_infinite() => write!(f, "-Infinity"),
+      CborValue::Float(value) => write!(f, "{:?}", value),
+      CborValue::Bool(value) => write!(f, "{}", value),
+      CborValue::Null => write!(f, "null"),
+      CborValue::Undefined => write!(f, "undefined"),
+      CborValue::Simple(value) => write!(f, "simple({})", value),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use common_testing::assert;
+
+  use super::*;
+
+  /// Decodes the bytes, checking that every byte was consumed.
+  fn decode(bytes: &[u8]) -> CborValue {
+    let mut slice = bytes;
+    let value = read_cbor_value(&mut slice).unwrap();
+    assert::equal(slice.len(), 0);
+    value
+  }
+
+  #[test]
+  fn natural_cmp_orders_like_native_types() {
+    let ordered = [
+      CborValue::from(-2),
+      CborValue::from(1.5),
+      CborValue::from(10),
+      CborValue::from(b"a".as_slice()),
+      CborValue::from("b"),
+      CborValue::from("ba"),
+      CborValue::from(vec![CborValue::from(1), CborValue::from("z")]),
+      CborValue::from(vec![CborValue::from(2)]),
+      CborValue::Tag(1, Box::new(CborValue::from(5))),
+      CborValue::Tag(1, Box::new(CborValue::from(5.5))),
+      CborValue::Bool(false),
+      CborValue::Null,
+    ];
+    for (i, a) in ordered.iter().enumerate() {
+      for (j, b) in ordered.iter().enumerate() {
+        assert::equal(a.natural_cmp(b), i.cmp(&j));
+      }
+    }
+  }
+
+  fn encode(value: &CborValue) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    value.cbor_write(&mut bytes).unwrap();
+    bytes
+  }
+
+  #[test]
+  fn read_cbor_value_integers() {
+    // Examples from RFC 8949 Appendix A.
+    assert::equal(decode(&[0x00]), CborValue::Integer(0));
+    assert::equal(decode(&[0x17]), CborValue::Integer(23));
+    assert::equal(decode(&[0x19, 0x03, 0xE8]), CborValue::Integer(1000));
+    assert::equal(
+      decode(&[0x1B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+      CborValue::from(u64::MAX),
+    );
+    assert::equal(decode(&[0x20]), CborValue::Integer(-1));
+    assert::equal(decode(&[0x29]), CborValue::Integer(-10));
+    assert::equal(decode(&[0x39, 0x03, 0xE7]), CborValue::Integer(-1000));
+    assert::equal(
+      decode(&[0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+      CborValue::Integer(-18446744073709551616),
+    );
+  }
+
+  #[test]
+  fn read_cbor_value_floats() {
+    assert::equal(decode(&[0xF9, 0x00, 0x00]), CborValue::Float(0.0));
+    assert::equal(decode(&[0xF9, 0x3C, 0x00]), CborValue::Float(1.0));
+    assert::equal(decode(&[0xF9, 0x3E, 0x00]), CborValue::Float(1.5));
+    assert::equal(decode(&[0xF9, 0x7B, 0xFF]), CborValue::Float(65504.0));
+    assert::equal(decode(&[0xF9, 0x00, 0x01]), CborValue::Float(5.960464477539063e-8));
+    assert::equal(decode(&[0xF9, 0xC4, 0x00]), CborValue::Float(-4.0));
+    assert::equal(decode(&[0xF9, 0x7C, 0x00]), CborValue::Float(f64::INFINITY));
+    assert::equal(decode(&[0xFA, 0x47, 0xC3, 0x50, 0x00]), CborValue::Float(100000.0));
+    assert::equal(
+      decode(&[0xFB, 0x3F, 0xF1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9A]),
+      CborValue::Float(1.1),
+    );
+    assert::equal(decode(&[0xF9, 0x7E, 0x00]).to_string(), "NaN");
+  }
+
+  #[test]
+  fn read_cbor_value_simple() {
+    assert::equal(decode(&[0xF4]), CborValue::Bool(false));
+    assert::equal(decode(&[0xF5]), CborValue::Bool(true));
+    assert::equal(decode(&[0xF6]), CborValue::Null);
+    assert::equal(decode(&[0xF7]), CborValue::Undefined);
+    assert::equal(decode(&[0xF0]), CborValue::Simple(16));
+    assert::equal(decode(&[0xF8, 0xFF]), CborValue::Simple(255));
+  }
+
+  #[test]
+  fn read_cbor_value_strings() {
+    assert::equal(decode(&[0x40]), CborValue::Bytes(vec![]));
+    assert::equal(
+      decode(&[0x44, 0x01, 0x02, 0x03, 0x04]),
+      CborValue::Bytes(vec![1, 2, 3, 4]),
+    );
+    assert::equal(decode(&[0x62, 0x22, 0x5C]), CborValue::from("\"\\"));
+    assert::equal(decode(&[0x63, 0xE6, 0xB0, 0xB4]), CborValue::from("水"));
+    assert::equal(
+      decode(&[0x5F, 0x42, 0x01, 0x02, 0x43, 0x03, 0x04, 0x05, 0xFF]),
+      CborValue::Bytes(vec![1, 2, 3, 4, 5]),
+    );
+    assert::equal(
+      decode(&[
+        0x7F, 0x65, 0x73, 0x74, 0x72, 0x65, 0x61, 0x64, 0x6D, 0x69, 0x6E, 0x67, 0xFF,
+      ]),
+      CborValue::from("streaming"),
+    );
+  }
+
+  #[test]
+  fn read_cbor_value_rejects_lengths_past_the_data() {
+    // Byte strings and text of 2^64 - 1 bytes, and a chunk of that length, with one byte of data.
+    for bytes in [
+      &[0x5B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01][..],
+      &[0x7B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x61],
+      &[0x5F, 0x5B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+    ] {
+      let error = read_cbor_value(&mut &bytes[..]).unwrap_err();
+      assert::equal(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+  }
+
+  #[test]
+  fn read_cbor_value_arrays_and_maps() {
+    let one_two_three = CborValue::Array(vec![1.into(), 2.into(), 3.into()]);
+    assert::equal(decode(&[0x80]), CborValue::Array(vec![]));
+    assert::equal(decode(&[0x83, 0x01, 0x02, 0x03]), one_two_three.clone());
+    assert::equal(decode(&[0x9F, 0x01, 0x02, 0x03, 0xFF]), one_two_three);
+    assert::equal(
+      decode(&[0x82, 0x61, 0x61, 0xBF, 0x61, 0x62, 0x61, 0x63, 0xFF]),
+      CborValue::Array(vec!["a".into(), CborValue::Map(vec![("b".into(), "c".into())])]),
+    );
+    assert::equal(
+      decode(&[0xA2, 0x01, 0x02, 0x03, 0x04]),
+      CborValue::Map(vec![(1.into(), 2.into()), (3.into(), 4.into())]),
+    );
+  }
+
+  #[test]
+  fn read_cbor_value_tags() {
+    assert::equal(
+      decode(&[0xC1, 0x1A, 0x51, 0x4B, 0x67, 0xB0]),
+      CborValue::Tag(1, Box::new(1363896240.into())),
+    );
+    assert::equal(
+      decode(&[0xD9, 0xD9, 0xF7, 0x80]),
+      CborValue::Tag(55799, Box::new(CborValue::Array(vec![]))),
+    );
+  }
+
+  #[test]
+  fn read_cbor_value_rejects_invalid_data() {
+    let invalid: [&[u8]; 6] = [
+      &[0xFF],             // A break outside of an indefinite-length item.
+      &[0x1C],             // Reserved additional info.
+      &[0x3F],             // An indefinite-length integer.
+      &[0x5F, 0x61, 0x61], // A text chunk in a byte string.
+      &[0xF8, 0x10],       // A simple value that fits in the initial byte.
+      &[0x82, 0x01],       // A truncated array.
+    ];
+    for bytes in invalid {
+      assert!(read_cbor_value(&mut &bytes[..]).is_err(), "{:?}", bytes);
+    }
+
+    let deeply_nested = [0x81; MAX_NESTING_DEPTH + 2];
+    assert!(read_cbor_value(&mut &deeply_nested[..]).is_err());
+  }
+
+  #[test]
+  fn cbor_write_round_trips() {
+    let value = CborValue::Map(vec![
+      ("small".into(), 10.into()),
+      ("large".into(), u64::MAX.into()),
+      ("negative".into(), CborValue::Integer(-18446744073709551616)),
+      ("bytes".into(), CborValue::Bytes(vec![0, 255])),
+      ("single".into(), 1.5.into()),
+      ("double".into(), 1.1.into()),
+      ("tagged".into(), CborValue::Tag(1, Box::new(0.into()))),
+      (
+        CborValue::Null,
+        CborValue::Array(vec![true.into(), CborValue::Undefined, CborValue::Simple(255)]),
+      ),
+    ]);
+    assert::equal(decode(&encode(&value)), value);
+  }
+
+  #[test]
+  fn cbor_write_uses_shortest_form() {
+    assert::equal_bytes(&encode(&CborValue::Integer(-1000)), &vec![0x39, 0x03, 0xE7]);
+    assert::equal_bytes(
+      &encode(&CborValue::Float(100000.0)),
+      &vec![0xFA, 0x47, 0xC3, 0x50, 0x00],
+    );
+    assert::equal_bytes(&encode(&CborValue::Simple(16)), &vec![0xF0]);
+    assert::equal_bytes(&encode(&CborValue::Simple(255)), &vec![0xF8, 0xFF]);
+    assert::equal_bytes(
+      &encode(&CborValue::Array(vec![1.into(), "a".into()])),
+      &vec![0x82, 0x01, 0x61, 0x61],
+    );
+    assert!(CborValue::Integer(i128::MAX).cbor_write(&mut Vec::new()).is_err());
+  }
+
+  #[test]
+  fn display_uses_diagnostic_notation() {
+    let value = CborValue::Array(vec![
+      CborValue::Bytes(vec![1, 0xAB]),
+      CborValue::Tag(0, Box::new("2013-03-21T20:04:00Z".into())),
+      CborValue::Float(f64::NEG_INFINITY),
+      CborValue::Float(1.0),
+      CborValue::Simple(16),
+    ]);
+    assert::equal(
+      value.to_string(),
+      r#"[h'01ab', 0("2013-03-21T20:04:00Z"), -Infinity, 1.0, simple(16)]"#,
+    );
+  }
+
+  #[test]
+  fn sstable_round_trips_values() {
+    let document = CborValue::Map(vec![("n".into(), (-3).into()), ("ok".into(), true.into())]);
+    let mut writer = crate::SSTableWriterBuilder::new("memory")
+      .build_with_writers(Vec::new(), Vec::new())
+      .unwrap();
+    writer.write(("a", document.clone())).unwrap();
+    writer.write(("b", CborValue::Null)).unwrap();
+    let (data, _) = writer.into_writers().unwrap();
+
+    let reader = crate::SSTableReader::<(String, CborValue), _>::new(data.as_slice());
+    let entries = reader.collect::<io::Result<Vec<_>>>().unwrap();
+    assert::equal(
+      entries,
+      vec![("a".to_string(), document), ("b".to_string(), CborValue::Null)],
+    );
+  }
+}
@@ -0,0 +1,514 @@
+//! Per-record checksums and verification of a table.
+//!
+//! Neither the data file nor the index can detect bit rot on their own, which matters for tables
+//! that sit in cold storage for years. With `SSTableWriterBuilder::checksums`, the writer keeps a
+//! checksum file beside the data file (`foo.crc.sst`) with a CRC32C of every record. The data file
+//! stays plain CBOR, so it can still be read, compressed and copied by any tool.
+//!
+//! `verify` scans a table and reports the offsets of corrupt records, whether or not it has
+//! checksums. Records that can't be read, records whose checksum doesn't match, and checksums for
+//! offsets where no record starts are all corrupt, and so are index entries that don't point at a
+//! record with their key. The first entry of the index or checksum file that can't be read is also
+//! reported, since none of the entries after it can be checked.
+//!
+//! # Format
+//!
+//! The checksum file is a CBOR sequence, like the index. Each entry is the offset of a record in the
+//! data file followed by the CRC32C of the record, which covers the encoded key and value exactly as
+//! they are in the data file.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::checksum::verify;
+//! use sstables::SSTableWriterBuilder;
+//!
+//! let path = std::env::temp_dir().join("checksum_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_checksum_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).checksums().build().unwrap();
+//! writer.write(("hello", "world")).unwrap();
+//! writer.close().unwrap();
+//!
+//! let verification = verify(&path).unwrap();
+//! assert!(verification.is_ok());
+//! assert_eq!(verification.checked_records, 1);
+//! ```
+
+use std::{
+  collections::BTreeMap,
+  fmt::{self, Display},
+  fs::{File, OpenOptions},
+  io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+  path::Path,
+};
+
+use crate::{
+  cbor::{read_cbor_raw, read_cbor_u64, CborWrite},
+  header::skip_header,
+  read::{create_checksum_path, create_index_path},
+};
+
+/// The reflected CRC32C (Castagnoli) polynomial.
+const CRC32C_POLYNOMIAL: u32 = 0x82F6_3B78;
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+  let mut table = [0; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut crc = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ CRC32C_POLYNOMIAL
+      } else {
+        crc >> 1
+      };
+      bit += 1;
+    }
+    table[i] = crc;
+    i += 1;
+  }
+  table
+}
+
+/// Returns the CRC32C of the bytes.
+pub fn crc32c(bytes: &[u8]) -> u32 {
+  crc32c_extend(0, bytes)
+}
+
+/// Returns the CRC32C of the bytes that were checksummed as `crc`, followed by `bytes`.
+pub fn crc32c_extend(crc: u32, bytes: &[u8]) -> u32 {
+  let mut crc = !crc;
+  for byte in bytes {
+    crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+  }
+  !crc
+}
+
+/// Returns the checksum of a record, from its encoded key and value.
+pub fn record_checksum(key_bytes: &[u8], value_bytes: &[u8]) -> u32 {
+  crc32c_extend(crc32c(key_bytes), value_bytes)
+}
+
+/// Writes a checksum entry for the record at the offset.
+pub(crate) fn write_checksum<W: Write>(writer: &mut W, offset: u64, checksum: u32) -> io::Result<()> {
+  offset.cbor_write(writer)?;
+  (checksum as u64).cbor_write(writer)
+}
+
+/// Reads the next entry of a checksum file, returning `None` at the end of the file or at the
+/// first entry that can't be read.
+fn read_checksum<R: BufRead>(reader: &mut R) -> Option<(u64, u32)> {
+  let offset = read_cbor_u64(reader).ok()?;
+  let checksum = read_cbor_u64(reader).ok()?;
+  u32::try_from(checksum).ok().map(|checksum| (offset, checksum))
+}
+
+/// Reads a checksum file into a map of record offsets to checksums, along with the position of its
+/// first entry that can't be read, if any. A missing file has no entries.
+fn read_checksums(path: &Path) -> io::Result<(BTreeMap<u64, u32>, Option<u64>)> {
+  let mut checksums = BTreeMap::new();
+  let mut reader = match File::open(path) {
+    Ok(file) => BufReader::new(file),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((checksums, None)),
+    Err(e) => return Err(e),
+  };
+
+  let mut position = 0;
+  while !reader.fill_buf()?.is_empty() {
+    let Some((offset, checksum)) = read_checksum(&mut reader) else {
+      return Ok((checksums, Some(position)));
+    };
+    checksums.insert(offset, checksum);
+    position = reader.stream_position()?;
+  }
+  Ok((checksums, None))
+}
+
+/// Brings the checksum file of a table up to date with its data file, which must already be
+/// recovered. Checksums of records past the end of the data file are removed, and records after the
+/// last checksum are checksummed, which also adds checksums to a table that had none. Returns the
+/// number of checksums added.
+pub(crate) fn recover_checksums(data_path: &Path, checksum_path: &Path) -> io::Result<u64> {
+  let data_size = match data_path.metadata() {
+    Ok(metadata) => metadata.len(),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+    Err(e) => return Err(e),
+  };
+
+  // Keep the entries for records that are still in the data file, which are in offset order.
+  let mut checksum_file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(checksum_path)?;
+  let mut reader = BufReader::new(&checksum_file);
+  let mut valid_size = 0;
+  let mut last_offset = None;
+  while let Some((offset, _)) = read_checksum(&mut reader) {
+    if offset >= data_size {
+      break;
+    }
+    valid_size = reader.stream_position()?;
+    last_offset = Some(offset);
+  }
+  checksum_file.set_len(valid_size)?;
+  checksum_file.seek(SeekFrom::End(0))?;
+
+  if data_size == 0 {
+    return Ok(0);
+  }
+
+  // Checksum every record after the last one with a checksum.
+  let mut data_reader = BufReader::new(File::open(data_path)?);
+  let mut offset = match last_offset {
+    Some(offset) => data_reader.seek(SeekFrom::Start(offset))?,
+    None => skip_header(&mut data_reader)?,
+  };
+  let (mut key_bytes, mut value_bytes) = (Vec::new(), Vec::new());
+  let mut restored = Vec::new();
+  let mut restored_checksums = 0;
+  while !data_reader.fill_buf()?.is_empty() {
+    key_bytes.clear();
+    value_bytes.clear();
+    read_cbor_raw(&mut data_reader, &mut key_bytes)?;
+    read_cbor_raw(&mut data_reader, &mut value_bytes)?;
+    if last_offset != Some(offset) {
+      write_checksum(&mut restored, offset, record_checksum(&key_bytes, &value_bytes))?;
+      restored_checksums += 1;
+    }
+    offset += (key_bytes.len() + value_bytes.len()) as u64;
+  }
+  checksum_file.write_all(&restored)?;
+
+  Ok(restored_checksums)
+}
+
+/// The result of verifying a table.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Verification {
+  /// The number of records read from the data file.
+  pub records: u64,
+  /// The number of records that had a checksum, whether or not it matched.
+  pub checked_records: u64,
+  /// The offsets of corrupt records in the data file, in order. The scan stops at a record that
+  /// can't be read, since the records after it can't be found.
+  pub corrupt_records: Vec<u64>,
+  /// The positions of index entries in the index file, in order, that don't point at a record
+  /// with their key. The index is read up to its first entry that can't be read, which is also
+  /// corrupt.
+  pub corrupt_index_entries: Vec<u64>,
+  /// The position of the first entry in the checksum file that can't be read. The checksums after
+  /// it can't be found, so the records they cover aren't checked.
+  pub corrupt_checksum_entry: Option<u64>,
+}
+
+impl Verification {
+  /// Whether no corruption was found.
+  pub fn is_ok(&self) -> bool {
+    self.corrupt_records.is_empty() && self.corrupt_index_entries.is_empty() && self.corrupt_checksum_entry.is_none()
+  }
+}
+
+impl Display for Verification {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} records, {} with checksums, {} corrupt records and {} corrupt index entries",
+      self.records,
+      self.checked_records,
+      self.corrupt_records.len(),
+      self.corrupt_index_entries.len()
+    )?;
+    if self.corrupt_checksum_entry.is_some() {
+      write!(f, ", with an unreadable checksum file")?;
+    }
+    Ok(())
+  }
+}
+
+/// Verifies the table at the data path, with its index and checksum files beside it. See
+/// [`verify_files`].
+pub fn verify<P: AsRef<Path>>(data_path: P) -> io::Result<Verification> {
+  let data_path = data_path.as_ref();
+  verify_files(
+    data_path,
+    &create_index_path(data_path),
+    &create_checksum_path(data_path),
+  )
+}
+
+/// Verifies a table by scanning its data file, checking each record against its checksum and each
+/// index entry against the record it points at. A missing index or checksum file has nothing to
+/// check, and either file is only read up to its first entry that can't be read, whose position is
+/// reported. Only errors opening or reading the files are returned, and corruption is reported in
+/// the `Verification`.
+pub fn verify_files(data_path: &Path, index_path: &Path, checksum_path: &Path) -> io::Result<Verification> {
+  let mut verification = Verification::default();
+  let (mut checksums, corrupt_checksum_entry) = read_checksums(checksum_path)?;
+  verification.corrupt_checksum_entry = corrupt_checksum_entry;
+
+  // The index entries by the offset they point at, with their position in the index file.
+  let mut index_entries: BTreeMap<u64, Vec<(u64, Vec<u8>)>> = BTreeMap::new();
+  match File::open(index_path) {
+    Ok(file) => {
+      let mut reader = BufReader::new(file);
+      let mut position = 0;
+      while !reader.fill_buf()?.is_empty() {
+        let mut key_bytes = Vec::new();
+        let Ok(offset) = read_cbor_raw(&mut reader, &mut key_bytes).and_then(|_| read_cbor_u64(&mut reader)) else {
+          verification.corrupt_index_entries.push(position);
+          break;
+        };
+        index_entries.entry(offset).or_default().push((position, key_bytes));
+        position = reader.stream_position()?;
+      }
+    }
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+    Err(e) => return Err(e),
+  }
+
+  let mut data_reader = BufReader::new(File::open(data_path)?);
+  let (mut key_bytes, mut value_bytes) = (Vec::new(), Vec::new());
+  let mut offset = skip_header(&mut data_reader)?;
+  while !data_reader.fill_buf()?.is_empty() {
+    key_bytes.clear();
+    value_bytes.clear();
+    let record =
+      read_cbor_raw(&mut data_reader, &mut key_bytes).and_then(|_| read_cbor_raw(&mut data_reader, &mut value_bytes));
+    if record.is_err() {
+      verification.corrupt_records.push(offset);
+      break;
+    }
+    verification.records += 1;
+
+    if let Some(checksum) = checksums.remove(&offset) {
+      verification.checked_records += 1;
+      if checksum != record_checksum(&key_bytes, &value_bytes) {
+        verification.corrupt_records.push(offset);
+      }
+    }
+    for (position, index_key_bytes) in index_entries.remove(&offset).unwrap_or_default() {
+      if index_key_bytes != key_bytes {
+        verification.corrupt_index_entries.push(position);
+      }
+    }
+
+    offset += (key_bytes.len() + value_bytes.len()) as u64;
+  }
+
+  // Whatever is left points at offsets where no record starts.
+  verification.corrupt_records.extend(checksums.into_keys());
+  verification.corrupt_records.sort_unstable();
+  verification.corrupt_records.dedup();
+  verification
+    .corrupt_index_entries
+    .extend(index_entries.into_values().flatten().map(|(position, _)| position));
+  verification.corrupt_index_entries.sort_unstable();
+
+  Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{wal::SyncPolicy, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::fs;
+
+  const TEST_FILE_NAME: &str = ".tmp/checksum_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/checksum_test.index.sst";
+  const TEST_CHECKSUM_FILE_NAME: &str = ".tmp/checksum_test.crc.sst";
+  const TEST_WAL_FILE_NAME: &str = ".tmp/checksum_test.wal.sst";
+
+  fn setup_test_sstable(n: u64, checksums: bool) {
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [
+      TEST_FILE_NAME,
+      TEST_INDEX_FILE_NAME,
+      TEST_CHECKSUM_FILE_NAME,
+      TEST_WAL_FILE_NAME,
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    let mut builder = SSTableWriterBuilder::new(TEST_FILE_NAME);
+    if checksums {
+      builder = builder.checksums();
+    }
+    let mut writer = builder.build().unwrap();
+    for i in 0..n {
+      writer.write((i, "value")).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  fn flip_bit(path: &str, position: usize) {
+    let mut bytes = fs::read(path).unwrap();
+    bytes[position] ^= 0x01;
+    fs::write(path, bytes).unwrap();
+  }
+
+  #[test]
+  fn crc32c_works() {
+    assert::equal(crc32c(b""), 0);
+    assert::equal(crc32c(b"123456789"), 0xE306_9283);
+    assert::equal(crc32c_extend(crc32c(b"1234"), b"56789"), 0xE306_9283);
+  }
+
+  #[test]
+  fn verifies_clean_tables() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(
+      verification,
+      Verification {
+        records: 3,
+        checked_records: 3,
+        ..Verification::default()
+      },
+    );
+
+    setup_test_sstable(3, false);
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert!(verification.is_ok());
+    assert::equal(verification.checked_records, 0);
+  }
+
+  #[test]
+  fn reports_corrupt_records() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+    // The last byte of the value of the second record, which starts at offset 7.
+    flip_bit(TEST_FILE_NAME, 13);
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.corrupt_records, vec![7]);
+    assert::equal(verification.corrupt_index_entries, Vec::<u64>::new());
+
+    // A broken key is also caught by the index.
+    flip_bit(TEST_FILE_NAME, 13);
+    flip_bit(TEST_FILE_NAME, 14);
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.corrupt_records, vec![14]);
+    assert::equal(verification.corrupt_index_entries, vec![4]);
+  }
+
+  #[test]
+  fn reports_unreadable_records() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+    // Make the second record a 24-byte text string, which runs past the end of the file.
+    let mut bytes = fs::read(TEST_FILE_NAME).unwrap();
+    bytes[7] = 0x78;
+    bytes[8] = 24;
+    fs::write(TEST_FILE_NAME, bytes).unwrap();
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.records, 1);
+    assert::equal(verification.corrupt_records, vec![7, 14]);
+    assert::equal(verification.corrupt_index_entries, vec![2, 4]);
+  }
+
+  #[test]
+  fn reports_unreadable_index_and_checksum_entries() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+    // Make the key of the second index entry, at position 2, a text string that runs past the end
+    // of the file, and cut the checksum file off partway through its second entry.
+    let mut bytes = fs::read(TEST_INDEX_FILE_NAME).unwrap();
+    bytes[2] = 0x78;
+    bytes[3] = 64;
+    fs::write(TEST_INDEX_FILE_NAME, bytes).unwrap();
+    let mut reader = BufReader::new(File::open(TEST_CHECKSUM_FILE_NAME).unwrap());
+    read_checksum(&mut reader).unwrap();
+    let checksum_position = reader.stream_position().unwrap();
+    let checksum_file = OpenOptions::new().write(true).open(TEST_CHECKSUM_FILE_NAME).unwrap();
+    checksum_file.set_len(checksum_position + 2).unwrap();
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert!(!verification.is_ok());
+    assert::equal(verification.records, 3);
+    assert::equal(verification.checked_records, 1);
+    assert::equal(verification.corrupt_records, Vec::<u64>::new());
+    assert::equal(verification.corrupt_index_entries, vec![2]);
+    assert::equal(verification.corrupt_checksum_entry, Some(checksum_position));
+  }
+
+  #[test]
+  fn reports_records_with_corrupt_lengths() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+    // Make the first key a text string whose 8-byte length is read from the value after it, which
+    // is far larger than the file.
+    let mut bytes = fs::read(TEST_FILE_NAME).unwrap();
+    bytes[0] = 0x7B;
+    fs::write(TEST_FILE_NAME, bytes).unwrap();
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.records, 0);
+    assert::equal(verification.corrupt_records, vec![0, 7, 14]);
+  }
+
+  #[test]
+  fn adds_checksums_to_existing_tables() {
+    let _lock = setup::sequential();
+    setup_test_sstable(2, false);
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).checksums().build().unwrap();
+    writer.write((2, "value")).unwrap();
+    writer.close().unwrap();
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.records, 3);
+    assert::equal(verification.checked_records, 3);
+    assert!(verification.is_ok());
+  }
+
+  #[test]
+  fn recovers_checksums_with_the_table() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, true);
+    // A checksum for a record that never made it to the data file, and one that is partial.
+    let mut checksum_file = OpenOptions::new().append(true).open(TEST_CHECKSUM_FILE_NAME).unwrap();
+    write_checksum(&mut checksum_file, 21, 0).unwrap();
+    checksum_file.write_all(&[0x18]).unwrap();
+    drop(checksum_file);
+
+    assert::equal(
+      recover_checksums(Path::new(TEST_FILE_NAME), Path::new(TEST_CHECKSUM_FILE_NAME)).unwrap(),
+      0,
+    );
+    let (checksums, corrupt_checksum_entry) = read_checksums(Path::new(TEST_CHECKSUM_FILE_NAME)).unwrap();
+    assert::equal(checksums.len(), 3);
+    assert::equal(corrupt_checksum_entry, None);
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .checksums()
+      .wal(SyncPolicy::EveryWrite)
+      .build()
+      .unwrap();
+    writer.write((3, "value")).unwrap();
+    writer.sync_wal().unwrap();
+    drop(writer);
+
+    // Reopening replays the log, which checksums the replayed record again.
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .checksums()
+      .wal(SyncPolicy::EveryWrite)
+      .build()
+      .unwrap();
+    writer.close().unwrap();
+
+    let verification = verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.records, 4);
+    assert::equal(verification.checked_records, 4);
+    assert!(verification.is_ok());
+  }
+}
@@ -0,0 +1,378 @@
+//! An embedded key-value store built from SSTables, in the style of a log-structured merge tree.
+//!
+//! Writes go to an in-memory memtable, which is sorted by key. When the memtable reaches its size
+//! limit it is flushed to a new SSTable in the database's directory, with an index and a bloom
+//! filter. Deletes are written as tombstones, so they hide the values of the key in older tables.
+//!
+//! Lookups check the memtable first and then the tables from the newest to the oldest, and scans
+//! merge the memtable with every table so that the newest value of each key wins.
+//!
+//! The live tables are listed in a `MANIFEST` file, a CBOR sequence of table file names from the
+//! oldest to the newest. It is replaced atomically after each flush, so a table is either complete
+//! and listed, or ignored.
+//!
+//! The memtable only lives in memory, so writes since the last flush are lost unless `flush` or
+//! `close` is called.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::db::DbBuilder;
+//!
+//! let dir = std::env::temp_dir().join("sstables_db_example");
+//! # std::fs::remove_dir_all(&dir).unwrap_or_default();
+//! let mut db = DbBuilder::new(&dir).open::<String, String>().unwrap();
+//! db.put("a".to_string(), "1".to_string()).unwrap();
+//! db.put("b".to_string(), "2".to_string()).unwrap();
+//! db.flush().unwrap();
+//! db.delete("a".to_string()).unwrap();
+//!
+//! assert_eq!(db.get(&"a".to_string()).unwrap(), None);
+//! assert_eq!(db.get(&"b".to_string()).unwrap(), Some("2".to_string()));
+//! ```
+
+use std::{
+  collections::BTreeMap,
+  fs::{self, File},
+  io::{self, BufReader, BufWriter, Write},
+  mem,
+  ops::RangeBounds,
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  bloom::DEFAULT_FALSE_POSITIVE_RATE,
+  cbor::{read_cbor_text, write_cbor_text, CborRead, CborWrite},
+  merge::{DuplicatePolicy, MergingIterator},
+  read::{create_bloom_path, create_index_path},
+  sstable::get_live,
+  SSTable, SSTableWriterBuilder,
+};
+
+/// The default size of the memtable before it is flushed to a new SSTable, 4 MiB.
+pub const DEFAULT_MEMTABLE_LIMIT: usize = 4 * 1024 * 1024;
+
+/// The name of the file that lists the live tables of a database.
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// Builder for a Db.
+pub struct DbBuilder {
+  dir: PathBuf,
+  memtable_limit: usize,
+}
+
+impl DbBuilder {
+  pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+    DbBuilder {
+      dir: dir.into(),
+      memtable_limit: DEFAULT_MEMTABLE_LIMIT,
+    }
+  }
+
+  /// The approximate number of bytes of entries to hold in the memtable before flushing it to a new
+  /// SSTable. Defaults to `DEFAULT_MEMTABLE_LIMIT`.
+  pub fn memtable_limit(mut self, bytes: usize) -> Self {
+    self.memtable_limit = bytes;
+    self
+  }
+
+  /// Opens the database in the directory, creating the directory if it doesn't exist, and opens
+  /// every table listed in its manifest.
+  pub fn open<K, V>(self) -> io::Result<Db<K, V>>
+  where
+    K: Ord + Clone,
+    BufReader<File>: CborRead<K> + CborRead<V>,
+  {
+    fs::create_dir_all(&self.dir)?;
+
+    let table_names = read_manifest(&self.dir.join(MANIFEST_FILE_NAME))?;
+    let next_table_id = table_names
+      .iter()
+      .filter_map(|name| name.strip_suffix(".sst")?.parse::<u64>().ok())
+      .max()
+      .map_or(0, |id| id + 1);
+    let tables = table_names
+      .iter()
+      .map(|name| open_table(&self.dir.join(name)))
+      .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(Db {
+      dir: self.dir,
+      memtable_limit: self.memtable_limit,
+      memtable: BTreeMap::new(),
+      memtable_bytes: 0,
+      table_names,
+      tables,
+      next_table_id,
+      scratch: Vec::new(),
+    })
+  }
+}
+
+/// Opens a table of the database. Tables are written from the memtable in the order of its keys, so
+/// they're sorted by the `Ord` of the key type, and lookups can seek with their index.
+fn open_table<K, V>(path: &Path) -> io::Result<SSTable<K, Option<V>>>
+where
+  K: Ord + Clone,
+  BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  Ok(SSTable::open(path)?.sorted(true))
+}
+
+/// Reads the table file names of a manifest, or none if it doesn't exist yet.
+fn read_manifest(path: &Path) -> io::Result<Vec<String>> {
+  let mut reader = match File::open(path) {
+    Ok(file) => BufReader::new(file),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(e),
+  };
+
+  let mut table_names = Vec::new();
+  loop {
+    match read_cbor_text(&mut reader) {
+      Ok(name) => table_names.push(name),
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(table_names),
+      Err(e) => return Err(e),
+    }
+  }
+}
+
+/// Replaces the manifest by writing a new one beside it and renaming it over the old one.
+fn write_manifest(path: &Path, table_names: &[String]) -> io::Result<()> {
+  let temp_path = path.with_extension("tmp");
+  let mut writer = BufWriter::new(File::create(&temp_path)?);
+  for name in table_names {
+    write_cbor_text(&mut writer, name)?;
+  }
+  writer.flush()?;
+  writer.get_ref().sync_all()?;
+  fs::rename(temp_path, path)
+}
+
+/// A key-value store with a memtable that is flushed to SSTables. Values are stored as `Option`s,
+/// where `None` is a tombstone for a deleted key.
+pub struct Db<K, V> {
+  dir: PathBuf,
+  memtable_limit: usize,
+  memtable: BTreeMap<K, Option<V>>,
+  memtable_bytes: usize,
+  /// The file names of the live tables, from the oldest to the newest.
+  table_names: Vec<String>,
+  tables: Vec<SSTable<K, Option<V>>>,
+  next_table_id: u64,
+  scratch: Vec<u8>,
+}
+
+impl<K, V> Db<K, V>
+where
+  K: Ord + Clone + CborWrite,
+  V: Clone + CborWrite,
+  BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  /// Sets the value of the key.
+  pub fn put(&mut self, key: K, value: V) -> io::Result<()> {
+    self.insert(key, Some(value))
+  }
+
+  /// Deletes the key by writing a tombstone, which hides its values in older tables.
+  pub fn delete(&mut self, key: K) -> io::Result<()> {
+    self.insert(key, None)
+  }
+
+  fn insert(&mut self, key: K, value: Option<V>) -> io::Result<()> {
+    // The encoded size is a fair estimate of the heap memory held by keys and values such as
+    // strings and bytes.
+    self.scratch.clear();
+    key.cbor_write(&mut self.scratch)?;
+    value.cbor_write(&mut self.scratch)?;
+    self.memtable_bytes += mem::size_of::<(K, Option<V>)>() + self.scratch.len();
+    self.memtable.insert(key, value);
+
+    if self.memtable_bytes >= self.memtable_limit {
+      self.flush()?;
+    }
+
+    Ok(())
+  }
+
+  /// Returns the newest value of the key, or `None` if it was deleted or never written.
+  pub fn get(&mut self, key: &K) -> io::Result<Option<V>> {
+    match self.memtable.get(key) {
+      Some(value) => Ok(value.clone()),
+      None => get_live(&mut self.tables, key),
+    }
+  }
+
+  /// Returns the newest value of every live key in the range, in order of their keys.
+  pub fn scan<B: RangeBounds<K>>(&mut self, range: B) -> io::Result<impl Iterator<Item = io::Result<(K, V)>> + '_> {
+    let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+
+    // Sources go from the oldest to the newest, so that keeping the last duplicate keeps the newest.
+    let mut sources: Vec<Box<dyn Iterator<Item = io::Result<(K, Option<V>)>> + '_>> = Vec::new();
+    for table in &mut self.tables {
+      sources.push(Box::new(table.range(bounds.clone())?));
+    }
+    let memtable = self.memtable.range(bounds);
+    sources.push(Box::new(memtable.map(|(key, value)| Ok((key.clone(), value.clone())))));
+
+    Ok(
+      MergingIterator::new(sources)
+        .resolve_duplicates(DuplicatePolicy::KeepLast)
+        .drop_tombstones(),
+    )
+  }
+
+  /// Writes the memtable to a new SSTable and adds it to the manifest. Does nothing if the memtable
+  /// is empty.
+  pub fn flush(&mut self) -> io::Result<()> {
+    if self.memtable.is_empty() {
+      return Ok(());
+    }
+
+    let name = format!("{:06}.sst", self.next_table_id);
+    let path = self.dir.join(&name);
+    // A table that isn't in the manifest is left over from a failed flush, and the writer appends.
+    for stale_path in [path.clone(), create_index_path(&path), create_bloom_path(&path)] {
+      fs::remove_file(stale_path).unwrap_or_default();
+    }
+
+    let mut writer = SSTableWriterBuilder::new(&path)
+      .bloom_filter(self.memtable.len(), DEFAULT_FALSE_POSITIVE_RATE)
+      .build()?;
+    for (key, value) in &self.memtable {
+      writer.write((key.clone(), value.clone()))?;
+    }
+    writer.close()?;
+    // The table is opened before it's listed, so that every table in the manifest is in `tables`.
+    let table = open_table(&path)?;
+
+    self.table_names.push(name);
+    if let Err(e) = write_manifest(&self.dir.join(MANIFEST_FILE_NAME), &self.table_names) {
+      self.table_names.pop();
+      return Err(e);
+    }
+    self.next_table_id += 1;
+    self.tables.push(table);
+    self.memtable.clear();
+    self.memtable_bytes = 0;
+
+    Ok(())
+  }
+
+  /// Flushes the memtable and closes the database.
+  pub fn close(mut self) -> io::Result<()> {
+    self.flush()
+  }
+
+  /// The number of live tables, not counting the memtable.
+  pub fn table_count(&self) -> usize {
+    self.tables.len()
+  }
+
+  /// The directory of the database.
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+
+  const TEST_DIR: &str = ".tmp/db_test";
+
+  fn setup_test_db(memtable_limit: usize) -> Db<String, String> {
+    setup::create_dir_all(".tmp").unwrap();
+    fs::remove_dir_all(TEST_DIR).unwrap_or_default();
+    DbBuilder::new(TEST_DIR).memtable_limit(memtable_limit).open().unwrap()
+  }
+
+  fn scan_all<B: RangeBounds<String>>(db: &mut Db<String, String>, range: B) -> Vec<String> {
+    db.scan(range)
+      .unwrap()
+      .map(|kv| kv.map(|(k, v)| format!("{}{}", k, v)))
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap()
+  }
+
+  #[test]
+  fn puts_gets_and_deletes_in_memtable() {
+    let _lock = setup::sequential();
+    let mut db = setup_test_db(DEFAULT_MEMTABLE_LIMIT);
+    db.put("a".to_string(), "1".to_string()).unwrap();
+    db.put("b".to_string(), "2".to_string()).unwrap();
+    db.put("a".to_string(), "3".to_string()).unwrap();
+    db.delete("b".to_string()).unwrap();
+
+    assert::equal(db.get(&"a".to_string()).unwrap(), "3".to_string());
+    assert::equal(db.get(&"b".to_string()).unwrap(), None);
+    assert::equal(db.table_count(), 0);
+  }
+
+  #[test]
+  fn newer_tables_shadow_older_tables() {
+    let _lock = setup::sequential();
+    let mut db = setup_test_db(DEFAULT_MEMTABLE_LIMIT);
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+      db.put(key.to_string(), value.to_string()).unwrap();
+    }
+    db.flush().unwrap();
+    db.put("a".to_string(), "4".to_string()).unwrap();
+    db.delete("b".to_string()).unwrap();
+    db.flush().unwrap();
+    db.put("d".to_string(), "5".to_string()).unwrap();
+
+    assert::equal(db.table_count(), 2);
+    assert::equal(db.get(&"a".to_string()).unwrap(), "4".to_string());
+    assert::equal(db.get(&"b".to_string()).unwrap(), None);
+    assert::equal(db.get(&"c".to_string()).unwrap(), "3".to_string());
+    assert::equal(scan_all(&mut db, ..), vec!["a4", "c3", "d5"]);
+    assert::equal(scan_all(&mut db, "b".to_string().."d".to_string()), vec!["c3"]);
+
+    db.delete("c".to_string()).unwrap();
+    assert::equal(scan_all(&mut db, ..), vec!["a4", "d5"]);
+  }
+
+  #[test]
+  fn flushes_when_memtable_is_full_and_reopens() {
+    let _lock = setup::sequential();
+    let mut db = setup_test_db(100);
+    for i in 0..100 {
+      db.put(format!("{:03}", i), i.to_string()).unwrap();
+    }
+    assert!(db.table_count() > 5);
+    db.close().unwrap();
+
+    let mut db = DbBuilder::new(TEST_DIR).open::<String, String>().unwrap();
+    assert::equal(db.get(&"042".to_string()).unwrap(), "42".to_string());
+    assert::equal(scan_all(&mut db, ..).len(), 100);
+
+    // Tables that aren't in the manifest are ignored.
+    let table_count = db.table_count();
+    fs::write(Path::new(TEST_DIR).join("999999.sst"), [0xFF]).unwrap();
+    let db = DbBuilder::new(TEST_DIR).open::<String, String>().unwrap();
+    assert::equal(db.table_count(), table_count);
+  }
+
+  #[test]
+  fn lookups_seek_with_the_index() {
+    let _lock = setup::sequential();
+    let mut db = setup_test_db(DEFAULT_MEMTABLE_LIMIT);
+    for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+      db.put(key.to_string(), value.to_string()).unwrap();
+    }
+    db.close().unwrap();
+
+    // The key of the first entry isn't text, so only a lookup that seeks past it can succeed.
+    let path = Path::new(TEST_DIR).join("000000.sst");
+    let mut data = fs::read(&path).unwrap();
+    data[1] = 0xff;
+    fs::write(&path, data).unwrap();
+
+    let mut db = DbBuilder::new(TEST_DIR).open::<String, String>().unwrap();
+    assert::equal(db.get(&"c".to_string()).unwrap(), "3".to_string());
+    assert::equal(scan_all(&mut db, "c".to_string()..), vec!["c3"]);
+    assert!(db.scan(..).unwrap().any(|kv| kv.is_err()));
+  }
+}
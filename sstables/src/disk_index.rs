@@ -0,0 +1,390 @@
+//! Binary search over an index file on disk.
+//!
+//! `SSTableIndex` reads every entry of an index into memory before the first lookup, which takes
+//! seconds and gigabytes for a multi-GB index. The entries of the index have different sizes, so
+//! entry N can't be found without reading the N entries before it. With
+//! `SSTableWriterBuilder::index_offsets`, the writer also keeps an offset table beside the index
+//! (`foo.offsets.sst`) with the position of each index entry in the index file. Every position has
+//! the same width, so entry N of the index is found by reading position N of the offset table.
+//!
+//! `DiskIndex` binary searches the index through the offset table, so a lookup reads O(log n)
+//! entries of each file and nothing is held in memory. Like the in-memory index, it can only be
+//! searched if its keys are sorted. `SSTable::open_with_disk_index` uses it to look up keys.
+//!
+//! # Format
+//!
+//! The offset table is a CBOR sequence, like the index. Each position is an unsigned integer
+//! encoded in its widest form, a `0x1B` head followed by eight big-endian bytes, so that every
+//! position takes nine bytes.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::disk_index::DiskIndex;
+//! use sstables::SSTableWriterBuilder;
+//!
+//! let path = std::env::temp_dir().join("disk_index_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_offsets_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).index_offsets().build().unwrap();
+//! writer.write(("apple", "1")).unwrap();
+//! writer.write(("banana", "2")).unwrap();
+//! writer.write(("cherry", "3")).unwrap();
+//! writer.close().unwrap();
+//!
+//! let mut index = DiskIndex::<String>::open(&path).unwrap();
+//! assert_eq!(index.len(), 3);
+//! assert_eq!(index.entry(1).unwrap(), ("banana".to_string(), 8));
+//! assert_eq!(index.partition_point(|key| key.as_str() < "b").unwrap(), 1);
+//! ```
+
+use std::{
+  fmt,
+  fs::{File, OpenOptions},
+  io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+  marker::PhantomData,
+  path::Path,
+};
+
+use crate::{
+  cbor::{read_cbor_raw, read_cbor_u64, CborRead},
+  read::{create_index_offsets_path, create_index_path},
+};
+
+/// The size of each position in the offset table.
+pub const INDEX_OFFSET_SIZE: u64 = 9;
+
+/// The CBOR head of an unsigned integer followed by eight bytes.
+const U64_HEAD: u8 = 0x1B;
+
+/// Writes the position of an index entry to the offset table.
+pub(crate) fn write_index_offset<W: Write>(writer: &mut W, position: u64) -> io::Result<()> {
+  writer.write_all(&[U64_HEAD])?;
+  writer.write_all(&position.to_be_bytes())
+}
+
+/// Reads the position at the current place in an offset table.
+fn read_index_offset<R: Read>(reader: &mut R) -> io::Result<u64> {
+  let mut bytes = [0; INDEX_OFFSET_SIZE as usize];
+  reader.read_exact(&mut bytes)?;
+  if bytes[0] != U64_HEAD {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Expected a nine byte unsigned integer in the offset table",
+    ));
+  }
+  read_cbor_u64(&mut bytes.as_slice())
+}
+
+/// Reads an index entry without decoding it, returning its size.
+fn skip_index_entry<R: Read>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<u64> {
+  buf.clear();
+  read_cbor_raw(reader, buf)?;
+  read_cbor_raw(reader, buf)?;
+  Ok(buf.len() as u64)
+}
+
+/// Brings the offset table of a table up to date with its index file, which must already be
+/// recovered. Positions past the end of the index and a partial position are removed, and the
+/// entries after the last position are added, which also creates the table for an index that had
+/// none. Returns the number of positions added.
+pub(crate) fn recover_index_offsets(index_path: &Path, offsets_path: &Path) -> io::Result<u64> {
+  let index_size = match index_path.metadata() {
+    Ok(metadata) => metadata.len(),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+    Err(e) => return Err(e),
+  };
+
+  // The positions increase, so only the last ones can be past the end of the index.
+  let mut offsets_file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(offsets_path)?;
+  let mut count = offsets_file.metadata()?.len() / INDEX_OFFSET_SIZE;
+  let mut last_position = None;
+  while count > 0 {
+    offsets_file.seek(SeekFrom::Start((count - 1) * INDEX_OFFSET_SIZE))?;
+    let position = read_index_offset(&mut offsets_file)?;
+    if position < index_size {
+      last_position = Some(position);
+      break;
+    }
+    count -= 1;
+  }
+  offsets_file.set_len(count * INDEX_OFFSET_SIZE)?;
+  offsets_file.seek(SeekFrom::End(0))?;
+
+  if index_size == 0 {
+    return Ok(0);
+  }
+
+  // Add the positions of the entries after the last one with a position.
+  let mut index_reader = BufReader::new(File::open(index_path)?);
+  let mut position = index_reader.seek(SeekFrom::Start(last_position.unwrap_or(0)))?;
+  let mut entry_bytes = Vec::new();
+  let mut restored = Vec::new();
+  let mut restored_offsets = 0;
+  while !index_reader.fill_buf()?.is_empty() {
+    if last_position != Some(position) {
+      write_index_offset(&mut restored, position)?;
+      restored_offsets += 1;
+    }
+    position += skip_index_entry(&mut index_reader, &mut entry_bytes)?;
+  }
+  offsets_file.write_all(&restored)?;
+
+  Ok(restored_offsets)
+}
+
+/// An index that is binary searched on disk through its offset table, without loading it into
+/// memory. Reads from files by default, but can read from any source that implements `Read` and
+/// `Seek`, such as a `Cursor` over bytes.
+pub struct DiskIndex<K, R = BufReader<File>> {
+  index_reader: R,
+  offsets_reader: R,
+  len: u64,
+  /// Reads a key, so that the searches don't need to know how.
+  read_key: fn(&mut R) -> io::Result<K>,
+  phantom: PhantomData<K>,
+}
+
+impl<K, R> fmt::Debug for DiskIndex<K, R> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DiskIndex")
+      .field("len", &self.len)
+      .finish_non_exhaustive()
+  }
+}
+
+impl<K> DiskIndex<K>
+where
+  BufReader<File>: CborRead<K>,
+{
+  /// Opens the index and offset table beside the data file at the given path.
+  pub fn open<P: AsRef<Path>>(data_path: P) -> io::Result<Self> {
+    let data_path = data_path.as_ref();
+    DiskIndex::new(
+      BufReader::new(File::open(create_index_path(data_path))?),
+      BufReader::new(File::open(create_index_offsets_path(data_path))?),
+    )
+  }
+}
+
+impl<K, R> DiskIndex<K, R>
+where
+  R: Read + Seek,
+{
+  /// Pairs an index with its offset table. The last entry of the index is read to check that the
+  /// offset table ends with it, since a table that doesn't belong to the index would send the
+  /// searches to the wrong places.
+  pub fn new(mut index_reader: R, mut offsets_reader: R) -> io::Result<Self>
+  where
+    R: CborRead<K>,
+  {
+    let offsets_size = offsets_reader.seek(SeekFrom::End(0))?;
+    let index_size = index_reader.seek(SeekFrom::End(0))?;
+    let len = offsets_size / INDEX_OFFSET_SIZE;
+
+    let last_entry_end = match len {
+      0 => 0,
+      _ => {
+        offsets_reader.seek(SeekFrom::Start((len - 1) * INDEX_OFFSET_SIZE))?;
+        let position = read_index_offset(&mut offsets_reader)?;
+        index_reader.seek(SeekFrom::Start(position))?;
+        position + skip_index_entry(&mut index_reader, &mut Vec::new())?
+      }
+    };
+    if offsets_size % INDEX_OFFSET_SIZE != 0 || last_entry_end != index_size {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The offset table does not match the index",
+      ));
+    }
+
+    Ok(DiskIndex {
+      index_reader,
+      offsets_reader,
+      len,
+      read_key: |reader| CborRead::<K>::cbor_read(reader),
+      phantom: PhantomData,
+    })
+  }
+
+  /// The number of entries in the index.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  /// Whether the index has no entries.
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Reads the key and data offset of the entry at the position in the index.
+  pub fn entry(&mut self, i: u64) -> io::Result<(K, u64)> {
+    if i >= self.len {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+          "Index entry {} is out of bounds for an index of {} entries",
+          i, self.len
+        ),
+      ));
+    }
+
+    self.offsets_reader.seek(SeekFrom::Start(i * INDEX_OFFSET_SIZE))?;
+    let position = read_index_offset(&mut self.offsets_reader)?;
+    self.index_reader.seek(SeekFrom::Start(position))?;
+    let key = (self.read_key)(&mut self.index_reader)?;
+    let offset = read_cbor_u64(&mut self.index_reader)?;
+    Ok((key, offset))
+  }
+
+  /// Returns the position of the first entry whose key doesn't match the predicate, assuming that
+  /// the keys that match all come first, like `slice::partition_point`.
+  pub fn partition_point<F>(&mut self, mut pred: F) -> io::Result<u64>
+  where
+    F: FnMut(&K) -> bool,
+  {
+    let (mut low, mut high) = (0, self.len);
+    while low < high {
+      let mid = low + (high - low) / 2;
+      let (key, _) = self.entry(mid)?;
+      if pred(&key) {
+        low = mid + 1;
+      } else {
+        high = mid;
+      }
+    }
+    Ok(low)
+  }
+
+  /// Returns the data offset to scan from to find the first entry with the key, which is the
+  /// offset of the last index entry before the key. Returns `None` if the key comes before every
+  /// index entry, so the scan starts at the first entry of the data file. Works for dense and
+  /// sparse indices, as long as the keys are sorted.
+  pub fn seek_offset(&mut self, key: &K) -> io::Result<Option<u64>>
+  where
+    K: Ord,
+  {
+    match self.partition_point(|k| k < key)? {
+      0 => Ok(None),
+      x => self.entry(x - 1).map(|(_, offset)| Some(offset)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{sstable_writer::IndexDensity, FromPath, SSTable, SSTableIndex, SSTableWriter, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::{fs, io::Cursor};
+
+  const TEST_FILE_NAME: &str = ".tmp/disk_index_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/disk_index_test.index.sst";
+  const TEST_OFFSETS_FILE_NAME: &str = ".tmp/disk_index_test.offsets.sst";
+
+  fn build_writer(index_density: IndexDensity) -> SSTableWriter {
+    SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(index_density)
+      .index_offsets()
+      .build()
+      .unwrap()
+  }
+
+  fn setup_test_sstable(index_density: IndexDensity, keys: &[&str]) {
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [TEST_FILE_NAME, TEST_INDEX_FILE_NAME, TEST_OFFSETS_FILE_NAME] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    let mut writer = build_writer(index_density);
+    for key in keys {
+      writer.write((*key, "value")).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  fn read_all(index: &mut DiskIndex<String>) -> Vec<(String, u64)> {
+    (0..index.len()).map(|i| index.entry(i).unwrap()).collect()
+  }
+
+  #[test]
+  fn reads_the_entries_of_the_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, &["a", "b", "b", "c", "d"]);
+
+    let mut index = DiskIndex::<String>::open(TEST_FILE_NAME).unwrap();
+    let in_memory = SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(read_all(&mut index), in_memory.indices.clone());
+    assert!(index.entry(5).is_err());
+
+    assert::equal(index.partition_point(|key| key.as_str() < "b").unwrap(), 1);
+    assert::equal(index.partition_point(|key| key.as_str() <= "b").unwrap(), 3);
+    assert::equal(index.seek_offset(&"a".to_string()).unwrap(), None);
+    assert::equal(
+      index.seek_offset(&"c".to_string()).unwrap(),
+      Some(in_memory.indices[2].1),
+    );
+  }
+
+  #[test]
+  fn keeps_up_with_appends_and_recovery() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::EveryRecords(2), &["a", "b", "c"]);
+
+    // A lost offset table is rebuilt, and a torn one is repaired, when the table is opened.
+    fs::remove_file(TEST_OFFSETS_FILE_NAME).unwrap();
+    let mut writer = build_writer(IndexDensity::EveryRecords(2));
+    writer.write(("d", "value")).unwrap();
+    writer.write(("e", "value")).unwrap();
+    writer.close().unwrap();
+    let mut offsets = fs::read(TEST_OFFSETS_FILE_NAME).unwrap();
+    offsets.extend_from_slice(&[U64_HEAD, 0, 0, 0, 0, 0, 0, 1, 0]);
+    fs::write(TEST_OFFSETS_FILE_NAME, offsets).unwrap();
+    assert!(DiskIndex::<String>::open(TEST_FILE_NAME).is_err());
+    build_writer(IndexDensity::EveryRecords(2)).close().unwrap();
+
+    let mut index = DiskIndex::<String>::open(TEST_FILE_NAME).unwrap();
+    let in_memory = SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(read_all(&mut index), in_memory.indices);
+    assert::equal(index.len(), 3);
+  }
+
+  #[test]
+  fn looks_up_keys_with_the_sstable() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::EveryRecords(3), &["a", "b", "c", "d", "e", "f", "g"]);
+
+    let mut sstable = SSTable::<String, String>::open_with_disk_index(TEST_FILE_NAME).unwrap();
+    assert!(sstable.index.is_none());
+    for key in ["a", "c", "d", "g"] {
+      assert::equal(sstable.get(&key.to_string()).unwrap(), Some("value".to_string()));
+    }
+    assert::equal(sstable.get(&"h".to_string()).unwrap(), None);
+    assert::equal(sstable.range("b".to_string().."e".to_string()).unwrap().count(), 3);
+  }
+
+  #[test]
+  fn reads_from_any_source() {
+    let mut index_bytes = Vec::new();
+    let mut offsets_bytes = Vec::new();
+    for (i, key) in ["a", "bb", "ccc"].into_iter().enumerate() {
+      write_index_offset(&mut offsets_bytes, index_bytes.len() as u64).unwrap();
+      crate::cbor::CborWrite::cbor_write(&key, &mut index_bytes).unwrap();
+      crate::cbor::CborWrite::cbor_write(&(i as u64 * 10), &mut index_bytes).unwrap();
+    }
+
+    let mut index = DiskIndex::<String, _>::new(Cursor::new(index_bytes.clone()), Cursor::new(offsets_bytes)).unwrap();
+    assert::equal(index.entry(2).unwrap(), ("ccc".to_string(), 20));
+    assert::equal(index.seek_offset(&"c".to_string()).unwrap(), Some(10));
+
+    // An offset table that doesn't end with the last entry of the index is refused.
+    let mut offsets_bytes = Vec::new();
+    write_index_offset(&mut offsets_bytes, 0).unwrap();
+    assert!(DiskIndex::<String, _>::new(Cursor::new(index_bytes), Cursor::new(offsets_bytes)).is_err());
+  }
+}
@@ -0,0 +1,426 @@
+//! External merge sort for tables that are larger than memory.
+//!
+//! Entries are collected into chunks until they reach a memory limit. Each full chunk is sorted and
+//! written to a temporary SSTable called a run, and when every entry has been pushed the runs are
+//! merged into a single sorted stream with a `MergingIterator`. Only one entry per run is held in memory
+//! during the merge, and each run is read sequentially, so the input can be many times larger than
+//! memory. Inputs that fit in a single chunk are sorted in memory without touching the disk.
+//!
+//! Each run being merged holds an open file, so at most `DEFAULT_MAX_FAN_IN` runs are merged at
+//! once. When there are more, neighbouring runs are first merged into larger runs, in as many passes
+//! as it takes, which keeps the sort within the open file limit of the process.
+//!
+//! The sort is stable, so entries with equal keys stay in the order they were pushed.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::external_sort::ExternalSorterBuilder;
+//!
+//! let mut sorter = ExternalSorterBuilder::new().memory_limit(64).build::<String, String>();
+//! for (key, value) in [("c", "1"), ("a", "2"), ("b", "3"), ("a", "4")] {
+//!   sorter.push((key.to_string(), value.to_string())).unwrap();
+//! }
+//!
+//! let sorted = sorter.finish().unwrap().collect::<std::io::Result<Vec<_>>>().unwrap();
+//! let keys = sorted.iter().map(|(k, v)| format!("{}{}", k, v)).collect::<Vec<_>>();
+//! assert_eq!(keys, ["a2", "a4", "b3", "c1"]);
+//! ```
+
+use std::{
+  fs::{self, File},
+  io::{self, BufWriter},
+  mem,
+  path::PathBuf,
+  process,
+  sync::atomic::{self, AtomicUsize},
+  vec,
+};
+
+use crate::{
+  cbor::{CborRead, CborWrite},
+  merge::MergingIterator,
+  FromPath, SSTableReader, SSTableWriterBuilder,
+};
+
+/// The default amount of memory used to hold entries before they are written to a run, 64 MiB.
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The default number of runs that are merged at once, each of which holds an open file.
+pub const DEFAULT_MAX_FAN_IN: usize = 64;
+
+/// Makes temporary file names unique within this process. The process id makes them unique
+/// between processes.
+static NEXT_SORT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Builder for an ExternalSorter.
+pub struct ExternalSorterBuilder {
+  memory_limit: usize,
+  max_fan_in: usize,
+  temp_dir: PathBuf,
+}
+
+impl Default for ExternalSorterBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ExternalSorterBuilder {
+  pub fn new() -> Self {
+    ExternalSorterBuilder {
+      memory_limit: DEFAULT_MEMORY_LIMIT,
+      max_fan_in: DEFAULT_MAX_FAN_IN,
+      temp_dir: std::env::temp_dir(),
+    }
+  }
+
+  /// The approximate number of bytes of entries to hold in memory before sorting them and
+  /// writing them to a run. Defaults to `DEFAULT_MEMORY_LIMIT`.
+  pub fn memory_limit(mut self, bytes: usize) -> Self {
+    self.memory_limit = bytes;
+    self
+  }
+
+  /// The most runs to merge at once, which is the most files the merge holds open. Runs beyond it
+  /// are merged into larger runs first. Defaults to `DEFAULT_MAX_FAN_IN`, and can't be less than 2.
+  pub fn max_fan_in(mut self, runs: usize) -> Self {
+    self.max_fan_in = runs.max(2);
+    self
+  }
+
+  /// The directory to write runs to, which needs room for a copy of the input. Defaults to the
+  /// system's temporary directory.
+  pub fn temp_dir<P: Into<PathBuf>>(mut self, path: P) -> Self {
+    self.temp_dir = path.into();
+    self
+  }
+
+  pub fn build<K, V>(self) -> ExternalSorter<K, V> {
+    ExternalSorter {
+      memory_limit: self.memory_limit,
+      max_fan_in: self.max_fan_in,
+      chunk: Vec::new(),
+      chunk_bytes: 0,
+      runs: Runs {
+        temp_dir: self.temp_dir,
+        sort_id: NEXT_SORT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+        paths: Vec::new(),
+      },
+      len: 0,
+      scratch: Vec::new(),
+    }
+  }
+}
+
+/// The temporary files of a sort, which are removed when it is dropped.
+struct Runs {
+  temp_dir: PathBuf,
+  sort_id: usize,
+  paths: Vec<PathBuf>,
+}
+
+impl Runs {
+  fn next_path(&self) -> PathBuf {
+    self.temp_dir.join(format!(
+      "sstables-sort-{}-{}-{}.sst",
+      process::id(),
+      self.sort_id,
+      self.paths.len()
+    ))
+  }
+}
+
+impl Drop for Runs {
+  fn drop(&mut self) {
+    for path in &self.paths {
+      // The file may not exist if creating it failed, and there's nothing to do about other errors.
+      fs::remove_file(path).unwrap_or_default();
+    }
+  }
+}
+
+/// Sorts entries by key using bounded memory, spilling sorted runs to temporary SSTables.
+pub struct ExternalSorter<K, V> {
+  memory_limit: usize,
+  max_fan_in: usize,
+  chunk: Vec<(K, V)>,
+  chunk_bytes: usize,
+  runs: Runs,
+  len: u64,
+  scratch: Vec<u8>,
+}
+
+impl<K, V> ExternalSorter<K, V>
+where
+  K: Ord + CborWrite,
+  V: CborWrite,
+{
+  /// Adds an entry, writing a run if the current chunk has reached the memory limit.
+  pub fn push(&mut self, entry: (K, V)) -> io::Result<()> {
+    // The encoded size is a fair estimate of the heap memory held by keys and values such as
+    // strings and bytes.
+    self.scratch.clear();
+    entry.0.cbor_write(&mut self.scratch)?;
+    entry.1.cbor_write(&mut self.scratch)?;
+    self.chunk_bytes += mem::size_of::<(K, V)>() + self.scratch.len();
+    self.chunk.push(entry);
+    self.len += 1;
+
+    if self.chunk_bytes >= self.memory_limit {
+      self.write_run()?;
+    }
+
+    Ok(())
+  }
+
+  /// The number of entries pushed so far.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// The number of runs written to disk so far, not counting runs written by merging other runs.
+  pub fn run_count(&self) -> usize {
+    self.runs.paths.len()
+  }
+
+  /// Sorts the current chunk and writes it to a new run.
+  fn write_run(&mut self) -> io::Result<()> {
+    let mut chunk = mem::take(&mut self.chunk);
+    self.chunk_bytes = 0;
+    chunk.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let path = self.runs.next_path();
+    self.runs.paths.push(path.clone());
+    let data_writer = BufWriter::new(File::create(&path)?);
+    let mut writer = SSTableWriterBuilder::new(&path).build_with_writers(data_writer, io::sink())?;
+    for entry in chunk {
+      writer.write(entry)?;
+    }
+    writer.flush()
+  }
+
+  /// Finishes pushing entries and returns them in order of their keys.
+  pub fn finish(mut self) -> io::Result<SortedEntries<K, V>>
+  where
+    io::BufReader<File>: CborRead<K> + CborRead<V>,
+  {
+    if self.runs.paths.is_empty() {
+      let mut chunk = mem::take(&mut self.chunk);
+      chunk.sort_by(|a, b| a.0.cmp(&b.0));
+      return Ok(SortedEntries {
+        chunk: chunk.into_iter(),
+        merge: None,
+      });
+    }
+
+    if !self.chunk.is_empty() {
+      self.write_run()?;
+    }
+
+    // Merge neighbouring runs until few enough are left, so that equal keys stay in push order.
+    let mut paths = self.runs.paths.clone();
+    while paths.len() > self.max_fan_in {
+      paths = paths
+        .chunks(self.max_fan_in)
+        .map(|group| self.merge_runs(group))
+        .collect::<io::Result<Vec<_>>>()?;
+    }
+
+    Ok(SortedEntries {
+      chunk: Vec::new().into_iter(),
+      merge: Some((MergingIterator::new(open_runs::<K, V>(&paths)?), self.runs)),
+    })
+  }
+
+  /// Merges the runs into a new run and removes them, returning the path of the new run.
+  fn merge_runs(&mut self, paths: &[PathBuf]) -> io::Result<PathBuf>
+  where
+    io::BufReader<File>: CborRead<K> + CborRead<V>,
+  {
+    if let [path] = paths {
+      return Ok(path.clone());
+    }
+
+    let path = self.runs.next_path();
+    self.runs.paths.push(path.clone());
+    let data_writer = BufWriter::new(File::create(&path)?);
+    let mut writer = SSTableWriterBuilder::new(&path).build_with_writers(data_writer, io::sink())?;
+    for entry in MergingIterator::new(open_runs::<K, V>(paths)?) {
+      writer.write(entry?)?;
+    }
+    writer.flush()?;
+
+    for path in paths {
+      fs::remove_file(path)?;
+    }
+    Ok(path)
+  }
+}
+
+fn open_runs<K, V>(paths: &[PathBuf]) -> io::Result<Vec<SSTableReader<(K, V)>>> {
+  paths.iter().map(SSTableReader::<(K, V)>::from_path).collect()
+}
+
+/// The entries of an `ExternalSorter` in order of their keys. Temporary runs are removed when this
+/// is dropped.
+pub struct SortedEntries<K, V> {
+  chunk: vec::IntoIter<(K, V)>,
+  /// The merge of the runs, and the runs so that they are removed after the merge is dropped.
+  merge: Option<(MergingIterator<K, V, SSTableReader<(K, V)>>, Runs)>,
+}
+
+impl<K, V> Iterator for SortedEntries<K, V>
+where
+  K: Ord,
+  SSTableReader<(K, V)>: Iterator<Item = io::Result<(K, V)>>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match &mut self.merge {
+      Some((merging_iterator, _)) => merging_iterator.next(),
+      None => self.chunk.next().map(Ok),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::SSTable;
+  use common_testing::{assert, setup};
+
+  const TEST_TEMP_DIR: &str = ".tmp/external_sort_test";
+  const TEST_FILE_NAME: &str = ".tmp/external_sort_test.sst";
+
+  /// Keys in a scrambled order with many duplicates, and values that record the push order.
+  fn scrambled_entries(n: u64) -> Vec<(u64, u64)> {
+    (0..n).map(|i| ((i * 7919) % 97, i)).collect()
+  }
+
+  fn sort_entries(memory_limit: usize, entries: &[(u64, u64)]) -> (usize, Vec<(u64, u64)>) {
+    setup::create_dir_all(TEST_TEMP_DIR).unwrap();
+    let mut sorter = ExternalSorterBuilder::new()
+      .memory_limit(memory_limit)
+      .temp_dir(TEST_TEMP_DIR)
+      .build();
+    for entry in entries {
+      sorter.push(*entry).unwrap();
+    }
+    let run_count = sorter.run_count();
+    let sorted = sorter.finish().unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+    (run_count, sorted)
+  }
+
+  #[test]
+  fn sorts_in_memory() {
+    let _lock = setup::sequential();
+    let entries = scrambled_entries(1000);
+    let (run_count, sorted) = sort_entries(DEFAULT_MEMORY_LIMIT, &entries);
+
+    let mut expected = entries.clone();
+    expected.sort_by_key(|(k, _)| *k);
+    assert::equal(run_count, 0);
+    assert::equal(sorted, expected);
+  }
+
+  #[test]
+  fn sorts_with_runs_and_keeps_duplicates_in_order() {
+    let _lock = setup::sequential();
+    let entries = scrambled_entries(1000);
+    let (run_count, sorted) = sort_entries(500, &entries);
+
+    let mut expected = entries.clone();
+    expected.sort_by_key(|(k, _)| *k);
+    assert!(run_count > 10);
+    assert::equal(sorted, expected);
+  }
+
+  #[test]
+  fn removes_runs_when_dropped() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(TEST_TEMP_DIR).unwrap();
+    let mut sorter = ExternalSorterBuilder::new()
+      .memory_limit(1)
+      .temp_dir(TEST_TEMP_DIR)
+      .build();
+    for entry in scrambled_entries(10) {
+      sorter.push(entry).unwrap();
+    }
+    let paths = sorter.runs.paths.clone();
+    assert::equal(paths.len(), 10);
+    assert!(paths.iter().all(|path| path.is_file()));
+
+    let mut sorted = sorter.finish().unwrap();
+    assert::equal(sorted.next().unwrap().unwrap(), (0, 0));
+    drop(sorted);
+    assert!(paths.iter().all(|path| !path.exists()));
+  }
+
+  #[test]
+  fn merges_runs_in_passes_and_keeps_duplicates_in_order() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(TEST_TEMP_DIR).unwrap();
+    let entries = scrambled_entries(100);
+    let mut sorter = ExternalSorterBuilder::new()
+      .memory_limit(100)
+      .max_fan_in(3)
+      .temp_dir(TEST_TEMP_DIR)
+      .build();
+    for entry in &entries {
+      sorter.push(*entry).unwrap();
+    }
+    let run_count = sorter.run_count();
+    let sorted = sorter.finish().unwrap();
+
+    // Only the last pass of runs is left, and the merged runs are removed.
+    let (merging_iterator, runs) = sorted.merge.as_ref().unwrap();
+    assert!(run_count > 9);
+    assert!(runs.paths.len() > run_count);
+    assert!(merging_iterator.source_count() <= 3);
+    assert::equal(
+      runs.paths.iter().filter(|path| path.exists()).count(),
+      merging_iterator.source_count(),
+    );
+
+    let mut expected = entries.clone();
+    expected.sort_by_key(|(k, _)| *k);
+    assert::equal(sorted.collect::<io::Result<Vec<_>>>().unwrap(), expected);
+  }
+
+  #[test]
+  fn writes_sorted_sstable_with_index() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(TEST_TEMP_DIR).unwrap();
+    setup::remove_file(TEST_FILE_NAME).unwrap();
+    setup::remove_file(".tmp/external_sort_test.index.sst").unwrap();
+
+    let mut sorter = ExternalSorterBuilder::new()
+      .memory_limit(100)
+      .temp_dir(TEST_TEMP_DIR)
+      .build();
+    for (key, value) in [("c", "1"), ("a", "2"), ("d", "3"), ("b", "4"), ("a", "5")] {
+      sorter.push((key.to_string(), value.to_string())).unwrap();
+    }
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    for entry in sorter.finish().unwrap() {
+      writer.write(entry.unwrap()).unwrap();
+    }
+    writer.close().unwrap();
+
+    let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(sstable.get(&"b".to_string()).unwrap(), "4".to_string());
+    let values = sstable
+      .get_all(&"a".to_string())
+      .unwrap()
+      .map(|kv| kv.map(|(_, v)| v))
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap();
+    assert::equal(values, vec!["2", "5"]);
+  }
+}
@@ -0,0 +1,283 @@
+//! Self-describing header
+//!
+//! A bare data file is only a sequence of keys and values, so it gives no sign of which variant of
+//! the format wrote it. With `SSTableWriterBuilder::header`, a new data file starts with a header
+//! that says so: the format version, the index density, whether the table has checksums, and
+//! optionally the type of its keys.
+//!
+//! Readers detect the header and skip it, and files without one are read as before, so old files
+//! stay readable. The offsets in the index are positions in the data file, so they count the header.
+//!
+//! # Format
+//!
+//! The header is a single CBOR data item: the self-describe tag 55799 (RFC 8949 Section 3.4.6)
+//! wrapping a map with text keys. The tag's encoding, `0xD9 0xD9 0xF7`, doesn't start any other
+//! item this library writes, which makes the header easy to detect, and lets tools like `file`
+//! recognize the data file as CBOR. The map holds:
+//!
+//! - `format`: the text `sstable`.
+//! - `version`: the format version of the library that wrote the file.
+//! - `min_version`: the oldest format version that can read the file.
+//! - `index_density`: `dense`, or an array of `every_records` or `every_bytes` and N.
+//! - `checksums`: whether the writer kept a checksum file.
+//! - `key_type`: the type of the keys, if the writer was told.
+//!
+//! Readers ignore keys they don't know. A reader refuses a file whose `min_version` is newer than
+//! the version it supports, so that a later format change can declare whether older readers can
+//! still read it.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::header::{load_header, FORMAT_VERSION};
+//! use sstables::{SSTableReader, SSTableWriterBuilder, FromPath};
+//!
+//! let path = std::env::temp_dir().join("header_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).key_type("text").build().unwrap();
+//! writer.write(("hello", "world")).unwrap();
+//! writer.close().unwrap();
+//!
+//! let header = load_header(&path).unwrap().unwrap();
+//! assert_eq!(header.version, FORMAT_VERSION);
+//! assert_eq!(header.key_type.as_deref(), Some("text"));
+//!
+//! let entries = SSTableReader::<(String, String)>::from_path(&path).unwrap();
+//! assert_eq!(entries.count(), 1);
+//! ```
+
+use crate::cbor::{read_cbor_raw, read_cbor_value, write_cbor_head, write_cbor_text, CborValue, CborWrite, MajorType};
+use crate::sstable_writer::IndexDensity;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// The self-describe tag, which marks the data item it tags as CBOR.
+pub const SELF_DESCRIBE_TAG: u64 = 55799;
+
+/// The encoding of the self-describe tag, which starts every header.
+pub const SELF_DESCRIBE_PREFIX: [u8; 3] = [0xD9, 0xD9, 0xF7];
+
+/// The name of the format in the header.
+pub const FORMAT_NAME: &str = "sstable";
+
+/// The newest format version this library reads and writes.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// The header of a data file. See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+  /// The format version of the library that wrote the file.
+  pub version: u64,
+  /// The oldest format version that can read the file.
+  pub min_version: u64,
+  /// How densely the data file is indexed.
+  pub index_density: IndexDensity,
+  /// Whether the writer kept a checksum file.
+  pub checksums: bool,
+  /// The type of the keys, if the writer was told, such as `text` or `integer`.
+  pub key_type: Option<String>,
+}
+
+impl Header {
+  /// Creates a header for a file written by this version of the library.
+  pub fn new(index_density: IndexDensity, checksums: bool, key_type: Option<String>) -> Self {
+    Header {
+      version: FORMAT_VERSION,
+      min_version: 1,
+      index_density,
+      checksums,
+      key_type,
+    }
+  }
+
+  /// Writes the header as a tagged CBOR map.
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_cbor_head(writer, MajorType::SemanticTag, SELF_DESCRIBE_TAG)?;
+    let pairs = if self.key_type.is_some() { 6 } else { 5 };
+    write_cbor_head(writer, MajorType::Object, pairs)?;
+    write_cbor_text(writer, "format")?;
+    write_cbor_text(writer, FORMAT_NAME)?;
+    write_cbor_text(writer, "version")?;
+    self.version.cbor_write(writer)?;
+    write_cbor_text(writer, "min_version")?;
+    self.min_version.cbor_write(writer)?;
+    write_cbor_text(writer, "index_density")?;
+    match self.index_density {
+      IndexDensity::Dense => write_cbor_text(writer, "dense")?,
+      IndexDensity::EveryRecords(n) => {
+        write_cbor_head(writer, MajorType::Array, 2)?;
+        write_cbor_text(writer, "every_records")?;
+        n.cbor_write(writer)?;
+      }
+      IndexDensity::EveryBytes(n) => {
+        write_cbor_head(writer, MajorType::Array, 2)?;
+        write_cbor_text(writer, "every_bytes")?;
+        n.cbor_write(writer)?;
+      }
+    }
+    write_cbor_text(writer, "checksums")?;
+    CborValue::Bool(self.checksums).cbor_write(writer)?;
+    if let Some(key_type) = &self.key_type {
+      write_cbor_text(writer, "key_type")?;
+      write_cbor_text(writer, key_type)?;
+    }
+    Ok(())
+  }
+
+  /// Returns the encoded header.
+  pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    self.write_to(&mut bytes)?;
+    Ok(bytes)
+  }
+
+  /// Parses the tagged map of a header, checking that this library can read the file.
+  fn from_value(value: CborValue) -> io::Result<Self> {
+    let CborValue::Tag(SELF_DESCRIBE_TAG, value) = value else {
+      return Err(invalid_data("Expected a self-described header"));
+    };
+    let CborValue::Map(pairs) = *value else {
+      return Err(invalid_data("Expected a map in the header"));
+    };
+
+    let mut format = None;
+    let mut header = Header::new(IndexDensity::Dense, false, None);
+    for (key, value) in pairs {
+      let CborValue::Text(key) = key else {
+        continue;
+      };
+      match (key.as_str(), value) {
+        ("format", CborValue::Text(name)) => format = Some(name),
+        ("version", CborValue::Integer(version)) => header.version = to_u64(version)?,
+        ("min_version", CborValue::Integer(version)) => header.min_version = to_u64(version)?,
+        ("index_density", value) => header.index_density = to_index_density(value)?,
+        ("checksums", CborValue::Bool(checksums)) => header.checksums = checksums,
+        ("key_type", CborValue::Text(key_type)) => header.key_type = Some(key_type),
+        _ => {}
+      }
+    }
+
+    if format.as_deref() != Some(FORMAT_NAME) {
+      return Err(invalid_data("The header is not for an SSTable"));
+    }
+    if header.min_version > FORMAT_VERSION {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+          "The file needs format version {}, but this library reads up to version {}",
+          header.min_version, FORMAT_VERSION
+        ),
+      ));
+    }
+    Ok(header)
+  }
+}
+
+/// Reads the header at the start of a data file, if it has one, returning it with its size in
+/// bytes. If there is no header, nothing is consumed. A file that starts with the self-describe tag
+/// but doesn't have a header this library can read is an error.
+pub fn read_header<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<Option<(Header, u64)>> {
+  if !reader.fill_buf()?.starts_with(&SELF_DESCRIBE_PREFIX) {
+    return Ok(None);
+  }
+
+  let mut bytes = Vec::new();
+  read_cbor_raw(reader, &mut bytes)?;
+  let header = Header::from_value(read_cbor_value(&mut bytes.as_slice())?)?;
+  Ok(Some((header, bytes.len() as u64)))
+}
+
+/// Skips the header at the start of a data file, if it has one, returning its size in bytes, which
+/// is the offset of the first entry.
+pub fn skip_header<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<u64> {
+  Ok(read_header(reader)?.map_or(0, |(_, size)| size))
+}
+
+/// Loads the header of the data file at the given path, if it has one.
+pub fn load_header<P: AsRef<Path>>(data_path: P) -> io::Result<Option<Header>> {
+  let mut reader = BufReader::new(File::open(data_path)?);
+  Ok(read_header(&mut reader)?.map(|(header, _)| header))
+}
+
+/// Returns the offset of the first entry of the data file at the given path, which is after its
+/// header. A missing file has no header.
+pub fn data_start(data_path: &Path) -> io::Result<u64> {
+  match File::open(data_path) {
+    Ok(file) => skip_header(&mut BufReader::new(file)),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+    Err(e) => Err(e),
+  }
+}
+
+/// Returns an error for a header that can't be read.
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn to_u64(value: i128) -> io::Result<u64> {
+  u64::try_from(value).map_err(|_| invalid_data("Expected an unsigned integer in the header"))
+}
+
+fn to_index_density(value: CborValue) -> io::Result<IndexDensity> {
+  match value {
+    CborValue::Text(text) if text == "dense" => Ok(IndexDensity::Dense),
+    CborValue::Array(items) => match items.as_slice() {
+      [CborValue::Text(kind), CborValue::Integer(n)] if kind == "every_records" => {
+        Ok(IndexDensity::EveryRecords(to_u64(*n)?))
+      }
+      [CborValue::Text(kind), CborValue::Integer(n)] if kind == "every_bytes" => {
+        Ok(IndexDensity::EveryBytes(to_u64(*n)?))
+      }
+      _ => Err(invalid_data("Unknown index density in the header")),
+    },
+    _ => Err(invalid_data("Unknown index density in the header")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::assert;
+
+  #[test]
+  fn round_trips() {
+    for header in [
+      Header::new(IndexDensity::Dense, false, None),
+      Header::new(IndexDensity::EveryRecords(4), true, Some("text".to_string())),
+      Header::new(IndexDensity::EveryBytes(4096), false, Some("tag 1".to_string())),
+    ] {
+      let mut bytes = header.to_bytes().unwrap();
+      assert::equal(bytes[0..3].to_vec(), SELF_DESCRIBE_PREFIX.to_vec());
+      let size = bytes.len() as u64;
+      bytes.extend_from_slice(&[0x61, b'a']);
+
+      let mut reader = bytes.as_slice();
+      assert::equal(read_header(&mut reader).unwrap(), Some((header, size)));
+      assert::equal(reader, [0x61, b'a'].as_slice());
+    }
+  }
+
+  #[test]
+  fn headerless_files_are_untouched() {
+    let bytes = [0x61, b'a', 0x61, b'1'];
+    let mut reader = bytes.as_slice();
+    assert::equal(read_header(&mut reader).unwrap(), None);
+    assert::equal(reader.len(), 4);
+    assert::equal(skip_header(&mut [].as_slice()).unwrap(), 0);
+  }
+
+  #[test]
+  fn rejects_newer_formats() {
+    let mut header = Header::new(IndexDensity::Dense, false, None);
+    header.version = FORMAT_VERSION + 1;
+    let bytes = header.to_bytes().unwrap();
+    assert!(read_header(&mut bytes.as_slice()).is_ok());
+
+    header.min_version = FORMAT_VERSION + 1;
+    let bytes = header.to_bytes().unwrap();
+    let error = read_header(&mut bytes.as_slice()).unwrap_err();
+    assert::equal(error.kind(), io::ErrorKind::Unsupported);
+  }
+}
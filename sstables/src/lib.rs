@@ -1,9 +1,27 @@
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod bloom;
 pub mod cbor;
+pub mod checksum;
+pub mod db;
+pub mod disk_index;
+pub mod external_sort;
+pub mod header;
+pub mod merge;
+pub mod metadata;
+#[cfg(feature = "mmap")]
+pub mod mmap;
 pub mod read;
+pub mod recovery;
+pub mod reindex;
+pub mod sstable;
 pub mod sstable_reader;
 pub mod sstable_writer;
+pub mod summary;
 pub mod traits;
+pub mod wal;
 
+pub use sstable::*;
 pub use sstable_reader::*;
 pub use sstable_writer::*;
 pub use traits::*;
@@ -0,0 +1,521 @@
+//! A k-way merge of sorted tables.
+//!
+//! `MergingIterator` merges any number of iterators of `(K, V)` entries that are each sorted by
+//! key, such as `SSTableReader`s, into a single sorted iterator. Each source is read sequentially
+//! and only its next entry is held in memory, so the data files are streamed from start to end
+//! without seeking through their indices.
+//!
+//! Entries with equal keys are returned in a stable order: by source, in the order the sources were
+//! given, and then in the order they appear within their source. If a source returns a key that is
+//! smaller than the one before it, the merge returns an `InvalidData` error rather than silently
+//! producing unsorted output. An error from a source, or an unsorted source, ends the merge: every
+//! entry read before it is returned, then the error, and then nothing more.
+//!
+//! By default every duplicate is kept. A `DuplicatePolicy` reduces each run of equal keys to a
+//! single entry instead. Given tables from oldest to newest, `DuplicatePolicy::KeepLast` is
+//! last-write-wins.
+//!
+//! When values are tombstones for deleted keys, such as `None` of an `Option`, last-write-wins lets
+//! a tombstone shadow the older values of its key. Once a merge covers every older table, the
+//! tombstones themselves can be dropped with `ResolveDuplicates::drop_tombstones`.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::merge::MergingIterator;
+//! use std::io;
+//!
+//! let a = vec![Ok((1u64, "a")), Ok((4, "b"))];
+//! let b = vec![Ok((2u64, "c")), Ok((3, "d")), Ok((4, "e"))];
+//! let merged = MergingIterator::new([a.into_iter(), b.into_iter()])
+//!   .collect::<io::Result<Vec<_>>>()
+//!   .unwrap();
+//! assert_eq!(merged, [(1, "a"), (2, "c"), (3, "d"), (4, "b"), (4, "e")]);
+//! ```
+
+use std::{
+  cmp::{Ordering, Reverse},
+  collections::BinaryHeap,
+  io,
+};
+
+use crate::traits::Tombstone;
+
+/// How a merge resolves entries with equal keys, which are seen in a stable order: by source, then
+/// by position within the source.
+#[derive(Default)]
+pub enum DuplicatePolicy<V> {
+  /// Keep every entry.
+  #[default]
+  KeepAll,
+  /// Keep only the first entry, which is the oldest when sources are given from oldest to newest.
+  KeepFirst,
+  /// Keep only the last entry, which is the newest when sources are given from oldest to newest.
+  KeepLast,
+  /// Combine the values with a reducer, which is called with the value so far and the next value.
+  Reduce(Box<dyn FnMut(V, V) -> V>),
+}
+
+impl<V> DuplicatePolicy<V> {
+  /// Creates a policy that combines the values of equal keys with a reducer.
+  pub fn reduce<F: FnMut(V, V) -> V + 'static>(reducer: F) -> Self {
+    DuplicatePolicy::Reduce(Box::new(reducer))
+  }
+}
+
+impl<V> std::fmt::Debug for DuplicatePolicy<V> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DuplicatePolicy::KeepAll => write!(f, "KeepAll"),
+      DuplicatePolicy::KeepFirst => write!(f, "KeepFirst"),
+      DuplicatePolicy::KeepLast => write!(f, "KeepLast"),
+      DuplicatePolicy::Reduce(_) => write!(f, "Reduce(..)"),
+    }
+  }
+}
+
+/// The next key of a source. Equal keys are ordered by source, so earlier sources come first.
+struct Head<K> {
+  key: K,
+  source: usize,
+}
+
+impl<K: Ord> PartialEq for Head<K> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl<K: Ord> Eq for Head<K> {}
+
+impl<K: Ord> PartialOrd for Head<K> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<K: Ord> Ord for Head<K> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.key.cmp(&other.key).then(self.source.cmp(&other.source))
+  }
+}
+
+/// Merges iterators of entries that are sorted by key into one iterator sorted by key.
+pub struct MergingIterator<K, V, I> {
+  sources: Vec<I>,
+  /// The value of each source's next entry, kept out of the heap so values don't need to be
+  /// comparable.
+  values: Vec<Option<V>>,
+  heap: BinaryHeap<Reverse<Head<K>>>,
+  /// Sources that haven't been read yet. The first entry of every source is read on the first call
+  /// to `next`, so that creating the iterator can't fail.
+  unstarted: bool,
+  /// An error reading ahead in a source, which is returned after the entry before it.
+  error: Option<io::Error>,
+  /// Whether an error has been returned, after which the merge has ended.
+  failed: bool,
+}
+
+impl<K, V, I> MergingIterator<K, V, I>
+where
+  K: Ord,
+  I: Iterator<Item = io::Result<(K, V)>>,
+{
+  pub fn new<S: IntoIterator<Item = I>>(sources: S) -> Self {
+    let sources = sources.into_iter().collect::<Vec<I>>();
+    MergingIterator {
+      values: sources.iter().map(|_| None).collect(),
+      heap: BinaryHeap::with_capacity(sources.len()),
+      sources,
+      unstarted: true,
+      error: None,
+      failed: false,
+    }
+  }
+
+  /// The number of sources being merged, including any that have been read to the end.
+  pub fn source_count(&self) -> usize {
+    self.sources.len()
+  }
+
+  /// Resolves entries with equal keys with the given policy.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use sstables::merge::{DuplicatePolicy, MergingIterator};
+  /// use std::io;
+  ///
+  /// let older = vec![Ok(("a", 1)), Ok(("b", 2))];
+  /// let newer = vec![Ok(("a", 3)), Ok(("c", 4))];
+  /// let merged = MergingIterator::new([older.into_iter(), newer.into_iter()])
+  ///   .resolve_duplicates(DuplicatePolicy::KeepLast)
+  ///   .collect::<io::Result<Vec<_>>>()
+  ///   .unwrap();
+  /// assert_eq!(merged, [("a", 3), ("b", 2), ("c", 4)]);
+  /// ```
+  pub fn resolve_duplicates(self, policy: DuplicatePolicy<V>) -> ResolveDuplicates<K, V, Self> {
+    ResolveDuplicates::new(self, policy)
+  }
+
+  /// Consumes the iterator, returning the sources.
+  pub fn into_sources(self) -> Vec<I> {
+    self.sources
+  }
+
+  /// Reads the next entry of a source into the heap, if there is one.
+  fn advance(&mut self, source: usize) -> io::Result<()> {
+    if let Some((key, value)) = self.sources[source].next().transpose()? {
+      self.values[source] = Some(value);
+      self.heap.push(Reverse(Head { key, source }));
+    }
+    Ok(())
+  }
+
+  fn start(&mut self) -> io::Result<()> {
+    self.unstarted = false;
+    for source in 0..self.sources.len() {
+      self.advance(source)?;
+    }
+    Ok(())
+  }
+}
+
+impl<K, V, I> Iterator for MergingIterator<K, V, I>
+where
+  K: Ord,
+  I: Iterator<Item = io::Result<(K, V)>>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed {
+      return None;
+    }
+    if let Some(e) = self.error.take() {
+      self.failed = true;
+      return Some(Err(e));
+    }
+    if self.unstarted {
+      if let Err(e) = self.start() {
+        self.failed = true;
+        return Some(Err(e));
+      }
+    }
+
+    let Reverse(Head { key, source }) = self.heap.pop()?;
+    let value = self.values[source].take()?;
+    match self.sources[source].next() {
+      Some(Ok((next_key, _))) if next_key < key => {
+        self.error = Some(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Source {} of the merge is not sorted by key", source),
+        ));
+      }
+      Some(Ok((next_key, next_value))) => {
+        self.values[source] = Some(next_value);
+        self.heap.push(Reverse(Head { key: next_key, source }));
+      }
+      Some(Err(e)) => self.error = Some(e),
+      None => {}
+    }
+
+    Some(Ok((key, value)))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    if self.failed || self.error.is_some() {
+      let remaining = usize::from(self.error.is_some());
+      return (remaining, Some(remaining));
+    }
+    let (lower, upper) = self
+      .sources
+      .iter()
+      .fold((0usize, Some(0usize)), |(lower, upper), source| {
+        let (source_lower, source_upper) = source.size_hint();
+        (
+          lower.saturating_add(source_lower),
+          upper.zip(source_upper).and_then(|(a, b)| a.checked_add(b)),
+        )
+      });
+    let buffered = self.heap.len();
+    (
+      lower.saturating_add(buffered),
+      upper.and_then(|upper| upper.checked_add(buffered)),
+    )
+  }
+}
+
+/// Resolves runs of equal keys in an iterator that is sorted by key with a `DuplicatePolicy`.
+pub struct ResolveDuplicates<K, V, I> {
+  inner: I,
+  policy: DuplicatePolicy<V>,
+  /// The first entry of the next run of equal keys, read while looking for the end of a run.
+  peeked: Option<(K, V)>,
+  /// An error read while looking for the end of a run, which is returned after the run.
+  error: Option<io::Error>,
+}
+
+impl<K, V, I> ResolveDuplicates<K, V, I>
+where
+  K: PartialEq,
+  I: Iterator<Item = io::Result<(K, V)>>,
+{
+  pub fn new(inner: I, policy: DuplicatePolicy<V>) -> Self {
+    ResolveDuplicates {
+      inner,
+      policy,
+      peeked: None,
+      error: None,
+    }
+  }
+
+  /// Drops tombstones after resolving duplicates, returning only the values of live keys. Only
+  /// drop tombstones when the merge covers every older table, since otherwise the older values
+  /// they shadow would come back, and only with a policy that lets the newest entry win.
+  pub fn drop_tombstones(self) -> DropTombstones<Self>
+  where
+    V: Tombstone,
+  {
+    DropTombstones { inner: self }
+  }
+
+  /// Consumes the adapter, returning the inner iterator. An entry or error that has been read ahead
+  /// is lost.
+  pub fn into_inner(self) -> I {
+    self.inner
+  }
+}
+
+impl<K, V, I> Iterator for ResolveDuplicates<K, V, I>
+where
+  K: PartialEq,
+  I: Iterator<Item = io::Result<(K, V)>>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Some(e) = self.error.take() {
+      return Some(Err(e));
+    }
+    let (key, mut value) = match self.peeked.take() {
+      Some(entry) => entry,
+      None => match self.inner.next()? {
+        Ok(entry) => entry,
+        Err(e) => return Some(Err(e)),
+      },
+    };
+    if let DuplicatePolicy::KeepAll = self.policy {
+      return Some(Ok((key, value)));
+    }
+
+    loop {
+      match self.inner.next() {
+        Some(Ok((next_key, next_value))) if next_key == key => {
+          value = match &mut self.policy {
+            DuplicatePolicy::KeepAll | DuplicatePolicy::KeepFirst => value,
+            DuplicatePolicy::KeepLast => next_value,
+            DuplicatePolicy::Reduce(reducer) => reducer(value, next_value),
+          };
+        }
+        Some(Ok(entry)) => {
+          self.peeked = Some(entry);
+          break;
+        }
+        Some(Err(e)) => {
+          // The run so far is returned first, though it may not include every entry of its key.
+          self.error = Some(e);
+          break;
+        }
+        None => break,
+      }
+    }
+
+    Some(Ok((key, value)))
+  }
+}
+
+/// Skips the entries of an iterator whose values are tombstones.
+pub struct DropTombstones<I> {
+  inner: I,
+}
+
+impl<K, V, I> Iterator for DropTombstones<I>
+where
+  V: Tombstone,
+  I: Iterator<Item = io::Result<(K, V)>>,
+{
+  type Item = io::Result<(K, V::Live)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      match self.inner.next()? {
+        Ok((key, value)) => {
+          if let Some(value) = value.into_live() {
+            return Some(Ok((key, value)));
+          }
+        }
+        Err(e) => return Some(Err(e)),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{SSTableReader, SSTableWriterBuilder};
+  use common_testing::assert;
+
+  type Source<K> = std::vec::IntoIter<io::Result<(K, u64)>>;
+
+  fn source<K>(keys: &[K], first_value: u64) -> Source<K>
+  where
+    K: Clone,
+  {
+    (first_value..)
+      .zip(keys)
+      .map(|(value, key)| Ok((key.clone(), value)))
+      .collect::<Vec<_>>()
+      .into_iter()
+  }
+
+  #[test]
+  fn merges_u64_keys() {
+    let merged = MergingIterator::new([source(&[1u64, 5, 9], 0), source(&[2, 5, 6], 10), source(&[], 20)])
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap();
+    assert::equal(merged, vec![(1, 0), (2, 10), (5, 1), (5, 11), (6, 12), (9, 2)]);
+  }
+
+  #[test]
+  fn merges_byte_keys() {
+    let a = source(&[vec![0u8], vec![0, 1], vec![2]], 0);
+    let b = source(&[vec![0u8, 0], vec![1]], 10);
+    let merged = MergingIterator::new([a, b])
+      .map(|kv| kv.map(|(k, _)| k))
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap();
+    assert::equal(merged, vec![vec![0], vec![0, 0], vec![0, 1], vec![1], vec![2]]);
+  }
+
+  #[test]
+  fn merges_no_sources() {
+    let mut merging_iterator = MergingIterator::new(Vec::<Source<u64>>::new());
+    assert::equal(merging_iterator.size_hint(), (0, Some(0)));
+    assert!(merging_iterator.next().is_none());
+  }
+
+  #[test]
+  fn size_hint_counts_every_source() {
+    let mut merging_iterator = MergingIterator::new([source(&[1u64, 2], 0), source(&[3], 10)]);
+    assert::equal(merging_iterator.size_hint(), (3, Some(3)));
+    merging_iterator.next().unwrap().unwrap();
+    assert::equal(merging_iterator.size_hint(), (2, Some(2)));
+  }
+
+  #[test]
+  fn rejects_unsorted_sources() {
+    let mut merging_iterator = MergingIterator::new([source(&[1u64, 3, 2], 0), source(&[4], 10)]);
+    assert::equal(merging_iterator.next().unwrap().unwrap(), (1, 0));
+    assert::equal(merging_iterator.next().unwrap().unwrap(), (3, 1));
+    assert::equal(merging_iterator.size_hint(), (1, Some(1)));
+    assert::equal(
+      merging_iterator.next().unwrap().unwrap_err().kind(),
+      io::ErrorKind::InvalidData,
+    );
+    assert!(merging_iterator.next().is_none());
+    assert::equal(merging_iterator.size_hint(), (0, Some(0)));
+  }
+
+  #[test]
+  fn returns_source_errors_after_the_entries_before_them() {
+    let failing = vec![Ok((1u64, 0)), Err(io::Error::other("failed"))].into_iter();
+    let mut merging_iterator = MergingIterator::new([failing, source(&[2], 10)]);
+    assert::equal(merging_iterator.next().unwrap().unwrap(), (1, 0));
+    assert::equal(
+      merging_iterator.next().unwrap().unwrap_err().to_string(),
+      "failed".to_string(),
+    );
+    assert!(merging_iterator.next().is_none());
+  }
+
+  #[test]
+  fn resolves_duplicates_before_returning_errors() {
+    let failing = vec![Ok((1u64, 0)), Ok((1, 1)), Err(io::Error::other("failed"))].into_iter();
+    let mut resolve_duplicates = ResolveDuplicates::new(failing, DuplicatePolicy::KeepLast);
+    assert::equal(resolve_duplicates.next().unwrap().unwrap(), (1, 1));
+    assert!(resolve_duplicates.next().unwrap().is_err());
+    assert!(resolve_duplicates.next().is_none());
+  }
+
+  #[test]
+  fn merges_sstable_readers() {
+    let mut sources = Vec::new();
+    for keys in [["a", "c", "e"], ["b", "d", "f"]] {
+      let mut writer = SSTableWriterBuilder::new("memory")
+        .build_with_writers(Vec::new(), io::sink())
+        .unwrap();
+      for key in keys {
+        writer.write((key, key.to_uppercase())).unwrap();
+      }
+      let (data, _) = writer.into_writers().unwrap();
+      sources.push(SSTableReader::<(String, String), _>::new(io::Cursor::new(data)));
+    }
+
+    let merged = MergingIterator::new(sources)
+      .map(|kv| kv.map(|(k, v)| format!("{}{}", k, v)))
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap();
+    assert::equal(merged, vec!["aA", "bB", "cC", "dD", "eE", "fF"]);
+  }
+
+  fn merge_with_policy(policy: DuplicatePolicy<u64>) -> Vec<(u64, u64)> {
+    let older = source(&[1u64, 2, 2, 4], 0);
+    let newer = source(&[1u64, 2, 3], 10);
+    MergingIterator::new([older, newer])
+      .resolve_duplicates(policy)
+      .collect::<io::Result<Vec<_>>>()
+      .unwrap()
+  }
+
+  #[test]
+  fn keeps_all_duplicates_in_stable_order() {
+    let merged = merge_with_policy(DuplicatePolicy::KeepAll);
+    assert::equal(merged, vec![(1, 0), (1, 10), (2, 1), (2, 2), (2, 11), (3, 12), (4, 3)]);
+  }
+
+  #[test]
+  fn keeps_first_duplicate() {
+    let merged = merge_with_policy(DuplicatePolicy::KeepFirst);
+    assert::equal(merged, vec![(1, 0), (2, 1), (3, 12), (4, 3)]);
+  }
+
+  #[test]
+  fn keeps_last_duplicate() {
+    let merged = merge_with_policy(DuplicatePolicy::KeepLast);
+    assert::equal(merged, vec![(1, 10), (2, 11), (3, 12), (4, 3)]);
+  }
+
+  #[test]
+  fn reduces_duplicates() {
+    let merged = merge_with_policy(DuplicatePolicy::reduce(|a, b| a * 100 + b));
+    assert::equal(merged, vec![(1, 10), (2, 10211), (3, 12), (4, 3)]);
+  }
+
+  #[test]
+  fn tombstones_shadow_older_values_and_can_be_dropped() {
+    let older = [(1u64, Some("a")), (2, Some("b")), (3, None)];
+    let newer = [(1u64, None), (3, Some("c")), (4, None)];
+    let merge = || {
+      MergingIterator::new([older.into_iter().map(Ok), newer.into_iter().map(Ok)])
+        .resolve_duplicates(DuplicatePolicy::KeepLast)
+    };
+
+    let merged = merge().collect::<io::Result<Vec<_>>>().unwrap();
+    assert::equal(merged, vec![(1, None), (2, Some("b")), (3, Some("c")), (4, None)]);
+
+    let merged = merge().drop_tombstones().collect::<io::Result<Vec<_>>>().unwrap();
+    assert::equal(merged, vec![(2, "b"), (3, "c")]);
+  }
+}
@@ -0,0 +1,609 @@
+//! Table metadata
+//!
+//! This module contains the `Metadata` struct, a small summary of a table that is written
+//! alongside its data and index files on `close()`. It holds the number of entries, the smallest
+//! and largest keys, the CBOR types of the keys and values, whether the keys are sorted, the sizes
+//! of the files, when the table was created, the version of the library that last wrote it, and a
+//! map of user-defined properties. Tools can read it without loading the index or scanning the
+//! data file.
+//!
+//! Keys are compared with `CborValue::natural_cmp`, which matches the order of the Rust types
+//! they are usually read as. `sorted_for_lookups` says whether `SSTable` lookups can rely on
+//! `sorted` to seek and stop early, which they only do for the key types where the two orders are
+//! known to match.
+//!
+//! # Format
+//!
+//! The metadata file is a single CBOR map with text keys, and therefore can be read by any CBOR
+//! implementation. Readers ignore keys they don't know, so fields can be added without a new
+//! format version. The size of the data file is used to detect stale metadata, like the bloom
+//! filter.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::metadata::load_metadata;
+//! use sstables::SSTableWriterBuilder;
+//!
+//! let path = std::env::temp_dir().join("metadata_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_metadata_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path)
+//!   .property("source", "example")
+//!   .build()
+//!   .unwrap();
+//! writer.write(("b", 2)).unwrap();
+//! writer.write(("a", 1)).unwrap();
+//! writer.close().unwrap();
+//!
+//! let metadata = load_metadata(&path).unwrap().unwrap();
+//! assert_eq!(metadata.entry_count, 2);
+//! assert_eq!(metadata.min_key, Some("a".into()));
+//! assert_eq!(metadata.key_type.as_deref(), Some("text"));
+//! assert!(!metadata.sorted);
+//! assert_eq!(metadata.property("source"), Some(&"example".into()));
+//! ```
+
+use crate::cbor::{
+  cbor_integer_from_bytes, read_cbor_head_u64, read_cbor_raw, read_cbor_value, write_cbor_head, write_cbor_text,
+  CborValue, CborWrite, MajorType, Timestamp, CBOR_NULL,
+};
+use crate::header::skip_header;
+use crate::read::create_metadata_path;
+use crate::traits::FromPath;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The version of the metadata format written by this library.
+pub const METADATA_FORMAT_VERSION: u64 = 1;
+
+/// The version of this library, recorded as the writer version.
+pub const WRITER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The type recorded when keys or values have more than one CBOR type.
+pub const MIXED_TYPE: &str = "mixed";
+
+/// The head of an epoch-based date/time, tag 1.
+const EPOCH_TIME_HEAD: u8 = 0xC1;
+
+/// A summary of a table. See the module documentation for the format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+  /// The version of the metadata format.
+  pub format_version: u64,
+  /// The version of the library that last wrote the table.
+  pub writer_version: String,
+  /// When the table was created.
+  pub created_at: Timestamp,
+  /// The number of entries in the data file.
+  pub entry_count: u64,
+  /// The smallest key, if there are any entries.
+  pub min_key: Option<CborValue>,
+  /// The largest key, if there are any entries.
+  pub max_key: Option<CborValue>,
+  /// The last key written, which is needed to keep track of `sorted` when appending.
+  pub last_key: Option<CborValue>,
+  /// The CBOR type of the keys, such as `text`, `integer` or `tag 1`, or `mixed`.
+  pub key_type: Option<String>,
+  /// The CBOR type of the values, not counting tombstones, or `mixed`.
+  pub value_type: Option<String>,
+  /// Whether every key is at least as large as the key before it.
+  pub sorted: bool,
+  /// The size of the data file.
+  pub data_size: u64,
+  /// The size of the index file, as of the last `close()`.
+  pub index_size: u64,
+  /// User-defined properties, in the order they were first set.
+  pub properties: Vec<(String, CborValue)>,
+}
+
+impl Default for Metadata {
+  fn default() -> Self {
+    Metadata::new()
+  }
+}
+
+impl Metadata {
+  /// Creates the metadata of an empty table, created now.
+  pub fn new() -> Self {
+    Metadata {
+      format_version: METADATA_FORMAT_VERSION,
+      writer_version: WRITER_VERSION.to_string(),
+      created_at: Timestamp::now(),
+      entry_count: 0,
+      min_key: None,
+      max_key: None,
+      last_key: None,
+      key_type: None,
+      value_type: None,
+      sorted: true,
+      data_size: 0,
+      index_size: 0,
+      properties: Vec::new(),
+    }
+  }
+
+  /// Returns the value of a user-defined property.
+  pub fn property(&self, name: &str) -> Option<&CborValue> {
+    self
+      .properties
+      .iter()
+      .find(|(property_name, _)| property_name == name)
+      .map(|(_, value)| value)
+  }
+
+  /// Sets a user-defined property, replacing any previous value.
+  pub fn set_property<N: Into<String>, V: Into<CborValue>>(&mut self, name: N, value: V) {
+    let name = name.into();
+    let value = value.into();
+    match self
+      .properties
+      .iter_mut()
+      .find(|(property_name, _)| *property_name == name)
+    {
+      Some((_, property_value)) => *property_value = value,
+      None => self.properties.push((name, value)),
+    }
+  }
+
+  /// Whether `sorted` can be trusted by lookups that compare keys with `Ord`. It's tracked with
+  /// `natural_cmp`, which matches the `Ord` of the types that text, byte string, integer and bool
+  /// keys are read as, and of `Timestamp` for epoch-based date/times (tag 1), which are ordered by
+  /// their number. It doesn't necessarily match the types read from arrays, maps or other tags, or
+  /// from keys of mixed types.
+  pub fn sorted_for_lookups(&self) -> bool {
+    self.sorted
+      && self
+        .key_type
+        .as_deref()
+        .is_none_or(|key_type| matches!(key_type, "text" | "bytes" | "integer" | "bool" | "tag 1"))
+  }
+
+  /// Updates the summary with an entry appended to the data file, from its encoded key and value.
+  /// The common key types are compared without decoding the key, which is only decoded when it
+  /// replaces the smallest or largest key.
+  pub fn record(&mut self, key_bytes: &[u8], value_bytes: &[u8]) -> io::Result<()> {
+    merge_type(&mut self.key_type, cbor_type_name(key_bytes)?);
+    if value_bytes.first() != Some(&CBOR_NULL) {
+      merge_type(&mut self.value_type, cbor_type_name(value_bytes)?);
+    }
+
+    let below_min = match &self.min_key {
+      Some(min_key) => cmp_encoded_key(key_bytes, min_key)?.is_lt(),
+      None => true,
+    };
+    if below_min {
+      set_encoded_key(&mut self.min_key, key_bytes)?;
+    }
+    let above_max = match &self.max_key {
+      Some(max_key) => cmp_encoded_key(key_bytes, max_key)?.is_ge(),
+      None => true,
+    };
+    if above_max {
+      set_encoded_key(&mut self.max_key, key_bytes)?;
+    }
+    if let Some(last_key) = &self.last_key {
+      if cmp_encoded_key(key_bytes, last_key)?.is_lt() {
+        self.sorted = false;
+      }
+    }
+    set_encoded_key(&mut self.last_key, key_bytes)?;
+
+    self.entry_count += 1;
+    self.data_size += (key_bytes.len() + value_bytes.len()) as u64;
+    Ok(())
+  }
+
+  /// Builds the metadata of an existing data file by reading every entry after its header. The
+  /// creation time is unknown, so it is set to now.
+  pub fn from_data_reader<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+    let mut metadata = Metadata::new();
+    metadata.data_size = skip_header(reader)?;
+    let mut key_bytes = Vec::new();
+    let mut value_bytes = Vec::new();
+
+    loop {
+      key_bytes.clear();
+      value_bytes.clear();
+      match read_cbor_raw(reader, &mut key_bytes).and_then(|_| read_cbor_raw(reader, &mut value_bytes)) {
+        Ok(()) => metadata.record(&key_bytes, &value_bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+        Err(e) => return Err(e),
+      }
+    }
+
+    Ok(metadata)
+  }
+
+  /// Writes the metadata as a CBOR map.
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    write_cbor_head(writer, MajorType::Object, 13)?;
+    write_cbor_text(writer, "format_version")?;
+    self.format_version.cbor_write(writer)?;
+    write_cbor_text(writer, "writer_version")?;
+    self.writer_version.cbor_write(writer)?;
+    write_cbor_text(writer, "created_at")?;
+    self.created_at.cbor_write(writer)?;
+    write_cbor_text(writer, "entry_count")?;
+    self.entry_count.cbor_write(writer)?;
+    write_cbor_text(writer, "min_key")?;
+    self.min_key.cbor_write(writer)?;
+    write_cbor_text(writer, "max_key")?;
+    self.max_key.cbor_write(writer)?;
+    write_cbor_text(writer, "last_key")?;
+    self.last_key.cbor_write(writer)?;
+    write_cbor_text(writer, "key_type")?;
+    self.key_type.cbor_write(writer)?;
+    write_cbor_text(writer, "value_type")?;
+    self.value_type.cbor_write(writer)?;
+    write_cbor_text(writer, "sorted")?;
+    CborValue::Bool(self.sorted).cbor_write(writer)?;
+    write_cbor_text(writer, "data_size")?;
+    self.data_size.cbor_write(writer)?;
+    write_cbor_text(writer, "index_size")?;
+    self.index_size.cbor_write(writer)?;
+    write_cbor_text(writer, "properties")?;
+    write_cbor_head(writer, MajorType::Object, self.properties.len() as u64)?;
+    for (name, value) in &self.properties {
+      write_cbor_text(writer, name)?;
+      value.cbor_write(writer)?;
+    }
+    Ok(())
+  }
+
+  /// Reads metadata that was written with [`Metadata::write_to`]. Unknown keys are ignored.
+  pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    let CborValue::Map(pairs) = read_cbor_value(reader)? else {
+      return Err(invalid_data("Expected a map of metadata"));
+    };
+
+    let mut metadata = Metadata::new();
+    let mut format_version = None;
+    for (key, value) in pairs {
+      let CborValue::Text(key) = key else {
+        continue;
+      };
+      match (key.as_str(), value) {
+        ("format_version", CborValue::Integer(version)) => format_version = u64::try_from(version).ok(),
+        ("writer_version", CborValue::Text(version)) => metadata.writer_version = version,
+        ("created_at", value) => metadata.created_at = value.try_into()?,
+        ("entry_count", value) => metadata.entry_count = to_u64(value)?,
+        ("min_key", value) => metadata.min_key = to_option(value),
+        ("max_key", value) => metadata.max_key = to_option(value),
+        ("last_key", value) => metadata.last_key = to_option(value),
+        ("key_type", CborValue::Text(key_type)) => metadata.key_type = Some(key_type),
+        ("value_type", CborValue::Text(value_type)) => metadata.value_type = Some(value_type),
+        ("sorted", CborValue::Bool(sorted)) => metadata.sorted = sorted,
+        ("data_size", value) => metadata.data_size = to_u64(value)?,
+        ("index_size", value) => metadata.index_size = to_u64(value)?,
+        ("properties", CborValue::Map(properties)) => {
+          for (name, value) in properties {
+            if let CborValue::Text(name) = name {
+              metadata.set_property(name, value);
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+
+    match format_version {
+      Some(version) if version <= METADATA_FORMAT_VERSION => {
+        metadata.format_version = version;
+        Ok(metadata)
+      }
+      Some(_) => Err(invalid_data("Unsupported metadata format version")),
+      None => Err(invalid_data("Missing metadata format version")),
+    }
+  }
+
+  /// Writes the metadata to the given path, replacing any previous metadata.
+  pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    self.write_to(&mut writer)?;
+    writer.flush()?;
+    writer.get_mut().sync_all()
+  }
+}
+
+/// Implementation of FromPath for Metadata. The path is the metadata file itself, not the data
+/// file.
+impl FromPath<Metadata> for Metadata {
+  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    Metadata::read_from(&mut BufReader::new(File::open(path)?))
+  }
+}
+
+/// Loads the metadata of the data file at the given path, if it exists, can be decoded, and is not
+/// stale. Metadata is stale if the data file has changed size since it was written. A metadata file
+/// that was cut off or corrupted is ignored, like a missing one.
+pub fn load_metadata<P: AsRef<Path>>(data_path: P) -> io::Result<Option<Metadata>> {
+  let data_path = data_path.as_ref();
+  let metadata_path = create_metadata_path(data_path);
+  if !metadata_path.is_file() {
+    return Ok(None);
+  }
+
+  let metadata = match Metadata::from_path(&metadata_path) {
+    Ok(metadata) => metadata,
+    Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => return Ok(None),
+    Err(e) => return Err(e),
+  };
+  if metadata.data_size != fs::metadata(data_path)?.len() {
+    return Ok(None);
+  }
+
+  Ok(Some(metadata))
+}
+
+/// Returns an error for metadata that can't be read.
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn to_u64(value: CborValue) -> io::Result<u64> {
+  match value {
+    CborValue::Integer(value) => u64::try_from(value).map_err(|_| invalid_data("Expected an unsigned integer")),
+    _ => Err(invalid_data("Expected an unsigned integer")),
+  }
+}
+
+fn to_option(value: CborValue) -> Option<CborValue> {
+  match value {
+    CborValue::Null => None,
+    value => Some(value),
+  }
+}
+
+/// Merges a new type into the known types, which become mixed if they differ.
+fn merge_type(known_type: &mut Option<String>, new_type: Cow<'static, str>) {
+  match known_type {
+    Some(known_type) if *known_type != new_type && known_type != MIXED_TYPE => *known_type = MIXED_TYPE.to_string(),
+    Some(_) => {}
+    None => *known_type = Some(new_type.into_owned()),
+  }
+}
+
+/// Returns the name of the CBOR type of an encoded item from its head, without decoding the rest.
+fn cbor_type_name(bytes: &[u8]) -> io::Result<Cow<'static, str>> {
+  let byte = *bytes.first().ok_or_else(|| invalid_data("Expected a CBOR item"))?;
+  Ok(match MajorType::from_u8(byte) {
+    MajorType::UnsignedInteger | MajorType::NegativeInteger => "integer".into(),
+    MajorType::Bytes => "bytes".into(),
+    MajorType::Text => "text".into(),
+    MajorType::Array => "array".into(),
+    MajorType::Object => "map".into(),
+    MajorType::SemanticTag => format!("tag {}", read_cbor_head_u64(&mut &bytes[1..], byte)?).into(),
+    MajorType::NoContentType => match byte {
+      0xF4 | 0xF5 => "bool".into(),
+      0xF6 => "null".into(),
+      0xF7 => "undefined".into(),
+      0xF9..=0xFB => "float".into(),
+      _ => "simple".into(),
+    },
+  })
+}
+
+/// Returns the major type and payload of an encoded text or byte string of definite length.
+fn string_payload(bytes: &[u8]) -> Option<(MajorType, &[u8])> {
+  let (&byte, mut rest) = bytes.split_first()?;
+  let major_type = MajorType::from_u8(byte);
+  if !matches!(major_type, MajorType::Text | MajorType::Bytes) || byte & 0x1F > 27 {
+    return None;
+  }
+  let len = read_cbor_head_u64(&mut rest, byte).ok()?;
+  Some((major_type, rest.get(..usize::try_from(len).ok()?)?))
+}
+
+/// Compares an encoded key with a decoded one with `CborValue::natural_cmp`. Text, byte string,
+/// integer and epoch-based date/time keys are compared without decoding the whole encoded key.
+fn cmp_encoded_key(key_bytes: &[u8], key: &CborValue) -> io::Result<Ordering> {
+  match (string_payload(key_bytes), key) {
+    (Some((MajorType::Text, payload)), CborValue::Text(key)) => return Ok(payload.cmp(key.as_bytes())),
+    (Some((MajorType::Bytes, payload)), CborValue::Bytes(key)) => return Ok(payload.cmp(key)),
+    _ => {}
+  }
+  if let (Some(integer), CborValue::Integer(key)) = (cbor_integer_from_bytes(key_bytes), key) {
+    return Ok(integer.cmp(key));
+  }
+  match (key_bytes, key) {
+    // The value of an epoch-based date/time is a number, which decodes without allocating.
+    ([EPOCH_TIME_HEAD, value_bytes @ ..], CborValue::Tag(1, key)) => {
+      Ok(read_cbor_value(&mut &value_bytes[..])?.natural_cmp(key))
+    }
+    _ => Ok(read_cbor_value(&mut &key_bytes[..])?.natural_cmp(key)),
+  }
+}
+
+/// Sets a key to an encoded one, reusing the buffer of the key it replaces for text and byte
+/// strings.
+fn set_encoded_key(key: &mut Option<CborValue>, key_bytes: &[u8]) -> io::Result<()> {
+  match (string_payload(key_bytes), key.as_mut()) {
+    (Some((MajorType::Text, payload)), Some(CborValue::Text(text))) => {
+      let payload = std::str::from_utf8(payload).map_err(|_| invalid_data("Expected UTF-8 text"))?;
+      text.clear();
+      text.push_str(payload);
+    }
+    (Some((MajorType::Bytes, payload)), Some(CborValue::Bytes(bytes))) => {
+      bytes.clear();
+      bytes.extend_from_slice(payload);
+    }
+    (_, Some(CborValue::Tag(1, value))) if key_bytes.first() == Some(&EPOCH_TIME_HEAD) => {
+      **value = read_cbor_value(&mut &key_bytes[1..])?;
+    }
+    _ => *key = Some(read_cbor_value(&mut &key_bytes[..])?),
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::SSTableWriterBuilder;
+  use common_testing::{assert, setup};
+
+  fn record_all(metadata: &mut Metadata, entries: &[(CborValue, CborValue)]) {
+    for (key, value) in entries {
+      let (mut key_bytes, mut value_bytes) = (Vec::new(), Vec::new());
+      key.cbor_write(&mut key_bytes).unwrap();
+      value.cbor_write(&mut value_bytes).unwrap();
+      metadata.record(&key_bytes, &value_bytes).unwrap();
+    }
+  }
+
+  #[test]
+  fn records_entries() {
+    let mut metadata = Metadata::new();
+    record_all(
+      &mut metadata,
+      &[
+        (CborValue::from(2), CborValue::from("b")),
+        (CborValue::from(10), CborValue::Null),
+        (CborValue::from(10), CborValue::from("c")),
+      ],
+    );
+    assert::equal(metadata.entry_count, 3);
+    assert::equal(metadata.min_key.clone(), Some(CborValue::from(2)));
+    assert::equal(metadata.max_key.clone(), Some(CborValue::from(10)));
+    assert::equal(metadata.key_type.clone(), Some("integer".to_string()));
+    assert::equal(metadata.value_type.clone(), Some("text".to_string()));
+    assert!(metadata.sorted);
+    assert!(metadata.sorted_for_lookups());
+    assert::equal(metadata.data_size, 8);
+
+    record_all(&mut metadata, &[(CborValue::from(-1), CborValue::from(1.5))]);
+    assert::equal(metadata.min_key.clone(), Some(CborValue::from(-1)));
+    assert::equal(metadata.value_type.clone(), Some(MIXED_TYPE.to_string()));
+    assert!(!metadata.sorted);
+    assert!(!metadata.sorted_for_lookups());
+  }
+
+  #[test]
+  fn only_trusts_sorted_for_keys_ordered_like_their_rust_types() {
+    // Epoch-based date/times are read as timestamps, which are ordered by time.
+    let mut metadata = Metadata::new();
+    record_all(
+      &mut metadata,
+      &[
+        (CborValue::Tag(1, Box::new(CborValue::from(5))), CborValue::Null),
+        (CborValue::Tag(1, Box::new(CborValue::from(5.5))), CborValue::Null),
+      ],
+    );
+    assert!(metadata.sorted);
+    assert!(metadata.sorted_for_lookups());
+
+    // Other tags are read as types whose order isn't known, like arrays and maps.
+    let mut metadata = Metadata::new();
+    record_all(
+      &mut metadata,
+      &[
+        (
+          CborValue::Tag(0, Box::new(CborValue::from("2013-03-21T20:04:00Z"))),
+          CborValue::Null,
+        ),
+        (
+          CborValue::Tag(0, Box::new(CborValue::from("2013-03-21T21:04:00+02:00"))),
+          CborValue::Null,
+        ),
+      ],
+    );
+    assert!(metadata.sorted);
+    assert!(!metadata.sorted_for_lookups());
+
+    let mut metadata = Metadata::new();
+    record_all(
+      &mut metadata,
+      &[
+        (CborValue::from(1), CborValue::Null),
+        (CborValue::from("a"), CborValue::Null),
+      ],
+    );
+    assert!(metadata.sorted);
+    assert!(!metadata.sorted_for_lookups());
+    assert!(Metadata::new().sorted_for_lookups());
+  }
+
+  #[test]
+  fn compares_encoded_keys_like_natural_cmp() {
+    let keys = [
+      CborValue::from(-2),
+      CborValue::from(1.5),
+      CborValue::from(10),
+      CborValue::from(u64::MAX),
+      CborValue::from(b"a".as_slice()),
+      CborValue::from(b"ab".as_slice()),
+      CborValue::from("b"),
+      CborValue::from("ab"),
+      CborValue::from("ba"),
+      CborValue::from(vec![CborValue::from(1), CborValue::from("z")]),
+      CborValue::Tag(1, Box::new(CborValue::from(5))),
+      CborValue::Tag(1, Box::new(CborValue::from(-5.5))),
+      CborValue::Tag(0, Box::new(CborValue::from("2013-03-21T20:04:00Z"))),
+      CborValue::Bool(false),
+    ];
+    for a in &keys {
+      let mut a_bytes = Vec::new();
+      a.cbor_write(&mut a_bytes).unwrap();
+      for b in &keys {
+        assert::equal(cmp_encoded_key(&a_bytes, b).unwrap(), a.natural_cmp(b));
+
+        let mut key = Some(b.clone());
+        set_encoded_key(&mut key, &a_bytes).unwrap();
+        assert::equal(key.as_ref(), Some(a));
+      }
+    }
+  }
+
+  #[test]
+  fn ignores_unreadable_metadata_files() {
+    let _lock = setup::sequential();
+    let path = Path::new(".tmp/metadata_unreadable_test.sst");
+    setup::create_dir_all(".tmp").unwrap();
+    setup::remove_file(path).unwrap();
+    setup::remove_file(crate::read::create_index_path(path)).unwrap();
+    let mut writer = SSTableWriterBuilder::new(path).metadata().build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.close().unwrap();
+    assert!(load_metadata(path).unwrap().is_some());
+
+    let metadata_path = create_metadata_path(path);
+    let bytes = fs::read(&metadata_path).unwrap();
+    fs::write(&metadata_path, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(load_metadata(path).unwrap().is_none());
+    fs::write(&metadata_path, [0xFF]).unwrap();
+    assert!(load_metadata(path).unwrap().is_none());
+    assert!(crate::SSTable::<String, String>::open(path).is_ok());
+  }
+
+  #[test]
+  fn round_trips() {
+    let mut metadata = Metadata::new();
+    record_all(
+      &mut metadata,
+      &[(CborValue::Tag(1, Box::new(CborValue::from(5))), CborValue::from("a"))],
+    );
+    metadata.index_size = 3;
+    metadata.set_property("source", "test");
+    metadata.set_property("version", 2);
+    metadata.set_property("source", "other");
+
+    let mut bytes = Vec::new();
+    metadata.write_to(&mut bytes).unwrap();
+    let read = Metadata::read_from(&mut bytes.as_slice()).unwrap();
+    assert::equal(read.clone(), metadata);
+    assert::equal(read.property("source"), Some(&CborValue::from("other")));
+    assert::equal(read.key_type, Some("tag 1".to_string()));
+  }
+
+  #[test]
+  fn rejects_newer_versions() {
+    let mut metadata = Metadata::new();
+    metadata.format_version = METADATA_FORMAT_VERSION + 1;
+    let mut bytes = Vec::new();
+    metadata.write_to(&mut bytes).unwrap();
+    assert!(Metadata::read_from(&mut bytes.as_slice()).is_err());
+  }
+}
@@ -0,0 +1,273 @@
+//! Memory-mapped reader
+//!
+//! `SSTableReader` copies every key and value out of a `BufReader` into a new `String` or
+//! `Vec<u8>`. With the `mmap` feature, `MmapReader` maps a local data file into memory instead, and
+//! decodes entries with [`CborBorrow`], so text and byte string keys and values are `&str` and
+//! `&[u8]` slices of the mapping. Scanning a table of small values then doesn't allocate at all.
+//!
+//! The mapping also gives random access: `entry_at` decodes the entry at an offset from the index,
+//! and `entries_from` scans from there.
+//!
+//! # Safety
+//!
+//! A mapped file must not be truncated or rewritten in place while it is mapped. Reading a part of
+//! the mapping that was cut off raises `SIGBUS` on most platforms, and any change to the mapped
+//! bytes is undefined behavior. Opening a table to write to it can truncate the data file:
+//! `SSTableWriterBuilder::build` cuts it back to the size in a write-ahead log, and `recover` cuts
+//! off a partial record. So no writer may be built for a table, in this process or any other, while
+//! a `MmapReader` of it is alive. Entries appended by a writer that was built before the table was
+//! mapped are fine, and aren't seen by the reader. `IndexRebuilder` only replaces the index, which
+//! isn't mapped.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::mmap::MmapReader;
+//! use sstables::SSTableWriterBuilder;
+//!
+//! let path = std::env::temp_dir().join("mmap_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).build().unwrap();
+//! writer.write(("apple", "1")).unwrap();
+//! writer.write(("banana", "2")).unwrap();
+//! writer.close().unwrap();
+//!
+//! let reader = MmapReader::open(&path).unwrap();
+//! let keys = reader.entries::<&str, &str>().map(|kv| kv.unwrap().0).collect::<Vec<_>>();
+//! assert_eq!(keys, vec!["apple", "banana"]);
+//!
+//! // ("apple", "1") is 8 bytes, so the second entry is at offset 8.
+//! assert_eq!(reader.entry_at::<&str, &str>(8).unwrap(), ("banana", "2"));
+//! ```
+
+use crate::cbor::CborBorrow;
+use crate::header::{read_header, Header};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Reads an SSTable data file through a read-only memory map. See the module documentation.
+#[derive(Debug)]
+pub struct MmapReader {
+  mmap: Mmap,
+  header: Option<Header>,
+  data_start: u64,
+}
+
+impl MmapReader {
+  /// Maps the data file at the given path, reading its header if it has one. No writer may be
+  /// built for the table until the reader is dropped, since building one can truncate the data
+  /// file. See the module documentation.
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let file = File::open(path)?;
+    // Safety: no writer may be built for the table while it's mapped, since building one can
+    // truncate the data file. See the module documentation.
+    let mmap = unsafe { Mmap::map(&file)? };
+    let header = read_header(&mut &mmap[..])?;
+    let data_start = header.as_ref().map_or(0, |(_, size)| *size);
+    Ok(MmapReader {
+      mmap,
+      header: header.map(|(header, _)| header),
+      data_start,
+    })
+  }
+
+  /// The header of the data file, if it has one.
+  pub fn header(&self) -> Option<&Header> {
+    self.header.as_ref()
+  }
+
+  /// The offset of the first entry in the data file, which is after the header.
+  pub fn data_start(&self) -> u64 {
+    self.data_start
+  }
+
+  /// The size of the data file when it was mapped.
+  pub fn len(&self) -> u64 {
+    self.mmap.len() as u64
+  }
+
+  /// Whether the data file was empty when it was mapped.
+  pub fn is_empty(&self) -> bool {
+    self.mmap.is_empty()
+  }
+
+  /// The mapped bytes of the data file, including the header.
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.mmap
+  }
+
+  /// Iterates over every entry, borrowing from the mapping.
+  pub fn entries<'a, K, V>(&'a self) -> MmapEntries<'a, K, V>
+  where
+    K: CborBorrow<'a>,
+    V: CborBorrow<'a>,
+  {
+    self.entries_from(self.data_start)
+  }
+
+  /// Iterates over the entries from the given offset, such as an offset from the index, to the end
+  /// of the data file.
+  pub fn entries_from<'a, K, V>(&'a self, offset: u64) -> MmapEntries<'a, K, V>
+  where
+    K: CborBorrow<'a>,
+    V: CborBorrow<'a>,
+  {
+    let start = (offset as usize).min(self.mmap.len());
+    MmapEntries {
+      bytes: &self.mmap[start..],
+      position: start as u64,
+      phantom: std::marker::PhantomData,
+    }
+  }
+
+  /// Decodes the entry at the given offset, such as an offset from the index.
+  pub fn entry_at<'a, K, V>(&'a self, offset: u64) -> io::Result<(K, V)>
+  where
+    K: CborBorrow<'a>,
+    V: CborBorrow<'a>,
+  {
+    let mut bytes = self.mmap.get(offset as usize..).unwrap_or_default();
+    Ok((K::cbor_borrow(&mut bytes)?, V::cbor_borrow(&mut bytes)?))
+  }
+}
+
+/// An iterator over the entries of a `MmapReader`, which borrow from the mapping. As with
+/// `SSTableReader`, a truncated last entry ends the iteration. Any other error is returned once,
+/// and then the iteration ends, since the start of the next entry isn't known.
+#[derive(Debug)]
+pub struct MmapEntries<'a, K, V> {
+  bytes: &'a [u8],
+  position: u64,
+  phantom: std::marker::PhantomData<(K, V)>,
+}
+
+impl<K, V> MmapEntries<'_, K, V> {
+  /// The offset of the next entry in the data file.
+  pub fn position(&self) -> u64 {
+    self.position
+  }
+}
+
+impl<'a, K, V> Iterator for MmapEntries<'a, K, V>
+where
+  K: CborBorrow<'a>,
+  V: CborBorrow<'a>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.bytes.is_empty() {
+      return None;
+    }
+
+    let mut bytes = self.bytes;
+    let result = K::cbor_borrow(&mut bytes).and_then(|k| V::cbor_borrow(&mut bytes).map(|v| (k, v)));
+    match result {
+      Ok(x) => {
+        self.position += (self.bytes.len() - bytes.len()) as u64;
+        self.bytes = bytes;
+        Some(Ok(x))
+      }
+      Err(e) => {
+        self.bytes = &[];
+        match e.kind() {
+          io::ErrorKind::UnexpectedEof => None,
+          _ => Some(Err(e)),
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cbor::CborValue;
+  use crate::read::create_index_path;
+  use crate::{FromPath, SSTableIndex, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::fs::OpenOptions;
+  use std::io::Write;
+
+  const TEST_FILE_NAME: &str = ".tmp/mmap_test.sst";
+
+  fn write_table(entries: &[(&str, &[u8])], header: bool) -> io::Result<()> {
+    setup::create_dir_all(".tmp")?;
+    setup::remove_file(TEST_FILE_NAME)?;
+    setup::remove_file(create_index_path(Path::new(TEST_FILE_NAME)))?;
+    let mut builder = SSTableWriterBuilder::new(TEST_FILE_NAME);
+    if header {
+      builder = builder.header();
+    }
+    let mut writer = builder.build()?;
+    for entry in entries {
+      writer.write(*entry)?;
+    }
+    writer.close()
+  }
+
+  #[test]
+  fn scans_without_copying() -> io::Result<()> {
+    let _lock = setup::sequential();
+    write_table(&[("a", b"1"), ("b", b"22"), ("c", b"333")], true)?;
+
+    let reader = MmapReader::open(TEST_FILE_NAME)?;
+    assert!(reader.header().is_some());
+    let mapped = reader.as_bytes().as_ptr_range();
+    let mut entries = reader.entries::<&str, &[u8]>();
+    assert::equal(entries.position(), reader.data_start());
+    let (key, value) = entries.next().unwrap()?;
+    assert::equal((key, value), ("a", b"1".as_slice()));
+    assert!(mapped.contains(&key.as_ptr()) && mapped.contains(&value.as_ptr()));
+    assert::equal(entries.position(), reader.data_start() + 4);
+
+    let rest = entries.map(|kv| kv.map(|(k, _)| k)).collect::<io::Result<Vec<_>>>()?;
+    assert::equal(rest, vec!["b", "c"]);
+    Ok(())
+  }
+
+  #[test]
+  fn reads_entries_at_index_offsets() -> io::Result<()> {
+    let _lock = setup::sequential();
+    write_table(&[("a", b"1"), ("b", b"2"), ("c", b"3")], false)?;
+    let index = SSTableIndex::<String>::from_path(create_index_path(Path::new(TEST_FILE_NAME)))?;
+
+    let reader = MmapReader::open(TEST_FILE_NAME)?;
+    for (key, offset) in &index.indices {
+      assert::equal(reader.entry_at::<&str, CborValue>(*offset)?.0, key.as_str());
+    }
+    let (_, offset) = index.indices[1];
+    let keys = reader
+      .entries_from::<String, CborValue>(offset)
+      .map(|kv| kv.unwrap().0)
+      .collect::<Vec<_>>();
+    assert::equal(keys, vec!["b", "c"]);
+    assert!(reader.entry_at::<&str, &[u8]>(reader.len()).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn stops_at_truncated_and_bad_entries() -> io::Result<()> {
+    let _lock = setup::sequential();
+    write_table(&[("a", b"1")], false)?;
+    let mut file = OpenOptions::new().append(true).open(TEST_FILE_NAME)?;
+    file.write_all(&[0x61, b'b', 0x43, b'2'])?;
+
+    let reader = MmapReader::open(TEST_FILE_NAME)?;
+    assert::equal(reader.entries::<&str, &[u8]>().count(), 1);
+
+    // The value is bytes, not text.
+    let mut entries = reader.entries::<&str, &str>();
+    assert!(entries.next().unwrap().is_err());
+    assert!(entries.next().is_none());
+
+    write_table(&[], false)?;
+    let reader = MmapReader::open(TEST_FILE_NAME)?;
+    assert!(reader.is_empty());
+    assert::equal(reader.entries::<&str, &str>().count(), 0);
+    Ok(())
+  }
+}
@@ -68,9 +68,10 @@ where
 ///
 /// # Performance Considerations
 ///
-/// This function allocates a new vector of length `len` on the heap. If you want to avoid this
-/// allocation, you can use [`take_byte_array`](crate::read::take_byte_array) instead, which
-/// allocates on the stack instead of the heap. However, this function is only available for
+/// This function allocates a new vector on the heap. It grows as bytes are read rather than being
+/// reserved up front, so a corrupt `len` doesn't allocate more than the reader holds. If you want
+/// to avoid this allocation, you can use [`take_byte_array`](crate::read::take_byte_array)
+/// instead, which allocates on the stack instead of the heap. However, this function is only available for
 /// arrays of length 1, 2, 4, 8, 16, 32, and 64. If you need a byte array of a different length,
 /// you can use [`take_byte`](crate::read::take_byte) to read the bytes one at a time. If you
 /// need a byte array of a different length and you need to avoid heap allocations, you can
@@ -80,26 +81,121 @@ pub fn take_byte_slice<T>(b: &mut T, len: usize) -> io::Result<Vec<u8>>
 where
   T: Read + ?Sized,
 {
-  let mut buf = vec![0; len];
-  b.read_exact(&mut buf)?;
+  let mut buf = Vec::new();
+  take_byte_slice_into(b, len, &mut buf)?;
 
   Ok(buf)
 }
 
-/// Creates a path to the index file for the given path. If the given path has an extension, the
-/// extension is replaced with `index.<extension>`. If the given path does not have an extension,
-/// the extension is set to `index`.
-pub fn create_index_path(path: &Path) -> PathBuf {
+/// Takes a byte slice of length `len` from the given reader into `buf`, replacing its contents and
+/// reusing its allocation. If the reader does not have enough bytes to satisfy the request, an
+/// error is returned.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use sstables::read::take_byte_slice_into;
+///
+/// let mut cursor = Cursor::new([1, 2, 3, 4, 5]);
+/// let mut buf = Vec::with_capacity(8);
+/// take_byte_slice_into(&mut cursor, 3, &mut buf).unwrap();
+/// assert_eq!(buf, [1, 2, 3]);
+/// take_byte_slice_into(&mut cursor, 2, &mut buf).unwrap();
+/// assert_eq!(buf, [4, 5]);
+/// ```
+///
+/// # Performance Considerations
+///
+/// The buffer only grows when `len` is larger than its capacity, so reading many values into the
+/// same buffer doesn't allocate once it's large enough. Bytes are read as they arrive rather than
+/// reserved up front, so a corrupt length doesn't allocate more than the reader holds.
+pub fn take_byte_slice_into<T>(b: &mut T, len: usize, buf: &mut Vec<u8>) -> io::Result<()>
+where
+  T: Read + ?Sized,
+{
+  buf.clear();
+  b.take(len as u64).read_to_end(buf)?;
+  if buf.len() < len {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+
+  Ok(())
+}
+
+/// Creates a path to a sidecar file for the given path. If the given path has an extension, the
+/// extension is replaced with `<name>.<extension>`. If the given path does not have an extension,
+/// the extension is set to `<name>`.
+///
+/// # Example
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use sstables::read::create_sidecar_path;
+///
+/// assert_eq!(create_sidecar_path(Path::new("foo.sst"), "bloom"), PathBuf::from("foo.bloom.sst"));
+/// assert_eq!(create_sidecar_path(Path::new("foo"), "bloom"), PathBuf::from("foo.bloom"));
+/// ```
+pub fn create_sidecar_path(path: &Path, name: &str) -> PathBuf {
   let mut path = path.to_path_buf();
   let ext_maybe = path.extension();
   match ext_maybe {
-    Some(ext) => path.set_extension(format!("index.{}", ext.to_str().unwrap())),
-    None => path.set_extension("index"),
+    Some(ext) => path.set_extension(format!("{}.{}", name, ext.to_str().unwrap())),
+    None => path.set_extension(name),
   };
 
   path
 }
 
+/// Creates a path to the index file for the given path. If the given path has an extension, the
+/// extension is replaced with `index.<extension>`. If the given path does not have an extension,
+/// the extension is set to `index`.
+pub fn create_index_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "index")
+}
+
+/// Creates a path to the bloom filter file for the given path. If the given path has an
+/// extension, the extension is replaced with `bloom.<extension>`. If the given path does not have
+/// an extension, the extension is set to `bloom`.
+pub fn create_bloom_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "bloom")
+}
+
+/// Creates a path to the write-ahead log file for the given path. If the given path has an
+/// extension, the extension is replaced with `wal.<extension>`. If the given path does not have
+/// an extension, the extension is set to `wal`.
+pub fn create_wal_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "wal")
+}
+
+/// Creates a path to the checksum file for the given path. If the given path has an extension, the
+/// extension is replaced with `crc.<extension>`. If the given path does not have an extension, the
+/// extension is set to `crc`.
+pub fn create_checksum_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "crc")
+}
+
+/// Creates a path to the metadata file for the given path. If the given path has an extension, the
+/// extension is replaced with `meta.<extension>`. If the given path does not have an extension, the
+/// extension is set to `meta`.
+pub fn create_metadata_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "meta")
+}
+
+/// Creates a path to the offset table of the index for the given path. If the given path has an
+/// extension, the extension is replaced with `offsets.<extension>`. If the given path does not have
+/// an extension, the extension is set to `offsets`.
+pub fn create_index_offsets_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "offsets")
+}
+
+/// Creates a path to the summary of the index for the given path. If the given path has an
+/// extension, the extension is replaced with `summary.<extension>`. If the given path does not have
+/// an extension, the extension is set to `summary`.
+pub fn create_summary_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "summary")
+}
+
 /// Gets a `BufWriter` for the given path and buffer size in append mode. If the file does not
 /// exist, it is created. File position is set to the end of the file. File creation errors and
 /// file append errors are returned.
@@ -181,6 +277,21 @@ mod tests {
     assert_eq!(take_byte_slice(&mut cursor, 3).unwrap(), [1, 2, 3]);
     assert_eq!(cursor.position(), 3);
   }
+
+  #[test]
+  fn test_take_byte_slice_into_reuses_the_buffer() {
+    let mut cursor = io::Cursor::new([1, 2, 3, 4, 5]);
+    let mut buf = Vec::with_capacity(4);
+    let ptr = buf.as_ptr();
+    take_byte_slice_into(&mut cursor, 3, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3]);
+    assert_eq!(buf.as_ptr(), ptr);
+    assert_eq!(
+      take_byte_slice_into(&mut cursor, 3, &mut buf).unwrap_err().kind(),
+      io::ErrorKind::UnexpectedEof
+    );
+    assert::cursor_completely_consumed(&cursor);
+  }
 }
 
 /// Test that `take_byte_slice` returns an error when there are not enough bytes to satisfy the
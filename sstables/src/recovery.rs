@@ -0,0 +1,383 @@
+//! Recovery of a table that was not closed cleanly, such as after a crash or power loss.
+//!
+//! The data and index files are appended to through buffers, so a process that dies mid-write can
+//! leave a partial CBOR item at the end of either file, an index entry that points past the end of
+//! the data file, or entries at the end of the data file that never made it into the index.
+//!
+//! `recover` checks the tail of both files before they are opened for appending. The index is read
+//! up to its last complete entry, and the data file is scanned from the indexed entry with the
+//! largest offset before its end. A partial record at the end of the data file is truncated, index
+//! entries past the new end are truncated too, and index entries for the complete records after
+//! the last indexed entry are re-derived according to the index density and written as they're
+//! found. The returned `Recovery` says what was repaired.
+//!
+//! An index that isn't in the order of the data file, such as one rebuilt in key order by
+//! `IndexRebuilder`, can't be appended to, so it's an error and neither file is changed.
+//!
+//! Only a record that runs into the end of the data file is treated as torn. Any other error while
+//! reading is returned without changing the files, since it means the files are not a table that
+//! can be appended to.
+
+use std::{
+  fmt::{self, Display},
+  fs::{File, OpenOptions},
+  io::{self, BufReader, BufWriter, Seek, SeekFrom, Write},
+  path::Path,
+};
+
+use crate::{
+  cbor::{read_cbor_raw, read_cbor_u64, CborWrite},
+  header::data_start,
+  sstable_writer::IndexDensity,
+};
+
+/// What was repaired when opening a table. Everything is zero if the table was closed cleanly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Recovery {
+  /// The number of bytes of a partial record removed from the end of the data file.
+  pub truncated_data_bytes: u64,
+  /// The number of bytes removed from the end of the index file, of a partial entry or of entries
+  /// that pointed past the end of the data file.
+  pub truncated_index_bytes: u64,
+  /// The number of index entries added for records that were missing from the index.
+  pub restored_index_entries: u64,
+}
+
+impl Recovery {
+  /// Whether nothing needed to be repaired.
+  pub fn is_clean(&self) -> bool {
+    *self == Recovery::default()
+  }
+}
+
+impl Display for Recovery {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "truncated {} bytes from the data file and {} bytes from the index file, and restored {} index entries",
+      self.truncated_data_bytes, self.truncated_index_bytes, self.restored_index_entries
+    )
+  }
+}
+
+/// Returns the size of the file, or zero if it doesn't exist.
+fn file_size(path: &Path) -> io::Result<u64> {
+  match path.metadata() {
+    Ok(metadata) => Ok(metadata.len()),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+    Err(e) => Err(e),
+  }
+}
+
+/// Returns the result, or `None` if it failed because the input ended early.
+fn complete<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+  match result {
+    Ok(value) => Ok(Some(value)),
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+    Err(e) => Err(e),
+  }
+}
+
+/// Reads the records of the data file from the start offset, calling `f` with the offset and key
+/// of each complete one, and returns the offset after the last. Reading stops at the end of the
+/// file, at a partial record that runs into it, or at the end offset, if there is one.
+fn scan_records<F>(data_path: &Path, start: u64, end: Option<u64>, mut f: F) -> io::Result<u64>
+where
+  F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+  let mut reader = BufReader::new(File::open(data_path)?);
+  let data_size = reader.seek(SeekFrom::End(0))?;
+  reader.seek(SeekFrom::Start(start))?;
+  let (mut key_bytes, mut value_bytes) = (Vec::new(), Vec::new());
+  let mut offset = start;
+  while end.is_none_or(|end| offset < end) {
+    key_bytes.clear();
+    value_bytes.clear();
+    match read_cbor_raw(&mut reader, &mut key_bytes).and_then(|_| read_cbor_raw(&mut reader, &mut value_bytes)) {
+      Ok(()) => {}
+      // Only a record that runs into the end of the file was torn by a crash.
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && reader.stream_position()? == data_size => break,
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("The record at offset {offset} ends early, before the end of the data file"),
+        ))
+      }
+      Err(e) => return Err(e),
+    }
+    f(offset, &key_bytes)?;
+    offset += (key_bytes.len() + value_bytes.len()) as u64;
+  }
+  Ok(offset)
+}
+
+/// Checks the tails of the data and index files of a table, repairing them so that the table can
+/// be appended to. Missing files are treated as empty.
+pub fn recover(data_path: &Path, index_path: &Path, index_density: IndexDensity) -> io::Result<Recovery> {
+  let mut recovery = Recovery::default();
+  let data_size = file_size(data_path)?;
+  let index_size = file_size(index_path)?;
+
+  // The end position in the index file and the data offset of each complete index entry. The
+  // offsets increase in an index in the order of the data file, the only order that can be
+  // appended to.
+  let mut index_entries: Vec<(u64, u64)> = Vec::new();
+  let mut data_order = true;
+  if index_size > 0 {
+    let mut reader = BufReader::new(File::open(index_path)?);
+    let (mut key_bytes, mut offset_bytes) = (Vec::new(), Vec::new());
+    let mut position = 0;
+    loop {
+      key_bytes.clear();
+      offset_bytes.clear();
+      let entry =
+        read_cbor_raw(&mut reader, &mut key_bytes).and_then(|_| read_cbor_raw(&mut reader, &mut offset_bytes));
+      if complete(entry)?.is_none() {
+        break;
+      }
+      position += (key_bytes.len() + offset_bytes.len()) as u64;
+      let offset = read_cbor_u64(&mut offset_bytes.as_slice())?;
+      data_order &= index_entries
+        .last()
+        .is_none_or(|(_, last_offset)| offset > *last_offset);
+      index_entries.push((position, offset));
+    }
+  }
+  // An index that only points past the end of the data file is cut, whatever its order.
+  if !data_order && index_entries.iter().any(|(_, offset)| *offset < data_size) {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "The index isn't in the order of the data file, so the table can't be appended to until the index is rebuilt in that order",
+    ));
+  }
+
+  // Scan the data file from the indexed record with the largest offset before its end, or from the
+  // first record after the header. A partial header is scanned as a partial record, which
+  // truncates it. Nothing is changed until the whole tail has been checked.
+  let header_size = complete(data_start(data_path))?.unwrap_or(0);
+  let scan_start = index_entries
+    .iter()
+    .map(|(_, offset)| *offset)
+    .filter(|offset| *offset < data_size)
+    .max()
+    .unwrap_or(header_size);
+  let valid_data_size = if data_size > scan_start {
+    scan_records(data_path, scan_start, None, |_, _| Ok(()))?
+  } else {
+    scan_start
+  };
+
+  if valid_data_size < data_size {
+    OpenOptions::new()
+      .write(true)
+      .open(data_path)?
+      .set_len(valid_data_size)?;
+    recovery.truncated_data_bytes = data_size - valid_data_size;
+  }
+
+  // Keep the index entries that point at complete records.
+  let kept_entries = index_entries
+    .iter()
+    .take_while(|(_, offset)| *offset < valid_data_size)
+    .count();
+  let valid_index_size = match kept_entries {
+    0 => 0,
+    n => index_entries[n - 1].0,
+  };
+  if valid_index_size < index_size {
+    OpenOptions::new()
+      .write(true)
+      .open(index_path)?
+      .set_len(valid_index_size)?;
+    recovery.truncated_index_bytes = index_size - valid_index_size;
+  }
+  // Index the records after the last kept index entry as the writer would have, writing the entries
+  // as they're read.
+  let mut last_indexed_offset = match kept_entries {
+    0 => None,
+    n => Some(index_entries[n - 1].1),
+  };
+  let mut records_since_indexed = 1;
+  let mut index_writer = None;
+  if valid_data_size > scan_start {
+    scan_records(data_path, scan_start, Some(valid_data_size), |offset, key_bytes| {
+      if last_indexed_offset.is_some_and(|last_indexed_offset| offset <= last_indexed_offset) {
+        return Ok(());
+      }
+      if index_density.should_index(last_indexed_offset, records_since_indexed, offset) {
+        let index_writer = match &mut index_writer {
+          Some(index_writer) => index_writer,
+          None => index_writer.insert(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(index_path)?,
+          )),
+        };
+        index_writer.write_all(key_bytes)?;
+        offset.cbor_write(index_writer)?;
+        last_indexed_offset = Some(offset);
+        records_since_indexed = 1;
+        recovery.restored_index_entries += 1;
+      } else {
+        records_since_indexed += 1;
+      }
+      Ok(())
+    })?;
+  }
+  if let Some(mut index_writer) = index_writer {
+    index_writer.flush()?;
+  }
+
+  Ok(recovery)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{FromPath, SSTableIndex, SSTableReader, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::fs;
+
+  const TEST_FILE_NAME: &str = ".tmp/recovery_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/recovery_test.index.sst";
+
+  fn setup_test_sstable(index_density: IndexDensity, n: u64) {
+    setup::create_dir_all(".tmp").unwrap();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(index_density)
+      .build()
+      .unwrap();
+    for i in 0..n {
+      writer.write((i, "value")).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  fn truncate(path: &str, bytes: u64) {
+    let size = fs::metadata(path).unwrap().len();
+    OpenOptions::new()
+      .write(true)
+      .open(path)
+      .unwrap()
+      .set_len(size - bytes)
+      .unwrap();
+  }
+
+  fn read_index() -> Vec<(u64, u64)> {
+    SSTableIndex::<u64>::from_path(TEST_INDEX_FILE_NAME).unwrap().indices
+  }
+
+  fn recover_test_sstable(index_density: IndexDensity) -> Recovery {
+    recover(
+      Path::new(TEST_FILE_NAME),
+      Path::new(TEST_INDEX_FILE_NAME),
+      index_density,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn clean_tables_are_unchanged() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, 3);
+    assert!(recover_test_sstable(IndexDensity::Dense).is_clean());
+    assert::equal(read_index(), vec![(0, 0), (1, 7), (2, 14)]);
+
+    fs::remove_file(TEST_FILE_NAME).unwrap();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap();
+    assert!(recover_test_sstable(IndexDensity::Dense).is_clean());
+  }
+
+  #[test]
+  fn truncates_partial_records() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, 3);
+    truncate(TEST_FILE_NAME, 2);
+
+    let recovery = recover_test_sstable(IndexDensity::Dense);
+    assert::equal(
+      recovery,
+      Recovery {
+        truncated_data_bytes: 5,
+        truncated_index_bytes: 2,
+        restored_index_entries: 0,
+      },
+    );
+    assert::equal(fs::metadata(TEST_FILE_NAME).unwrap().len(), 14);
+    assert::equal(read_index(), vec![(0, 0), (1, 7)]);
+  }
+
+  #[test]
+  fn truncates_partial_index_entries_and_restores_missing_ones() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, 4);
+    truncate(TEST_INDEX_FILE_NAME, 3);
+
+    let recovery = recover_test_sstable(IndexDensity::Dense);
+    assert::equal(
+      recovery,
+      Recovery {
+        truncated_data_bytes: 0,
+        truncated_index_bytes: 1,
+        restored_index_entries: 2,
+      },
+    );
+    assert::equal(read_index(), vec![(0, 0), (1, 7), (2, 14), (3, 21)]);
+  }
+
+  #[test]
+  fn rejects_indexes_out_of_data_order() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, 3);
+    let mut index = Vec::new();
+    for (key, offset) in [(2u64, 14u64), (1, 7), (0, 0)] {
+      key.cbor_write(&mut index).unwrap();
+      offset.cbor_write(&mut index).unwrap();
+    }
+    fs::write(TEST_INDEX_FILE_NAME, &index).unwrap();
+    truncate(TEST_FILE_NAME, 2);
+
+    let result = recover(
+      Path::new(TEST_FILE_NAME),
+      Path::new(TEST_INDEX_FILE_NAME),
+      IndexDensity::Dense,
+    );
+    assert::equal(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert::equal(fs::metadata(TEST_FILE_NAME).unwrap().len(), 19);
+    assert::equal(fs::read(TEST_INDEX_FILE_NAME).unwrap(), index);
+  }
+
+  #[test]
+  fn rejects_corrupt_records_before_the_end() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, 3);
+    fs::write(TEST_INDEX_FILE_NAME, []).unwrap();
+    // Reserved additional info in the key of the second record.
+    let mut bytes = fs::read(TEST_FILE_NAME).unwrap();
+    bytes[7] = 0x1C;
+    fs::write(TEST_FILE_NAME, &bytes).unwrap();
+
+    let result = recover(
+      Path::new(TEST_FILE_NAME),
+      Path::new(TEST_INDEX_FILE_NAME),
+      IndexDensity::Dense,
+    );
+    assert::equal(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert::equal(fs::read(TEST_FILE_NAME).unwrap(), bytes);
+    assert::equal(fs::metadata(TEST_INDEX_FILE_NAME).unwrap().len(), 0);
+  }
+
+  #[test]
+  fn restores_sparse_index_entries() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::EveryRecords(2), 6);
+    assert::equal(read_index(), vec![(0, 0), (2, 14), (4, 28)]);
+    fs::write(TEST_INDEX_FILE_NAME, [0x00, 0x00]).unwrap();
+
+    let recovery = recover_test_sstable(IndexDensity::EveryRecords(2));
+    assert::equal(recovery.restored_index_entries, 2);
+    assert::equal(read_index(), vec![(0, 0), (2, 14), (4, 28)]);
+    let entries = SSTableReader::<(u64, String)>::from_path(TEST_FILE_NAME).unwrap();
+    assert::equal(entries.count(), 6);
+  }
+}
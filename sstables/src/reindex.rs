@@ -0,0 +1,435 @@
+//! Rebuilds the index file of an SSTable from its data file.
+//!
+//! The data file is streamed from start to end, recording the offset of each entry, and a fresh
+//! index is written beside it. This recovers a lost or corrupt index, and adds an index to data
+//! files that were written without one.
+//!
+//! The index can be dense or sparse, like the one written by `SSTableWriter`, and its entries can
+//! stay in the order of the data file or be sorted. A sorted index of an unsorted data file can be
+//! binary searched for the offsets of a key, either in CBOR order with `cbor_binary_search_first`
+//! or in the native order of the key type. `SSTable::open` doesn't seek with an index whose offsets
+//! are out of order, so it scans such a table, but `SSTable::open_with_disk_index` and
+//! `SSTable::open_with_summary` only scan it if the metadata of the table says it isn't sorted. A
+//! sparse index only works for sorted data, so it can't be sorted.
+//!
+//! The writer appends index entries in the order of the data file, so a table whose index is in
+//! another order can't be appended to, and `SSTableWriterBuilder::build` refuses to open it. Rebuild
+//! the index in `IndexOrder::Data` first.
+//!
+//! If the table has an offset table for its index, it is rebuilt along with the index beside the
+//! data file. So is the summary of the index, if the rebuilder is given its block size, and
+//! otherwise it is removed, since it no longer matches the index. See [`crate::disk_index`] and
+//! [`crate::summary`].
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::reindex::{IndexOrder, IndexRebuilder};
+//! use sstables::{FromPath, SSTableIndex, SSTableWriterBuilder};
+//!
+//! let path = std::env::temp_dir().join("reindex_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! let index_path = sstables::read::create_index_path(&path);
+//! let mut writer = SSTableWriterBuilder::new(&path).build().unwrap();
+//! writer.write(("b", "1")).unwrap();
+//! writer.write(("a", "2")).unwrap();
+//! writer.close().unwrap();
+//! std::fs::remove_file(&index_path).unwrap();
+//!
+//! let entries = IndexRebuilder::new(&path).order(IndexOrder::Native).rebuild::<String>().unwrap();
+//! assert_eq!(entries, 2);
+//!
+//! let index = SSTableIndex::<String>::from_path(&index_path).unwrap();
+//! let position = index.indices.binary_search_by(|(key, _)| key.as_str().cmp("b")).unwrap();
+//! assert_eq!(index.indices[position], ("b".to_string(), 0));
+//! ```
+
+use std::{
+  fs::{self, File},
+  io::{self, BufRead, BufReader, BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  cbor::{cbor_head_size, cbor_raw_cmp, read_cbor_raw, CborRead, CborWrite},
+  disk_index::write_index_offset,
+  header::skip_header,
+  read::{create_index_offsets_path, create_index_path, create_sidecar_path, create_summary_path},
+  sstable_writer::IndexDensity,
+  summary::recover_summary,
+};
+
+/// The order of the entries of a rebuilt index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexOrder {
+  /// The order of the data file, as written by `SSTableWriter`.
+  #[default]
+  Data,
+  /// Sorted by the encoded keys, in the order of `cbor::cbor_sort`.
+  Cbor,
+  /// Sorted by the keys in their native order, as used by `SSTable`.
+  Native,
+}
+
+/// Builder for rebuilding the index of a data file.
+pub struct IndexRebuilder {
+  data_path: PathBuf,
+  index_path: Option<PathBuf>,
+  index_density: IndexDensity,
+  order: IndexOrder,
+  summary_block_size: Option<u64>,
+}
+
+impl IndexRebuilder {
+  pub fn new<P: Into<PathBuf>>(data_path: P) -> Self {
+    IndexRebuilder {
+      data_path: data_path.into(),
+      index_path: None,
+      index_density: IndexDensity::Dense,
+      order: IndexOrder::Data,
+      summary_block_size: None,
+    }
+  }
+
+  /// Set a custom path for the index file. If not set, the index file is the one beside the data
+  /// file, the same one `SSTableWriterBuilder` uses.
+  pub fn index_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+    self.index_path = Some(path.into());
+    self
+  }
+
+  /// Set how often entries are added to the index. If not set, every entry is indexed.
+  pub fn index_density(mut self, index_density: IndexDensity) -> Self {
+    self.index_density = index_density;
+    self
+  }
+
+  /// Set the order of the entries of the index. If not set, they are in the order of the data file.
+  pub fn order(mut self, order: IndexOrder) -> Self {
+    self.order = order;
+    self
+  }
+
+  /// Rebuild the summary of the index beside the data file too, with blocks of `entries_per_block`
+  /// index entries. If not set, a summary beside the data file is removed.
+  pub fn summary(mut self, entries_per_block: u64) -> Self {
+    self.summary_block_size = Some(entries_per_block.max(1));
+    self
+  }
+
+  /// Streams the data file and replaces the index file, returning the number of index entries. The
+  /// keys are only read as `K` to sort them in their native order.
+  pub fn rebuild<K>(self) -> io::Result<u64>
+  where
+    K: Ord,
+    for<'a> &'a [u8]: CborRead<K>,
+  {
+    if self.order != IndexOrder::Data && self.index_density != IndexDensity::Dense {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "a sparse index only works for sorted data, so it can't be sorted",
+      ));
+    }
+
+    // Only an offset table or summary beside the data file belongs to the index beside it.
+    let offsets_path = Some(create_index_offsets_path(&self.data_path))
+      .filter(|offsets_path| self.index_path.is_none() && offsets_path.is_file());
+    let summary_path = Some(create_summary_path(&self.data_path)).filter(|_| self.index_path.is_none());
+    let index_path = self.index_path.unwrap_or_else(|| create_index_path(&self.data_path));
+    let mut data_reader = BufReader::new(File::open(&self.data_path)?);
+
+    // The new index replaces the old one only once it is complete.
+    let temp_path = create_temp_path(&index_path);
+    let mut index_writer = IndexWriter {
+      index_writer: BufWriter::new(File::create(&temp_path)?),
+      offsets_writer: match &offsets_path {
+        Some(offsets_path) => Some(BufWriter::new(File::create(create_temp_path(offsets_path))?)),
+        None => None,
+      },
+      position: 0,
+    };
+    let mut entries = Vec::new();
+    let mut entry_count = 0;
+    let mut last_indexed_offset = None;
+    let mut records_since_indexed = 0;
+    let mut offset = skip_header(&mut data_reader)?;
+    let mut value_bytes = Vec::new();
+
+    while !data_reader.fill_buf()?.is_empty() {
+      let mut key_bytes = Vec::new();
+      value_bytes.clear();
+      read_cbor_raw(&mut data_reader, &mut key_bytes)
+        .and_then(|_| read_cbor_raw(&mut data_reader, &mut value_bytes))
+        .map_err(|e| match e.kind() {
+          io::ErrorKind::UnexpectedEof => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("partial record at offset {} of the data file", offset),
+          ),
+          _ => e,
+        })?;
+      let record_size = (key_bytes.len() + value_bytes.len()) as u64;
+
+      if self
+        .index_density
+        .should_index(last_indexed_offset, records_since_indexed, offset)
+      {
+        match self.order {
+          IndexOrder::Data => index_writer.write_entry(&key_bytes, offset)?,
+          IndexOrder::Cbor | IndexOrder::Native => entries.push((key_bytes, offset)),
+        }
+        entry_count += 1;
+        last_indexed_offset = Some(offset);
+        records_since_indexed = 1;
+      } else {
+        records_since_indexed += 1;
+      }
+
+      offset += record_size;
+    }
+
+    // Equal keys stay in the order of the data file, so that their values are read forward.
+    match self.order {
+      IndexOrder::Data => {}
+      IndexOrder::Cbor => {
+        entries.sort_by(|(a, a_offset), (b, b_offset)| cbor_raw_cmp(a, b).then(a_offset.cmp(b_offset)))
+      }
+      IndexOrder::Native => {
+        let mut keyed_entries = entries
+          .into_iter()
+          .map(|(key_bytes, offset)| Ok((CborRead::<K>::cbor_read(&mut key_bytes.as_slice())?, key_bytes, offset)))
+          .collect::<io::Result<Vec<_>>>()?;
+        keyed_entries.sort_by(|(a, _, a_offset), (b, _, b_offset)| a.cmp(b).then(a_offset.cmp(b_offset)));
+        entries = keyed_entries
+          .into_iter()
+          .map(|(_, key_bytes, offset)| (key_bytes, offset))
+          .collect();
+      }
+    }
+    for (key_bytes, offset) in entries {
+      index_writer.write_entry(&key_bytes, offset)?;
+    }
+
+    index_writer.index_writer.flush()?;
+    index_writer.index_writer.get_ref().sync_all()?;
+    fs::rename(temp_path, &index_path)?;
+    if let (Some(mut offsets_writer), Some(offsets_path)) = (index_writer.offsets_writer, offsets_path) {
+      offsets_writer.flush()?;
+      offsets_writer.get_ref().sync_all()?;
+      fs::rename(create_temp_path(&offsets_path), offsets_path)?;
+    }
+    match (summary_path, self.summary_block_size) {
+      (Some(summary_path), Some(block_size)) => {
+        File::create(&summary_path)?;
+        recover_summary(&index_path, &summary_path, block_size)?;
+      }
+      (Some(summary_path), None) => match fs::remove_file(summary_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+      },
+      (None, _) => {}
+    }
+
+    Ok(entry_count)
+  }
+}
+
+/// Creates the path of the file a rebuilt file is written to before it replaces the one at the path.
+/// It's a sidecar of that file, so it's in the same directory and can't be one of the user's files.
+fn create_temp_path(path: &Path) -> PathBuf {
+  create_sidecar_path(path, "index-tmp")
+}
+
+/// Writes the entries of the rebuilt index, and their positions to the offset table if there is one.
+struct IndexWriter {
+  index_writer: BufWriter<File>,
+  offsets_writer: Option<BufWriter<File>>,
+  position: u64,
+}
+
+impl IndexWriter {
+  fn write_entry(&mut self, key_bytes: &[u8], offset: u64) -> io::Result<()> {
+    if let Some(offsets_writer) = &mut self.offsets_writer {
+      write_index_offset(offsets_writer, self.position)?;
+    }
+    self.index_writer.write_all(key_bytes)?;
+    offset.cbor_write(&mut self.index_writer)?;
+    self.position += key_bytes.len() as u64 + cbor_head_size(offset);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{cbor::is_cbor_sorted, disk_index::DiskIndex, FromPath, SSTableIndex, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::path::Path;
+
+  const TEST_FILE_NAME: &str = ".tmp/reindex_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/reindex_test.index.sst";
+
+  fn setup_test_sstable(keys: &[&str]) -> Vec<(String, u64)> {
+    setup::create_dir_all(".tmp").unwrap();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    for key in keys {
+      writer.write((*key, "value")).unwrap();
+    }
+    writer.close().unwrap();
+
+    let indices = read_index();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap();
+    indices
+  }
+
+  fn read_index() -> Vec<(String, u64)> {
+    SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap().indices
+  }
+
+  fn index_keys() -> Vec<String> {
+    read_index().into_iter().map(|(key, _)| key).collect()
+  }
+
+  #[test]
+  fn rebuilds_the_index_the_writer_wrote() {
+    let _lock = setup::sequential();
+    let written = setup_test_sstable(&["a", "b", "b", "c"]);
+
+    let entries = IndexRebuilder::new(TEST_FILE_NAME).rebuild::<String>().unwrap();
+    assert::equal(entries, 4);
+    assert::equal(read_index(), written);
+  }
+
+  #[test]
+  fn rebuilds_a_sparse_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["a", "b", "c", "d", "e"]);
+
+    let entries = IndexRebuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryRecords(2))
+      .rebuild::<String>()
+      .unwrap();
+    assert::equal(entries, 3);
+    assert::equal(index_keys(), vec!["a", "c", "e"]);
+
+    let result = IndexRebuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryRecords(2))
+      .order(IndexOrder::Native)
+      .rebuild::<String>();
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn sorts_the_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["bb", "a", "c", "a"]);
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .order(IndexOrder::Native)
+      .rebuild::<String>()
+      .unwrap();
+    assert::equal(
+      read_index(),
+      vec![
+        ("a".to_string(), 9),
+        ("a".to_string(), 25),
+        ("bb".to_string(), 0),
+        ("c".to_string(), 17),
+      ],
+    );
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .order(IndexOrder::Cbor)
+      .rebuild::<String>()
+      .unwrap();
+    assert::equal(index_keys(), vec!["a", "a", "c", "bb"]);
+    assert!(is_cbor_sorted(&read_index()));
+  }
+
+  #[test]
+  fn refuses_appends_to_tables_with_sorted_indexes() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["c", "a", "b"]);
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .order(IndexOrder::Native)
+      .rebuild::<String>()
+      .unwrap();
+    let indices = read_index();
+    let result = SSTableWriterBuilder::new(TEST_FILE_NAME).build();
+    assert::equal(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    assert::equal(read_index(), indices);
+
+    IndexRebuilder::new(TEST_FILE_NAME).rebuild::<String>().unwrap();
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    writer.write(("d", "value")).unwrap();
+    writer.close().unwrap();
+    assert::equal(index_keys(), vec!["c", "a", "b", "d"]);
+  }
+
+  #[test]
+  fn writes_beside_other_files_without_replacing_them() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["a", "b"]);
+    let index_path = ".tmp/reindex_test.idx";
+    let other_path = ".tmp/reindex_test.tmp";
+    fs::write(other_path, "not an index").unwrap();
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .index_path(index_path)
+      .rebuild::<String>()
+      .unwrap();
+    assert::equal(SSTableIndex::<String>::from_path(index_path).unwrap().indices.len(), 2);
+    assert::equal(fs::read_to_string(other_path).unwrap(), "not an index".to_string());
+    assert!(!create_temp_path(Path::new(index_path)).exists());
+    fs::remove_file(index_path).unwrap();
+    fs::remove_file(other_path).unwrap();
+  }
+
+  #[test]
+  fn rebuilds_the_offset_table() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["bb", "a", "c"]);
+    let offsets_path = create_index_offsets_path(Path::new(TEST_FILE_NAME));
+    fs::write(&offsets_path, []).unwrap();
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .order(IndexOrder::Native)
+      .rebuild::<String>()
+      .unwrap();
+    let mut index = DiskIndex::<String>::open(TEST_FILE_NAME).unwrap();
+    let entries = (0..index.len()).map(|i| index.entry(i).unwrap()).collect::<Vec<_>>();
+    assert::equal(entries, read_index());
+    fs::remove_file(offsets_path).unwrap();
+  }
+
+  #[test]
+  fn rebuilds_the_summary() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["c", "a", "b", "d"]);
+    let summary_path = create_summary_path(Path::new(TEST_FILE_NAME));
+
+    IndexRebuilder::new(TEST_FILE_NAME)
+      .order(IndexOrder::Native)
+      .summary(3)
+      .rebuild::<String>()
+      .unwrap();
+    let summary = SSTableIndex::<String>::from_path(&summary_path).unwrap();
+    assert::equal(summary.indices, vec![("a".to_string(), 0), ("d".to_string(), 9)]);
+
+    IndexRebuilder::new(TEST_FILE_NAME).rebuild::<String>().unwrap();
+    assert!(!summary_path.exists());
+  }
+
+  #[test]
+  fn fails_on_partial_records() {
+    let _lock = setup::sequential();
+    setup_test_sstable(&["a"]);
+    let mut data = fs::read(TEST_FILE_NAME).unwrap();
+    data.push(0x61);
+    fs::write(TEST_FILE_NAME, data).unwrap();
+
+    assert!(IndexRebuilder::new(TEST_FILE_NAME).rebuild::<String>().is_err());
+  }
+}
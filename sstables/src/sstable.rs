@@ -0,0 +1,827 @@
+//! SSTable
+//!
+//! This module contains the `SSTable` struct, which pairs an `SSTableReader` with its
+//! `SSTableIndex` and bloom filter to look up keys. Point lookups, range scans and prefix scans
+//! all return iterators over the matching key-value pairs, in the order they appear in the data
+//! file.
+//!
+//! When the table is sorted, lookups seek to the nearest index entry before the first matching key
+//! and stop at the first key past the end of the range, which works for both dense and sparse
+//! indices. Whether it's sorted comes from the table's metadata (see [`crate::metadata`]), since
+//! the keys of a sparse index can be in order when the data isn't. Tables without metadata, which
+//! is how tables are written by default, are presumed to be sorted unless their index is out of
+//! order, by key or by offset. A scan of such a table checks the order of the keys it reads, and once it reads a key
+//! smaller than the one before it, it reads the rest of the data file and then the entries before
+//! where it started. When the metadata says the table isn't sorted, or without an index, every
+//! entry of the data file is scanned. Keys are compared by their native ordering.
+//!
+//! The metadata compares keys in their natural CBOR order, so it's only trusted for text, byte
+//! string, integer, bool and epoch-based date/time keys, where that order matches the `Ord` of
+//! `String`, `Vec<u8>`, the integer types, `bool` and `Timestamp`. For other keys, such a table is
+//! scanned, and a table of keys read as a type with a different `Ord` has to be paired with its
+//! index by `SSTable::new` instead.
+//!
+//! Point lookups check the bloom filter first, so a key that it rules out doesn't touch the index
+//! or the data file. `SSTable::open` reads the whole index into memory, on the first lookup that
+//! needs it. For tables with an offset table, `SSTable::open_with_disk_index` binary searches the
+//! index on disk instead, and for tables with a summary, `SSTable::open_with_summary` only holds
+//! the summary in memory and reads blocks of the index as needed. See [`crate::disk_index`] and
+//! [`crate::summary`].
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::{SSTable, SSTableWriterBuilder};
+//!
+//! let path = std::env::temp_dir().join("sstable_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).build().unwrap();
+//! writer.write(("apple", "1")).unwrap();
+//! writer.write(("apricot", "2")).unwrap();
+//! writer.write(("banana", "3")).unwrap();
+//! writer.close().unwrap();
+//!
+//! let mut sstable = SSTable::<String, String>::open(&path).unwrap();
+//! assert_eq!(sstable.get(&"banana".to_string()).unwrap(), Some("3".to_string()));
+//!
+//! let keys = sstable
+//!   .prefix(&"ap".to_string())
+//!   .unwrap()
+//!   .map(|kv| kv.map(|(k, _)| k))
+//!   .collect::<std::io::Result<Vec<String>>>()
+//!   .unwrap();
+//! assert_eq!(keys, vec!["apple", "apricot"]);
+//! ```
+//!
+//! # Tombstones
+//!
+//! A key is deleted by writing a tombstone with `SSTableWriter::write_tombstone`, which reads as
+//! `None` when values are read as an `Option`. `get_live` looks up a key in a set of tables from the
+//! newest to the oldest, so a tombstone hides the values of the key in every older table.
+//!
+//! # Errors
+//!
+//! Opening fails if the data file cannot be read. A missing index or bloom filter is not an error.
+//! Iterators yield any `io::Error` from the data file and then stop.
+
+use crate::bloom::{load_bloom_filter, BloomFilter};
+use crate::cbor::{CborRead, CborWrite};
+use crate::disk_index::DiskIndex;
+use crate::metadata::load_metadata;
+use crate::read::create_index_path;
+use crate::sstable_reader::{SSTableIndex, SSTableReader};
+use crate::summary::SummaryIndex;
+use crate::traits::{FromPath, Tombstone};
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+
+/// Keys that can be scanned by prefix.
+pub trait KeyPrefix {
+  /// Returns true if this key starts with the prefix.
+  fn starts_with_key(&self, prefix: &Self) -> bool;
+}
+
+impl KeyPrefix for String {
+  fn starts_with_key(&self, prefix: &Self) -> bool {
+    self.starts_with(prefix.as_str())
+  }
+}
+
+impl KeyPrefix for Vec<u8> {
+  fn starts_with_key(&self, prefix: &Self) -> bool {
+    self.starts_with(prefix)
+  }
+}
+
+/// An SSTable data file with its index and bloom filter, if they exist. Reads from a file by
+/// default, but can read from any source that implements `Read` and `Seek`.
+#[derive(Debug)]
+pub struct SSTable<K, V, R = BufReader<File>> {
+  pub reader: SSTableReader<(K, V), R>,
+  /// The index, which `open` only reads on the first lookup that seeks.
+  pub index: Option<SSTableIndex<K>>,
+  /// The path of an index that hasn't been read yet, and how to read it.
+  unread_index: Option<(PathBuf, IndexLoader<K>)>,
+  pub bloom_filter: Option<BloomFilter>,
+  /// The index searched on disk, used instead of `index` if present.
+  pub disk_index: Option<DiskIndex<K>>,
+  /// The summary of the index, used instead of `index` if present.
+  pub summary: Option<SummaryIndex<K>>,
+  /// Whether the data file is sorted by key, so the index can be used to seek.
+  order: KeyOrder,
+}
+
+/// Reads the index at a path.
+type IndexLoader<K> = fn(&Path) -> io::Result<SSTableIndex<K>>;
+
+/// What is known about the order of the keys in a data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyOrder {
+  /// The keys are sorted, so lookups seek with the index and stop at the first key past the end.
+  Sorted,
+  /// The keys are presumed to be sorted, as for `Sorted`, but scans check it as they read.
+  Presumed,
+  /// The keys aren't known to be sorted, so every lookup scans the whole data file.
+  Unsorted,
+}
+
+impl<K, V> SSTable<K, V>
+where
+  K: Ord + Clone,
+  BufReader<File>: CborRead<K> + CborRead<V>,
+{
+  /// Opens the data file at the given path, along with its index and bloom filter if they exist.
+  /// The index is only read on the first lookup that the bloom filter doesn't rule out. An index
+  /// that cannot be read is ignored, and a stale bloom filter is never used. The index is used to
+  /// seek unless the metadata of the table says it isn't sorted, or the keys or offsets of the index
+  /// are out of order.
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref();
+    let reader = SSTableReader::<(K, V)>::from_path(path)?;
+    let bloom_filter = load_bloom_filter(path)?;
+    let order = load_order(path)?;
+    let load_index: IndexLoader<K> = |index_path| SSTableIndex::<K>::from_path(index_path);
+
+    Ok(SSTable {
+      unread_index: Some((create_index_path(path), load_index)),
+      order,
+      ..SSTable::new(reader, None, bloom_filter)
+    })
+  }
+
+  /// Opens the data file at the given path like `open`, but binary searches the index on disk
+  /// through its offset table instead of reading it into memory. As with `open`, the index isn't
+  /// used if the metadata of the table says it isn't sorted. Without an offset table that matches
+  /// the index, every lookup scans the data file.
+  pub fn open_with_disk_index<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref();
+    let reader = SSTableReader::<(K, V)>::from_path(path)?;
+    let disk_index = DiskIndex::<K>::open(path).ok();
+    let bloom_filter = load_bloom_filter(path)?;
+    let order = load_order(path)?;
+
+    Ok(SSTable {
+      reader,
+      index: None,
+      unread_index: None,
+      bloom_filter,
+      disk_index,
+      summary: None,
+      order,
+    })
+  }
+
+  /// Opens the data file at the given path like `open`, but only reads the summary of the index
+  /// into memory, and reads blocks of the index as lookups need them. As with `open`, the summary
+  /// isn't used if the metadata of the table says it isn't sorted. Without a summary that matches
+  /// the index, every lookup scans the data file.
+  pub fn open_with_summary<P: AsRef<Path>>(path: P) -> io::Result<Self>
+  where
+    for<'a> &'a [u8]: CborRead<K>,
+  {
+    let path = path.as_ref();
+    let reader = SSTableReader::<(K, V)>::from_path(path)?;
+    let summary = SummaryIndex::<K>::open(path).ok();
+    let bloom_filter = load_bloom_filter(path)?;
+    let order = load_order(path)?;
+
+    Ok(SSTable {
+      reader,
+      index: None,
+      unread_index: None,
+      bloom_filter,
+      disk_index: None,
+      summary,
+      order,
+    })
+  }
+}
+
+impl<K, V, R> SSTable<K, V, R>
+where
+  K: Ord + Clone,
+  R: Seek,
+  SSTableReader<(K, V), R>: Iterator<Item = io::Result<(K, V)>>,
+{
+  /// Pairs a reader with an index and bloom filter. The table isn't known to be sorted, so every
+  /// lookup scans the data, unless `sorted` says otherwise.
+  pub fn new(
+    reader: SSTableReader<(K, V), R>,
+    index: Option<SSTableIndex<K>>,
+    bloom_filter: Option<BloomFilter>,
+  ) -> Self {
+    SSTable {
+      reader,
+      index,
+      unread_index: None,
+      bloom_filter,
+      disk_index: None,
+      summary: None,
+      order: KeyOrder::Unsorted,
+    }
+  }
+
+  /// Sets whether the data is sorted by key, so lookups can seek with the index and stop at the
+  /// first key past the end. Only set it if the table is known to be sorted, such as from its
+  /// metadata, or lookups miss keys.
+  pub fn sorted(mut self, sorted: bool) -> Self {
+    self.order = if sorted { KeyOrder::Sorted } else { KeyOrder::Unsorted };
+    self
+  }
+
+  /// Returns the first value of the key, if any.
+  pub fn get(&mut self, key: &K) -> io::Result<Option<V>>
+  where
+    K: CborWrite,
+  {
+    self.get_all(key)?.next().transpose().map(|kv| kv.map(|(_, v)| v))
+  }
+
+  /// Returns the last value of the key, which is the newest one written to the table.
+  pub fn get_last(&mut self, key: &K) -> io::Result<Option<V>>
+  where
+    K: CborWrite,
+  {
+    let mut last = None;
+    for kv_result in self.get_all(key)? {
+      last = Some(kv_result?.1);
+    }
+    Ok(last)
+  }
+
+  /// Returns every entry with the key.
+  pub fn get_all(&mut self, key: &K) -> io::Result<Scan<'_, K, V, R>>
+  where
+    K: CborWrite,
+  {
+    // A key that the bloom filter rules out doesn't need the index or the data file.
+    if self
+      .bloom_filter
+      .as_ref()
+      .is_some_and(|bloom_filter| !bloom_filter.may_contain(key))
+    {
+      return Ok(Scan {
+        reader: &mut self.reader,
+        start: Bound::Unbounded,
+        end: Bound::Unbounded,
+        prefix: None,
+        order: self.order,
+        last_key: None,
+        offset: 0,
+        wrapped: false,
+        done: true,
+      });
+    }
+
+    self.scan(Bound::Included(key.clone()), Bound::Included(key.clone()), None)
+  }
+
+  /// Returns every entry with a key in the range.
+  pub fn range<B: RangeBounds<K>>(&mut self, range: B) -> io::Result<Scan<'_, K, V, R>> {
+    self.scan(range.start_bound().cloned(), range.end_bound().cloned(), None)
+  }
+
+  /// Returns every entry with a key that starts with the prefix.
+  pub fn prefix(&mut self, prefix: &K) -> io::Result<Scan<'_, K, V, R>>
+  where
+    K: KeyPrefix,
+  {
+    let starts_with_key: fn(&K, &K) -> bool = K::starts_with_key;
+    self.scan(
+      Bound::Included(prefix.clone()),
+      Bound::Unbounded,
+      Some((prefix.clone(), starts_with_key)),
+    )
+  }
+
+  /// Seeks to where the start bound could first appear, and returns a scan from there.
+  fn scan(
+    &mut self,
+    start: Bound<K>,
+    end: Bound<K>,
+    prefix: Option<(K, fn(&K, &K) -> bool)>,
+  ) -> io::Result<Scan<'_, K, V, R>> {
+    let key = match &start {
+      Bound::Included(key) | Bound::Excluded(key) if self.order != KeyOrder::Unsorted => Some(key),
+      _ => None,
+    };
+    if let (Some(_), Some((index_path, load_index))) = (key, self.unread_index.take()) {
+      self.index = load_index(&index_path).ok();
+      // An index that's out of order, by key or by offset, isn't of a sorted data file.
+      if self.order == KeyOrder::Presumed
+        && self
+          .index
+          .as_ref()
+          .is_some_and(|index| !index.indices.is_sorted_by(|a, b| a.0 <= b.0 && a.1 < b.1))
+      {
+        self.order = KeyOrder::Unsorted;
+      }
+    }
+    let key = key.filter(|_| self.order != KeyOrder::Unsorted);
+    let offset = match (key, &mut self.summary, &mut self.disk_index, &self.index) {
+      (Some(key), Some(summary), _, _) => summary.seek_offset(key)?,
+      (Some(key), None, Some(disk_index), _) => disk_index.seek_offset(key)?,
+      (Some(key), None, None, Some(index)) => match index.indices.partition_point(|(k, _)| k < key) {
+        0 => None,
+        x => Some(index.indices[x - 1].1),
+      },
+      _ => None,
+    };
+    let offset = offset.unwrap_or(self.reader.data_start());
+    self.reader.seek(SeekFrom::Start(offset))?;
+
+    Ok(Scan {
+      reader: &mut self.reader,
+      start,
+      end,
+      prefix,
+      order: self.order,
+      last_key: None,
+      offset,
+      wrapped: false,
+      done: false,
+    })
+  }
+}
+
+/// Whether the metadata of the table at the path says that its keys are sorted, in an order that
+/// matches the `Ord` of the key types lookups read them as. A table without metadata, or with
+/// stale metadata, is presumed to be sorted.
+fn load_order(path: &Path) -> io::Result<KeyOrder> {
+  Ok(match load_metadata(path)? {
+    Some(metadata) if metadata.sorted_for_lookups() => KeyOrder::Sorted,
+    Some(_) => KeyOrder::Unsorted,
+    None => KeyOrder::Presumed,
+  })
+}
+
+/// Looks up the newest value of a key in tables given from oldest to newest. The newest table with
+/// the key decides, so a tombstone shadows the values in every older table. Returns `None` if the
+/// key was deleted or was never written.
+pub fn get_live<K, V, R>(tables: &mut [SSTable<K, V, R>], key: &K) -> io::Result<Option<V::Live>>
+where
+  K: Ord + Clone + CborWrite,
+  V: Tombstone,
+  R: Seek,
+  SSTableReader<(K, V), R>: Iterator<Item = io::Result<(K, V)>>,
+{
+  for table in tables.iter_mut().rev() {
+    if let Some(value) = table.get_last(key)? {
+      return Ok(value.into_live());
+    }
+  }
+  Ok(None)
+}
+
+/// An iterator over the entries of an SSTable that match a key, range or prefix.
+pub struct Scan<'a, K, V, R = BufReader<File>> {
+  reader: &'a mut SSTableReader<(K, V), R>,
+  start: Bound<K>,
+  end: Bound<K>,
+  prefix: Option<(K, fn(&K, &K) -> bool)>,
+  /// Whether the data is sorted, so the scan can stop at the first key past the end.
+  order: KeyOrder,
+  /// The last key read while the order is presumed, to check that the keys don't go backwards.
+  last_key: Option<K>,
+  /// Where the scan started, and whether it has gone back to the start of the data file to read the
+  /// entries before it, after finding keys out of order.
+  offset: u64,
+  wrapped: bool,
+  done: bool,
+}
+
+impl<K, V, R> Scan<'_, K, V, R>
+where
+  K: Ord,
+{
+  fn is_before_start(&self, key: &K) -> bool {
+    match &self.start {
+      Bound::Included(start) => key < start,
+      Bound::Excluded(start) => key <= start,
+      Bound::Unbounded => false,
+    }
+  }
+
+  fn is_past_end(&self, key: &K) -> bool {
+    let past_prefix = match &self.prefix {
+      Some((prefix, starts_with_key)) => key > prefix && !starts_with_key(key, prefix),
+      None => false,
+    };
+
+    past_prefix
+      || match &self.end {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+      }
+  }
+}
+
+impl<K, V, R> Scan<'_, K, V, R>
+where
+  R: Seek,
+  SSTableReader<(K, V), R>: Iterator<Item = io::Result<(K, V)>>,
+{
+  /// Reads the next entry, going back to the start of the data file at the end if the scan found
+  /// keys out of order after it seeked, and stopping at where it started.
+  fn read_next(&mut self) -> io::Result<Option<(K, V)>> {
+    if self.wrapped && self.reader.stream_position()? >= self.offset {
+      return Ok(None);
+    }
+
+    match self.reader.next().transpose()? {
+      Some(kv) => Ok(Some(kv)),
+      None if self.order == KeyOrder::Unsorted && !self.wrapped && self.offset > self.reader.data_start() => {
+        self.wrapped = true;
+        self.reader.seek(SeekFrom::Start(self.reader.data_start()))?;
+        self.read_next()
+      }
+      None => Ok(None),
+    }
+  }
+}
+
+impl<K, V, R> Iterator for Scan<'_, K, V, R>
+where
+  K: Ord + Clone,
+  R: Seek,
+  SSTableReader<(K, V), R>: Iterator<Item = io::Result<(K, V)>>,
+{
+  type Item = io::Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while !self.done {
+      let (key, value) = match self.read_next() {
+        Ok(Some(kv)) => kv,
+        Ok(None) => {
+          self.done = true;
+          return None;
+        }
+        Err(e) => {
+          self.done = true;
+          return Some(Err(e));
+        }
+      };
+
+      if self.order == KeyOrder::Presumed {
+        if self.last_key.as_ref().is_some_and(|last_key| key < *last_key) {
+          self.order = KeyOrder::Unsorted;
+          self.last_key = None;
+        } else {
+          self.last_key = Some(key.clone());
+        }
+      }
+
+      if self.is_past_end(&key) {
+        self.done = self.order != KeyOrder::Unsorted;
+      } else if !self.is_before_start(&key) {
+        return Some(Ok((key, value)));
+      }
+    }
+
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cbor::Timestamp;
+  use crate::sstable_writer::{IndexDensity, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+  use std::fs;
+  use std::io::{Cursor, Read};
+
+  const TEST_FILE_NAME: &str = ".tmp/sstable_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/sstable_test.index.sst";
+  const TEST_BLOOM_FILE_NAME: &str = ".tmp/sstable_test.bloom.sst";
+  const TEST_METADATA_FILE_NAME: &str = ".tmp/sstable_test.meta.sst";
+
+  fn setup_test_sstable(index_density: IndexDensity, entries: &[(&str, &str)]) {
+    setup::create_dir_all(".tmp").unwrap();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_BLOOM_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_METADATA_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(index_density)
+      .bloom_filter(100, 0.01)
+      .metadata()
+      .build()
+      .unwrap();
+    for entry in entries {
+      writer.write(*entry).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  fn collect_values<R>(scan: io::Result<Scan<'_, String, String, R>>) -> Vec<String>
+  where
+    R: Read + Seek,
+  {
+    scan
+      .unwrap()
+      .map(|kv| kv.map(|(_, v)| v))
+      .collect::<io::Result<Vec<String>>>()
+      .unwrap()
+  }
+
+  const SORTED_ENTRIES: [(&str, &str); 7] = [
+    ("a", "1"),
+    ("ab", "2"),
+    ("ab", "3"),
+    ("abc", "4"),
+    ("b", "5"),
+    ("ba", "6"),
+    ("c", "7"),
+  ];
+
+  #[test]
+  fn test_get() {
+    let _lock = setup::sequential();
+    for index_density in [IndexDensity::Dense, IndexDensity::EveryRecords(3)] {
+      setup_test_sstable(index_density, &SORTED_ENTRIES);
+      let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+
+      assert::equal(sstable.get(&"a".to_string()).unwrap(), "1".to_string());
+      assert::equal(sstable.get(&"ab".to_string()).unwrap(), "2".to_string());
+      assert::equal(sstable.get(&"c".to_string()).unwrap(), "7".to_string());
+      assert::equal(sstable.get(&"bb".to_string()).unwrap(), None);
+      assert::equal(sstable.get(&"d".to_string()).unwrap(), None);
+    }
+  }
+
+  #[test]
+  fn test_bloom_filter_is_checked_before_the_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, &SORTED_ENTRIES);
+    let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::none(&sstable.index);
+
+    let position = sstable.reader.stream_position().unwrap();
+    assert::equal(sstable.get(&"d".to_string()).unwrap(), None);
+    assert::none(&sstable.index);
+    assert::equal(sstable.reader.stream_position().unwrap(), position);
+
+    assert::equal(sstable.get(&"b".to_string()).unwrap(), "5".to_string());
+    assert!(sstable.index.is_some());
+  }
+
+  #[test]
+  fn test_get_all() {
+    let _lock = setup::sequential();
+    for index_density in [IndexDensity::Dense, IndexDensity::EveryRecords(3)] {
+      setup_test_sstable(index_density, &SORTED_ENTRIES);
+      let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+
+      assert::equal(collect_values(sstable.get_all(&"ab".to_string())), vec!["2", "3"]);
+      assert::equal(
+        collect_values(sstable.get_all(&"abcd".to_string())),
+        Vec::<String>::new(),
+      );
+    }
+  }
+
+  #[test]
+  fn test_range() {
+    let _lock = setup::sequential();
+    for index_density in [IndexDensity::Dense, IndexDensity::EveryRecords(2)] {
+      setup_test_sstable(index_density, &SORTED_ENTRIES);
+      let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+
+      let range = sstable.range("ab".to_string().."b".to_string());
+      assert::equal(collect_values(range), vec!["2", "3", "4"]);
+      let range = sstable.range("ab".to_string()..="b".to_string());
+      assert::equal(collect_values(range), vec!["2", "3", "4", "5"]);
+      let range = sstable.range(.."ab".to_string());
+      assert::equal(collect_values(range), vec!["1"]);
+      let range = sstable.range("ba".to_string()..);
+      assert::equal(collect_values(range), vec!["6", "7"]);
+    }
+  }
+
+  #[test]
+  fn test_prefix() {
+    let _lock = setup::sequential();
+    for index_density in [IndexDensity::Dense, IndexDensity::EveryRecords(2)] {
+      setup_test_sstable(index_density, &SORTED_ENTRIES);
+      let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+
+      assert::equal(collect_values(sstable.prefix(&"ab".to_string())), vec!["2", "3", "4"]);
+      assert::equal(collect_values(sstable.prefix(&"b".to_string())), vec!["5", "6"]);
+      assert::equal(collect_values(sstable.prefix(&"d".to_string())), Vec::<String>::new());
+    }
+  }
+
+  #[test]
+  fn test_linear_scan_without_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, &[("b", "1"), ("a", "2"), ("c", "3"), ("a", "4")]);
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap();
+
+    let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::none(&sstable.index);
+    assert::equal(collect_values(sstable.get_all(&"a".to_string())), vec!["2", "4"]);
+    assert::equal(collect_values(sstable.range(.."c".to_string())), vec!["1", "2", "4"]);
+  }
+
+  #[test]
+  fn test_linear_scan_with_unsorted_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, &[("b", "1"), ("a", "2"), ("c", "3"), ("a", "4")]);
+
+    let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(collect_values(sstable.get_all(&"a".to_string())), vec!["2", "4"]);
+  }
+
+  #[test]
+  fn test_linear_scan_with_sorted_sparse_index_of_unsorted_data() {
+    let _lock = setup::sequential();
+    // The index has "a" and "b", which are in order, but "c" is between them in the data.
+    setup_test_sstable(IndexDensity::EveryRecords(2), &[("a", "1"), ("c", "2"), ("b", "3")]);
+
+    let mut sstable = SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(collect_values(sstable.get_all(&"c".to_string())), vec!["2"]);
+  }
+
+  /// Writes a table without metadata, with an index of the given density, an offset table and a
+  /// summary.
+  fn setup_test_sstable_without_metadata(index_density: IndexDensity, entries: &[(&str, &str)]) {
+    setup_test_sstable(IndexDensity::Dense, &[]);
+    for path in [
+      TEST_METADATA_FILE_NAME,
+      ".tmp/sstable_test.offsets.sst",
+      ".tmp/sstable_test.summary.sst",
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(index_density)
+      .index_offsets()
+      .summary(2)
+      .build()
+      .unwrap();
+    for entry in entries {
+      writer.write(*entry).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  #[test]
+  fn test_tables_without_metadata_are_presumed_sorted() {
+    let _lock = setup::sequential();
+    setup_test_sstable_without_metadata(IndexDensity::EveryRecords(2), &SORTED_ENTRIES);
+    let mut sstables = [
+      SSTable::<String, String>::open(TEST_FILE_NAME).unwrap(),
+      SSTable::<String, String>::open_with_disk_index(TEST_FILE_NAME).unwrap(),
+      SSTable::<String, String>::open_with_summary(TEST_FILE_NAME).unwrap(),
+    ];
+
+    // The key of the first entry isn't text, so only a lookup that seeks past it can succeed.
+    let mut data = fs::read(TEST_FILE_NAME).unwrap();
+    data[1] = 0xff;
+    fs::write(TEST_FILE_NAME, data).unwrap();
+
+    for sstable in &mut sstables {
+      assert::equal(collect_values(sstable.get_all(&"ba".to_string())), vec!["6"]);
+      assert::equal(
+        collect_values(sstable.range("abc".to_string()..)),
+        vec!["4", "5", "6", "7"],
+      );
+      assert!(sstable.range(.."b".to_string()).unwrap().any(|kv| kv.is_err()));
+    }
+  }
+
+  #[test]
+  fn test_scans_of_tables_presumed_sorted_check_the_order() {
+    let _lock = setup::sequential();
+    let entries = [("a", "1"), ("c", "2"), ("e", "3"), ("b", "4"), ("f", "5")];
+    for index_density in [IndexDensity::Dense, IndexDensity::EveryRecords(2)] {
+      setup_test_sstable_without_metadata(index_density, &entries);
+      let mut sstables = [
+        SSTable::<String, String>::open(TEST_FILE_NAME).unwrap(),
+        SSTable::<String, String>::open_with_disk_index(TEST_FILE_NAME).unwrap(),
+        SSTable::<String, String>::open_with_summary(TEST_FILE_NAME).unwrap(),
+      ];
+
+      // Finding "b" after "e" means the rest of the data file and the entries before where the
+      // scan started have to be read too.
+      for sstable in &mut sstables {
+        assert::equal(collect_values(sstable.range("c".to_string()..)), vec!["2", "3", "5"]);
+        assert::equal(
+          collect_values(sstable.range(.."f".to_string())),
+          vec!["1", "2", "3", "4"],
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_timestamp_keys_seek_with_the_index() {
+    let _lock = setup::sequential();
+    setup_test_sstable(IndexDensity::Dense, &[]);
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryRecords(2))
+      .metadata()
+      .build()
+      .unwrap();
+    for seconds in 0..6 {
+      writer
+        .write((Timestamp::from_secs(seconds * 60), seconds.to_string()))
+        .unwrap();
+    }
+    writer.close().unwrap();
+
+    // The first entry isn't a timestamp, so only a lookup that seeks past it can succeed.
+    let mut data = fs::read(TEST_FILE_NAME).unwrap();
+    data[0] = 0xff;
+    fs::write(TEST_FILE_NAME, data).unwrap();
+
+    let mut sstable = SSTable::<Timestamp, String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(sstable.get(&Timestamp::from_secs(240)).unwrap(), "4".to_string());
+    let values = sstable
+      .range(Timestamp::from_secs(150)..)
+      .unwrap()
+      .map(|kv| kv.map(|(_, v)| v))
+      .collect::<io::Result<Vec<String>>>()
+      .unwrap();
+    assert::equal(values, vec!["3", "4", "5"]);
+  }
+
+  #[test]
+  fn test_in_memory() {
+    // ("a", "1"), ("b", "2"), ("c", "3") with a sparse index of "a" and "c".
+    let data = vec![0x61, b'a', 0x61, b'1', 0x61, b'b', 0x61, b'2', 0x61, b'c', 0x61, b'3'];
+    let index = SSTableIndex {
+      indices: vec![("a".to_string(), 0), ("c".to_string(), 8)],
+    };
+    let reader = SSTableReader::new(Cursor::new(data));
+    let mut sstable = SSTable::<String, String, _>::new(reader, Some(index), None).sorted(true);
+
+    assert::equal(sstable.get(&"b".to_string()).unwrap(), "2".to_string());
+    assert::equal(collect_values(sstable.range("b".to_string()..)), vec!["2", "3"]);
+  }
+
+  #[test]
+  fn test_signed_integer_keys() {
+    let mut writer = SSTableWriterBuilder::new("memory")
+      .index_density(IndexDensity::EveryRecords(2))
+      .build_with_writers(Vec::new(), Vec::new())
+      .unwrap();
+    for key in [-1000i64, -25, -1, 0, 1, 1000] {
+      writer.write((key, key.to_string())).unwrap();
+    }
+    let (data, index) = writer.into_writers().unwrap();
+    let index = SSTableIndex::from_reader(index.as_slice()).unwrap();
+    let reader = SSTableReader::new(Cursor::new(data));
+    let mut sstable = SSTable::<i64, String, _>::new(reader, Some(index), None).sorted(true);
+
+    assert::equal(sstable.get(&-25).unwrap(), "-25".to_string());
+    assert::equal(sstable.get(&-2).unwrap(), None);
+    let values = sstable
+      .range(-25..1)
+      .unwrap()
+      .map(|kv| kv.map(|(_, v)| v))
+      .collect::<io::Result<Vec<String>>>()
+      .unwrap();
+    assert::equal(values, vec!["-25", "-1", "0"]);
+  }
+
+  #[test]
+  fn test_tombstones_shadow_older_tables() {
+    let tables = [
+      vec![("a", Some("1")), ("b", Some("2")), ("c", Some("3"))],
+      vec![("a", Some("4")), ("b", None), ("b", Some("5")), ("c", None)],
+    ];
+    let mut sstables = Vec::new();
+    for entries in tables {
+      let mut writer = SSTableWriterBuilder::new("memory")
+        .build_with_writers(Vec::new(), Vec::new())
+        .unwrap();
+      for (key, value) in entries {
+        match value {
+          Some(value) => writer.write((key, value)).unwrap(),
+          None => writer.write_tombstone(key).unwrap(),
+        }
+      }
+      let (data, index) = writer.into_writers().unwrap();
+      let index = SSTableIndex::from_reader(index.as_slice()).unwrap();
+      let reader = SSTableReader::new(Cursor::new(data));
+      sstables.push(SSTable::<String, Option<String>, _>::new(reader, Some(index), None));
+    }
+
+    assert::equal(sstables[1].get(&"b".to_string()).unwrap(), Some(None));
+    assert::equal(
+      sstables[1].get_last(&"b".to_string()).unwrap(),
+      Some(Some("5".to_string())),
+    );
+    assert::equal(get_live(&mut sstables, &"a".to_string()).unwrap(), "4".to_string());
+    assert::equal(get_live(&mut sstables, &"b".to_string()).unwrap(), "5".to_string());
+    assert::equal(get_live(&mut sstables, &"c".to_string()).unwrap(), None);
+    assert::equal(get_live(&mut sstables, &"d".to_string()).unwrap(), None);
+  }
+}
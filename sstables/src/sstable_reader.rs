@@ -1,7 +1,8 @@
-use crate::cbor::{read_cbor_u64, CborRead};
+use crate::cbor::{read_cbor_u64, CborRead, CborReadInto};
+use crate::header::{read_header, Header};
 use crate::traits::FromPath;
 use std::fs::File;
-use std::io::{self, BufReader, Seek};
+use std::io::{self, BufRead, BufReader, Read, Seek};
 use std::path::Path;
 
 /// Reads and holds the indices of an SSTable in memory, so that we can seek to
@@ -12,16 +13,14 @@ pub struct SSTableIndex<K> {
   pub indices: Vec<(K, u64)>,
 }
 
-/// Implementation of FromPath for SSTableIndex for any type that implements
-/// CborRead. The index is stored as a series of CBOR-encoded tuples of
-/// (key, offset). The index is read entirely into memory when the SSTableIndex
-/// is created.
-impl<T> FromPath<T> for SSTableIndex<T>
-where
-  io::BufReader<File>: CborRead<T>,
-{
-  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-    let mut reader = BufReader::new(File::open(path)?);
+impl<T> SSTableIndex<T> {
+  /// Reads an index from any reader, such as a `Cursor` over bytes. The index
+  /// is stored as a series of CBOR-encoded tuples of (key, offset), and is read
+  /// entirely into memory.
+  pub fn from_reader<R>(mut reader: R) -> io::Result<Self>
+  where
+    R: Read + CborRead<T>,
+  {
     let mut indices = Vec::new();
 
     // Read the entire file into memory.
@@ -44,38 +43,163 @@ where
   }
 }
 
+/// Implementation of FromPath for SSTableIndex for any type that implements
+/// CborRead. The index is read entirely into memory when the SSTableIndex is
+/// created.
+impl<T> FromPath<T> for SSTableIndex<T>
+where
+  io::BufReader<File>: CborRead<T>,
+{
+  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    SSTableIndex::from_reader(BufReader::new(File::open(path)?))
+  }
+}
+
 /// A SSTable reader that can read a series of bytes or text from an SSTable.
+/// Reads from a file by default, but can read from any source, such as a
+/// `Cursor` over bytes or a decompressing stream.
 #[derive(Debug)]
-pub struct SSTableReader<T> {
-  pub data_reader: BufReader<File>,
+pub struct SSTableReader<T, R = BufReader<File>> {
+  pub data_reader: R,
+  header: Option<Header>,
+  data_start: u64,
   phantom: std::marker::PhantomData<T>,
 }
 
-impl<T> FromPath<T> for SSTableReader<T> {
-  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+impl<T, R> SSTableReader<T, R> {
+  /// Creates a reader over any source of SSTable data.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use sstables::SSTableReader;
+  /// use std::io::Cursor;
+  ///
+  /// let data = [0x61, b'a', 0x61, b'1', 0x61, b'b', 0x61, b'2'];
+  /// let reader = SSTableReader::<(String, String), _>::new(Cursor::new(data));
+  /// let keys = reader.map(|kv| kv.unwrap().0).collect::<Vec<String>>();
+  ///
+  /// assert_eq!(keys, vec!["a", "b"]);
+  /// ```
+  pub fn new(data_reader: R) -> Self {
+    SSTableReader {
+      data_reader,
+      header: None,
+      data_start: 0,
+      phantom: std::marker::PhantomData,
+    }
+  }
+
+  /// Creates a reader over any source of SSTable data that may start with a header, which is read
+  /// and skipped. Sources without a header are read from the start. See [`crate::header`].
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use sstables::header::Header;
+  /// use sstables::{IndexDensity, SSTableReader};
+  /// use std::io::Cursor;
+  ///
+  /// let mut data = Header::new(IndexDensity::Dense, false, None).to_bytes().unwrap();
+  /// let data_start = data.len() as u64;
+  /// data.extend_from_slice(&[0x61, b'a', 0x61, b'1']);
+  ///
+  /// let reader = SSTableReader::<(String, String), _>::with_header(Cursor::new(data)).unwrap();
+  /// assert_eq!(reader.data_start(), data_start);
+  /// assert_eq!(reader.count(), 1);
+  /// ```
+  pub fn with_header(mut data_reader: R) -> io::Result<Self>
+  where
+    R: BufRead,
+  {
+    let header = read_header(&mut data_reader)?;
+    let data_start = header.as_ref().map_or(0, |(_, size)| *size);
     Ok(SSTableReader {
-      data_reader: BufReader::new(File::open(path)?),
+      data_reader,
+      header: header.map(|(header, _)| header),
+      data_start,
       phantom: std::marker::PhantomData,
     })
   }
+
+  /// The header of the data, if it had one and the reader was created with `with_header` or
+  /// `from_path`.
+  pub fn header(&self) -> Option<&Header> {
+    self.header.as_ref()
+  }
+
+  /// The offset of the first entry in the data, which is after the header.
+  pub fn data_start(&self) -> u64 {
+    self.data_start
+  }
+
+  /// Returns the underlying reader.
+  pub fn into_inner(self) -> R {
+    self.data_reader
+  }
+}
+
+impl<T> FromPath<T> for SSTableReader<T> {
+  fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    SSTableReader::with_header(BufReader::new(File::open(path)?))
+  }
 }
 
 /// Implementation of Seek for SSTableReader. The seek operation is delegated to
 /// the underlying reader.
-impl<T> Seek for SSTableReader<T> {
+impl<T, R: Seek> Seek for SSTableReader<T, R> {
   fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
     self.data_reader.seek(pos)
   }
+
+  fn stream_position(&mut self) -> io::Result<u64> {
+    self.data_reader.stream_position()
+  }
+}
+
+impl<K, V, R> SSTableReader<(K, V), R>
+where
+  R: CborReadInto<K> + CborReadInto<V>,
+{
+  /// Reads the next entry into `key` and `value`, returning false at the end of the data. Unlike
+  /// the iterator, the buffers of text and byte string keys and values are reused, so a scan
+  /// doesn't allocate for every entry once they are large enough.
+  ///
+  /// # Example
+  ///
+  /// ```
+  /// use sstables::SSTableReader;
+  ///
+  /// let data: &[u8] = &[0x61, b'a', 0x41, 1, 0x61, b'b', 0x42, 2, 3];
+  /// let mut reader = SSTableReader::<(String, Vec<u8>), _>::new(data);
+  /// let (mut key, mut value) = (String::new(), Vec::new());
+  /// let mut total = 0;
+  /// while reader.next_into(&mut key, &mut value).unwrap() {
+  ///   total += value.len();
+  /// }
+  /// assert_eq!((key.as_str(), total), ("b", 3));
+  /// ```
+  pub fn next_into(&mut self, key: &mut K, value: &mut V) -> io::Result<bool> {
+    let reader = &mut self.data_reader;
+    let result = reader.cbor_read_into(key).and_then(|_| reader.cbor_read_into(value));
+
+    match result {
+      Ok(()) => Ok(true),
+      Err(e) => match e.kind() {
+        io::ErrorKind::UnexpectedEof => Ok(false),
+        _ => Err(e),
+      },
+    }
+  }
 }
 
 /// Implementation of Iterator for SSTableReader for any type that implements
 /// CborRead. The iterator returns a series of tuples of (key, value). The
 /// iterator will return an error if the underlying reader returns an error, or
 /// None if the end of the file is reached.
-impl<K, V> Iterator for SSTableReader<(K, V)>
+impl<K, V, R> Iterator for SSTableReader<(K, V), R>
 where
-  io::BufReader<File>: CborRead<K>,
-  io::BufReader<File>: CborRead<V>,
+  R: CborRead<K> + CborRead<V>,
 {
   type Item = io::Result<(K, V)>;
 
@@ -92,3 +216,113 @@ where
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::SSTableWriterBuilder;
+  use common_testing::{assert, setup};
+  use std::fs;
+  use std::io::{Cursor, SeekFrom};
+
+  const TEST_FILE_NAME: &str = ".tmp/sstable_reader_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/sstable_reader_test.index.sst";
+
+  #[test]
+  fn test_read_from_cursor() {
+    // ("a", "1"), ("b", b"2")
+    let data = vec![0x61, b'a', 0x61, b'1', 0x61, b'b', 0x61, b'2'];
+    let mut reader = SSTableReader::<(String, String), _>::new(Cursor::new(data));
+
+    assert::equal(reader.next().unwrap().unwrap(), ("a".to_string(), "1".to_string()));
+    assert::equal(reader.stream_position().unwrap(), 4);
+    assert::equal(reader.next().unwrap().unwrap(), ("b".to_string(), "2".to_string()));
+    assert::none(&reader.next());
+
+    reader.seek(SeekFrom::Start(4)).unwrap();
+    assert::equal(reader.next().unwrap().unwrap(), ("b".to_string(), "2".to_string()));
+  }
+
+  #[test]
+  fn test_read_from_slice() {
+    // (1, b"a"), (2, b"b")
+    let data: &[u8] = &[0x01, 0x41, b'a', 0x02, 0x41, b'b'];
+    let reader = SSTableReader::<(u64, Vec<u8>), _>::new(data);
+
+    let entries = reader.collect::<io::Result<Vec<(u64, Vec<u8>)>>>().unwrap();
+    assert::equal(entries, vec![(1, b"a".to_vec()), (2, b"b".to_vec())]);
+  }
+
+  #[test]
+  fn test_next_into_reuses_buffers() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).header().build().unwrap();
+    writer.write(("a", Some("1"))).unwrap();
+    writer.write_tombstone("b").unwrap();
+    writer.write(("c", Some("333"))).unwrap();
+    writer.close().unwrap();
+
+    let mut reader = SSTableReader::<(String, Option<String>)>::from_path(TEST_FILE_NAME).unwrap();
+    let mut key = String::with_capacity(8);
+    let mut value = Some(String::with_capacity(8));
+    let (key_ptr, value_ptr) = (key.as_ptr(), value.as_ref().unwrap().as_ptr());
+    let mut entries = Vec::new();
+    while reader.next_into(&mut key, &mut value).unwrap() {
+      entries.push((key.clone(), value.clone()));
+      if entries.len() == 1 {
+        assert::equal(value.as_ref().unwrap().as_ptr(), value_ptr);
+      }
+    }
+    assert::equal(key.as_ptr(), key_ptr);
+    assert::equal(
+      entries,
+      vec![
+        ("a".to_string(), Some("1".to_string())),
+        ("b".to_string(), None),
+        ("c".to_string(), Some("333".to_string())),
+      ],
+    );
+
+    // A truncated entry ends the scan, and an invalid one is an error.
+    let mut reader = SSTableReader::<(String, String), _>::new(Cursor::new(vec![0x61, b'a', 0x62, b'1']));
+    let (mut key, mut value) = (String::new(), String::new());
+    assert::equal(reader.next_into(&mut key, &mut value).unwrap(), false);
+    let mut reader = SSTableReader::<(String, String), _>::new(Cursor::new(vec![0x61, 0xFF, 0x61, b'1']));
+    assert!(reader.next_into(&mut key, &mut value).is_err());
+    assert::equal(key, String::new());
+  }
+
+  #[test]
+  fn test_read_error_is_returned() {
+    // A key that is not valid UTF-8.
+    let data = vec![0x61, 0xFF, 0x61, b'1'];
+    let mut reader = SSTableReader::<(String, String), _>::new(Cursor::new(data));
+
+    assert!(reader.next().unwrap().is_err());
+  }
+
+  #[test]
+  fn test_index_from_reader_matches_from_path() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.write(("b", "2")).unwrap();
+    writer.close().unwrap();
+
+    let index_bytes = fs::read(TEST_INDEX_FILE_NAME).unwrap();
+    let from_reader = SSTableIndex::<String>::from_reader(Cursor::new(index_bytes)).unwrap();
+    let from_path = SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(&from_reader.indices, &from_path.indices);
+
+    let data_bytes = fs::read(TEST_FILE_NAME).unwrap();
+    let mut reader = SSTableReader::<(String, String), _>::new(Cursor::new(data_bytes));
+    reader.seek(SeekFrom::Start(from_reader.indices[1].1)).unwrap();
+    assert::equal(reader.next().unwrap().unwrap(), ("b".to_string(), "2".to_string()));
+  }
+}
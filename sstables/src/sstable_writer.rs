@@ -15,6 +15,10 @@
 //! multiple threads, you should use a `RwLock`. If you need to write to multiple files, you should
 //! use multiple `SSTableWriter`s.
 //!
+//! By default the `SSTableWriter` writes to files, but it can write to any pair of `Write` sinks,
+//! such as a `Vec<u8>`, a compressor, or a socket. Offsets are tracked by the writer, so the sinks
+//! do not need to implement `Seek`.
+//!
 //! # Example
 //!
 //! ```
@@ -38,22 +42,100 @@
 //! written first, followed by the file offset of the value in the data file. If writing a single
 //! value, the file offset of the value in the data file is written directly.
 //!
+//! By default every entry is indexed. A sparse index only records every Nth entry, or one entry
+//! every N bytes of data, which keeps the index small for very large tables. The first entry
+//! written after opening a file is always indexed. To find a key with a sparse index, seek to the
+//! nearest index entry before the key and scan the data file forward from there.
+//!
+//! With an offset table enabled, a file beside the index holds the position of every index entry,
+//! so that the index can be binary searched on disk. See [`crate::disk_index`]. With a summary
+//! enabled, a file beside the index holds the first key of every block of N index entries, so that
+//! only the summary has to be held in memory. See [`crate::summary`].
+//!
+//! With checksums enabled, a checksum file beside the data file holds the offset and CRC32C of every
+//! record. See [`crate::checksum`].
+//!
+//! With a header enabled, a new data file starts with a self-describing header with the format
+//! version, and the offsets of the entries start after it. See [`crate::header`].
+//!
+//! If enabled, a bloom filter file is written on `close()` alongside the data and index files. See
+//! [`crate::bloom`] for its format. A metadata file with a summary of the table can be written the
+//! same way. See [`crate::metadata`].
+//!
+//! When opening files to append to, the builder first checks that they end with complete records,
+//! truncating a partial record and restoring missing index entries. See [`crate::recovery`].
+//!
+//! The writer only syncs the files on `close()`. To make every write durable sooner, enable a
+//! write-ahead log with `SSTableWriterBuilder::wal`, which is replayed into the table the next time
+//! it is opened. See [`crate::wal`].
+//!
 //! # Errors
 //!
 //! The `SSTableWriter` struct will return an error if the file cannot be opened for writing, or if
 //! the file cannot be flushed to disk. All errors are standard `io::Error`s.
 //!
 
-use crate::cbor::CborWrite;
-use crate::read::{create_index_path, get_file_writer};
-use std::fs::File;
-use std::io::{self, BufWriter, Result, Seek, Write};
-use std::path::PathBuf;
+use crate::bloom::BloomFilter;
+use crate::cbor::{cbor_head_size, CborValue, CborWrite};
+use crate::checksum::{record_checksum, recover_checksums, write_checksum};
+use crate::disk_index::{recover_index_offsets, write_index_offset};
+use crate::header::{data_start, Header};
+use crate::metadata::{Metadata, WRITER_VERSION};
+use crate::read::{
+  create_bloom_path, create_checksum_path, create_index_offsets_path, create_index_path, create_metadata_path,
+  create_summary_path, create_wal_path, get_file_writer,
+};
+use crate::recovery::{recover, Recovery};
+use crate::summary::{recover_summary, write_summary_entry};
+use crate::traits::FromPath;
+use crate::wal::{SyncPolicy, Wal, WalReader};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
 
 /// The default buffer size for the `SSTableWriter`.
 const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
 
-/// Builder for `SSTableWriter`
+/// How often the `SSTableWriter` adds an entry to the index file.
+///
+/// # Example
+///
+/// ```
+/// use sstables::sstable_writer::{IndexDensity, SSTableWriterBuilder};
+///
+/// // One index entry for every 64 KiB of data.
+/// let mut writer = SSTableWriterBuilder::new("test")
+///  .index_density(IndexDensity::EveryBytes(64 * 1024))
+///  .build()
+///  .unwrap();
+///
+/// writer.write(("hello", "world")).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexDensity {
+  /// Every entry is indexed.
+  #[default]
+  Dense,
+  /// One entry is indexed for every N entries written.
+  EveryRecords(u64),
+  /// One entry is indexed once at least N bytes of data were written since the last indexed entry.
+  EveryBytes(u64),
+}
+
+impl IndexDensity {
+  /// Whether the entry at the data offset should get an index entry, given the offset of the last
+  /// indexed entry and the number of entries written since it, counting the indexed one.
+  pub(crate) fn should_index(self, last_indexed_offset: Option<u64>, records_since_indexed: u64, offset: u64) -> bool {
+    match (last_indexed_offset, self) {
+      (None, _) | (_, IndexDensity::Dense) => true,
+      (Some(_), IndexDensity::EveryRecords(n)) => records_since_indexed >= n,
+      (Some(last_indexed_offset), IndexDensity::EveryBytes(n)) => offset - last_indexed_offset >= n,
+    }
+  }
+}
+
+/// Builder for `SSTableWriter`. Builds a writer for files with `build`, or for any pair of `Write`
+/// sinks with `build_with_writers`.
 ///
 /// # Example
 ///
@@ -82,10 +164,37 @@ const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
 ///
 /// writer.write(("hello", "world")).unwrap();
 /// ```
+///
+/// # Example
+///
+/// ```
+/// use sstables::sstable_writer::SSTableWriterBuilder;
+///
+/// let mut writer = SSTableWriterBuilder::new("memory")
+///  .build_with_writers(Vec::new(), Vec::new())
+///  .unwrap();
+///
+/// writer.write(("hello", "world")).unwrap();
+///
+/// let (data, index) = writer.into_writers().unwrap();
+/// assert_eq!(data, b"\x65hello\x65world");
+/// assert_eq!(index, b"\x65hello\x00");
+/// ```
 pub struct SSTableWriterBuilder {
   data_writer_path: PathBuf,
   index_writer_path: Option<PathBuf>,
+  bloom_filter_path: Option<PathBuf>,
+  bloom_filter: Option<(usize, f64)>,
+  index_density: IndexDensity,
   buffer_size: usize,
+  wal: Option<SyncPolicy>,
+  checksums: bool,
+  metadata: bool,
+  properties: Vec<(String, CborValue)>,
+  header: bool,
+  key_type: Option<String>,
+  index_offsets: bool,
+  summary_block_size: Option<u64>,
 }
 
 impl SSTableWriterBuilder {
@@ -93,7 +202,18 @@ impl SSTableWriterBuilder {
     SSTableWriterBuilder {
       data_writer_path: data_writer_path.into(),
       index_writer_path: None,
+      bloom_filter_path: None,
+      bloom_filter: None,
+      index_density: IndexDensity::Dense,
       buffer_size: DEFAULT_BUFFER_SIZE,
+      wal: None,
+      checksums: false,
+      metadata: false,
+      properties: Vec::new(),
+      header: false,
+      key_type: None,
+      index_offsets: false,
+      summary_block_size: None,
     }
   }
 
@@ -111,78 +231,670 @@ impl SSTableWriterBuilder {
     self
   }
 
+  /// Set how often entries are added to the index file. If not set, every entry is indexed.
+  pub fn index_density(mut self, index_density: IndexDensity) -> Self {
+    self.index_density = index_density;
+    self
+  }
+
+  /// Keep an offset table beside the index with the position of every index entry, so that the
+  /// index can be binary searched on disk with `DiskIndex` instead of being loaded into memory. The
+  /// table is brought up to date with the index when the writer is built. Only used for files, not
+  /// by `build_with_writers`. See [`crate::disk_index`].
+  pub fn index_offsets(mut self) -> Self {
+    self.index_offsets = true;
+    self
+  }
+
+  /// Keep a summary beside the index with the first key of every block of `entries_per_block`
+  /// index entries, so that lookups with `SummaryIndex` only hold the summary in memory. The summary
+  /// is brought up to date with the index when the writer is built. Only used for files, not by
+  /// `build_with_writers`. See [`crate::summary`].
+  pub fn summary(mut self, entries_per_block: u64) -> Self {
+    self.summary_block_size = Some(entries_per_block.max(1));
+    self
+  }
+
+  /// Write a bloom filter file sized for `expected_items` keys at the given false positive rate.
+  /// If the data file already has entries and its bloom filter is missing, stale or saturated, the
+  /// filter is rebuilt from the data file with room for twice as many keys.
+  pub fn bloom_filter(mut self, expected_items: usize, false_positive_rate: f64) -> Self {
+    self.bloom_filter = Some((expected_items, false_positive_rate));
+    self
+  }
+
+  /// Set a custom path for the bloom filter file. If not set, the bloom filter file will be created
+  /// in the same directory as the data file, with the same name as the data file, but with "bloom"
+  /// prepended to the extension.
+  pub fn bloom_filter_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+    self.bloom_filter_path = Some(path.into());
+    self
+  }
+
+  /// Append every write to a write-ahead log beside the data file, synced with the given policy.
+  /// When the table is opened with a log that has entries, they are replayed into the table first.
+  /// Opening such a table without a log is an error, since the next replay would undo anything
+  /// appended in between. Only used for files, not by `build_with_writers`.
+  pub fn wal(mut self, sync_policy: SyncPolicy) -> Self {
+    self.wal = Some(sync_policy);
+    self
+  }
+
+  /// Keep a CRC32C of every record in a checksum file beside the data file, so that corruption can
+  /// be found with `checksum::verify`. Records already in the data file without a checksum are
+  /// checksummed when the writer is built. Only used for files, not by `build_with_writers`.
+  pub fn checksums(mut self) -> Self {
+    self.checksums = true;
+    self
+  }
+
+  /// Write a metadata file on `close()` with a summary of the table, such as its entry count and
+  /// key range. If the data file already has entries and its metadata is missing or stale, the
+  /// metadata is rebuilt from the data file. See [`crate::metadata`].
+  pub fn metadata(mut self) -> Self {
+    self.metadata = true;
+    self
+  }
+
+  /// Set a user-defined property in the metadata file, which enables the metadata file.
+  pub fn property<N: Into<String>, V: Into<CborValue>>(mut self, name: N, value: V) -> Self {
+    self.metadata = true;
+    self.properties.push((name.into(), value.into()));
+    self
+  }
+
+  /// Start a new data file with a self-describing header, which records the format version, the
+  /// index density and whether there are checksums. A data file that already has entries keeps
+  /// whatever it starts with. See [`crate::header`].
+  pub fn header(mut self) -> Self {
+    self.header = true;
+    self
+  }
+
+  /// Record the type of the keys in the header, such as `text` or `integer`, which enables the
+  /// header.
+  pub fn key_type<T: Into<String>>(mut self, key_type: T) -> Self {
+    self.header = true;
+    self.key_type = Some(key_type.into());
+    self
+  }
+
+  /// The header for a new data file, if enabled.
+  fn new_header(&self) -> Option<Header> {
+    self
+      .header
+      .then(|| Header::new(self.index_density, self.checksums, self.key_type.clone()))
+  }
+
   /// Consumes the builder, returning a `SSTableWriter`.
   pub fn build(self) -> io::Result<SSTableWriter> {
+    let header = self.new_header();
     let data_writer_path = self.data_writer_path;
-    let data_writer = get_file_writer(&data_writer_path, self.buffer_size)?;
 
     // If the index writer path is not set, create it from the data writer path.
     let index_writer_path = self
       .index_writer_path
       .unwrap_or_else(|| create_index_path(&data_writer_path));
 
+    // Anything past the sizes at the start of the log was not synced, and is replayed from the log.
+    // A log without entries was checkpointed, so the files are left alone.
+    let wal_path = create_wal_path(&data_writer_path);
+    let mut wal_reader = match WalReader::open(&wal_path)? {
+      Some(mut wal_reader) => wal_reader.has_entries()?.then_some(wal_reader),
+      None => None,
+    };
+    if let Some(wal_reader) = &wal_reader {
+      if self.wal.is_none() {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "The table has a write-ahead log with entries that were never replayed, so open it with a write-ahead log first",
+        ));
+      }
+      if file_size(&data_writer_path)? < wal_reader.data_size || file_size(&index_writer_path)? < wal_reader.index_size
+      {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          "The write-ahead log is stale, since the table is smaller than when the log was started",
+        ));
+      }
+      truncate_file(&data_writer_path, wal_reader.data_size)?;
+      truncate_file(&index_writer_path, wal_reader.index_size)?;
+    }
+
+    // A partial record at the end of either file would corrupt everything appended after it.
+    let recovery = recover(&data_writer_path, &index_writer_path, self.index_density)?;
+
+    // The checksums have to match the records that are left, before any are replayed from the log.
+    let checksum_writer = if self.checksums {
+      let checksum_path = create_checksum_path(&data_writer_path);
+      recover_checksums(&data_writer_path, &checksum_path)?;
+      Some(get_file_writer(&checksum_path, self.buffer_size)?)
+    } else {
+      None
+    };
+
+    // The offset table has to match the index that is left, before any entries are replayed.
+    let index_offsets_writer = if self.index_offsets {
+      let index_offsets_path = create_index_offsets_path(&data_writer_path);
+      recover_index_offsets(&index_writer_path, &index_offsets_path)?;
+      Some(get_file_writer(&index_offsets_path, self.buffer_size)?)
+    } else {
+      None
+    };
+
+    let (summary_writer, summary_block_entries) = match self.summary_block_size {
+      Some(block_size) => {
+        let summary_path = create_summary_path(&data_writer_path);
+        let block_entries = recover_summary(&index_writer_path, &summary_path, block_size)?;
+        (Some(get_file_writer(&summary_path, self.buffer_size)?), block_entries)
+      }
+      None => (None, 0),
+    };
+
+    let mut data_writer = get_file_writer(&data_writer_path, self.buffer_size)?;
     let index_writer = get_file_writer(&index_writer_path, self.buffer_size)?;
+    let data_size = data_writer.get_ref().metadata()?.len();
+    let index_size = index_writer.get_ref().metadata()?.len();
+
+    // If the bloom filter path is not set, create it from the data writer path.
+    let bloom_filter_path = self
+      .bloom_filter_path
+      .unwrap_or_else(|| create_bloom_path(&data_writer_path));
+
+    let bloom_filter = match self.bloom_filter {
+      Some((expected_items, false_positive_rate)) => Some(open_bloom_filter(
+        &data_writer_path,
+        &bloom_filter_path,
+        data_size,
+        expected_items,
+        false_positive_rate,
+      )?),
+      None => None,
+    };
+
+    // A new data file starts with the header. Appending continues from the end of the data file.
+    let header_size = match header {
+      Some(header) if data_size == 0 => write_header(&mut data_writer, &header)?,
+      _ => data_start(&data_writer_path)?,
+    };
+    let data_offset = data_size.max(header_size);
+
+    let metadata_path = create_metadata_path(&data_writer_path);
+    let metadata = if self.metadata {
+      Some(open_metadata(
+        &data_writer_path,
+        &metadata_path,
+        data_size,
+        header_size,
+        self.properties,
+      )?)
+    } else {
+      None
+    };
+
+    let mut writer = SSTableWriter {
+      data_writer_path,
+      data_writer,
+      index_writer_path,
+      index_writer,
+      bloom_filter_path,
+      bloom_filter,
+      index_density: self.index_density,
+      data_offset,
+      index_size,
+      last_indexed_offset: None,
+      records_since_indexed: 0,
+      key_buffer: Vec::new(),
+      value_buffer: Vec::new(),
+      wal: None,
+      checksum_writer,
+      index_offsets_writer,
+      summary_writer,
+      summary_block_size: self.summary_block_size.unwrap_or(0),
+      summary_block_entries,
+      metadata_path,
+      metadata,
+      recovery,
+      poisoned: false,
+    };
+
+    if let Some(sync_policy) = self.wal {
+      if let Some(wal_reader) = &mut wal_reader {
+        while wal_reader.next_entry(&mut writer.key_buffer, &mut writer.value_buffer)? {
+          writer.write_buffers()?;
+        }
+      }
+      writer.sync_files()?;
+      let index_size = writer.index_writer.get_ref().metadata()?.len();
+      writer.wal = Some(Wal::create(wal_path, sync_policy, writer.data_offset, index_size)?);
+    }
+
+    Ok(writer)
+  }
+
+  /// Consumes the builder, returning a `SSTableWriter` that writes to the given sinks instead of
+  /// files. Offsets start at zero, so the sinks should be empty. The paths of the builder are kept
+  /// as the names of the files that the sinks will become, but nothing is written to them. The
+  /// buffer size is not used, so wrap the sinks in a `BufWriter` if they are unbuffered.
+  pub fn build_with_writers<D: Write, I: Write>(
+    self,
+    mut data_writer: D,
+    index_writer: I,
+  ) -> io::Result<SSTableWriter<D, I>> {
+    let data_offset = match self.new_header() {
+      Some(header) => write_header(&mut data_writer, &header)?,
+      None => 0,
+    };
+    let data_writer_path = self.data_writer_path;
+    let index_writer_path = self
+      .index_writer_path
+      .unwrap_or_else(|| create_index_path(&data_writer_path));
+    let bloom_filter_path = self
+      .bloom_filter_path
+      .unwrap_or_else(|| create_bloom_path(&data_writer_path));
+    let bloom_filter = self
+      .bloom_filter
+      .map(|(expected_items, false_positive_rate)| BloomFilter::new(expected_items, false_positive_rate));
+    let metadata_path = create_metadata_path(&data_writer_path);
+    let metadata = self.metadata.then(|| {
+      let mut metadata = Metadata::new();
+      metadata.data_size = data_offset;
+      with_properties(metadata, self.properties)
+    });
 
     Ok(SSTableWriter {
       data_writer_path,
       data_writer,
       index_writer_path,
       index_writer,
+      bloom_filter_path,
+      bloom_filter,
+      index_density: self.index_density,
+      data_offset,
+      index_size: 0,
+      last_indexed_offset: None,
+      records_since_indexed: 0,
+      key_buffer: Vec::new(),
+      value_buffer: Vec::new(),
+      wal: None,
+      checksum_writer: None,
+      index_offsets_writer: None,
+      summary_writer: None,
+      summary_block_size: 0,
+      summary_block_entries: 0,
+      metadata_path,
+      metadata,
+      recovery: Recovery::default(),
+      poisoned: false,
     })
   }
 }
 
+/// The size of a file, or zero if it doesn't exist.
+fn file_size(path: &Path) -> io::Result<u64> {
+  match fs::metadata(path) {
+    Ok(metadata) => Ok(metadata.len()),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+    Err(e) => Err(e),
+  }
+}
+
+/// Shortens a file to the size, if it exists and is longer.
+fn truncate_file(path: &Path, size: u64) -> io::Result<()> {
+  match OpenOptions::new().write(true).open(path) {
+    Ok(file) if file.metadata()?.len() > size => file.set_len(size),
+    Ok(_) => Ok(()),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(e),
+  }
+}
+
+/// Writes the header to the start of a new data file, returning its size.
+fn write_header<W: Write>(data_writer: &mut W, header: &Header) -> io::Result<u64> {
+  let bytes = header.to_bytes()?;
+  data_writer.write_all(&bytes)?;
+  Ok(bytes.len() as u64)
+}
+
+/// Opens the metadata of an existing data file so that it can be appended to. New metadata is
+/// created for an empty data file, which only has the header, and the metadata is rebuilt from the
+/// data file if it is missing, stale, or can't be read.
+fn open_metadata(
+  data_path: &Path,
+  metadata_path: &Path,
+  data_size: u64,
+  header_size: u64,
+  properties: Vec<(String, CborValue)>,
+) -> io::Result<Metadata> {
+  let metadata = if data_size == 0 {
+    let mut metadata = Metadata::new();
+    metadata.data_size = header_size;
+    metadata
+  } else {
+    match Metadata::from_path(metadata_path) {
+      Ok(metadata) if metadata.data_size == data_size => metadata,
+      _ => Metadata::from_data_reader(&mut BufReader::new(File::open(data_path)?))?,
+    }
+  };
+  Ok(with_properties(metadata, properties))
+}
+
+/// Sets the properties of the builder on the metadata, as written by this version of the library.
+fn with_properties(mut metadata: Metadata, properties: Vec<(String, CborValue)>) -> Metadata {
+  metadata.writer_version = WRITER_VERSION.to_string();
+  for (name, value) in properties {
+    metadata.set_property(name, value);
+  }
+  metadata
+}
+
+/// Opens the bloom filter of an existing data file so that it can be appended to. A new filter is
+/// created for an empty data file, and the filter is rebuilt from the data file if it is missing,
+/// stale, or saturated past twice the requested false positive rate.
+fn open_bloom_filter(
+  data_path: &Path,
+  bloom_filter_path: &Path,
+  data_size: u64,
+  expected_items: usize,
+  false_positive_rate: f64,
+) -> io::Result<BloomFilter> {
+  if data_size == 0 {
+    return Ok(BloomFilter::new(expected_items, false_positive_rate));
+  }
+
+  if bloom_filter_path.is_file() {
+    if let Ok(bloom_filter) = BloomFilter::from_path(bloom_filter_path) {
+      if bloom_filter.data_size == data_size && bloom_filter.false_positive_rate() <= false_positive_rate * 2.0 {
+        return Ok(bloom_filter);
+      }
+    }
+  }
+
+  let mut data_reader = BufReader::new(File::open(data_path)?);
+  BloomFilter::from_data_reader(&mut data_reader, expected_items, false_positive_rate)
+}
+
 /// A convenience wrapper around two `BufWriter`s for appending to a data and index file in a
 /// performant manner. The data and index is written as a sequence of CBOR-encoded arrays or maps,
 /// and therefore can be read by any CBOR implementation. If written as single entries, the index
 /// will be an array of file offsets, and if written as a key-value tuple, the index will be a map
 /// of keys to file offsets.
-pub struct SSTableWriter {
+///
+/// Writes to files by default, but can write to any pair of `Write` sinks. See
+/// `SSTableWriterBuilder::build_with_writers`.
+///
+/// A write that fails may have written part of its entry, so the writer can't tell where the next
+/// entry would start. Every later write and flush returns an error instead of writing entries at
+/// the wrong offsets.
+pub struct SSTableWriter<D = BufWriter<File>, I = BufWriter<File>> {
   pub data_writer_path: PathBuf,
-  pub data_writer: BufWriter<File>,
+  pub data_writer: D,
   pub index_writer_path: PathBuf,
-  pub index_writer: BufWriter<File>,
+  pub index_writer: I,
+  pub bloom_filter_path: PathBuf,
+  bloom_filter: Option<BloomFilter>,
+  index_density: IndexDensity,
+  /// The offset in the data sink of the next entry.
+  data_offset: u64,
+  /// The position in the index sink of the next index entry.
+  index_size: u64,
+  last_indexed_offset: Option<u64>,
+  records_since_indexed: u64,
+  key_buffer: Vec<u8>,
+  value_buffer: Vec<u8>,
+  wal: Option<Wal>,
+  checksum_writer: Option<BufWriter<File>>,
+  index_offsets_writer: Option<BufWriter<File>>,
+  summary_writer: Option<BufWriter<File>>,
+  /// The number of index entries in each block of the summary.
+  summary_block_size: u64,
+  /// The number of index entries in the current block of the summary.
+  summary_block_entries: u64,
+  pub metadata_path: PathBuf,
+  metadata: Option<Metadata>,
+  recovery: Recovery,
+  /// Whether a write failed partway, after which the sinks no longer match the offsets, so every
+  /// later write and flush fails.
+  poisoned: bool,
 }
 
-impl SSTableWriter {
+impl<D: Write, I: Write> SSTableWriter<D, I> {
   pub fn write<K, V>(&mut self, entry: (K, V)) -> io::Result<()>
   where
     K: CborWrite,
     V: CborWrite,
   {
-    let initial_offset = self.data_writer.stream_position()?;
+    self.check_poisoned()?;
+    let (key, value) = entry;
+
+    // Encode the key once, since it is written to both files and hashed into the bloom filter.
+    // The value is encoded first too, so that the offset of the next entry is known.
+    self.key_buffer.clear();
+    key.cbor_write(&mut self.key_buffer)?;
+    self.value_buffer.clear();
+    value.cbor_write(&mut self.value_buffer)?;
+
+    let result = match &mut self.wal {
+      Some(wal) => wal.append(&self.key_buffer, &self.value_buffer),
+      None => Ok(()),
+    }
+    .and_then(|_| self.write_buffers());
+    self.poisoned = result.is_err();
+    result
+  }
+
+  /// Returns an error if an earlier write failed partway.
+  fn check_poisoned(&self) -> io::Result<()> {
+    if self.poisoned {
+      return Err(io::Error::other("The writer can't be used after a failed write"));
+    }
+    Ok(())
+  }
+
+  /// Writes the encoded key and value in the buffers to the data and index.
+  fn write_buffers(&mut self) -> io::Result<()> {
+    let initial_offset = self.data_offset;
+    let should_index = self.should_index(initial_offset);
     let data_writer = &mut self.data_writer;
     let index_writer = &mut self.index_writer;
-    let (key, value) = entry;
+    let key_buffer = &self.key_buffer;
+
+    data_writer
+      .write_all(key_buffer)
+      .and_then(|_| data_writer.write_all(&self.value_buffer))?;
+    self.data_offset += (key_buffer.len() + self.value_buffer.len()) as u64;
+
+    if should_index {
+      index_writer
+        .write_all(key_buffer)
+        .and_then(|_| initial_offset.cbor_write(index_writer))?;
+      if let Some(index_offsets_writer) = &mut self.index_offsets_writer {
+        write_index_offset(index_offsets_writer, self.index_size)?;
+      }
+      if let Some(summary_writer) = &mut self.summary_writer {
+        if self.summary_block_entries == self.summary_block_size {
+          self.summary_block_entries = 0;
+        }
+        if self.summary_block_entries == 0 {
+          write_summary_entry(summary_writer, key_buffer, self.index_size)?;
+        }
+        self.summary_block_entries += 1;
+      }
+      self.index_size += key_buffer.len() as u64 + cbor_head_size(initial_offset);
+      self.last_indexed_offset = Some(initial_offset);
+      self.records_since_indexed = 1;
+    } else {
+      self.records_since_indexed += 1;
+    }
+
+    if let Some(bloom_filter) = &mut self.bloom_filter {
+      bloom_filter.insert_key_bytes(key_buffer);
+    }
 
-    key
-      .cbor_write(data_writer)
-      .and_then(|_| value.cbor_write(data_writer))
-      .and_then(|_| key.cbor_write(index_writer))
-      .and_then(|_| initial_offset.cbor_write(index_writer))
+    if let Some(metadata) = &mut self.metadata {
+      metadata.record(key_buffer, &self.value_buffer)?;
+    }
+
+    if let Some(checksum_writer) = &mut self.checksum_writer {
+      write_checksum(
+        checksum_writer,
+        initial_offset,
+        record_checksum(key_buffer, &self.value_buffer),
+      )?;
+    }
+
+    Ok(())
+  }
+
+  /// Writes a tombstone for the key, which is the key with a null value. When reading values as
+  /// `Option`, it is read as `None`, and it shadows the older values of the key in lookups and
+  /// merges.
+  pub fn write_tombstone<K: CborWrite>(&mut self, key: K) -> io::Result<()> {
+    self.write((key, CborValue::Null))
+  }
+
+  /// Whether the entry at the given data offset should get an index entry.
+  fn should_index(&self, offset: u64) -> bool {
+    self
+      .index_density
+      .should_index(self.last_indexed_offset, self.records_since_indexed, offset)
+  }
+
+  /// What was repaired at the ends of the data and index files when they were opened, which is
+  /// clean unless the last writer didn't finish. See [`crate::recovery`].
+  pub fn recovery(&self) -> Recovery {
+    self.recovery
+  }
+
+  /// The metadata of the entries written so far, if enabled.
+  pub fn metadata(&self) -> Option<&Metadata> {
+    self.metadata.as_ref()
+  }
+
+  /// The bloom filter of the keys written so far, if enabled.
+  pub fn bloom_filter(&self) -> Option<&BloomFilter> {
+    self.bloom_filter.as_ref()
+  }
+
+  /// The number of bytes written to the data sink, including any data that existed when the file
+  /// was opened for appending.
+  pub fn data_size(&self) -> u64 {
+    self.data_offset
   }
 
   pub fn flush(&mut self) -> Result<()> {
+    self.check_poisoned()?;
     self.data_writer.flush()?;
-    self.index_writer.flush()
+    self.index_writer.flush()?;
+    if let Some(checksum_writer) = &mut self.checksum_writer {
+      checksum_writer.flush()?;
+    }
+    if let Some(index_offsets_writer) = &mut self.index_offsets_writer {
+      index_offsets_writer.flush()?;
+    }
+    if let Some(summary_writer) = &mut self.summary_writer {
+      summary_writer.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Consumes the writer after flushing, returning the data and index sinks. Take the bloom filter
+  /// with `bloom_filter` first, if enabled, since it is not written anywhere.
+  pub fn into_writers(mut self) -> Result<(D, I)> {
+    self.flush()?;
+    Ok((self.data_writer, self.index_writer))
   }
+}
 
+impl SSTableWriter {
+  /// Flushes and syncs the files and writes the bloom filter and metadata. With a write-ahead log, the log is
+  /// started over, since everything in it is now in the table.
   pub fn close(&mut self) -> Result<()> {
+    self.sync_files()?;
+    self.write_bloom_filter()?;
+    self.write_metadata()?;
+    if let Some(wal) = &mut self.wal {
+      let index_size = self.index_writer.get_ref().metadata()?.len();
+      wal.checkpoint(self.data_offset, index_size)?;
+    }
+    Ok(())
+  }
+
+  /// Syncs the write-ahead log, if enabled, so that every write so far survives a crash. Useful to
+  /// commit a batch of writes when the sync policy doesn't sync after every write.
+  pub fn sync_wal(&mut self) -> Result<()> {
+    match &mut self.wal {
+      Some(wal) => wal.sync(),
+      None => Ok(()),
+    }
+  }
+
+  fn sync_files(&mut self) -> Result<()> {
     self.flush()?;
     self.data_writer.get_mut().sync_all()?;
-    self.index_writer.get_mut().sync_all()
+    self.index_writer.get_mut().sync_all()?;
+    if let Some(checksum_writer) = &mut self.checksum_writer {
+      checksum_writer.get_mut().sync_all()?;
+    }
+    if let Some(index_offsets_writer) = &mut self.index_offsets_writer {
+      index_offsets_writer.get_mut().sync_all()?;
+    }
+    if let Some(summary_writer) = &mut self.summary_writer {
+      summary_writer.get_mut().sync_all()?;
+    }
+    Ok(())
+  }
+
+  /// Writes the bloom filter file, if enabled. The data file must already be flushed, because the
+  /// filter records the size of the data file to detect when it becomes stale.
+  fn write_bloom_filter(&mut self) -> Result<()> {
+    if let Some(bloom_filter) = &mut self.bloom_filter {
+      bloom_filter.data_size = self.data_offset;
+      bloom_filter.write_to_path(&self.bloom_filter_path)?;
+    }
+    Ok(())
+  }
+
+  /// Writes the metadata file, if enabled. The index file must already be flushed, because its size
+  /// is recorded.
+  fn write_metadata(&mut self) -> Result<()> {
+    if let Some(metadata) = &mut self.metadata {
+      metadata.index_size = self.index_writer.get_ref().metadata()?.len();
+      metadata.write_to_path(&self.metadata_path)?;
+    }
+    Ok(())
   }
 
   /// Consumes the writer, returning all inner files.
   pub fn into_files(mut self) -> Result<Vec<(PathBuf, File)>> {
     // Necessary because we're dropping the buffers.
-    self.data_writer.flush()?;
-    self.index_writer.flush()?;
+    self.flush()?;
+    self.write_bloom_filter()?;
+    self.write_metadata()?;
 
-    Ok(vec![
-      (self.data_writer_path, self.data_writer.into_inner()?),
+    let mut files = vec![
+      (self.data_writer_path.clone(), self.data_writer.into_inner()?),
       (self.index_writer_path, self.index_writer.into_inner()?),
-    ])
+    ];
+    if let Some(checksum_writer) = self.checksum_writer {
+      files.push((
+        create_checksum_path(&self.data_writer_path),
+        checksum_writer.into_inner()?,
+      ));
+    }
+    if let Some(index_offsets_writer) = self.index_offsets_writer {
+      files.push((
+        create_index_offsets_path(&self.data_writer_path),
+        index_offsets_writer.into_inner()?,
+      ));
+    }
+    if let Some(summary_writer) = self.summary_writer {
+      files.push((
+        create_summary_path(&self.data_writer_path),
+        summary_writer.into_inner()?,
+      ));
+    }
+    Ok(files)
   }
 }
 
@@ -192,12 +904,13 @@ mod tests {
   use crate::{FromPath, SSTableIndex, SSTableReader, SSTableWriterBuilder};
   use common_testing::{assert, setup};
   use std::fs;
-  use std::io::SeekFrom;
+  use std::io::{Seek, SeekFrom};
 
   use super::*;
 
   const TEST_FILE_NAME: &str = ".tmp/test.sst";
   const TEST_INDEX_FILE_NAME: &str = ".tmp/test.index.sst";
+  const TEST_BLOOM_FILE_NAME: &str = ".tmp/test.bloom.sst";
 
   #[test]
   fn test_append_string_tuple() {
@@ -403,4 +1116,480 @@ mod tests {
       assert::equal(sstable.next(), (b"foo".to_vec(), b"bar".to_vec()));
     }
   }
+
+  #[test]
+  fn test_bloom_filter_written_on_close() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_BLOOM_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .bloom_filter(100, 0.01)
+      .build()
+      .unwrap();
+    writer.write(("hello", "world")).unwrap();
+    writer.write(("foo", "bar")).unwrap();
+    writer.close().unwrap();
+
+    let bloom_filter = crate::bloom::load_bloom_filter(TEST_FILE_NAME).unwrap().unwrap();
+    assert::equal(bloom_filter.len(), 2);
+    assert::equal(bloom_filter.may_contain(&"hello"), true);
+    assert::equal(bloom_filter.may_contain(&"foo"), true);
+  }
+
+  #[test]
+  fn test_bloom_filter_survives_append() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_BLOOM_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .bloom_filter(100, 0.01)
+      .build()
+      .unwrap();
+    writer.write(("hello", "world")).unwrap();
+    writer.close().unwrap();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .bloom_filter(100, 0.01)
+      .build()
+      .unwrap();
+    writer.write(("foo", "bar")).unwrap();
+    writer.close().unwrap();
+
+    let bloom_filter = crate::bloom::load_bloom_filter(TEST_FILE_NAME).unwrap().unwrap();
+    assert::equal(bloom_filter.len(), 2);
+    assert::equal(bloom_filter.may_contain(&"hello"), true);
+    assert::equal(bloom_filter.may_contain(&"foo"), true);
+  }
+
+  #[test]
+  fn test_bloom_filter_rebuilt_when_stale() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_BLOOM_FILE_NAME).unwrap_or_default();
+
+    // Written without a bloom filter, so the filter must be rebuilt from the data file.
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    writer.write(("hello", "world")).unwrap();
+    writer.close().unwrap();
+    assert::none(&crate::bloom::load_bloom_filter(TEST_FILE_NAME).unwrap());
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .bloom_filter(100, 0.01)
+      .build()
+      .unwrap();
+    writer.write(("foo", "bar")).unwrap();
+    writer.close().unwrap();
+
+    let bloom_filter = crate::bloom::load_bloom_filter(TEST_FILE_NAME).unwrap().unwrap();
+    assert::equal(bloom_filter.len(), 2);
+    assert::equal(bloom_filter.may_contain(&"hello"), true);
+    assert::equal(bloom_filter.may_contain(&"foo"), true);
+  }
+
+  #[test]
+  fn test_sparse_index_every_records() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryRecords(3))
+      .build()
+      .unwrap();
+    for i in 0..8u64 {
+      writer.write((i, "value")).unwrap();
+    }
+    writer.close().unwrap();
+
+    // Each entry is 7 bytes: a one byte key and a six byte value.
+    let sstable_index = SSTableIndex::<u64>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(sstable_index.indices, vec![(0, 0), (3, 21), (6, 42)]);
+  }
+
+  #[test]
+  fn test_sparse_index_every_bytes() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryBytes(16))
+      .build()
+      .unwrap();
+    for i in 0..8u64 {
+      writer.write((i, "value")).unwrap();
+    }
+    writer.close().unwrap();
+
+    let sstable_index = SSTableIndex::<u64>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(sstable_index.indices, vec![(0, 0), (3, 21), (6, 42)]);
+  }
+
+  #[test]
+  fn test_sparse_index_indexes_first_entry_after_reopen() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    for i in 0..2u64 {
+      let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+        .index_density(IndexDensity::EveryRecords(10))
+        .build()
+        .unwrap();
+      writer.write((i, "value")).unwrap();
+      writer.write((i + 10, "value")).unwrap();
+      writer.close().unwrap();
+    }
+
+    let sstable_index = SSTableIndex::<u64>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(sstable_index.indices, vec![(0, 0), (1, 14)]);
+  }
+
+  #[test]
+  fn test_write_to_memory_matches_file() {
+    let _lock = setup::sequential();
+    fs::remove_file(TEST_FILE_NAME).unwrap_or_default();
+    fs::remove_file(TEST_INDEX_FILE_NAME).unwrap_or_default();
+
+    let entries = [("a", "1"), ("b", "22"), ("c", "333")];
+
+    let mut file_writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    let mut memory_writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .build_with_writers(Vec::new(), Vec::new())
+      .unwrap();
+    for entry in entries {
+      file_writer.write(entry).unwrap();
+      memory_writer.write(entry).unwrap();
+    }
+    file_writer.close().unwrap();
+
+    assert::equal(memory_writer.data_size(), 15);
+    assert::equal(&memory_writer.index_writer_path, &file_writer.index_writer_path);
+    let (data, index) = memory_writer.into_writers().unwrap();
+    assert::equal_bytes(&data, &fs::read(TEST_FILE_NAME).unwrap());
+    assert::equal_bytes(&index, &fs::read(TEST_INDEX_FILE_NAME).unwrap());
+  }
+
+  #[test]
+  fn test_write_to_memory_with_sparse_index_and_bloom_filter() {
+    let mut writer = SSTableWriterBuilder::new("memory")
+      .index_density(IndexDensity::EveryBytes(10))
+      .bloom_filter(10, 0.01)
+      .build_with_writers(Vec::new(), Vec::new())
+      .unwrap();
+    for i in 0..5u64 {
+      writer.write((i, "value")).unwrap();
+    }
+
+    assert::equal(writer.bloom_filter().unwrap().may_contain(&3u64), true);
+    let (data, index) = writer.into_writers().unwrap();
+    let sstable_index = SSTableIndex::<u64>::from_reader(index.as_slice()).unwrap();
+    assert::equal(sstable_index.indices, vec![(0, 0), (2, 14), (4, 28)]);
+
+    let mut sstable_reader = SSTableReader::<(u64, String), _>::new(io::Cursor::new(data));
+    sstable_reader.seek(SeekFrom::Start(14)).unwrap();
+    assert::equal(sstable_reader.next().unwrap().unwrap(), (2, "value".to_string()));
+  }
+
+  #[test]
+  fn test_failed_write_poisons_the_writer() {
+    // Room for the first entry and part of the second.
+    let mut data = [0u8; 6];
+    let mut writer = SSTableWriterBuilder::new("memory")
+      .build_with_writers(data.as_mut_slice(), Vec::new())
+      .unwrap();
+    writer.write(("a", "1")).unwrap();
+    assert::equal(writer.write(("b", "22")).unwrap_err().kind(), io::ErrorKind::WriteZero);
+
+    assert::equal(writer.data_size(), 4);
+    assert!(writer.write(("c", "3")).is_err());
+    assert!(writer.flush().is_err());
+    assert!(writer.into_writers().is_err());
+  }
+
+  const TEST_WAL_FILE_NAME: &str = ".tmp/test.wal.sst";
+
+  fn read_test_entries() -> Vec<(String, String)> {
+    let entries = SSTableReader::<(String, String)>::from_path(TEST_FILE_NAME).unwrap();
+    entries.collect::<io::Result<Vec<_>>>().unwrap()
+  }
+
+  fn read_test_index() -> Vec<(String, u64)> {
+    SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap().indices
+  }
+
+  #[test]
+  fn test_wal_replays_unflushed_writes() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [
+      TEST_FILE_NAME,
+      TEST_INDEX_FILE_NAME,
+      TEST_BLOOM_FILE_NAME,
+      TEST_WAL_FILE_NAME,
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+
+    let builder = || SSTableWriterBuilder::new(TEST_FILE_NAME).wal(SyncPolicy::EveryWrite);
+    let mut writer = builder().build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.close().unwrap();
+    writer.write(("b", "2")).unwrap();
+    writer.write(("c", "3")).unwrap();
+    // Crash without flushing the buffers of the data and index files.
+    std::mem::forget(writer);
+    assert::equal(read_test_entries().len(), 1);
+
+    let mut writer = builder().build().unwrap();
+    assert::equal(read_test_entries().len(), 3);
+    writer.write(("d", "4")).unwrap();
+    writer.close().unwrap();
+    drop(writer);
+
+    // A torn write at the end of the data file is removed before the log is replayed.
+    let mut writer = builder().build().unwrap();
+    writer.write(("e", "5")).unwrap();
+    writer.flush().unwrap();
+    std::mem::forget(writer);
+    let mut file = OpenOptions::new().append(true).open(TEST_FILE_NAME).unwrap();
+    file.write_all(&[0x61, b'f', 0x65, b'6']).unwrap();
+
+    builder().build().unwrap().close().unwrap();
+    let entries = read_test_entries();
+    let keys = entries.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+    assert::equal(keys, vec!["a", "b", "c", "d", "e"]);
+    assert::equal(
+      read_test_index(),
+      vec![
+        ("a".to_string(), 0),
+        ("b".to_string(), 4),
+        ("c".to_string(), 8),
+        ("d".to_string(), 12),
+        ("e".to_string(), 16),
+      ],
+    );
+  }
+
+  #[test]
+  fn test_wal_keeps_appends_made_without_it() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [
+      TEST_FILE_NAME,
+      TEST_INDEX_FILE_NAME,
+      TEST_BLOOM_FILE_NAME,
+      TEST_WAL_FILE_NAME,
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+
+    let builder = || SSTableWriterBuilder::new(TEST_FILE_NAME).wal(SyncPolicy::EveryWrite);
+    let mut writer = builder().build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.close().unwrap();
+    drop(writer);
+
+    // The log was checkpointed by close, so appending without it is safe.
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    writer.write(("b", "2")).unwrap();
+    writer.close().unwrap();
+
+    builder().build().unwrap().close().unwrap();
+    let keys = read_test_entries().into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+    assert::equal(keys, vec!["a", "b"]);
+
+    // With entries that were never replayed, the table can't be opened without the log.
+    let mut writer = builder().build().unwrap();
+    writer.write(("c", "3")).unwrap();
+    std::mem::forget(writer);
+    let error = SSTableWriterBuilder::new(TEST_FILE_NAME).build().err().unwrap();
+    assert::equal(error.kind(), io::ErrorKind::InvalidInput);
+
+    // A log that claims more data than the table has is stale.
+    let mut wal = Wal::create(TEST_WAL_FILE_NAME, SyncPolicy::EveryWrite, 1000, 0).unwrap();
+    wal.append(&[0x61, b'd'], &[0x61, b'4']).unwrap();
+    drop(wal);
+    let error = builder().build().err().unwrap();
+    assert::equal(error.kind(), io::ErrorKind::InvalidData);
+    assert::equal(read_test_entries().len(), 2);
+  }
+
+  #[test]
+  fn test_wal_group_commit() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [
+      TEST_FILE_NAME,
+      TEST_INDEX_FILE_NAME,
+      TEST_BLOOM_FILE_NAME,
+      TEST_WAL_FILE_NAME,
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .wal(SyncPolicy::GroupCommit(100))
+      .build()
+      .unwrap();
+    let empty_wal_size = fs::metadata(TEST_WAL_FILE_NAME).unwrap().len();
+    writer.write(("a", "1")).unwrap();
+    assert::equal(fs::metadata(TEST_WAL_FILE_NAME).unwrap().len(), empty_wal_size);
+    writer.sync_wal().unwrap();
+    assert::equal(fs::metadata(TEST_WAL_FILE_NAME).unwrap().len(), empty_wal_size + 4);
+    std::mem::forget(writer);
+
+    SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .wal(SyncPolicy::GroupCommit(100))
+      .build()
+      .unwrap()
+      .close()
+      .unwrap();
+    assert::equal(read_test_entries(), vec![("a".to_string(), "1".to_string())]);
+  }
+
+  #[test]
+  fn test_build_recovers_torn_writes() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [
+      TEST_FILE_NAME,
+      TEST_INDEX_FILE_NAME,
+      TEST_BLOOM_FILE_NAME,
+      TEST_WAL_FILE_NAME,
+    ] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.close().unwrap();
+    assert!(writer.recovery().is_clean());
+    let mut file = OpenOptions::new().append(true).open(TEST_FILE_NAME).unwrap();
+    file.write_all(&[0x61, b'b', 0x65, b'2']).unwrap();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).build().unwrap();
+    assert::equal(writer.recovery().truncated_data_bytes, 4);
+    writer.write(("c", "3")).unwrap();
+    writer.close().unwrap();
+
+    assert::equal(
+      read_test_entries(),
+      vec![("a".to_string(), "1".to_string()), ("c".to_string(), "3".to_string())],
+    );
+    assert::equal(read_test_index(), vec![("a".to_string(), 0), ("c".to_string(), 4)]);
+  }
+
+  #[test]
+  fn test_metadata_is_kept_up_to_date() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    let metadata_path = create_metadata_path(Path::new(TEST_FILE_NAME));
+    for path in [TEST_FILE_NAME, TEST_INDEX_FILE_NAME] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    fs::remove_file(&metadata_path).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .property("source", "test")
+      .build()
+      .unwrap();
+    writer.write(("b", "2")).unwrap();
+    writer.write(("c", "3")).unwrap();
+    writer.close().unwrap();
+    let metadata = Metadata::from_path(&metadata_path).unwrap();
+    assert::equal(metadata.entry_count, 2);
+    assert::equal(metadata.index_size, 6);
+    assert!(metadata.sorted);
+
+    // Appending keeps the metadata, including when the table was created and its properties.
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).metadata().build().unwrap();
+    writer.write(("a", "1")).unwrap();
+    writer.close().unwrap();
+    let appended = Metadata::from_path(&metadata_path).unwrap();
+    assert::equal(appended.entry_count, 3);
+    assert::equal(appended.created_at, metadata.created_at);
+    assert::equal(appended.min_key.clone(), Some(CborValue::from("a")));
+    assert::equal(appended.max_key.clone(), Some(CborValue::from("c")));
+    assert::equal(appended.property("source"), Some(&CborValue::from("test")));
+    assert!(!appended.sorted);
+
+    // Stale metadata is rebuilt from the data file.
+    SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .build()
+      .unwrap()
+      .write(("d", "4"))
+      .unwrap();
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME).metadata().build().unwrap();
+    assert::equal(writer.metadata().unwrap().entry_count, 4);
+    writer.close().unwrap();
+    assert::equal(Metadata::from_path(&metadata_path).unwrap().entry_count, 4);
+  }
+
+  #[test]
+  fn test_header_is_skipped_by_readers() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    let data_path = Path::new(TEST_FILE_NAME);
+    for path in [TEST_FILE_NAME, TEST_INDEX_FILE_NAME, TEST_BLOOM_FILE_NAME] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    fs::remove_file(create_checksum_path(data_path)).unwrap_or_default();
+    fs::remove_file(create_metadata_path(data_path)).unwrap_or_default();
+
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .key_type("text")
+      .checksums()
+      .metadata()
+      .bloom_filter(10, 0.01)
+      .build()
+      .unwrap();
+    let header_size = writer.data_size();
+    assert!(header_size > 0);
+    writer.write(("a", "1")).unwrap();
+    writer.write(("b", "2")).unwrap();
+    writer.close().unwrap();
+
+    let header = crate::header::load_header(TEST_FILE_NAME).unwrap().unwrap();
+    assert::equal(header.key_type.as_deref(), Some("text"));
+    assert!(header.checksums);
+    let index = SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    assert::equal(index.indices[0].clone(), ("a".to_string(), header_size));
+
+    // Appending keeps the header, even without asking for one.
+    let mut writer = SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .checksums()
+      .metadata()
+      .bloom_filter(10, 0.01)
+      .build()
+      .unwrap();
+    assert!(writer.recovery().is_clean());
+    writer.write(("c", "3")).unwrap();
+    writer.close().unwrap();
+
+    let reader = SSTableReader::<(String, String)>::from_path(TEST_FILE_NAME).unwrap();
+    assert::equal(reader.data_start(), header_size);
+    assert::equal(reader.header(), Some(&header));
+    assert::equal(reader.count(), 3);
+
+    let mut sstable = crate::SSTable::<String, String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(sstable.get(&"b".to_string()).unwrap(), Some("2".to_string()));
+    assert::equal(sstable.range(..).unwrap().count(), 3);
+
+    let verification = crate::checksum::verify(TEST_FILE_NAME).unwrap();
+    assert::equal(verification.records, 3);
+    assert!(verification.is_ok());
+
+    let metadata = crate::metadata::load_metadata(TEST_FILE_NAME).unwrap().unwrap();
+    assert::equal(metadata.entry_count, 3);
+    let mut data_reader = BufReader::new(File::open(TEST_FILE_NAME).unwrap());
+    let rebuilt = Metadata::from_data_reader(&mut data_reader).unwrap();
+    assert::equal(rebuilt.data_size, metadata.data_size);
+    assert::equal(rebuilt.entry_count, 3);
+  }
 }
@@ -0,0 +1,346 @@
+//! Two-level index, with a summary of the index
+//!
+//! Even a sparse index of a table with billions of entries is too large to hold in memory. With
+//! `SSTableWriterBuilder::summary`, the writer splits the index into blocks of N entries and keeps
+//! a summary beside it (`foo.summary.sst`) with the first key of every block and the position of
+//! the block in the index file. The summary is N times smaller than the index.
+//!
+//! `SummaryIndex` holds only the summary in memory. A lookup binary searches the summary for the
+//! block that could hold the key, reads that one block from the index file, and searches it. The
+//! most recently used blocks are cached, so lookups of nearby keys don't read the index again. Like
+//! the in-memory index, it can only be searched if its keys are sorted.
+//!
+//! # Format
+//!
+//! The summary has the same format as the index: a CBOR sequence of the first key of each block
+//! followed by the position of that entry in the index file. It can be read with `SSTableIndex`
+//! like any index. Each block ends where the next one starts, and the last one at the end of the
+//! index file.
+//!
+//! # Example
+//!
+//! ```
+//! use sstables::summary::SummaryIndex;
+//! use sstables::SSTableWriterBuilder;
+//!
+//! let path = std::env::temp_dir().join("summary_example.sst");
+//! # std::fs::remove_file(&path).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_index_path(&path)).unwrap_or_default();
+//! # std::fs::remove_file(sstables::read::create_summary_path(&path)).unwrap_or_default();
+//! let mut writer = SSTableWriterBuilder::new(&path).summary(2).build().unwrap();
+//! for key in ["a", "b", "c", "d", "e"] {
+//!   writer.write((key, "value")).unwrap();
+//! }
+//! writer.close().unwrap();
+//!
+//! let mut index = SummaryIndex::<String>::open(&path).unwrap();
+//! assert_eq!(index.blocks(), 3);
+//! assert_eq!(index.seek_offset(&"c".to_string()).unwrap(), Some(8));
+//! ```
+
+use std::{
+  fmt,
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+  path::Path,
+};
+
+use crate::{
+  cbor::{read_cbor_raw, read_cbor_u64, CborRead, CborWrite},
+  read::{create_index_path, create_summary_path},
+  sstable_reader::SSTableIndex,
+};
+
+/// The number of index blocks a `SummaryIndex` caches by default.
+pub const DEFAULT_CACHE_CAPACITY: usize = 16;
+
+/// Writes the entry of a block to the summary.
+pub(crate) fn write_summary_entry<W: Write>(writer: &mut W, key_bytes: &[u8], position: u64) -> io::Result<()> {
+  writer.write_all(key_bytes)?;
+  position.cbor_write(writer)
+}
+
+/// Reads an entry of the index or the summary without decoding the key, returning its size.
+fn read_raw_entry<R: Read>(reader: &mut R, key_bytes: &mut Vec<u8>) -> io::Result<(u64, u64)> {
+  key_bytes.clear();
+  read_cbor_raw(reader, key_bytes)?;
+  let mut value_bytes = Vec::new();
+  read_cbor_raw(reader, &mut value_bytes)?;
+  let value = read_cbor_u64(&mut value_bytes.as_slice())?;
+  Ok((value, (key_bytes.len() + value_bytes.len()) as u64))
+}
+
+/// Brings the summary of a table up to date with its index file, which must already be recovered.
+/// Blocks that start past the end of the index and a partial entry are removed, and the entries of
+/// the index after the start of the last block are split into blocks, which also creates the
+/// summary of an index that had none. Returns the number of entries in the last block, so that the
+/// writer knows when to start the next one.
+pub(crate) fn recover_summary(index_path: &Path, summary_path: &Path, block_size: u64) -> io::Result<u64> {
+  let index_size = match index_path.metadata() {
+    Ok(metadata) => metadata.len(),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+    Err(e) => return Err(e),
+  };
+
+  // Keep the blocks that start in the index, which are in position order.
+  let mut summary_file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(summary_path)?;
+  let mut reader = BufReader::new(&summary_file);
+  let mut key_bytes = Vec::new();
+  let mut valid_size = 0;
+  let mut last_position = None;
+  while let Ok((position, entry_size)) = read_raw_entry(&mut reader, &mut key_bytes) {
+    if position >= index_size {
+      break;
+    }
+    valid_size += entry_size;
+    last_position = Some(position);
+  }
+  summary_file.set_len(valid_size)?;
+  summary_file.seek(SeekFrom::End(0))?;
+
+  if index_size == 0 {
+    return Ok(0);
+  }
+
+  // Split the entries from the start of the last block into blocks.
+  let mut index_reader = BufReader::new(File::open(index_path)?);
+  let mut position = index_reader.seek(SeekFrom::Start(last_position.unwrap_or(0)))?;
+  let mut block_entries = 0;
+  let mut restored = Vec::new();
+  while !index_reader.fill_buf()?.is_empty() {
+    let (_, entry_size) = read_raw_entry(&mut index_reader, &mut key_bytes)?;
+    if block_entries == block_size {
+      block_entries = 0;
+    }
+    if block_entries == 0 && last_position != Some(position) {
+      write_summary_entry(&mut restored, &key_bytes, position)?;
+    }
+    block_entries += 1;
+    position += entry_size;
+  }
+  summary_file.write_all(&restored)?;
+
+  Ok(block_entries)
+}
+
+/// An index that keeps only its summary in memory, and reads blocks of the index file as lookups
+/// need them. See the module documentation.
+pub struct SummaryIndex<K> {
+  /// The first key of each block, and the position of the block in the index file.
+  pub summary: SSTableIndex<K>,
+  index_reader: BufReader<File>,
+  index_size: u64,
+  /// The most recently used blocks by their number, with the most recent last.
+  cache: Vec<(usize, Vec<(K, u64)>)>,
+  cache_capacity: usize,
+  /// Decodes the entries of a block, so that the searches don't need to know how.
+  read_block: fn(&[u8]) -> io::Result<Vec<(K, u64)>>,
+}
+
+impl<K> fmt::Debug for SummaryIndex<K> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SummaryIndex")
+      .field("blocks", &self.summary.indices.len())
+      .field("cached_blocks", &self.cache.len())
+      .finish_non_exhaustive()
+  }
+}
+
+impl<K> SummaryIndex<K> {
+  /// Opens the summary beside the data file at the given path, and the index it summarizes. The
+  /// last block is read to check that the summary ends with it, since a summary that doesn't
+  /// belong to the index would send the searches to the wrong places.
+  pub fn open<P: AsRef<Path>>(data_path: P) -> io::Result<Self>
+  where
+    for<'a> &'a [u8]: CborRead<K>,
+  {
+    let data_path = data_path.as_ref();
+    let summary = SSTableIndex::from_reader(fs::read(create_summary_path(data_path))?.as_slice())?;
+    let index_file = File::open(create_index_path(data_path))?;
+    let index_size = index_file.metadata()?.len();
+
+    let mut summary_index = SummaryIndex {
+      summary,
+      index_reader: BufReader::new(index_file),
+      index_size,
+      cache: Vec::new(),
+      cache_capacity: DEFAULT_CACHE_CAPACITY,
+      read_block: |bytes: &[u8]| SSTableIndex::from_reader(bytes).map(|index| index.indices),
+    };
+    let is_complete = match summary_index.blocks() {
+      0 => index_size == 0,
+      n => summary_index.summary.indices[n - 1].1 < index_size && summary_index.block(n - 1).is_ok(),
+    };
+    if !is_complete {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The summary does not match the index",
+      ));
+    }
+    Ok(summary_index)
+  }
+
+  /// Set how many blocks of the index are cached. If not set, 16 blocks are cached.
+  pub fn cache_capacity(mut self, cache_capacity: usize) -> Self {
+    self.cache_capacity = cache_capacity;
+    self.cache.truncate(cache_capacity);
+    self
+  }
+
+  /// The number of blocks in the index.
+  pub fn blocks(&self) -> usize {
+    self.summary.indices.len()
+  }
+
+  /// Returns the entries of the block with the number, reading it from the index file unless it is
+  /// cached.
+  pub fn block(&mut self, block: usize) -> io::Result<&[(K, u64)]> {
+    match self.cache.iter().position(|(cached, _)| *cached == block) {
+      Some(i) => {
+        let entry = self.cache.remove(i);
+        self.cache.push(entry);
+      }
+      None => {
+        let start = self.summary.indices[block].1;
+        let end = match self.summary.indices.get(block + 1) {
+          Some((_, end)) => *end,
+          None => self.index_size,
+        };
+        let mut bytes = vec![0; end.saturating_sub(start) as usize];
+        self.index_reader.seek(SeekFrom::Start(start))?;
+        self.index_reader.read_exact(&mut bytes)?;
+        let entries = (self.read_block)(&bytes)?;
+
+        if self.cache.len() >= self.cache_capacity.max(1) {
+          self.cache.remove(0);
+        }
+        self.cache.push((block, entries));
+      }
+    }
+    Ok(&self.cache[self.cache.len() - 1].1)
+  }
+
+  /// Returns the data offset to scan from to find the first entry with the key, which is the
+  /// offset of the last index entry before the key. Returns `None` if the key comes before every
+  /// index entry, so the scan starts at the first entry of the data file. Reads at most one block.
+  pub fn seek_offset(&mut self, key: &K) -> io::Result<Option<u64>>
+  where
+    K: Ord,
+  {
+    // The last entry before the key is in the last block that starts before it.
+    match self.summary.indices.partition_point(|(k, _)| k < key) {
+      0 => Ok(None),
+      x => {
+        let block = self.block(x - 1)?;
+        Ok(match block.partition_point(|(k, _)| k < key) {
+          0 => None,
+          y => Some(block[y - 1].1),
+        })
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{sstable_writer::IndexDensity, FromPath, SSTable, SSTableWriter, SSTableWriterBuilder};
+  use common_testing::{assert, setup};
+
+  const TEST_FILE_NAME: &str = ".tmp/summary_test.sst";
+  const TEST_INDEX_FILE_NAME: &str = ".tmp/summary_test.index.sst";
+  const TEST_SUMMARY_FILE_NAME: &str = ".tmp/summary_test.summary.sst";
+
+  fn build_writer(block_size: u64) -> SSTableWriter {
+    SSTableWriterBuilder::new(TEST_FILE_NAME)
+      .index_density(IndexDensity::EveryRecords(2))
+      .summary(block_size)
+      .build()
+      .unwrap()
+  }
+
+  fn setup_test_sstable(block_size: u64, keys: &[&str]) {
+    setup::create_dir_all(".tmp").unwrap();
+    for path in [TEST_FILE_NAME, TEST_INDEX_FILE_NAME, TEST_SUMMARY_FILE_NAME] {
+      fs::remove_file(path).unwrap_or_default();
+    }
+    let mut writer = build_writer(block_size);
+    for key in keys {
+      writer.write((*key, "value")).unwrap();
+    }
+    writer.close().unwrap();
+  }
+
+  /// The keys of the index entries of each block, separated by commas.
+  fn read_blocks(index: &mut SummaryIndex<String>) -> Vec<String> {
+    (0..index.blocks())
+      .map(|block| {
+        let keys = index.block(block).unwrap().iter().map(|(k, _)| k.as_str());
+        keys.collect::<Vec<_>>().join(",")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn splits_the_index_into_blocks() {
+    let _lock = setup::sequential();
+    setup_test_sstable(2, &["a", "b", "c", "d", "e", "f", "g", "h", "i"]);
+
+    let mut index = SummaryIndex::<String>::open(TEST_FILE_NAME).unwrap().cache_capacity(1);
+    assert::equal(read_blocks(&mut index), vec!["a,c", "e,g", "i"]);
+
+    let in_memory = SSTableIndex::<String>::from_path(TEST_INDEX_FILE_NAME).unwrap();
+    let offset_of = |i: usize| Some(in_memory.indices[i].1);
+    assert::equal(index.seek_offset(&"a".to_string()).unwrap(), None);
+    assert::equal(index.seek_offset(&"b".to_string()).unwrap(), offset_of(0));
+    assert::equal(index.seek_offset(&"e".to_string()).unwrap(), offset_of(1));
+    assert::equal(index.seek_offset(&"z".to_string()).unwrap(), offset_of(4));
+  }
+
+  #[test]
+  fn keeps_up_with_appends_and_recovery() {
+    let _lock = setup::sequential();
+    setup_test_sstable(2, &["a", "b", "c"]);
+
+    // The next block starts after the last one is full, and a lost summary is rebuilt.
+    let mut writer = build_writer(2);
+    for key in ["d", "e", "f"] {
+      writer.write((key, "value")).unwrap();
+    }
+    writer.close().unwrap();
+    let mut index = SummaryIndex::<String>::open(TEST_FILE_NAME).unwrap();
+    let blocks = read_blocks(&mut index);
+    assert::equal(blocks.clone(), vec!["a,c", "d,f"]);
+
+    fs::remove_file(TEST_SUMMARY_FILE_NAME).unwrap();
+    assert!(SummaryIndex::<String>::open(TEST_FILE_NAME).is_err());
+    build_writer(2).close().unwrap();
+    let mut index = SummaryIndex::<String>::open(TEST_FILE_NAME).unwrap();
+    assert::equal(read_blocks(&mut index), blocks);
+
+    // A summary with a block past the end of the index is refused.
+    let mut summary = fs::read(TEST_SUMMARY_FILE_NAME).unwrap();
+    write_summary_entry(&mut summary, &[0x61, b'z'], 1000).unwrap();
+    fs::write(TEST_SUMMARY_FILE_NAME, summary).unwrap();
+    assert!(SummaryIndex::<String>::open(TEST_FILE_NAME).is_err());
+  }
+
+  #[test]
+  fn looks_up_keys_with_the_sstable() {
+    let _lock = setup::sequential();
+    setup_test_sstable(3, &["a", "b", "c", "d", "e", "f", "g", "h"]);
+
+    let mut sstable = SSTable::<String, String>::open_with_summary(TEST_FILE_NAME).unwrap();
+    assert!(sstable.index.is_none());
+    for key in ["a", "c", "d", "h"] {
+      assert::equal(sstable.get(&key.to_string()).unwrap(), Some("value".to_string()));
+    }
+    assert::equal(sstable.get(&"i".to_string()).unwrap(), None);
+    assert::equal(sstable.range("b".to_string().."f".to_string()).unwrap().count(), 4);
+  }
+}
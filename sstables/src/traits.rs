@@ -1,3 +1,4 @@
+use crate::cbor::CborValue;
 use std::{io, path::Path};
 
 /// Converts a path to some type.
@@ -6,3 +7,32 @@ pub trait FromPath<T> {
   where
     Self: Sized;
 }
+
+/// Values that can be a tombstone, which marks a deleted key and shadows every older value of the
+/// key. Tombstones are written as CBOR null.
+pub trait Tombstone {
+  /// The type of the values that are not tombstones.
+  type Live;
+
+  /// Returns the value, or `None` if it is a tombstone.
+  fn into_live(self) -> Option<Self::Live>;
+}
+
+impl<T> Tombstone for Option<T> {
+  type Live = T;
+
+  fn into_live(self) -> Option<T> {
+    self
+  }
+}
+
+impl Tombstone for CborValue {
+  type Live = CborValue;
+
+  fn into_live(self) -> Option<CborValue> {
+    match self {
+      CborValue::Null => None,
+      value => Some(value),
+    }
+  }
+}
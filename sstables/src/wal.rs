@@ -0,0 +1,253 @@
+//! Write-ahead log for an `SSTableWriter`.
+//!
+//! The writer buffers its data and index files and only syncs them on `close()`, so a crash can
+//! lose any entry written since. With a write-ahead log, every entry is also appended to a log file
+//! beside the table, which is synced according to a `SyncPolicy`. When the table is opened again,
+//! any entries in the log are replayed into it.
+//!
+//! # Format
+//!
+//! The log is a CBOR sequence. It starts with the size of the data file and the size of the index
+//! file when the log was started, which are both known to be synced to disk. Then each entry is its
+//! encoded key followed by its encoded value, exactly as they are written to the data file.
+//!
+//! To recover, the data and index files are truncated to the sizes at the start of the log, which
+//! removes anything that was only partly written, and then each complete entry of the log is
+//! written again. A partial entry at the end of the log was never synced, so it is dropped.
+//!
+//! After a table is closed, its data and index files are synced and the log is started over with
+//! their new sizes, so a log without entries has nothing to recover and the files are left alone.
+//! A log with entries is only replayed if the files are at least as large as its header says. A
+//! table with such a log can't be opened without the log, since appending to it would be undone by
+//! the next replay.
+
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, BufRead, BufReader, BufWriter, Seek, Write},
+  path::{Path, PathBuf},
+  time::{Duration, Instant},
+};
+
+use crate::cbor::{read_cbor_raw, read_cbor_u64, CborWrite};
+
+/// When the write-ahead log is synced to disk. Entries that are not synced yet can be lost in a
+/// crash, so syncing less often trades durability for speed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+  /// Sync after every write, so every write that returns is durable.
+  EveryWrite,
+  /// Sync on the first write after the interval has passed since the last sync. There is no timer,
+  /// so if writes stop, the last ones are only synced when the writer is closed or dropped, or by
+  /// `SSTableWriter::sync_wal`. Until then a crash can lose them.
+  Interval(Duration),
+  /// Sync once every N writes, committing them as a group. Call `SSTableWriter::sync_wal` to commit
+  /// a partial group, such as at the end of a batch.
+  GroupCommit(usize),
+}
+
+/// An open write-ahead log.
+#[derive(Debug)]
+pub struct Wal {
+  pub path: PathBuf,
+  writer: BufWriter<File>,
+  policy: SyncPolicy,
+  last_sync: Instant,
+  unsynced_writes: usize,
+}
+
+impl Wal {
+  /// Starts a new log at the path, replacing any existing log, for a table whose data and index
+  /// files are synced with the given sizes.
+  pub fn create<P: Into<PathBuf>>(path: P, policy: SyncPolicy, data_size: u64, index_size: u64) -> io::Result<Self> {
+    let path = path.into();
+    let file = OpenOptions::new().create(true).write(true).truncate(true).open(&path)?;
+    let mut wal = Wal {
+      path,
+      writer: BufWriter::new(file),
+      policy,
+      last_sync: Instant::now(),
+      unsynced_writes: 0,
+    };
+    wal.write_header(data_size, index_size)?;
+    Ok(wal)
+  }
+
+  fn write_header(&mut self, data_size: u64, index_size: u64) -> io::Result<()> {
+    data_size.cbor_write(&mut self.writer)?;
+    index_size.cbor_write(&mut self.writer)?;
+    self.sync()
+  }
+
+  /// Appends an encoded entry, syncing if the policy says so.
+  pub fn append(&mut self, key_bytes: &[u8], value_bytes: &[u8]) -> io::Result<()> {
+    self.writer.write_all(key_bytes)?;
+    self.writer.write_all(value_bytes)?;
+    self.unsynced_writes += 1;
+
+    let should_sync = match self.policy {
+      SyncPolicy::EveryWrite => true,
+      SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+      SyncPolicy::GroupCommit(n) => self.unsynced_writes >= n,
+    };
+    if should_sync {
+      self.sync()?;
+    }
+
+    Ok(())
+  }
+
+  /// Flushes and syncs every entry appended so far.
+  pub fn sync(&mut self) -> io::Result<()> {
+    self.writer.flush()?;
+    self.writer.get_ref().sync_data()?;
+    self.last_sync = Instant::now();
+    self.unsynced_writes = 0;
+    Ok(())
+  }
+
+  /// Starts the log over once the table's data and index files are synced with the given sizes.
+  pub fn checkpoint(&mut self, data_size: u64, index_size: u64) -> io::Result<()> {
+    self.writer.flush()?;
+    self.writer.get_ref().set_len(0)?;
+    // The file was opened without append, so the position has to be reset too.
+    self.writer.get_mut().rewind()?;
+    self.write_header(data_size, index_size)
+  }
+}
+
+/// Syncs the entries that the policy hasn't synced yet, so that dropping the writer without
+/// closing it doesn't lose them. Errors can't be returned from `drop`, so they are ignored.
+impl Drop for Wal {
+  fn drop(&mut self) {
+    if self.unsynced_writes > 0 {
+      let _ = self.sync();
+    }
+  }
+}
+
+/// Reads the entries of a write-ahead log so that they can be replayed.
+pub struct WalReader {
+  reader: BufReader<File>,
+  /// The synced size of the data file when the log was started.
+  pub data_size: u64,
+  /// The synced size of the index file when the log was started.
+  pub index_size: u64,
+}
+
+impl WalReader {
+  /// Opens the log at the path, or returns `None` if there is no log or it doesn't have a complete
+  /// header, in which case there is nothing to replay.
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+    let mut reader = match File::open(path) {
+      Ok(file) => BufReader::new(file),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e),
+    };
+
+    let header = read_cbor_u64(&mut reader).and_then(|data_size| Ok((data_size, read_cbor_u64(&mut reader)?)));
+    match header {
+      Ok((data_size, index_size)) => Ok(Some(WalReader {
+        reader,
+        data_size,
+        index_size,
+      })),
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Whether the log has anything after its header, which means entries were written that were
+  /// never checkpointed into the table.
+  pub fn has_entries(&mut self) -> io::Result<bool> {
+    Ok(!self.reader.fill_buf()?.is_empty())
+  }
+
+  /// Reads the next complete entry into the buffers, returning false at the end of the log. A
+  /// partial entry at the end of the log is ignored.
+  pub fn next_entry(&mut self, key_bytes: &mut Vec<u8>, value_bytes: &mut Vec<u8>) -> io::Result<bool> {
+    key_bytes.clear();
+    value_bytes.clear();
+    match read_cbor_raw(&mut self.reader, key_bytes).and_then(|_| read_cbor_raw(&mut self.reader, value_bytes)) {
+      Ok(()) => Ok(true),
+      Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+      Err(e) => Err(e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common_testing::{assert, setup};
+  use std::fs;
+
+  const TEST_WAL_NAME: &str = ".tmp/wal_test.wal.sst";
+
+  fn read_entries(path: &str) -> (u64, u64, Vec<(Vec<u8>, Vec<u8>)>) {
+    let mut reader = WalReader::open(path).unwrap().unwrap();
+    let mut entries = Vec::new();
+    let (mut key, mut value) = (Vec::new(), Vec::new());
+    while reader.next_entry(&mut key, &mut value).unwrap() {
+      entries.push((key.clone(), value.clone()));
+    }
+    (reader.data_size, reader.index_size, entries)
+  }
+
+  #[test]
+  fn appends_and_reads_entries() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    let mut wal = Wal::create(TEST_WAL_NAME, SyncPolicy::GroupCommit(2), 10, 4).unwrap();
+    wal.append(&[0x61, b'a'], &[0x61, b'1']).unwrap();
+    wal.append(&[0x61, b'b'], &[0x61, b'2']).unwrap();
+    wal.append(&[0x61, b'c'], &[0x61, b'3']).unwrap();
+    wal.sync().unwrap();
+
+    let (data_size, index_size, entries) = read_entries(TEST_WAL_NAME);
+    assert::equal((data_size, index_size), (10, 4));
+    assert!(WalReader::open(TEST_WAL_NAME).unwrap().unwrap().has_entries().unwrap());
+    assert::equal(entries.len(), 3);
+    assert::equal(entries[2].clone(), (vec![0x61, b'c'], vec![0x61, b'3']));
+
+    wal.checkpoint(22, 8).unwrap();
+    assert::equal(read_entries(TEST_WAL_NAME), (22, 8, Vec::new()));
+    assert!(!WalReader::open(TEST_WAL_NAME).unwrap().unwrap().has_entries().unwrap());
+  }
+
+  #[test]
+  fn syncs_on_drop() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    let mut wal = Wal::create(TEST_WAL_NAME, SyncPolicy::Interval(Duration::from_secs(3600)), 0, 0).unwrap();
+    wal.append(&[0x61, b'a'], &[0x61, b'1']).unwrap();
+    let (_, _, entries) = read_entries(TEST_WAL_NAME);
+    assert::equal(entries.len(), 0);
+
+    drop(wal);
+    let (_, _, entries) = read_entries(TEST_WAL_NAME);
+    assert::equal(entries.len(), 1);
+  }
+
+  #[test]
+  fn ignores_partial_entries() {
+    let _lock = setup::sequential();
+    setup::create_dir_all(".tmp").unwrap();
+    let mut wal = Wal::create(TEST_WAL_NAME, SyncPolicy::EveryWrite, 0, 0).unwrap();
+    wal.append(&[0x61, b'a'], &[0x61, b'1']).unwrap();
+    wal.append(&[0x61, b'b'], &[0x63, b'2', b'2', b'2']).unwrap();
+    drop(wal);
+
+    let size = fs::metadata(TEST_WAL_NAME).unwrap().len();
+    OpenOptions::new()
+      .write(true)
+      .open(TEST_WAL_NAME)
+      .unwrap()
+      .set_len(size - 2)
+      .unwrap();
+    let (_, _, entries) = read_entries(TEST_WAL_NAME);
+    assert::equal(entries, vec![(vec![0x61, b'a'], vec![0x61, b'1'])]);
+
+    fs::write(TEST_WAL_NAME, [0x05]).unwrap();
+    assert!(WalReader::open(TEST_WAL_NAME).unwrap().is_none());
+  }
+}